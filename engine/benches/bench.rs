@@ -7,8 +7,14 @@ static A: System = System;
 
 use criterion::{
     criterion_group, criterion_main, Bencher, Benchmark, Criterion, ParameterizedBenchmark,
+    Throughput,
+};
+use std::{
+    borrow::Cow,
+    clone::Clone,
+    fmt::Debug,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
 };
-use std::{borrow::Cow, clone::Clone, fmt::Debug, net::IpAddr};
 use wirefilter::{
     ExecutionContext, FilterAst, Function, FunctionArgKind, FunctionArgs, FunctionImpl,
     FunctionParam, GetType, LhsValue, Scheme, Type,
@@ -138,6 +144,51 @@ impl<'a, T: 'static + Copy + Debug + Into<LhsValue<'static>>> FieldBench<'a, T>
                     values.iter().cloned(),
                 ),
             );
+
+            c.bench(
+                "bytecode compilation",
+                Benchmark::new(name, {
+                    let mut scheme = Scheme::default();
+                    scheme.add_field(field.to_owned(), ty).unwrap();
+                    for (name, function) in functions {
+                        scheme
+                            .add_function((*name).into(), function.clone())
+                            .unwrap();
+                    }
+                    move |b: &mut Bencher| {
+                        let filter = scheme.parse(filter).unwrap();
+
+                        b.iter_with_setup(move || filter.clone(), FilterAst::compile_to_bytecode);
+                    }
+                }),
+            );
+
+            c.bench(
+                "bytecode execution",
+                ParameterizedBenchmark::new(
+                    name,
+                    {
+                        let mut scheme = Scheme::default();
+                        scheme.add_field(field.to_owned(), ty).unwrap();
+                        for (name, function) in functions {
+                            scheme
+                                .add_function((*name).into(), function.clone())
+                                .unwrap();
+                        }
+                        move |b: &mut Bencher, value: &T| {
+                            let filter = scheme.parse(filter).unwrap();
+
+                            let filter = filter.compile_to_bytecode();
+
+                            let mut exec_ctx = ExecutionContext::new(&scheme);
+                            exec_ctx.set_field_value(field, *value).unwrap();
+
+                            b.iter(|| filter.execute(&exec_ctx));
+                        }
+                    },
+                    values.iter().cloned(),
+                ),
+            );
         }
     }
 }
@@ -218,6 +269,7 @@ fn bench_string_function_comparison(c: &mut Criterion) {
                     opt_params: vec![],
                     return_type: Type::Bytes,
                     implementation: FunctionImpl::new(lowercase),
+                    pure: true,
                 },
             ),
             (
@@ -230,6 +282,7 @@ fn bench_string_function_comparison(c: &mut Criterion) {
                     opt_params: vec![],
                     return_type: Type::Bytes,
                     implementation: FunctionImpl::new(uppercase),
+                    pure: true,
                 },
             ),
         ],
@@ -242,15 +295,171 @@ fn bench_string_function_comparison(c: &mut Criterion) {
     .run(c)
 }
 
+fn bench_batch_execution(c: &mut Criterion) {
+    // Pipelines over logs run a single filter against many records, so
+    // compare a batch with executing each record separately.
+    c.bench(
+        "batch execution",
+        Benchmark::new("loop", {
+            let scheme = batch_scheme();
+            move |b: &mut Bencher| {
+                let filter = scheme.parse(BATCH_FILTER).unwrap().compile();
+                let ctxs = batch_contexts(&scheme);
+                b.iter(|| {
+                    ctxs.iter()
+                        .map(|ctx| filter.execute(ctx).unwrap())
+                        .collect::<Vec<_>>()
+                });
+            }
+        })
+        .with_function("batch", {
+            let scheme = batch_scheme();
+            move |b: &mut Bencher| {
+                let filter = scheme.parse(BATCH_FILTER).unwrap().compile();
+                let ctxs = batch_contexts(&scheme);
+                let ctxs = ctxs.iter().collect::<Vec<_>>();
+                b.iter(|| filter.execute_batch(&ctxs).unwrap());
+            }
+        })
+        .throughput(Throughput::Elements(BATCH_SIZE as u32)),
+    );
+}
+
+const BATCH_FILTER: &str = r#"tcp.port in { 80 443 } and http.host contains "example""#;
+
+const BATCH_SIZE: usize = 1000;
+
+fn batch_scheme() -> Scheme {
+    let mut scheme = Scheme::default();
+    scheme.add_field("tcp.port".into(), Type::Int).unwrap();
+    scheme.add_field("http.host".into(), Type::Bytes).unwrap();
+    scheme
+}
+
+fn batch_contexts(scheme: &Scheme) -> Vec<ExecutionContext<'_>> {
+    (0..BATCH_SIZE)
+        .map(|i| {
+            let mut ctx = ExecutionContext::new(scheme);
+            ctx.set_field_value("tcp.port", [80, 443, 8080][i % 3])
+                .unwrap();
+            ctx.set_field_value(
+                "http.host",
+                ["www.example.org", "example.com", "other.net"][i % 3],
+            )
+            .unwrap();
+            ctx
+        })
+        .collect()
+}
+
+fn bench_parsing_short_lists(c: &mut Criterion) {
+    // Rules are usually made of many comparisons with short lists, so
+    // parsing them is dominated by allocations of list literals.
+    let filter = r#"
+        (tcp.port in { 80 443 } and ip.src in { 10.0.0.0/8 192.168.0.0/16 })
+        or (http.host in { "example.org" "example.com" } and tcp.port in { 8080 })
+        or (ip.src in { 2001:db8::/32 } and http.host in { "example.net" })
+        or (tcp.port in { 22 23 2222 } and not ip.src in { 127.0.0.1 })
+    "#;
+
+    c.bench(
+        "parsing",
+        Benchmark::new("short lists", {
+            let mut scheme = Scheme::default();
+            scheme.add_field("tcp.port".into(), Type::Int).unwrap();
+            scheme.add_field("ip.src".into(), Type::Ip).unwrap();
+            scheme.add_field("http.host".into(), Type::Bytes).unwrap();
+            move |b: &mut Bencher| {
+                b.iter(|| scheme.parse(filter).unwrap());
+            }
+        }),
+    );
+}
+
+fn bench_large_ip_lists(c: &mut Criterion) {
+    // Blocklists of IP reputation feeds have tens of thousands of prefixes,
+    // which are merged into sorted ranges and looked up with a binary
+    // search.
+    let prefixes = (0..LARGE_IP_LIST_SIZE as u32 / 2)
+        .map(|i| format!("{}/24", Ipv4Addr::from(0x0a00_0000 | i << 9)))
+        .chain(
+            (0..LARGE_IP_LIST_SIZE as u128 / 2)
+                .map(|i| format!("{}/48", Ipv6Addr::from(0x2001_0db8_u128 << 96 | i << 81))),
+        )
+        .collect::<Vec<_>>();
+    let filter = format!("ip.src in {{ {} }}", prefixes.join(" "));
+
+    let scheme = || {
+        let mut scheme = Scheme::default();
+        scheme.add_field("ip.src".into(), Type::Ip).unwrap();
+        scheme
+    };
+
+    c.bench(
+        "parsing",
+        Benchmark::new("100k prefixes", {
+            let scheme = scheme();
+            let filter = filter.clone();
+            move |b: &mut Bencher| {
+                b.iter(|| scheme.parse(&filter).unwrap());
+            }
+        })
+        .sample_size(10),
+    );
+
+    c.bench(
+        "compilation",
+        Benchmark::new("100k prefixes", {
+            let scheme = scheme();
+            let filter = filter.clone();
+            move |b: &mut Bencher| {
+                let filter = scheme.parse(&filter).unwrap();
+
+                b.iter_with_setup(move || filter.clone(), FilterAst::compile);
+            }
+        })
+        .sample_size(10),
+    );
+
+    c.bench(
+        "execution",
+        ParameterizedBenchmark::new(
+            "100k prefixes",
+            {
+                let scheme = scheme();
+                move |b: &mut Bencher, value: &IpAddr| {
+                    let filter = scheme.parse(&filter).unwrap().compile();
+
+                    let mut exec_ctx = ExecutionContext::new(&scheme);
+                    exec_ctx.set_field_value("ip.src", *value).unwrap();
+
+                    b.iter(|| filter.execute(&exec_ctx));
+                }
+            },
+            vec![
+                IpAddr::from([10, 0, 0, 1]),
+                IpAddr::from([10, 0, 1, 1]),
+                IpAddr::from([0x2001, 0x0db8, 0, 0, 0, 0, 0, 1]),
+                IpAddr::from([0x2001, 0x0db8, 1, 0, 0, 0, 0, 1]),
+            ],
+        ),
+    );
+}
+
+const LARGE_IP_LIST_SIZE: usize = 100_000;
+
 criterion_group! {
     name = field_benchmarks;
     config = Criterion::default();
     targets =
         bench_ip_comparisons,
+        bench_large_ip_lists,
         bench_int_comparisons,
         bench_string_comparisons,
         bench_string_matches,
         bench_string_function_comparison,
+        bench_parsing_short_lists,
+        bench_batch_execution,
 }
 
 criterion_main!(field_benchmarks);
@@ -1,18 +1,296 @@
-use std::env::args;
+use serde_json::{json, Value};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    collections::HashMap,
+    env::args,
+    fs::read_to_string,
+    io::{stdin, BufRead},
+    net::IpAddr,
+    process::exit,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
 use wirefilter::{
-    Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionOptParam, FunctionParam,
-    LhsValue, Scheme, Type,
+    ExecutionContext, Filter, FilterAst, Function, FunctionArgKind, FunctionArgs, FunctionImpl,
+    FunctionOptParam, FunctionParam, LhsValue, Scheme, SetFieldValueError, Type, TypeMismatchError,
 };
 
+/// Counts allocations so that `bench` can report them.
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
 fn panic_function<'a>(_: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
     panic!();
 }
 
+fn set_json_value(ctx: &mut ExecutionContext<'_>, name: &str, value: &Value) -> Result<(), String> {
+    let res = match value {
+        Value::Bool(value) => ctx.set_field_value(name, *value),
+        Value::Number(value) => match value.as_i64() {
+            Some(value) => ctx.set_field_value(name, value),
+            None => return Err(format!("{} is not a valid integer", value)),
+        },
+        Value::String(value) => match ctx
+            .set_field_value(name, LhsValue::Bytes(value.as_bytes().to_vec().into()))
+        {
+            // Strings are used for both bytes and IPs, so retry as an IP.
+            Err(SetFieldValueError::TypeMismatch(TypeMismatchError {
+                expected: Type::Ip, ..
+            })) => match value.parse::<IpAddr>() {
+                Ok(value) => ctx.set_field_value(name, value),
+                Err(err) => return Err(format!("{}: {}", value, err)),
+            },
+            res => res,
+        },
+        _ => return Err(format!("unsupported value {}", value)),
+    };
+    res.map_err(|err| format!("{}: {}", name, err))
+}
+
+fn json_record_context<'s>(
+    scheme: &'s Scheme,
+    record: &str,
+) -> Result<ExecutionContext<'s>, String> {
+    let record: Value = serde_json::from_str(record).map_err(|err| err.to_string())?;
+
+    let mut ctx = ExecutionContext::new(scheme);
+
+    if let Some(fields) = record.as_object() {
+        for (name, value) in fields {
+            set_json_value(&mut ctx, name, value)?;
+        }
+    }
+
+    Ok(ctx)
+}
+
+fn as_nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + u64::from(duration.subsec_nanos())
+}
+
+fn execute_json_record<'s>(
+    scheme: &'s Scheme,
+    ast: &FilterAst<'s>,
+    filter: &Filter<'s>,
+    record: &str,
+) -> Value {
+    let ctx = match json_record_context(scheme, record) {
+        Ok(ctx) => ctx,
+        Err(err) => return json!({ "error": err }),
+    };
+
+    // Executing with a missing field panics, which would end the whole
+    // stream, so it's reported for this record instead.
+    if let Err(missing) = ctx.validate_for(ast) {
+        let names = missing
+            .iter()
+            .map(|field| field.name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        return json!({ "error": format!("missing values of fields {}", names) });
+    }
+
+    let start = Instant::now();
+    let verdict = filter.execute(&ctx).unwrap();
+    let elapsed = start.elapsed();
+
+    json!({
+        "verdict": verdict,
+        "elapsed_ns": as_nanos(elapsed),
+    })
+}
+
+/// Parses `--name value` options and `--name` flags of a subcommand.
+fn options(
+    mut args: impl Iterator<Item = String>,
+    names: &[&str],
+    flags: &[&str],
+) -> HashMap<String, String> {
+    let mut options = HashMap::new();
+
+    while let Some(arg) = args.next() {
+        let value = if flags.contains(&arg.as_str()) {
+            String::new()
+        } else if names.contains(&arg.as_str()) {
+            args.next()
+                .unwrap_or_else(|| panic!("Expected a value after {}", arg))
+        } else {
+            panic!("Unknown argument {}", arg);
+        };
+        options.insert(arg, value);
+    }
+
+    options
+}
+
+/// Reads a file with a path given in an option.
+fn read_option(options: &HashMap<String, String>, name: &str) -> String {
+    let path = options
+        .get(name)
+        .unwrap_or_else(|| panic!("Expected {}", name));
+    read_to_string(path).unwrap_or_else(|err| panic!("Could not read {}: {}", path, err))
+}
+
+/// Reads a scheme from a JSON object mapping field names to types.
+fn read_scheme(options: &HashMap<String, String>) -> Scheme {
+    serde_json::from_str(&read_option(options, "--scheme")).unwrap()
+}
+
+/// Runs `bench --scheme s.json --filter f.txt --inputs data.jsonl`.
+///
+/// The scheme is a JSON object mapping field names to types, and inputs are
+/// JSON records, one per line. Each record is executed a number of times and
+/// latency percentiles and allocations per execution are reported.
+fn bench(args: impl Iterator<Item = String>) {
+    const ITERATIONS: u32 = 1000;
+
+    let options = options(args, &["--scheme", "--filter", "--inputs"], &[]);
+
+    let scheme = read_scheme(&options);
+    let filter = read_option(&options, "--filter");
+    let inputs = read_option(&options, "--inputs");
+
+    let filter = match scheme.parse(filter.trim()) {
+        Ok(ast) => ast.compile(),
+        Err(err) => panic!("{}", err),
+    };
+
+    // Contexts are filled up front so that only execution is measured.
+    let contexts = inputs
+        .lines()
+        .filter(|record| !record.trim().is_empty())
+        .enumerate()
+        .map(|(i, record)| {
+            json_record_context(&scheme, record)
+                .unwrap_or_else(|err| panic!("Invalid record #{}: {}", i + 1, err))
+        })
+        .collect::<Vec<_>>();
+
+    if contexts.is_empty() {
+        panic!("Expected at least one input record");
+    }
+
+    let mut latencies = Vec::with_capacity(contexts.len());
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+
+    for ctx in &contexts {
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            filter.execute(ctx).unwrap();
+        }
+        latencies.push(as_nanos(start.elapsed()) / u64::from(ITERATIONS));
+    }
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+
+    latencies.sort();
+
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+
+    println!("records: {}", contexts.len());
+    println!("p50: {} ns", percentile(50));
+    println!("p99: {} ns", percentile(99));
+    println!(
+        "allocations per record: {:.2}",
+        allocations as f64 / (contexts.len() as f64 * f64::from(ITERATIONS))
+    );
+}
+
+/// Runs `corpus --scheme s.json --corpus corpus.jsonl [--record]`.
+///
+/// Each line of the corpus is a JSON object with a `filter`, a `context`
+/// record and an `expected` verdict. Records whose verdict differs are
+/// printed as JSON and the process fails if there are any.
+///
+/// With `--record`, the whole corpus is printed back with `expected` set to
+/// the actual verdicts instead. This allows to compare two versions of the
+/// engine by recording a corpus with one of them and checking it with the
+/// other one.
+fn corpus(args: impl Iterator<Item = String>) {
+    let options = options(args, &["--scheme", "--corpus"], &["--record"]);
+
+    let scheme = read_scheme(&options);
+    let corpus = read_option(&options, "--corpus");
+    let record = options.contains_key("--record");
+
+    let mut differences = 0;
+
+    for (i, line) in corpus.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let mut entry: Value = serde_json::from_str(line)
+            .unwrap_or_else(|err| panic!("Invalid entry on line {}: {}", i + 1, err));
+
+        let actual = match entry["filter"].as_str().map(|filter| scheme.parse(filter)) {
+            Some(Ok(ast)) => match json_record_context(&scheme, &entry["context"].to_string()) {
+                Ok(ctx) => json!(ast.compile().execute(&ctx).unwrap()),
+                Err(err) => json!({ "error": err }),
+            },
+            Some(Err(err)) => json!({ "error": err.to_string() }),
+            None => json!({ "error": "missing filter" }),
+        };
+
+        if record {
+            entry["expected"] = actual;
+            println!("{}", entry);
+        } else if entry["expected"] != actual {
+            differences += 1;
+            println!(
+                "{}",
+                json!({
+                    "line": i + 1,
+                    "filter": entry["filter"],
+                    "expected": entry["expected"],
+                    "actual": actual,
+                })
+            );
+        }
+    }
+
+    if differences > 0 {
+        eprintln!("{} differences found", differences);
+        exit(1);
+    }
+}
+
 fn main() {
-    let filter = args()
-        .nth(1)
+    let mut args = args().skip(1);
+
+    let filter = args
+        .next()
         .expect("Expected an input as a command-line argument");
 
+    match filter.as_str() {
+        "bench" => return bench(args),
+        "corpus" => return corpus(args),
+        _ => {}
+    }
+
+    // With `--json`, execute the filter against JSON records read line by
+    // line from stdin and print a JSON result for each of them.
+    let json = match args.next() {
+        Some(ref arg) if arg == "--json" => true,
+        Some(arg) => panic!("Unknown argument {}", arg),
+        None => false,
+    };
+
     let mut scheme = Scheme! {
         ip: Ip,
         str: Bytes,
@@ -34,12 +312,22 @@ fn main() {
                 }],
                 return_type: Type::Bytes,
                 implementation: FunctionImpl::new(panic_function),
+                pure: false,
             },
         )
         .unwrap();
 
     match scheme.parse(&filter) {
+        Ok(res) if json => {
+            let filter = res.clone().compile();
+
+            for record in stdin().lock().lines() {
+                let record = record.unwrap();
+                println!("{}", execute_json_record(&scheme, &res, &filter, &record));
+            }
+        }
         Ok(res) => println!("{:#?}", res),
+        Err(err) if json => println!("{}", json!({ "error": err.to_string() })),
         Err(err) => println!("{}", err),
     }
 }
@@ -0,0 +1,188 @@
+use crate::{
+    rhs_types::{Bytes, ExplicitIpRange, IpRange, Regex},
+    types::{RhsValue, RhsValues},
+};
+use cidr::{Cidr, IpCidr};
+use fnv::FnvBuildHasher;
+use indexmap::IndexMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// Placeholders are allocated from ranges reserved for private use and
+// documentation, so they can't be mistaken for real addresses.
+const V4_BASE: u32 = 0x0a00_0000; // 10.0.0.0/8
+const V6_BASE: u128 = 0x2001_0db8 << 96; // 2001:db8::/32
+
+fn anonymize_byte(b: u8) -> u8 {
+    match b {
+        b'a'..=b'z' => b'x',
+        b'A'..=b'Z' => b'X',
+        b'0'..=b'9' => b'0',
+        b' ' => b,
+        _ if b.is_ascii_punctuation() => b,
+        _ => b'x',
+    }
+}
+
+// Allocates a block of `2^host_bits` addresses aligned to its size. Networks
+// larger than the base range are masked by the caller.
+fn allocate(next: &mut u128, host_bits: u32) -> u128 {
+    let size = 1u128.checked_shl(host_bits).unwrap_or(0);
+    let start = if size == 0 {
+        0
+    } else {
+        next.wrapping_add(size - 1) & !(size - 1)
+    };
+    *next = start.wrapping_add(size);
+    start
+}
+
+/// Replaces literals with placeholders of the same shape, see
+/// [`FilterAst::anonymize`](::FilterAst::anonymize).
+///
+/// The same networks are always replaced with the same placeholders, so that
+/// relations between literals in a filter are preserved.
+#[derive(Default)]
+pub(crate) struct Anonymizer {
+    networks: IndexMap<(IpAddr, u8), IpAddr, FnvBuildHasher>,
+    next_v4: u128,
+    next_v6: u128,
+}
+
+impl Anonymizer {
+    pub fn bytes(&mut self, bytes: &Bytes) -> Bytes {
+        match bytes {
+            // Non-ASCII characters are replaced with as many placeholders as
+            // they have bytes, so that the length is preserved.
+            Bytes::Str(s) => s
+                .bytes()
+                .map(|b| char::from(anonymize_byte(b)))
+                .collect::<String>()
+                .into(),
+            Bytes::Raw(raw) => raw
+                .iter()
+                .map(|&b| anonymize_byte(b))
+                .collect::<Vec<u8>>()
+                .into(),
+        }
+    }
+
+    pub fn regex(&mut self, regex: &Regex) -> Regex {
+        let source = regex.as_str();
+        let mut anonymized = String::with_capacity(source.len());
+        let mut escaped = false;
+        let mut in_repetition = false;
+        let mut in_flags = false;
+
+        // Only literal characters are replaced, while escapes, repetitions
+        // and group flags are kept as they are.
+        for c in source.chars() {
+            let keep = escaped || in_repetition || in_flags;
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '{' => in_repetition = true,
+                '}' => in_repetition = false,
+                '?' if anonymized.ends_with('(') => in_flags = true,
+                ':' | ')' | '<' => in_flags = false,
+                _ => {}
+            }
+            if keep || !c.is_ascii_alphanumeric() {
+                anonymized.push(c);
+            } else if c.is_ascii_digit() {
+                anonymized.push('0');
+            } else {
+                anonymized.push('x');
+            }
+        }
+
+        anonymized.parse().unwrap_or_else(|_| {
+            "x".repeat(source.len())
+                .parse()
+                .expect("a plain literal is a valid regex")
+        })
+    }
+
+    fn network(&mut self, addr: IpAddr, len: u8) -> IpAddr {
+        if let Some(placeholder) = self.networks.get(&(addr, len)) {
+            return *placeholder;
+        }
+
+        let placeholder = match addr {
+            IpAddr::V4(_) => {
+                let host_bits = 32 - u32::from(len);
+                let offset = allocate(&mut self.next_v4, host_bits) as u32;
+                let mask = (!0u32).checked_shl(host_bits).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(V4_BASE.wrapping_add(offset) & mask))
+            }
+            IpAddr::V6(_) => {
+                let host_bits = 128 - u32::from(len);
+                let offset = allocate(&mut self.next_v6, host_bits);
+                let mask = (!0u128).checked_shl(host_bits).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(V6_BASE.wrapping_add(offset) & mask))
+            }
+        };
+
+        self.networks.insert((addr, len), placeholder);
+        placeholder
+    }
+
+    pub fn ip(&mut self, addr: IpAddr) -> IpAddr {
+        let len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        self.network(addr, len)
+    }
+
+    pub fn ip_range(&mut self, range: &IpRange) -> IpRange {
+        match range {
+            IpRange::Cidr(cidr) => {
+                let len = cidr.network_length();
+                let addr = self.network(cidr.first_address(), len);
+                IpRange::Cidr(IpCidr::new(addr, len).expect("placeholders are aligned"))
+            }
+            IpRange::Explicit(ExplicitIpRange::V4(range)) => {
+                match (
+                    self.ip(IpAddr::V4(*range.start())),
+                    self.ip(IpAddr::V4(*range.end())),
+                ) {
+                    (IpAddr::V4(start), IpAddr::V4(end)) => {
+                        IpRange::Explicit(ExplicitIpRange::V4(start.min(end)..=start.max(end)))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            IpRange::Explicit(ExplicitIpRange::V6(range)) => {
+                match (
+                    self.ip(IpAddr::V6(*range.start())),
+                    self.ip(IpAddr::V6(*range.end())),
+                ) {
+                    (IpAddr::V6(start), IpAddr::V6(end)) => {
+                        IpRange::Explicit(ExplicitIpRange::V6(start.min(end)..=start.max(end)))
+                    }
+                    _ => unreachable!(),
+                }
+            }
+        }
+    }
+
+    pub fn rhs_value(&mut self, value: &RhsValue) -> RhsValue {
+        match value {
+            RhsValue::Ip(addr) => RhsValue::Ip(self.ip(*addr)),
+            RhsValue::Bytes(bytes) => RhsValue::Bytes(self.bytes(bytes)),
+            value => value.clone(),
+        }
+    }
+
+    pub fn rhs_values(&mut self, values: &RhsValues) -> RhsValues {
+        match values {
+            RhsValues::Ip(ranges) => {
+                RhsValues::Ip(ranges.iter().map(|range| self.ip_range(range)).collect())
+            }
+            RhsValues::Bytes(values) => {
+                RhsValues::Bytes(values.iter().map(|bytes| self.bytes(bytes)).collect())
+            }
+            values => values.clone(),
+        }
+    }
+}
@@ -0,0 +1,404 @@
+use super::{
+    combined_expr::CombiningOp,
+    repr::{ExprRepr, FieldExprRepr, LhsRepr, ValueRepr},
+    simple_expr::UnaryOp,
+    FilterAst,
+};
+use crate::{
+    rhs_types::{Timestamp, TimestampLiteral},
+    scheme::Scheme,
+};
+use cidr::IpCidr;
+use failure::Fail;
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::{Not, RangeInclusive},
+};
+
+/// An error that occurs when an [`ExprBuilder`] doesn't describe a valid
+/// filter for a given scheme, e.g. because it refers to an unknown field or
+/// compares a field with a literal of another type.
+#[derive(Debug, PartialEq, Eq, Clone, Fail)]
+#[fail(display = "invalid expression: {}", reason)]
+pub struct ExprBuildError {
+    /// Why the expression is invalid.
+    pub reason: String,
+}
+
+/// An untyped literal for the right-hand side of a comparison built with
+/// an [`ExprBuilder`].
+///
+/// Literals get their type from the field they are compared with, so e.g.
+/// a string can become either a byte string, an IP address or a timestamp.
+#[derive(Debug, Clone)]
+pub struct Literal(ValueRepr);
+
+impl From<i32> for Literal {
+    fn from(value: i32) -> Self {
+        Literal(ValueRepr::Int(value.into()))
+    }
+}
+
+impl From<u32> for Literal {
+    fn from(value: u32) -> Self {
+        Literal(ValueRepr::Int(value.into()))
+    }
+}
+
+impl From<i64> for Literal {
+    fn from(value: i64) -> Self {
+        Literal(ValueRepr::Int(value))
+    }
+}
+
+impl From<f64> for Literal {
+    fn from(value: f64) -> Self {
+        Literal(ValueRepr::Float(value))
+    }
+}
+
+impl<'a> From<&'a str> for Literal {
+    fn from(value: &'a str) -> Self {
+        Literal(ValueRepr::String(value.to_owned()))
+    }
+}
+
+impl From<String> for Literal {
+    fn from(value: String) -> Self {
+        Literal(ValueRepr::String(value))
+    }
+}
+
+impl<'a> From<&'a [u8]> for Literal {
+    fn from(value: &'a [u8]) -> Self {
+        Literal(ValueRepr::List(
+            value.iter().map(|&b| ValueRepr::Int(b.into())).collect(),
+        ))
+    }
+}
+
+impl From<Vec<u8>> for Literal {
+    fn from(value: Vec<u8>) -> Self {
+        Literal::from(&value[..])
+    }
+}
+
+impl From<IpAddr> for Literal {
+    fn from(value: IpAddr) -> Self {
+        Literal(ValueRepr::String(value.to_string()))
+    }
+}
+
+impl From<Ipv4Addr> for Literal {
+    fn from(value: Ipv4Addr) -> Self {
+        IpAddr::from(value).into()
+    }
+}
+
+impl From<Ipv6Addr> for Literal {
+    fn from(value: Ipv6Addr) -> Self {
+        IpAddr::from(value).into()
+    }
+}
+
+impl From<IpCidr> for Literal {
+    fn from(value: IpCidr) -> Self {
+        Literal(ValueRepr::String(value.to_string()))
+    }
+}
+
+impl From<Timestamp> for Literal {
+    fn from(value: Timestamp) -> Self {
+        Literal(ValueRepr::String(value.to_string()))
+    }
+}
+
+impl From<TimestampLiteral> for Literal {
+    fn from(value: TimestampLiteral) -> Self {
+        match value {
+            TimestampLiteral::Absolute(timestamp) => timestamp.into(),
+            TimestampLiteral::Relative(_) => Literal(ValueRepr::String(value.to_string())),
+        }
+    }
+}
+
+impl<T: Into<Literal>> From<RangeInclusive<T>> for Literal {
+    fn from(value: RangeInclusive<T>) -> Self {
+        let (start, end) = value.into_inner();
+        Literal(ValueRepr::Range {
+            start: Box::new(start.into().0),
+            end: Box::new(end.into().0),
+        })
+    }
+}
+
+/// A filter under construction, which is turned into a [`FilterAst`] with
+/// [`ExprBuilder::build`].
+///
+/// This allows services generating rules to construct filters without
+/// formatting strings, and so without having to quote and escape literals:
+///
+/// ```
+/// use wirefilter::{ExprBuilder, Scheme};
+///
+/// let scheme = Scheme! {
+///     http.host: Bytes,
+///     tcp.port: Int,
+/// };
+///
+/// let ast = ExprBuilder::field("http.host")
+///     .eq("example.com")
+///     .and(ExprBuilder::field("tcp.port").one_of(vec![80, 443]))
+///     .build(&scheme)
+///     .unwrap();
+///
+/// assert_eq!(
+///     ast,
+///     scheme
+///         .parse(r#"http.host == "example.com" && tcp.port in { 80 443 }"#)
+///         .unwrap()
+/// );
+/// ```
+///
+/// Names and types are checked only when the filter is built, with the
+/// same rules and [parser settings](::ParserSettings) that apply to parsed
+/// filters.
+#[derive(Debug, Clone)]
+pub struct ExprBuilder(ExprRepr);
+
+impl ExprBuilder {
+    /// Starts a comparison of a field.
+    pub fn field(name: &str) -> FieldExprBuilder {
+        FieldExprBuilder(LhsRepr::Field(name.to_owned()))
+    }
+
+    /// Creates a filter that is always `true` or always `false`.
+    pub fn literal(value: bool) -> Self {
+        ExprBuilder(ExprRepr::Literal(value))
+    }
+
+    fn combine(self, op: CombiningOp, other: Self) -> Self {
+        let mut items = Vec::new();
+        for ExprBuilder(expr) in [self, other] {
+            match expr {
+                ExprRepr::Combining {
+                    op: item_op,
+                    items: item_items,
+                } if item_op == op => items.extend(item_items),
+                expr => items.push(expr),
+            }
+        }
+        ExprBuilder(ExprRepr::Combining { op, items })
+    }
+
+    /// Combines two filters into `self && other`.
+    pub fn and(self, other: Self) -> Self {
+        self.combine(CombiningOp::And, other)
+    }
+
+    /// Combines two filters into `self || other`.
+    pub fn or(self, other: Self) -> Self {
+        self.combine(CombiningOp::Or, other)
+    }
+
+    /// Combines two filters into `self ^^ other`.
+    pub fn xor(self, other: Self) -> Self {
+        self.combine(CombiningOp::Xor, other)
+    }
+
+    /// Resolves names and checks types against a scheme.
+    pub fn build<'s>(&self, scheme: &'s Scheme) -> Result<FilterAst<'s>, ExprBuildError> {
+        let op = self
+            .0
+            .bind(scheme)
+            .map_err(|reason| ExprBuildError { reason })?;
+        Ok(FilterAst { scheme, op })
+    }
+}
+
+impl Not for ExprBuilder {
+    type Output = ExprBuilder;
+
+    /// Negates a filter into `not self`.
+    fn not(self) -> ExprBuilder {
+        ExprBuilder(ExprRepr::Unary {
+            op: UnaryOp::Not,
+            arg: Box::new(self.0),
+        })
+    }
+}
+
+/// A left-hand side of a comparison, created with
+/// [`ExprBuilder::field`].
+#[derive(Debug, Clone)]
+pub struct FieldExprBuilder(LhsRepr);
+
+impl FieldExprBuilder {
+    fn op(self, op: &str, rhs: Option<Literal>) -> ExprBuilder {
+        ExprBuilder(ExprRepr::Field(FieldExprRepr {
+            lhs: self.0,
+            op: op.to_owned(),
+            rhs: rhs.map(|Literal(rhs)| rhs),
+            rhs_expr: None,
+            zone: None,
+            bitwise: None,
+            quantifier: None,
+        }))
+    }
+
+    /// Checks a boolean field.
+    pub fn is_true(self) -> ExprBuilder {
+        self.op("IsTrue", None)
+    }
+
+    /// Compares with `==`.
+    pub fn eq(self, value: impl Into<Literal>) -> ExprBuilder {
+        self.op("Equal", Some(value.into()))
+    }
+
+    /// Compares with `!=`.
+    pub fn ne(self, value: impl Into<Literal>) -> ExprBuilder {
+        self.op("NotEqual", Some(value.into()))
+    }
+
+    /// Compares with `<`.
+    pub fn lt(self, value: impl Into<Literal>) -> ExprBuilder {
+        self.op("LessThan", Some(value.into()))
+    }
+
+    /// Compares with `<=`.
+    pub fn le(self, value: impl Into<Literal>) -> ExprBuilder {
+        self.op("LessThanEqual", Some(value.into()))
+    }
+
+    /// Compares with `>`.
+    pub fn gt(self, value: impl Into<Literal>) -> ExprBuilder {
+        self.op("GreaterThan", Some(value.into()))
+    }
+
+    /// Compares with `>=`.
+    pub fn ge(self, value: impl Into<Literal>) -> ExprBuilder {
+        self.op("GreaterThanEqual", Some(value.into()))
+    }
+
+    /// Checks whether a byte string contains a substring.
+    pub fn contains(self, value: impl Into<Literal>) -> ExprBuilder {
+        self.op("Contains", Some(value.into()))
+    }
+
+    /// Matches a byte string against a regular expression.
+    pub fn matches(self, regex: &str) -> ExprBuilder {
+        self.op("Matches", Some(regex.into()))
+    }
+
+    /// Matches a byte string against a wildcard pattern.
+    pub fn wildcard(self, pattern: &str) -> ExprBuilder {
+        self.op("Wildcard", Some(pattern.into()))
+    }
+
+    /// Checks whether a value is one of the given values or ranges.
+    pub fn one_of<T: Into<Literal>>(self, values: impl IntoIterator<Item = T>) -> ExprBuilder {
+        let values = values
+            .into_iter()
+            .map(|value| match value.into().0 {
+                // Numbers are serialized in lists as single-value ranges.
+                value @ ValueRepr::Int(_) | value @ ValueRepr::Float(_) => ValueRepr::Range {
+                    start: Box::new(value.clone()),
+                    end: Box::new(value),
+                },
+                value => value,
+            })
+            .collect();
+        self.op("OneOf", Some(Literal(ValueRepr::List(values))))
+    }
+
+    /// Checks whether a value is in a list registered in the scheme.
+    pub fn in_list(self, name: &str) -> ExprBuilder {
+        self.op("InList", Some(name.into()))
+    }
+}
+
+#[test]
+fn test_expr_builder() {
+    let scheme = &Scheme! {
+        http.host: Bytes,
+        http.cookies: Bytes,
+        tcp.port: Int,
+        ip.src: Ip,
+        ssl: Bool,
+        score: Float,
+    };
+
+    let assert_builds = |builder: ExprBuilder, filter: &str| {
+        assert_eq!(builder.build(scheme), Ok(scheme.parse(filter).unwrap()));
+    };
+
+    assert_builds(
+        ExprBuilder::field("http.host")
+            .eq("example.com")
+            .and(ExprBuilder::field("tcp.port").ge(1024))
+            .and(ExprBuilder::field("ssl").is_true()),
+        r#"http.host == "example.com" && tcp.port >= 1024 && ssl"#,
+    );
+
+    // Literals don't need to be escaped.
+    assert_builds(
+        ExprBuilder::field("http.cookies").contains(r#"a"b\c"#),
+        r#"http.cookies contains "a\"b\\c""#,
+    );
+    assert_builds(
+        ExprBuilder::field("http.host").matches(r"^a\.b$"),
+        r#"http.host matches "^a\.b$""#,
+    );
+
+    // Parentheses are added where needed.
+    assert_builds(
+        ExprBuilder::field("ssl")
+            .is_true()
+            .or(ExprBuilder::field("tcp.port").eq(80))
+            .and(
+                !ExprBuilder::field("score")
+                    .lt(0.5)
+                    .xor(ExprBuilder::literal(true)),
+            ),
+        "(ssl or tcp.port == 80) and not (score < 0.5 xor true)",
+    );
+
+    assert_builds(
+        ExprBuilder::field("tcp.port")
+            .one_of(vec![Literal::from(80), (8000..=8080).into()])
+            .and(ExprBuilder::field("ip.src").one_of(vec![
+                Literal::from(Ipv4Addr::new(10, 0, 0, 1)),
+                "192.168.0.0/16".into(),
+            ])),
+        "tcp.port in { 80 8000..8080 } and ip.src in { 10.0.0.1 192.168.0.0/16 }",
+    );
+    assert_builds(
+        ExprBuilder::field("ip.src").ne(Ipv6Addr::LOCALHOST),
+        "ip.src != ::1",
+    );
+
+    // Names and types are checked.
+    assert_eq!(
+        ExprBuilder::field("http.path").eq("/").build(scheme),
+        Err(ExprBuildError {
+            reason: "unknown field http.path".to_owned()
+        })
+    );
+    assert_eq!(
+        ExprBuilder::field("tcp.port").eq("80").build(scheme),
+        Err(ExprBuildError {
+            reason: "invalid literal of type Int".to_owned()
+        })
+    );
+    assert_eq!(
+        ExprBuilder::field("tcp.port").contains("80").build(scheme),
+        Err(ExprBuildError {
+            reason: "unsupported operator Contains for type Int".to_owned()
+        })
+    );
+    assert!(ExprBuilder::field("http.host")
+        .matches("(")
+        .build(scheme)
+        .is_err());
+}
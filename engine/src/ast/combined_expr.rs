@@ -1,10 +1,21 @@
-use super::{simple_expr::SimpleExpr, Expr};
+use super::{
+    field_expr::FieldExpr,
+    function_expr::CachedCalls,
+    simple_expr::{SimpleExpr, UnaryOp},
+    BindField, Expr, Folded, NormalFormSizeError, OperatorStyle, TraceNode,
+};
 use crate::{
+    execution_context::ExecutionContext,
     filter::CompiledExpr,
     lex::{skip_space, Lex, LexResult, LexWith},
+    parser_settings::ParserSettings,
+    rhs_types::RegexSet,
     scheme::{Field, Scheme},
+    types::LhsValue,
+    vm::{Instr, Program},
 };
 use serde::Serialize;
+use std::fmt;
 
 lex_enum!(#[derive(PartialOrd, Ord)] CombiningOp {
     "or" | "||" => Or,
@@ -23,7 +34,168 @@ pub enum CombinedExpr<'s> {
 }
 
 impl<'s> CombinedExpr<'s> {
-    fn lex_combining_op(input: &str) -> (Option<CombiningOp>, &str) {
+    /// Structurally combines two expressions with a given operator.
+    ///
+    /// Operands using the same operator are flattened and operands with a
+    /// lower precedence are parenthesized, so that the result is the same as
+    /// if the combined filter was parsed from a string.
+    pub(crate) fn combine(self, op: CombiningOp, other: Self) -> Self {
+        let mut items = Vec::new();
+
+        for item in [self, other] {
+            match item {
+                CombinedExpr::Combining {
+                    op: item_op,
+                    items: item_items,
+                } if item_op == op => items.extend(item_items),
+                CombinedExpr::Combining { op: item_op, .. } if item_op < op => items.push(
+                    CombinedExpr::Simple(SimpleExpr::Parenthesized(Box::new(item))),
+                ),
+                item => items.push(item),
+            }
+        }
+
+        CombinedExpr::Combining { op, items }
+    }
+
+    /// Binds an expression to another scheme by looking up all the fields
+    /// and functions by name.
+    ///
+    /// Returns `None` if any of them is missing or has a different type.
+    pub(crate) fn rebind<'p>(&self, scheme: &'p Scheme) -> Option<CombinedExpr<'p>> {
+        self.rebind_with(scheme, &|field| field.rebind(scheme))
+    }
+
+    /// Binds an expression to another scheme, mapping fields with a given
+    /// function and looking up functions by name.
+    pub(crate) fn rebind_with<'p>(
+        &self,
+        scheme: &'p Scheme,
+        bind_field: BindField<'_, 's, 'p>,
+    ) -> Option<CombinedExpr<'p>> {
+        Some(match self {
+            CombinedExpr::Simple(op) => CombinedExpr::Simple(op.rebind(scheme, bind_field)?),
+            CombinedExpr::Combining { op, items } => CombinedExpr::Combining {
+                op: *op,
+                items: items
+                    .iter()
+                    .map(|item| item.rebind_with(scheme, bind_field))
+                    .collect::<Option<_>>()?,
+            },
+        })
+    }
+
+    /// Negates an expression, parenthesizing it if necessary.
+    pub(crate) fn negate(self) -> Self {
+        let arg = match self {
+            CombinedExpr::Simple(arg) => arg,
+            op => SimpleExpr::Parenthesized(Box::new(op)),
+        };
+
+        CombinedExpr::Simple(SimpleExpr::Unary {
+            op: UnaryOp::Not,
+            arg: Box::new(arg),
+        })
+    }
+
+    /// Returns terms of a disjunctive normal form of an expression or, if
+    /// `negated` is set, of its negation.
+    ///
+    /// Each term is a conjunction of comparisons, each of them possibly
+    /// negated. Fails if there are more than `max_terms` terms.
+    pub(crate) fn dnf_terms<'a>(
+        &'a self,
+        negated: bool,
+        max_terms: usize,
+    ) -> Result<Vec<Term<'a, 's>>, NormalFormSizeError> {
+        match self {
+            CombinedExpr::Simple(op) => simple_dnf_terms(op, negated, max_terms),
+            // De Morgan's laws turn a negated `&&` into `||` and vice versa.
+            CombinedExpr::Combining { op, items } => match (op, negated) {
+                (CombiningOp::And, false) | (CombiningOp::Or, true) => {
+                    items.iter().try_fold(vec![Vec::new()], |terms, item| {
+                        let item_terms = item.dnf_terms(negated, max_terms)?;
+                        dnf_and(&terms, &item_terms, max_terms)
+                    })
+                }
+                (CombiningOp::Or, false) | (CombiningOp::And, true) => {
+                    items.iter().try_fold(Vec::new(), |mut terms, item| {
+                        terms.extend(item.dnf_terms(negated, max_terms)?);
+                        if terms.len() > max_terms {
+                            return Err(NormalFormSizeError { max_terms });
+                        }
+                        Ok(terms)
+                    })
+                }
+                (CombiningOp::Xor, _) => {
+                    // Both the result and its negation are tracked, since
+                    // `a ^^ b` is `a && !b || !a && b`, and `!(a ^^ b)` is
+                    // `a && b || !a && !b`.
+                    let mut result = items[0].dnf_terms(false, max_terms)?;
+                    let mut negation = items[0].dnf_terms(true, max_terms)?;
+
+                    for item in &items[1..] {
+                        let item_result = item.dnf_terms(false, max_terms)?;
+                        let item_negation = item.dnf_terms(true, max_terms)?;
+
+                        let mut next_result = dnf_and(&result, &item_negation, max_terms)?;
+                        next_result.extend(dnf_and(&negation, &item_result, max_terms)?);
+
+                        let mut next_negation = dnf_and(&result, &item_result, max_terms)?;
+                        next_negation.extend(dnf_and(&negation, &item_negation, max_terms)?);
+
+                        if next_result.len().max(next_negation.len()) > max_terms {
+                            return Err(NormalFormSizeError { max_terms });
+                        }
+
+                        result = next_result;
+                        negation = next_negation;
+                    }
+
+                    Ok(if negated { negation } else { result })
+                }
+            },
+        }
+    }
+
+    /// Builds an expression out of terms of a normal form.
+    ///
+    /// Terms of a disjunctive normal form are combined with `||` and
+    /// comparisons inside of them with `&&`, and the other way around for a
+    /// conjunctive normal form.
+    pub(crate) fn from_terms(terms: Vec<Term<'_, 's>>, op: CombiningOp) -> Self {
+        let term_op = match op {
+            CombiningOp::Or => CombiningOp::And,
+            _ => CombiningOp::Or,
+        };
+
+        let mut items = terms.into_iter().map(|term| {
+            let mut items = term
+                .into_iter()
+                .map(|(negated, expr)| {
+                    let expr = CombinedExpr::Simple(SimpleExpr::Field(expr.clone()));
+                    if negated {
+                        expr.negate()
+                    } else {
+                        expr
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            match items.len() {
+                0 => CombinedExpr::Simple(SimpleExpr::Literal(term_op == CombiningOp::And)),
+                1 => items.pop().unwrap(),
+                _ => CombinedExpr::Combining { op: term_op, items },
+            }
+        });
+
+        match items.next() {
+            Some(first) => items.fold(first, |lhs, rhs| lhs.combine(op, rhs)),
+            None => CombinedExpr::Simple(SimpleExpr::Literal(op == CombiningOp::And)),
+        }
+    }
+
+    pub(crate) fn lex_combining_op(input: &str) -> (Option<CombiningOp>, &str) {
         match CombiningOp::lex(skip_space(input)) {
             Ok((op, input)) => (Some(op), skip_space(input)),
             Err(_) => (None, input),
@@ -94,37 +266,301 @@ impl<'s> Expr<'s> for CombinedExpr<'s> {
         }
     }
 
-    fn compile(self) -> CompiledExpr<'s> {
+    fn for_each_field_expr<'a>(&'a self, visit: &mut dyn FnMut(&'a FieldExpr<'s>)) {
         match self {
-            CombinedExpr::Simple(op) => op.compile(),
-            CombinedExpr::Combining { op, items } => {
-                let items = items
-                    .into_iter()
-                    .map(Expr::compile)
-                    .collect::<Vec<_>>()
-                    .into_boxed_slice();
+            CombinedExpr::Simple(op) => op.for_each_field_expr(visit),
+            CombinedExpr::Combining { items, .. } => {
+                for op in items {
+                    op.for_each_field_expr(visit);
+                }
+            }
+        }
+    }
 
-                match op {
-                    CombiningOp::And => {
-                        CompiledExpr::new(move |ctx| items.iter().all(|item| item.execute(ctx)))
+    fn for_each_field_expr_mut(&mut self, visit: &mut dyn FnMut(&mut FieldExpr<'s>)) {
+        match self {
+            CombinedExpr::Simple(op) => op.for_each_field_expr_mut(visit),
+            CombinedExpr::Combining { items, .. } => {
+                for op in items {
+                    op.for_each_field_expr_mut(visit);
+                }
+            }
+        }
+    }
+
+    fn fmt_filter(&self, f: &mut fmt::Formatter<'_>, style: OperatorStyle) -> fmt::Result {
+        match self {
+            CombinedExpr::Simple(op) => op.fmt_filter(f, style),
+            CombinedExpr::Combining { op, items } => {
+                let op = match op {
+                    CombiningOp::And => style.pick("&&", "and"),
+                    CombiningOp::Or => style.pick("||", "or"),
+                    CombiningOp::Xor => style.pick("^^", "xor"),
+                };
+
+                for (i, item) in items.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, " {} ", op)?;
                     }
-                    CombiningOp::Or => {
-                        CompiledExpr::new(move |ctx| items.iter().any(|item| item.execute(ctx)))
+                    item.fmt_filter(f, style)?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn fold_constants(self, constants: Option<&ExecutionContext<'s>>) -> Folded<Self> {
+        match self {
+            CombinedExpr::Simple(op) => op.fold_constants(constants).map(CombinedExpr::Simple),
+            CombinedExpr::Combining { op, items } => {
+                let mut remaining = Vec::new();
+                let mut negate = false;
+
+                for item in items {
+                    match (op, item.fold_constants(constants)) {
+                        (_, Folded::Expr(item)) => remaining.push(item),
+                        (CombiningOp::And, Folded::Constant(false)) => {
+                            return Folded::Constant(false);
+                        }
+                        (CombiningOp::Or, Folded::Constant(true)) => {
+                            return Folded::Constant(true);
+                        }
+                        (CombiningOp::Xor, Folded::Constant(value)) => negate ^= value,
+                        // `true` in `and` and `false` in `or` can be dropped.
+                        (_, Folded::Constant(_)) => {}
                     }
-                    CombiningOp::Xor => CompiledExpr::new(move |ctx| {
+                }
+
+                let expr = match remaining.len() {
+                    0 => return Folded::Constant(op == CombiningOp::And || negate),
+                    1 => remaining.pop().unwrap(),
+                    _ => CombinedExpr::Combining {
+                        op,
+                        items: remaining,
+                    },
+                };
+
+                Folded::Expr(if negate { expr.negate() } else { expr })
+            }
+        }
+    }
+
+    fn compile_with_settings(
+        self,
+        settings: &ParserSettings,
+        cached_calls: &CachedCalls<'s>,
+    ) -> CompiledExpr<'s> {
+        match self {
+            CombinedExpr::Simple(op) => op.compile_with_settings(settings, cached_calls),
+            CombinedExpr::Combining { op, items } => {
+                let items = match op {
+                    CombiningOp::Or => compile_or_items(items, settings, cached_calls),
+                    _ => items
+                        .into_iter()
+                        .map(|item| item.compile_with_settings(settings, cached_calls))
+                        .collect(),
+                }
+                .into_boxed_slice();
+
+                match op {
+                    CombiningOp::And => CompiledExpr::new(move |ctx, calls| {
+                        items.iter().all(|item| item.execute_with(ctx, calls))
+                    }),
+                    CombiningOp::Or => CompiledExpr::new(move |ctx, calls| {
+                        items.iter().any(|item| item.execute_with(ctx, calls))
+                    }),
+                    CombiningOp::Xor => CompiledExpr::new(move |ctx, calls| {
                         items
                             .iter()
-                            .fold(false, |acc, item| acc ^ item.execute(ctx))
+                            .fold(false, |acc, item| acc ^ item.execute_with(ctx, calls))
                     }),
                 }
             }
         }
     }
+
+    fn trace(&self, ctx: &ExecutionContext<'s>) -> TraceNode<'s> {
+        match self {
+            CombinedExpr::Simple(op) => op.trace(ctx),
+            CombinedExpr::Combining { op, items } => {
+                let mut operands = Vec::new();
+                let mut result = *op == CombiningOp::And;
+
+                for item in items {
+                    let operand = item.trace(ctx);
+                    match op {
+                        CombiningOp::And => result &= operand.result(),
+                        CombiningOp::Or => result |= operand.result(),
+                        CombiningOp::Xor => result ^= operand.result(),
+                    }
+                    operands.push(operand);
+
+                    // Compiled filters stop as soon as the result is known.
+                    if result == (*op == CombiningOp::Or) && *op != CombiningOp::Xor {
+                        break;
+                    }
+                }
+
+                TraceNode::Combination {
+                    operator: match op {
+                        CombiningOp::And => "&&",
+                        CombiningOp::Or => "||",
+                        CombiningOp::Xor => "^^",
+                    },
+                    operands,
+                    result,
+                }
+            }
+        }
+    }
+
+    fn emit(
+        self,
+        settings: &ParserSettings,
+        cached_calls: &CachedCalls<'s>,
+        program: &mut Program<'s>,
+    ) {
+        match self {
+            CombinedExpr::Simple(op) => op.emit(settings, cached_calls, program),
+            CombinedExpr::Combining { op, items } => {
+                let mut jumps = Vec::new();
+
+                for (i, item) in items.into_iter().enumerate() {
+                    if i != 0 {
+                        match op {
+                            // Skip the rest of the operands once the result
+                            // is known.
+                            CombiningOp::And => jumps.push(program.push(Instr::JumpIfFalse(0))),
+                            CombiningOp::Or => jumps.push(program.push(Instr::JumpIfTrue(0))),
+                            CombiningOp::Xor => {
+                                program.push(Instr::Push);
+                            }
+                        }
+                    }
+
+                    item.emit(settings, cached_calls, program);
+
+                    if i != 0 && op == CombiningOp::Xor {
+                        program.push(Instr::Xor);
+                    }
+                }
+
+                for jump in jumps {
+                    program.patch_jump(jump);
+                }
+            }
+        }
+    }
+}
+
+// A conjunction or a disjunction of comparisons, each of them negated if the
+// flag is set.
+pub(crate) type Term<'a, 's> = Vec<(bool, &'a FieldExpr<'s>)>;
+
+fn simple_dnf_terms<'a, 's>(
+    expr: &'a SimpleExpr<'s>,
+    negated: bool,
+    max_terms: usize,
+) -> Result<Vec<Term<'a, 's>>, NormalFormSizeError> {
+    match expr {
+        SimpleExpr::Field(expr) => Ok(vec![vec![(negated, expr)]]),
+        SimpleExpr::Parenthesized(expr) => expr.dnf_terms(negated, max_terms),
+        SimpleExpr::Unary {
+            op: UnaryOp::Not,
+            arg,
+        } => simple_dnf_terms(arg, !negated, max_terms),
+        // `true` is a single empty conjunction, and `false` has no terms.
+        SimpleExpr::Literal(value) if *value != negated => Ok(vec![Vec::new()]),
+        SimpleExpr::Literal(_) => Ok(Vec::new()),
+    }
+}
+
+// Distributes `&&` over terms of two disjunctive normal forms.
+fn dnf_and<'a, 's>(
+    lhs: &[Term<'a, 's>],
+    rhs: &[Term<'a, 's>],
+    max_terms: usize,
+) -> Result<Vec<Term<'a, 's>>, NormalFormSizeError> {
+    if lhs.len() * rhs.len() > max_terms {
+        return Err(NormalFormSizeError { max_terms });
+    }
+
+    Ok(lhs
+        .iter()
+        .flat_map(|lhs| {
+            rhs.iter().map(move |rhs| {
+                let mut term = lhs.clone();
+                for literal in rhs {
+                    if !term.contains(literal) {
+                        term.push(*literal);
+                    }
+                }
+                term
+            })
+        })
+        .collect())
+}
+
+// Compiles operands of `or`, fusing `contains` and `matches` comparisons of
+// the same field into a single regex set, so that the field is scanned once
+// instead of once per comparison.
+fn compile_or_items<'s>(
+    items: Vec<CombinedExpr<'s>>,
+    settings: &ParserSettings,
+    cached_calls: &CachedCalls<'s>,
+) -> Vec<CompiledExpr<'s>> {
+    let mut compiled = Vec::new();
+    let mut groups: Vec<(Field<'s>, Vec<String>, Vec<CompiledExpr<'s>>)> = Vec::new();
+
+    for item in items {
+        let pattern = match &item {
+            CombinedExpr::Simple(SimpleExpr::Field(expr)) => expr.field_pattern(),
+            _ => None,
+        };
+
+        let item = item.compile_with_settings(settings, cached_calls);
+
+        match pattern {
+            Some((field, pattern)) => match groups.iter_mut().find(|group| group.0 == field) {
+                Some((_, patterns, items)) => {
+                    patterns.push(pattern);
+                    items.push(item);
+                }
+                None => groups.push((field, vec![pattern], vec![item])),
+            },
+            None => compiled.push(item),
+        }
+    }
+
+    for (field, patterns, items) in groups {
+        let set = if items.len() > 1 {
+            RegexSet::new(&patterns)
+        } else {
+            None
+        };
+
+        match set {
+            Some(set) => compiled.push(CompiledExpr::new(move |ctx, calls| {
+                // Matched spans are recorded only by individual comparisons.
+                if ctx.records_matches() {
+                    return items.iter().any(|item| item.execute_with(ctx, calls));
+                }
+
+                ctx.any_field_value(field, |x| match x {
+                    LhsValue::Bytes(bytes) => set.is_match(&bytes),
+                    _ => unreachable!(),
+                })
+            })),
+            None => compiled.extend(items),
+        }
+    }
+
+    compiled
 }
 
 #[test]
 fn test() {
-    use super::field_expr::FieldExpr;
     use crate::{execution_context::ExecutionContext, lex::complete};
 
     let scheme = &Scheme! {
@@ -325,3 +761,111 @@ fn test() {
         }
     );
 }
+
+#[test]
+fn test_chained_comparison() {
+    use crate::{execution_context::ExecutionContext, lex::complete};
+
+    let scheme = &Scheme! {
+        t: Bool,
+        tcp.port: Int,
+        ip.addr: Ip,
+    };
+
+    let ctx = &mut ExecutionContext::new(scheme);
+
+    let expr = assert_ok!(
+        CombinedExpr::lex_with("100 <= tcp.port < 1000 or t", scheme),
+        CombinedExpr::Combining {
+            op: CombiningOp::Or,
+            items: vec![
+                CombinedExpr::Simple(SimpleExpr::Parenthesized(Box::new(
+                    complete(CombinedExpr::lex_with(
+                        "tcp.port >= 100 and tcp.port < 1000",
+                        scheme
+                    ))
+                    .unwrap()
+                ))),
+                complete(CombinedExpr::lex_with("t", scheme)).unwrap(),
+            ],
+        }
+    );
+
+    let expr = expr.compile();
+
+    ctx.set_field_value("t", false).unwrap();
+
+    for &(port, expected) in &[(99, false), (100, true), (999, true), (1000, false)] {
+        ctx.set_field_value("tcp.port", port).unwrap();
+        assert_eq!(expr.execute(ctx), expected);
+    }
+
+    assert_ok!(
+        CombinedExpr::lex_with("10.0.0.0 < ip.addr <= 10.255.255.255", scheme),
+        CombinedExpr::Simple(SimpleExpr::Parenthesized(Box::new(
+            complete(CombinedExpr::lex_with(
+                "ip.addr > 10.0.0.0 and ip.addr <= 10.255.255.255",
+                scheme
+            ))
+            .unwrap()
+        )))
+    );
+
+    // A bound must have the same type as the field.
+    assert!(complete(CombinedExpr::lex_with("1 < ip.addr < 2", scheme)).is_err());
+}
+
+#[test]
+fn test_fused_patterns() {
+    use crate::{execution_context::ExecutionContext, lex::complete};
+
+    let scheme = &Scheme! {
+        http.host: Bytes,
+        http.uri: Bytes,
+        tcp.port: Int,
+    };
+
+    let expr = complete(CombinedExpr::lex_with(
+        r#"http.uri contains "union" or http.uri ~ "^/wp-(admin|login)" or tcp.port == 22 or http.host contains "a.b" or http.uri contains 00:ff"#,
+        scheme,
+    ))
+    .unwrap();
+
+    let items = match expr.clone() {
+        CombinedExpr::Combining { items, .. } => items,
+        _ => unreachable!(),
+    };
+
+    // `http.uri` comparisons are fused, the rest is compiled as is.
+    assert_eq!(
+        compile_or_items(items, &ParserSettings::default(), &Default::default()).len(),
+        3
+    );
+
+    let expr = expr.compile();
+    let ctx = &mut ExecutionContext::new(scheme);
+
+    ctx.set_field_value("http.host", "example.org").unwrap();
+    ctx.set_field_value("tcp.port", 443).unwrap();
+
+    for &(uri, expected) in &[
+        (&b"/?q=1 union select"[..], true),
+        (b"/wp-login.php", true),
+        (b"/blog/wp-login.php", false),
+        (b"/\x00\xff", true),
+        (b"/index.html", false),
+    ] {
+        ctx.set_field_value("http.uri", uri).unwrap();
+        assert_eq!(expr.execute(ctx), expected);
+
+        ctx.set_record_matches(true);
+        assert_eq!(expr.execute(ctx), expected);
+        ctx.set_record_matches(false);
+    }
+
+    // Contained bytes are matched literally.
+    ctx.set_field_value("http.host", "axb").unwrap();
+    assert_eq!(expr.execute(ctx), false);
+    ctx.set_field_value("http.host", "a.b").unwrap();
+    assert_eq!(expr.execute(ctx), true);
+}
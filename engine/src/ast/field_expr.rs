@@ -1,20 +1,42 @@
 // use crate::filter::CompiledExpr;
-use super::{function_expr::FunctionCallExpr, Expr};
+use super::{
+    function_expr::{CachedCalls, CallCache, FunctionCallExpr},
+    repr::{BindResult, FieldExprRepr, ValueRepr},
+    BindField, Expr, Folded, LhsValueLiteral, OperatorStyle, Styled, TraceNode,
+};
 use crate::{
+    anonymizer::Anonymizer,
+    bit_set::BitSet,
+    bloom_filter::BloomFilter,
+    execution_context::ExecutionContext,
     filter::CompiledExpr,
     heap_searcher::HeapSearcher,
-    lex::{skip_space, span, Lex, LexErrorKind, LexResult, LexWith},
-    range_set::RangeSet,
-    rhs_types::{Bytes, ExplicitIpRange, Regex},
-    scheme::{Field, Scheme},
+    lex::{expect, skip_space, span, Lex, LexErrorKind, LexResult, LexWith},
+    parser_settings::{IntOverflow, Ipv6Zones, ParserSettings},
+    range_set::{IpRangeSet, RangeSet},
+    rhs_types::{
+        lex_ip_zone, unmap_ipv4, Bytes, Domain, ExplicitIpRange, Float, IpRange, Regex, Timestamp,
+        TimestampLiteral, Wildcard,
+    },
+    scheme::{Field, List, Scheme},
     strict_partial_ord::StrictPartialOrd,
-    types::{GetType, LhsValue, RhsValue, RhsValues, Type},
+    types::{GetType, LhsValue, RhsValue, RhsValues, Type, TypeMismatchError},
+    vm::Program,
 };
+use cidr::Cidr;
+use failure::Fail;
 use fnv::FnvBuildHasher;
 use indexmap::IndexSet;
 use memmem::Searcher;
 use serde::{Serialize, Serializer};
-use std::{cmp::Ordering, net::IpAddr};
+use std::{
+    cmp::Ordering,
+    convert::TryFrom,
+    fmt::{self, Debug, Formatter, Write},
+    mem,
+    net::IpAddr,
+    str,
+};
 
 const LESS: u8 = 0b001;
 const GREATER: u8 = 0b010;
@@ -47,27 +69,124 @@ impl OrderingOp {
             None => self == OrderingOp::NotEqual,
         }
     }
+
+    /// Returns an operator that gives the same result with swapped operands.
+    pub fn reverse(self) -> Self {
+        match self {
+            OrderingOp::GreaterThanEqual => OrderingOp::LessThanEqual,
+            OrderingOp::LessThanEqual => OrderingOp::GreaterThanEqual,
+            OrderingOp::GreaterThan => OrderingOp::LessThan,
+            OrderingOp::LessThan => OrderingOp::GreaterThan,
+            op => op,
+        }
+    }
 }
 
 lex_enum!(IntOp {
     "&" | "bitwise_and" => BitwiseAnd,
+    "|" | "bitwise_or" => BitwiseOr,
+    "^" | "bitwise_xor" => BitwiseXor,
+    "<<" | "shift_left" => ShiftLeft,
+    ">>" | "shift_right" => ShiftRight,
+});
+
+impl IntOp {
+    /// Applies the operation, or returns `None` if it overflows and the
+    /// overflow should make a comparison not match.
+    pub fn apply(self, lhs: i64, rhs: i64, overflow: IntOverflow) -> Option<i64> {
+        // Shifts by negative or too large amounts push out all bits rather
+        // than panicking.
+        let shift = u32::try_from(rhs).unwrap_or(u32::MAX);
+        Some(match self {
+            IntOp::BitwiseAnd => lhs & rhs,
+            IntOp::BitwiseOr => lhs | rhs,
+            IntOp::BitwiseXor => lhs ^ rhs,
+            IntOp::ShiftLeft => {
+                let result = lhs.checked_shl(shift).unwrap_or(0);
+                // Shifting back has to restore the value, including its sign.
+                if result.checked_shr(shift).unwrap_or(result >> 63) == lhs {
+                    result
+                } else {
+                    match overflow {
+                        IntOverflow::Wrapping => result,
+                        IntOverflow::Saturating if lhs < 0 => i64::MIN,
+                        IntOverflow::Saturating => i64::MAX,
+                        IntOverflow::NoMatch => return None,
+                    }
+                }
+            }
+            IntOp::ShiftRight => lhs.checked_shr(shift).unwrap_or(lhs >> 63),
+        })
+    }
+
+    fn operator(self) -> ComparisonOperator {
+        match self {
+            IntOp::BitwiseAnd => ComparisonOperator::BitwiseAnd,
+            IntOp::BitwiseOr => ComparisonOperator::BitwiseOr,
+            IntOp::BitwiseXor => ComparisonOperator::BitwiseXor,
+            IntOp::ShiftLeft => ComparisonOperator::ShiftLeft,
+            IntOp::ShiftRight => ComparisonOperator::ShiftRight,
+        }
+    }
+
+    fn fmt_filter(self, f: &mut Formatter<'_>, style: OperatorStyle) -> fmt::Result {
+        f.write_str(match self {
+            IntOp::BitwiseAnd => style.pick("&", "bitwise_and"),
+            IntOp::BitwiseOr => style.pick("|", "bitwise_or"),
+            IntOp::BitwiseXor => style.pick("^", "bitwise_xor"),
+            IntOp::ShiftLeft => style.pick("<<", "shift_left"),
+            IntOp::ShiftRight => style.pick(">>", "shift_right"),
+        })
+    }
+}
+
+/// A bitwise operation applied to integers before comparing them, like
+/// `& 0x12` in `tcp.flags & 0x12 == 0x12`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, serde::Deserialize)]
+pub(crate) struct Bitwise {
+    pub op: IntOp,
+    pub rhs: i64,
+}
+
+impl Bitwise {
+    fn apply(self, value: LhsValue<'_>, overflow: IntOverflow) -> Option<LhsValue<'static>> {
+        match value {
+            LhsValue::Int(value) => self.op.apply(value, self.rhs, overflow).map(LhsValue::Int),
+            _ => unreachable!(),
+        }
+    }
+}
+
+lex_enum!(Quantifier {
+    "any" => Any,
+    "all" => All,
 });
 
+impl Quantifier {
+    fn is_any(&self) -> bool {
+        *self == Quantifier::Any
+    }
+}
+
 lex_enum!(BytesOp {
     "contains" => Contains,
     "~" | "matches" => Matches,
+    "wildcard" | "like" => Wildcard,
+    "in_domain" => InDomain,
 });
 
+// Operators are tried in order, so longer ones go before their prefixes,
+// like `in_domain` before `in` and shifts before `<` and `>`.
 lex_enum!(ComparisonOp {
+    BytesOp => Bytes,
     "in" => In,
-    OrderingOp => Ordering,
     IntOp => Int,
-    BytesOp => Bytes,
+    OrderingOp => Ordering,
 });
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 #[serde(untagged)]
-enum FieldOp {
+enum FieldOp<'s> {
     #[serde(serialize_with = "serialize_is_true")]
     IsTrue,
 
@@ -76,9 +195,36 @@ enum FieldOp {
         rhs: RhsValue,
     },
 
+    /// Equality with an IPv6 address in a given zone, like `fe80::1%eth0`,
+    /// see [`Ipv6Zones::Compare`].
+    ScopedIp {
+        op: OrderingOp,
+        rhs: RhsValue,
+        zone: String,
+    },
+
+    /// Ordering against a field or a function call evaluated at runtime.
+    ///
+    /// It's serialized under a separate key so that a field name can't be
+    /// confused with a string literal.
+    OrderingExpr {
+        op: OrderingOp,
+        #[serde(rename = "rhs_expr")]
+        rhs: LhsFieldExpr<'s>,
+    },
+
+    /// A bitwise operation with a non-zero result.
     Int {
         op: IntOp,
-        rhs: i32,
+        rhs: i64,
+    },
+
+    /// Ordering of the result of a bitwise operation, like
+    /// `tcp.flags & 0x12 == 0x12`.
+    BitwiseOrdering {
+        bitwise: Bitwise,
+        op: OrderingOp,
+        rhs: RhsValue,
     },
 
     #[serde(serialize_with = "serialize_contains")]
@@ -87,8 +233,159 @@ enum FieldOp {
     #[serde(serialize_with = "serialize_matches")]
     Matches(Regex),
 
+    #[serde(serialize_with = "serialize_wildcard")]
+    Wildcard(Wildcard),
+
+    #[serde(serialize_with = "serialize_in_domain")]
+    InDomain(Domain),
+
     #[serde(serialize_with = "serialize_one_of")]
     OneOf(RhsValues),
+
+    /// Membership in a named list with contents provided at runtime.
+    #[serde(serialize_with = "serialize_in_list")]
+    InList(List<'s>),
+}
+
+impl<'s> FieldOp<'s> {
+    fn rhs(&self) -> Option<ComparisonRhs<'_>> {
+        Some(match self {
+            FieldOp::IsTrue | FieldOp::OrderingExpr { .. } => return None,
+            FieldOp::Ordering { rhs, .. }
+            | FieldOp::ScopedIp { rhs, .. }
+            | FieldOp::BitwiseOrdering { rhs, .. } => ComparisonRhs::Value(rhs),
+            FieldOp::Int { rhs, .. } => ComparisonRhs::Int(*rhs),
+            FieldOp::Contains(bytes) => ComparisonRhs::Bytes(bytes),
+            FieldOp::Matches(regex) => ComparisonRhs::Regex(regex),
+            FieldOp::Wildcard(wildcard) => ComparisonRhs::Wildcard(wildcard),
+            FieldOp::InDomain(domain) => ComparisonRhs::Domain(domain),
+            FieldOp::OneOf(values) => ComparisonRhs::Values(values),
+            FieldOp::InList(list) => ComparisonRhs::List(list.name()),
+        })
+    }
+
+    // Comparisons against the time of execution can't be folded into
+    // constants.
+    fn is_time_dependent(&self) -> bool {
+        matches!(
+            self,
+            FieldOp::Ordering {
+                rhs: RhsValue::Timestamp(TimestampLiteral::Relative(_)),
+                ..
+            }
+        )
+    }
+
+    fn cost(&self) -> u64 {
+        match self {
+            FieldOp::IsTrue
+            | FieldOp::Ordering { .. }
+            | FieldOp::ScopedIp { .. }
+            | FieldOp::Int { .. }
+            | FieldOp::BitwiseOrdering { .. } => 1,
+            FieldOp::OrderingExpr { rhs, .. } => 1 + rhs.cost(),
+            FieldOp::Contains(_) | FieldOp::Wildcard(_) | FieldOp::InDomain(_) => 4,
+            // Longer patterns tend to compile into larger automata.
+            FieldOp::Matches(regex) => 10 + regex.as_str().len() as u64,
+            // Lists are compiled into sets with logarithmic or constant
+            // lookups.
+            FieldOp::OneOf(values) => 1 + u64::from(64 - (values.len() as u64).leading_zeros()),
+            // Sizes of named lists are only known at runtime.
+            FieldOp::InList(_) => 16,
+        }
+    }
+
+    fn operator(&self) -> ComparisonOperator {
+        match self {
+            FieldOp::IsTrue => ComparisonOperator::IsTrue,
+            FieldOp::Ordering { op, .. }
+            | FieldOp::ScopedIp { op, .. }
+            | FieldOp::OrderingExpr { op, .. }
+            | FieldOp::BitwiseOrdering { op, .. } => match op {
+                OrderingOp::Equal => ComparisonOperator::Equal,
+                OrderingOp::NotEqual => ComparisonOperator::NotEqual,
+                OrderingOp::GreaterThanEqual => ComparisonOperator::GreaterThanEqual,
+                OrderingOp::LessThanEqual => ComparisonOperator::LessThanEqual,
+                OrderingOp::GreaterThan => ComparisonOperator::GreaterThan,
+                OrderingOp::LessThan => ComparisonOperator::LessThan,
+            },
+            FieldOp::Int { op, .. } => op.operator(),
+            FieldOp::Contains(_) => ComparisonOperator::Contains,
+            FieldOp::Matches(_) => ComparisonOperator::Matches,
+            FieldOp::Wildcard(_) => ComparisonOperator::Wildcard,
+            FieldOp::InDomain(_) => ComparisonOperator::InDomain,
+            FieldOp::OneOf(_) | FieldOp::InList(_) => ComparisonOperator::In,
+        }
+    }
+
+    fn uses(&self, field: Field<'s>) -> bool {
+        match self {
+            FieldOp::OrderingExpr { rhs, .. } => rhs.uses(field),
+            _ => false,
+        }
+    }
+
+    fn rebind<'p>(
+        &self,
+        scheme: &'p Scheme,
+        bind_field: BindField<'_, 's, 'p>,
+    ) -> Option<FieldOp<'p>> {
+        Some(match self {
+            FieldOp::IsTrue => FieldOp::IsTrue,
+            FieldOp::Ordering { op, rhs } => FieldOp::Ordering {
+                op: *op,
+                rhs: rhs.clone(),
+            },
+            FieldOp::ScopedIp { op, rhs, zone } => FieldOp::ScopedIp {
+                op: *op,
+                rhs: rhs.clone(),
+                zone: zone.clone(),
+            },
+            FieldOp::OrderingExpr { op, rhs } => FieldOp::OrderingExpr {
+                op: *op,
+                rhs: rhs.rebind(scheme, bind_field)?,
+            },
+            FieldOp::Int { op, rhs } => FieldOp::Int { op: *op, rhs: *rhs },
+            FieldOp::BitwiseOrdering { bitwise, op, rhs } => FieldOp::BitwiseOrdering {
+                bitwise: *bitwise,
+                op: *op,
+                rhs: rhs.clone(),
+            },
+            FieldOp::Contains(bytes) => FieldOp::Contains(bytes.clone()),
+            FieldOp::Matches(regex) => FieldOp::Matches(regex.clone()),
+            FieldOp::Wildcard(wildcard) => FieldOp::Wildcard(wildcard.clone()),
+            FieldOp::InDomain(domain) => FieldOp::InDomain(domain.clone()),
+            FieldOp::OneOf(values) => FieldOp::OneOf(values.clone()),
+            FieldOp::InList(list) => FieldOp::InList(list.rebind(scheme)?),
+        })
+    }
+
+    // Creates a comparison with a scoped IPv6 literal, see
+    // `ParserSettings::ipv6_zones`.
+    fn with_ip_zone(
+        op: OrderingOp,
+        rhs: RhsValue,
+        zone: &str,
+        settings: &ParserSettings,
+    ) -> Result<Self, LexErrorKind> {
+        if settings.ipv6_zones == Ipv6Zones::Reject {
+            return Err(LexErrorKind::ForbiddenIpZone);
+        }
+
+        match (&rhs, op) {
+            (RhsValue::Ip(IpAddr::V6(_)), OrderingOp::Equal | OrderingOp::NotEqual) => {}
+            _ => return Err(LexErrorKind::InvalidIpZone),
+        }
+
+        Ok(match settings.ipv6_zones {
+            Ipv6Zones::Compare => FieldOp::ScopedIp {
+                op,
+                rhs,
+                zone: zone.to_owned(),
+            },
+            _ => FieldOp::Ordering { op, rhs },
+        })
+    }
 }
 
 fn serialize_op_rhs<T: Serialize, S: Serializer>(
@@ -120,10 +417,154 @@ fn serialize_matches<S: Serializer>(rhs: &Regex, ser: S) -> Result<S::Ok, S::Err
     serialize_op_rhs("Matches", rhs, ser)
 }
 
+fn serialize_wildcard<S: Serializer>(rhs: &Wildcard, ser: S) -> Result<S::Ok, S::Error> {
+    serialize_op_rhs("Wildcard", rhs, ser)
+}
+
+fn serialize_in_domain<S: Serializer>(rhs: &Domain, ser: S) -> Result<S::Ok, S::Error> {
+    serialize_op_rhs("InDomain", rhs, ser)
+}
+
 fn serialize_one_of<S: Serializer>(rhs: &RhsValues, ser: S) -> Result<S::Ok, S::Error> {
     serialize_op_rhs("OneOf", rhs, ser)
 }
 
+fn serialize_in_list<S: Serializer>(rhs: &List<'_>, ser: S) -> Result<S::Ok, S::Error> {
+    serialize_op_rhs("InList", rhs, ser)
+}
+
+fn fmt_bytes(f: &mut Formatter<'_>, bytes: &Bytes) -> fmt::Result {
+    match bytes {
+        Bytes::Raw(raw) if !raw.is_empty() => bytes.fmt(f),
+        _ => {
+            f.write_char('"')?;
+            for c in String::from_utf8_lossy(bytes).chars() {
+                match c {
+                    '"' | '\\' => write!(f, "\\{}", c)?,
+                    c if c.is_ascii_control() => write!(f, "\\x{:02x}", c as u8)?,
+                    c => f.write_char(c)?,
+                }
+            }
+            f.write_char('"')
+        }
+    }
+}
+
+fn fmt_regex(f: &mut Formatter<'_>, regex: &Regex) -> fmt::Result {
+    // Only quotes outside of character classes need to be escaped, see the
+    // `Regex` lexer.
+    let mut in_char_class = false;
+    let mut chars = regex.as_str().chars();
+
+    f.write_char('"')?;
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                f.write_char(c)?;
+                if let Some(c) = chars.next() {
+                    f.write_char(c)?;
+                }
+            }
+            '"' if !in_char_class => f.write_str("\\\"")?,
+            '[' if !in_char_class => {
+                in_char_class = true;
+                f.write_char(c)?;
+            }
+            ']' if in_char_class => {
+                in_char_class = false;
+                f.write_char(c)?;
+            }
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+pub(super) fn fmt_rhs_value(f: &mut Formatter<'_>, value: &RhsValue) -> fmt::Result {
+    match value {
+        RhsValue::Ip(ip) => write!(f, "{}", ip),
+        RhsValue::Bytes(bytes) => fmt_bytes(f, bytes),
+        RhsValue::Int(int) => write!(f, "{}", int),
+        RhsValue::Float(float) => write!(f, "{}", float),
+        RhsValue::Timestamp(timestamp) => write!(f, "{}", timestamp),
+        RhsValue::Bool(b) => match *b {},
+    }
+}
+
+pub(super) fn fmt_lhs_value(f: &mut Formatter<'_>, value: &LhsValue<'_>) -> fmt::Result {
+    match value {
+        LhsValue::Ip(ip) => write!(f, "{}", ip),
+        LhsValue::Bytes(bytes) => match str::from_utf8(bytes) {
+            Ok(s) => fmt_bytes(f, &Bytes::from(s.to_owned())),
+            Err(_) => fmt_bytes(f, &Bytes::from(bytes.to_vec())),
+        },
+        LhsValue::Int(int) => write!(f, "{}", int),
+        LhsValue::Bool(b) => write!(f, "{}", b),
+        LhsValue::Float(float) => write!(f, "{}", float),
+        LhsValue::Timestamp(timestamp) => write!(f, "\"{}\"", timestamp),
+    }
+}
+
+fn fmt_rhs_values(f: &mut Formatter<'_>, values: &RhsValues) -> fmt::Result {
+    f.write_char('{')?;
+    match values {
+        RhsValues::Ip(ranges) => {
+            for range in ranges {
+                match range {
+                    IpRange::Cidr(cidr) => {
+                        write!(f, " {}/{}", cidr.first_address(), cidr.network_length())?
+                    }
+                    IpRange::Explicit(ExplicitIpRange::V4(range)) => {
+                        write!(f, " {}..{}", range.start(), range.end())?
+                    }
+                    IpRange::Explicit(ExplicitIpRange::V6(range)) => {
+                        write!(f, " {}..{}", range.start(), range.end())?
+                    }
+                }
+            }
+        }
+        RhsValues::Int(ranges) => {
+            for range in ranges {
+                if range.start() == range.end() {
+                    write!(f, " {}", range.start())?;
+                } else {
+                    write!(f, " {}..{}", range.start(), range.end())?;
+                }
+            }
+        }
+        RhsValues::Float(ranges) => {
+            for range in ranges {
+                if range.start() == range.end() {
+                    write!(f, " {}", range.start())?;
+                } else {
+                    write!(f, " {}..{}", range.start(), range.end())?;
+                }
+            }
+        }
+        RhsValues::Timestamp(ranges) => {
+            for range in ranges {
+                if range.start() == range.end() {
+                    write!(f, " \"{}\"", range.start())?;
+                } else {
+                    write!(f, " \"{}\"..\"{}\"", range.start(), range.end())?;
+                }
+            }
+        }
+        RhsValues::Bytes(values) => {
+            for value in values {
+                f.write_char(' ')?;
+                fmt_bytes(f, value)?;
+            }
+        }
+        RhsValues::Bool(values) => {
+            if let Some(b) = values.first() {
+                match *b {}
+            }
+        }
+    }
+    f.write_str(" }")
+}
+
 #[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 #[serde(untagged)]
 pub(crate) enum LhsFieldExpr<'s> {
@@ -139,24 +580,105 @@ impl<'s> LhsFieldExpr<'s> {
         }
     }
 
-    fn compile_with<F: 's>(self, func: F) -> CompiledExpr<'s>
+    pub fn fmt_filter(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LhsFieldExpr::Field(field) => f.write_str(field.name()),
+            LhsFieldExpr::FunctionCallExpr(call) => call.fmt_filter(f),
+        }
+    }
+
+    pub fn rebind<'p>(
+        &self,
+        scheme: &'p Scheme,
+        bind_field: BindField<'_, 's, 'p>,
+    ) -> Option<LhsFieldExpr<'p>> {
+        Some(match self {
+            LhsFieldExpr::Field(field) => LhsFieldExpr::Field(bind_field(*field)?),
+            LhsFieldExpr::FunctionCallExpr(call) => {
+                LhsFieldExpr::FunctionCallExpr(call.rebind(scheme, bind_field)?)
+            }
+        })
+    }
+
+    pub fn anonymize(&mut self, anonymizer: &mut Anonymizer) {
+        if let LhsFieldExpr::FunctionCallExpr(call) = self {
+            call.anonymize(anonymizer);
+        }
+    }
+
+    pub fn cost(&self) -> u64 {
+        match self {
+            LhsFieldExpr::Field(_) => 1,
+            LhsFieldExpr::FunctionCallExpr(call) => call.cost(),
+        }
+    }
+
+    /// Evaluates the expression, or returns `None` if a function call
+    /// panicked.
+    fn execute(&self, ctx: &'s ExecutionContext<'s>) -> Option<LhsValue<'_>> {
+        match self {
+            LhsFieldExpr::Field(f) => ctx.lookup_field_value(*f),
+            LhsFieldExpr::FunctionCallExpr(call) if call.is_constant() => call
+                .execute_constant()
+                .or_else(|| call.execute_isolated(ctx)),
+            LhsFieldExpr::FunctionCallExpr(call) => call.execute_isolated(ctx),
+        }
+    }
+
+    fn compile_with<F: 's>(self, cached_calls: &CachedCalls<'s>, func: F) -> CompiledExpr<'s>
     where
         F: Fn(LhsValue<'_>) -> bool,
     {
-        match self {
-            LhsFieldExpr::FunctionCallExpr(call) => {
-                CompiledExpr::new(move |ctx| func(call.execute(ctx)))
+        // Pure functions with constant arguments are folded right away.
+        // Calls that panicked are left for execution.
+        match &self {
+            LhsFieldExpr::FunctionCallExpr(call) if call.is_constant() => {
+                if let Some(value) = call.execute_constant() {
+                    let result = func(value);
+                    return CompiledExpr::new(move |_, _| result);
+                }
             }
+            _ => {}
+        }
+
+        match self {
+            LhsFieldExpr::FunctionCallExpr(call) => match cached_calls.slot(&call) {
+                // Repeated calls are evaluated once per execution.
+                Some(slot) => CompiledExpr::new(move |_, calls| calls.with_value(slot, &func)),
+                None => CompiledExpr::new(move |ctx, _| match call.execute_isolated(ctx) {
+                    Some(value) => func(value),
+                    None => false,
+                }),
+            },
             LhsFieldExpr::Field(f) => {
-                CompiledExpr::new(move |ctx| func(ctx.get_field_value_unchecked(f)))
+                CompiledExpr::new(move |ctx, _| ctx.any_field_value(f, &func))
             }
         }
     }
+
+    /// Checks whether any value of the expression satisfies a predicate,
+    /// see [`Scheme::set_field_multi_valued`](::Scheme::set_field_multi_valued).
+    fn any(
+        &self,
+        ctx: &'s ExecutionContext<'s>,
+        mut func: impl FnMut(LhsValue<'_>) -> bool,
+    ) -> bool {
+        match self {
+            LhsFieldExpr::Field(f) => ctx.any_field_value(*f, func),
+            _ => match self.execute(ctx) {
+                Some(x) => func(x),
+                None => false,
+            },
+        }
+    }
 }
 
 impl<'i, 's> LexWith<'i, &'s Scheme> for LhsFieldExpr<'s> {
     fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
         Ok(match FunctionCallExpr::lex_with(input, scheme) {
+            Ok((_, rest)) if !scheme.parser_settings().allow_function_calls => {
+                return Err((LexErrorKind::ForbiddenFunctionCall, span(input, rest)));
+            }
             Ok((call, input)) => (LhsFieldExpr::FunctionCallExpr(call), input),
             // Fallback to field
             Err(_) => {
@@ -178,14 +700,220 @@ impl<'s> GetType for LhsFieldExpr<'s> {
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 pub struct FieldExpr<'s> {
+    /// Whether any or all values of a multi-valued left-hand side have to
+    /// match, see [`Scheme::set_field_multi_valued`](::Scheme::set_field_multi_valued).
+    #[serde(skip_serializing_if = "Quantifier::is_any")]
+    quantifier: Quantifier,
+
     lhs: LhsFieldExpr<'s>,
 
     #[serde(flatten)]
-    op: FieldOp,
+    op: FieldOp<'s>,
+}
+
+/// An operator used in a single comparison of a [`FilterAst`](::FilterAst).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+pub enum ComparisonOperator {
+    /// A boolean field used on its own.
+    IsTrue,
+    /// `==` or `eq`.
+    Equal,
+    /// `!=` or `ne`.
+    NotEqual,
+    /// `>=` or `ge`.
+    GreaterThanEqual,
+    /// `<=` or `le`.
+    LessThanEqual,
+    /// `>` or `gt`.
+    GreaterThan,
+    /// `<` or `lt`.
+    LessThan,
+    /// `&` or `bitwise_and`.
+    BitwiseAnd,
+    /// `|` or `bitwise_or`.
+    BitwiseOr,
+    /// `^` or `bitwise_xor`.
+    BitwiseXor,
+    /// `<<` or `shift_left`.
+    ShiftLeft,
+    /// `>>` or `shift_right`.
+    ShiftRight,
+    /// `contains`.
+    Contains,
+    /// `~` or `matches`.
+    Matches,
+    /// `wildcard` or `like`.
+    Wildcard,
+    /// `in_domain`.
+    InDomain,
+    /// `in { ... }`.
+    In,
+}
+
+/// A right-hand side of a single comparison of a [`FilterAst`](::FilterAst).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ComparisonRhs<'a> {
+    /// A single value for ordering operators, including the ones that
+    /// compare the result of a [bitwise operation](ComparisonRef::bitwise).
+    Value(&'a RhsValue),
+    /// An integer mask for bitwise operators.
+    Int(i64),
+    /// A substring for `contains`.
+    Bytes(&'a Bytes),
+    /// A regular expression for `matches`.
+    Regex(&'a Regex),
+    /// A pattern for `wildcard`.
+    Wildcard(&'a Wildcard),
+    /// A domain name for `in_domain`.
+    Domain(&'a Domain),
+    /// A list of values for `in`.
+    Values(&'a RhsValues),
+    /// A name of a list for `in $name`.
+    List(&'a str),
+}
+
+/// A new right-hand side for a comparison, returned from a
+/// [`FilterAst::rewrite_rhs`](::FilterAst::rewrite_rhs) callback.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RhsReplacement {
+    /// Replaces a single value.
+    ///
+    /// This is accepted by ordering, bitwise, `contains`, `wildcard` and
+    /// `in_domain` operators.
+    Value(RhsValue),
+    /// Replaces a list of values.
+    ///
+    /// This is accepted by `in`, and by `==` which then becomes `in`.
+    Values(RhsValues),
+}
+
+impl GetType for RhsReplacement {
+    fn get_type(&self) -> Type {
+        match self {
+            RhsReplacement::Value(value) => value.get_type(),
+            RhsReplacement::Values(values) => values.get_type(),
+        }
+    }
+}
+
+/// An error that occurs when a replacement returned from
+/// [`FilterAst::rewrite_rhs`](::FilterAst::rewrite_rhs) can't be used in
+/// place of the original right-hand side.
+#[derive(Debug, PartialEq, Fail)]
+pub enum RhsRewriteError {
+    /// The replacement has a different type than the left-hand side.
+    #[fail(display = "{}", _0)]
+    TypeMismatch(#[cause] TypeMismatchError),
+
+    /// The replacement is not supported by the comparison operator.
+    #[fail(display = "cannot use this replacement with operator {:?}", _0)]
+    UnsupportedOp(ComparisonOperator),
+}
+
+/// A read-only view of a single comparison in a [`FilterAst`](::FilterAst).
+///
+/// These are produced by [`FilterAst::comparisons`](::FilterAst::comparisons).
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct ComparisonRef<'a, 's> {
+    scheme: &'s Scheme,
+    expr: &'a FieldExpr<'s>,
+}
+
+impl<'a, 's> Debug for ComparisonRef<'a, 's> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.expr.fmt(f)
+    }
+}
+
+impl<'a, 's> ComparisonRef<'a, 's> {
+    pub(crate) fn new(scheme: &'s Scheme, expr: &'a FieldExpr<'s>) -> Self {
+        ComparisonRef { scheme, expr }
+    }
+
+    /// Returns the name of the field on the left-hand side, or `None` if
+    /// the left-hand side is a function call.
+    pub fn field(&self) -> Option<&'s str> {
+        match &self.expr.lhs {
+            LhsFieldExpr::Field(field) => Some(field.name()),
+            LhsFieldExpr::FunctionCallExpr(_) => None,
+        }
+    }
+
+    /// Returns the name of the function on the left-hand side, if any.
+    pub fn function(&self) -> Option<&'a str> {
+        match &self.expr.lhs {
+            LhsFieldExpr::Field(_) => None,
+            LhsFieldExpr::FunctionCallExpr(call) => Some(&call.name),
+        }
+    }
+
+    /// Checks whether the comparison uses a given field name, either
+    /// directly or as a function argument.
+    pub fn uses(&self, field_name: &str) -> bool {
+        match self.scheme.get_field_index(field_name) {
+            Ok(field) => self.expr.uses(field),
+            Err(_) => false,
+        }
+    }
+
+    pub(crate) fn expr(&self) -> &'a FieldExpr<'s> {
+        self.expr
+    }
+
+    /// Returns the comparison operator.
+    pub fn op(&self) -> ComparisonOperator {
+        self.expr.op.operator()
+    }
+
+    /// Returns the right-hand side, or `None` for boolean fields used on
+    /// their own and for fields or function calls on the right-hand side.
+    pub fn rhs(&self) -> Option<ComparisonRhs<'a>> {
+        self.expr.op.rhs()
+    }
+
+    /// Checks whether the comparison has to hold for all values of a
+    /// multi-valued left-hand side, like in `all(tls.ciphersuite != 0x0005)`,
+    /// rather than for any of them.
+    pub fn is_all(&self) -> bool {
+        self.expr.quantifier == Quantifier::All
+    }
+
+    /// Returns the bitwise operator and its operand applied to the
+    /// left-hand side before the comparison, like `& 0x12` in
+    /// `tcp.flags & 0x12 == 0x12`.
+    pub fn bitwise(&self) -> Option<(ComparisonOperator, i64)> {
+        match self.expr.op {
+            FieldOp::BitwiseOrdering { bitwise, .. } => Some((bitwise.op.operator(), bitwise.rhs)),
+            _ => None,
+        }
+    }
+
+    /// Returns an estimated cost of evaluating this comparison, see
+    /// [`FilterAst::cost`](::FilterAst::cost).
+    pub fn cost(&self) -> u64 {
+        self.expr.lhs.cost() + self.expr.op.cost()
+    }
 }
 
 impl<'i, 's> LexWith<'i, &'s Scheme> for FieldExpr<'s> {
     fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
+        // Functions with the same names as quantifiers take precedence.
+        if let Ok((quantifier, rest)) = Quantifier::lex(input) {
+            let name = &input[..input.len() - rest.len()];
+            if let (Ok(rest), Err(_)) = (expect(rest, "("), scheme.get_function(name)) {
+                let (mut expr, rest) = FieldExpr::lex_comparison(skip_space(rest), scheme)?;
+                let rest = expect(skip_space(rest), ")")?;
+                expr.quantifier = quantifier;
+                return Ok((expr, rest));
+            }
+        }
+
+        FieldExpr::lex_comparison(input, scheme)
+    }
+}
+
+impl<'s> FieldExpr<'s> {
+    fn lex_comparison<'i>(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
         let initial_input = input;
 
         let (lhs, input) = LhsFieldExpr::lex_with(input, scheme)?;
@@ -199,52 +927,1059 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for FieldExpr<'s> {
 
             let input_after_op = input;
 
-            let input = skip_space(input);
+            let input = skip_space(input);
+
+            match (lhs_type, op) {
+                (_, ComparisonOp::In) if input.starts_with('$') => {
+                    let (list, rest) = List::lex_with(input, scheme)?;
+                    let list_type = list.get_type();
+                    if list_type != lhs_type {
+                        return Err((
+                            LexErrorKind::InvalidRhsType(TypeMismatchError {
+                                expected: lhs_type,
+                                actual: list_type,
+                            }),
+                            span(input, rest),
+                        ));
+                    }
+                    (FieldOp::InList(list), rest)
+                }
+                (_, ComparisonOp::In) => {
+                    let max_elements = scheme.parser_settings().max_list_elements;
+                    let (rhs, input) = RhsValues::lex_with_limit(input, lhs_type, max_elements)?;
+                    (FieldOp::OneOf(rhs), input)
+                }
+                (_, ComparisonOp::Ordering(op)) => match RhsValue::lex_with(input, lhs_type) {
+                    Ok((rhs, rest)) if lhs_type == Type::Ip && rest.starts_with('%') => {
+                        let (zone, rest) = lex_ip_zone(rest)?;
+                        let op = FieldOp::with_ip_zone(op, rhs, zone, scheme.parser_settings())
+                            .map_err(|kind| (kind, span(input, rest)))?;
+                        (op, rest)
+                    }
+                    Ok((rhs, input)) => (FieldOp::Ordering { op, rhs }, input),
+                    // Fallback to a field or a function call
+                    Err(err) => {
+                        let (rhs, rest) = LhsFieldExpr::lex_with(input, scheme).map_err(|_| err)?;
+                        let rhs_type = rhs.get_type();
+                        if rhs_type != lhs_type {
+                            return Err((
+                                LexErrorKind::InvalidRhsType(TypeMismatchError {
+                                    expected: lhs_type,
+                                    actual: rhs_type,
+                                }),
+                                span(input, rest),
+                            ));
+                        }
+                        (FieldOp::OrderingExpr { op, rhs }, rest)
+                    }
+                },
+                (Type::Int, ComparisonOp::Int(op)) => {
+                    let (rhs, input) = i64::lex(input)?;
+                    // Bitwise operators bind tighter than comparisons, so
+                    // `tcp.flags & 0x12 == 0x12` compares the masked value.
+                    match OrderingOp::lex(skip_space(input)) {
+                        Ok((ordering_op, rest)) => {
+                            let (value, rest) = RhsValue::lex_with(skip_space(rest), lhs_type)?;
+                            (
+                                FieldOp::BitwiseOrdering {
+                                    bitwise: Bitwise { op, rhs },
+                                    op: ordering_op,
+                                    rhs: value,
+                                },
+                                rest,
+                            )
+                        }
+                        Err(_) => (FieldOp::Int { op, rhs }, input),
+                    }
+                }
+                (Type::Bytes, ComparisonOp::Bytes(op)) => match op {
+                    BytesOp::Contains => {
+                        let (bytes, input) = Bytes::lex(input)?;
+                        (FieldOp::Contains(bytes), input)
+                    }
+                    BytesOp::Matches => {
+                        let (regex, input) = Regex::lex(input)?;
+                        (FieldOp::Matches(regex), input)
+                    }
+                    BytesOp::Wildcard => {
+                        let (wildcard, input) = Wildcard::lex(input)?;
+                        (FieldOp::Wildcard(wildcard), input)
+                    }
+                    BytesOp::InDomain => {
+                        let (domain, input) = Domain::lex(input)?;
+                        (FieldOp::InDomain(domain), input)
+                    }
+                },
+                _ => {
+                    return Err((
+                        LexErrorKind::UnsupportedOp { lhs_type },
+                        span(initial_input, input_after_op),
+                    ));
+                }
+            }
+        };
+
+        let mut expr = FieldExpr {
+            quantifier: Quantifier::Any,
+            lhs,
+            op,
+        };
+
+        if scheme.parser_settings().ipv4_mapped_ipv6_interop {
+            expr.unmap_ipv4();
+        }
+
+        expr.check_operators(scheme.parser_settings())
+            .and_then(|_| expr.validate_rhs())
+            .map_err(|kind| (kind, span(initial_input, input)))?;
+
+        Ok((expr, input))
+    }
+
+    /// Binds a deserialized comparison to a scheme, performing the same
+    /// checks as the parser.
+    pub(crate) fn bind(repr: &FieldExprRepr, scheme: &'s Scheme) -> BindResult<Self> {
+        let lhs = repr.lhs.bind(scheme)?;
+        let lhs_type = lhs.get_type();
+
+        let rhs = || {
+            repr.rhs
+                .as_ref()
+                .ok_or_else(|| format!("missing right-hand side of {}", repr.op))
+        };
+
+        let ordering_op = match repr.op.as_str() {
+            "Equal" => Some(OrderingOp::Equal),
+            "NotEqual" => Some(OrderingOp::NotEqual),
+            "GreaterThanEqual" => Some(OrderingOp::GreaterThanEqual),
+            "LessThanEqual" => Some(OrderingOp::LessThanEqual),
+            "GreaterThan" => Some(OrderingOp::GreaterThan),
+            "LessThan" => Some(OrderingOp::LessThan),
+            _ => None,
+        };
+
+        if repr.zone.is_some() && (ordering_op.is_none() || repr.rhs_expr.is_some()) {
+            return Err(LexErrorKind::InvalidIpZone.to_string());
+        }
+
+        let op = match (lhs_type, repr.op.as_str(), ordering_op) {
+            (Type::Bool, "IsTrue", _) => FieldOp::IsTrue,
+            (Type::Bool, ..) | (_, "IsTrue", _) => {
+                return Err(format!(
+                    "unsupported operator {} for type {:?}",
+                    repr.op, lhs_type
+                ));
+            }
+            (_, "InList", _) => match rhs()? {
+                ValueRepr::String(name) => {
+                    let list = scheme
+                        .get_list(name)
+                        .map_err(|_| format!("unknown list {}", name))?;
+                    if list.get_type() != lhs_type {
+                        return Err(format!("list {} must be of type {:?}", name, lhs_type));
+                    }
+                    FieldOp::InList(list)
+                }
+                _ => return Err("list name must be a string".to_owned()),
+            },
+            (_, "OneOf", _) => FieldOp::OneOf(
+                rhs()?.to_rhs_values(lhs_type, scheme.parser_settings().max_list_elements)?,
+            ),
+            (_, _, Some(op)) => match &repr.rhs_expr {
+                Some(rhs) => {
+                    let rhs = rhs.bind(scheme)?;
+                    if rhs.get_type() != lhs_type {
+                        return Err(format!(
+                            "right-hand side of {} must be of type {:?}",
+                            repr.op, lhs_type
+                        ));
+                    }
+                    FieldOp::OrderingExpr { op, rhs }
+                }
+                None => {
+                    let rhs = rhs()?.to_rhs_value(lhs_type)?;
+                    match &repr.zone {
+                        Some(zone) => {
+                            FieldOp::with_ip_zone(op, rhs, zone, scheme.parser_settings())
+                                .map_err(|kind| kind.to_string())?
+                        }
+                        None => FieldOp::Ordering { op, rhs },
+                    }
+                }
+            },
+            (Type::Int, "BitwiseAnd", _) => FieldOp::Int {
+                op: IntOp::BitwiseAnd,
+                rhs: rhs()?.int()?,
+            },
+            (Type::Int, "BitwiseOr", _) => FieldOp::Int {
+                op: IntOp::BitwiseOr,
+                rhs: rhs()?.int()?,
+            },
+            (Type::Int, "BitwiseXor", _) => FieldOp::Int {
+                op: IntOp::BitwiseXor,
+                rhs: rhs()?.int()?,
+            },
+            (Type::Int, "ShiftLeft", _) => FieldOp::Int {
+                op: IntOp::ShiftLeft,
+                rhs: rhs()?.int()?,
+            },
+            (Type::Int, "ShiftRight", _) => FieldOp::Int {
+                op: IntOp::ShiftRight,
+                rhs: rhs()?.int()?,
+            },
+            (Type::Bytes, "Contains", _) => FieldOp::Contains(rhs()?.bytes()?),
+            (Type::Bytes, "Matches", _) => match rhs()? {
+                ValueRepr::String(regex) => {
+                    FieldOp::Matches(regex.parse::<Regex>().map_err(|err| err.to_string())?)
+                }
+                _ => return Err("regex must be a string".to_owned()),
+            },
+            (Type::Bytes, "Wildcard", _) => FieldOp::Wildcard(rhs()?.bytes()?.into()),
+            (Type::Bytes, "InDomain", _) => FieldOp::InDomain(rhs()?.bytes()?.into()),
+            _ => {
+                return Err(format!(
+                    "unsupported operator {} for type {:?}",
+                    repr.op, lhs_type
+                ));
+            }
+        };
+
+        let op = match (repr.bitwise, op) {
+            (None, op) => op,
+            (Some(bitwise), FieldOp::Ordering { op, rhs }) if lhs_type == Type::Int => {
+                FieldOp::BitwiseOrdering { bitwise, op, rhs }
+            }
+            (Some(_), _) => {
+                return Err(format!(
+                    "bitwise operations are not supported with operator {}",
+                    repr.op
+                ));
+            }
+        };
+
+        let mut expr = FieldExpr {
+            quantifier: repr.quantifier.unwrap_or(Quantifier::Any),
+            lhs,
+            op,
+        };
+
+        if scheme.parser_settings().ipv4_mapped_ipv6_interop {
+            expr.unmap_ipv4();
+        }
+
+        expr.check_operators(scheme.parser_settings())
+            .and_then(|_| expr.validate_rhs())
+            .map_err(|kind| kind.to_string())?;
+        Ok(expr)
+    }
+
+    // See `ParserSettings::ipv4_mapped_ipv6_interop`.
+    fn unmap_ipv4(&mut self) {
+        match &mut self.op {
+            FieldOp::Ordering {
+                rhs: RhsValue::Ip(addr),
+                ..
+            } => *addr = unmap_ipv4(*addr),
+            FieldOp::OneOf(RhsValues::Ip(ranges)) => {
+                *ranges = mem::take(ranges)
+                    .into_iter()
+                    .map(IpRange::unmap_ipv4)
+                    .collect();
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns whether the left-hand side is a
+    /// [case-insensitive](::Scheme::set_field_comparison_mode) field.
+    fn is_case_insensitive(&self) -> bool {
+        match self.lhs {
+            LhsFieldExpr::Field(field) => {
+                field
+                    .scheme()
+                    .get_field_comparison_mode(field)
+                    .case_insensitive
+            }
+            LhsFieldExpr::FunctionCallExpr(_) => false,
+        }
+    }
+
+    // Values of case-insensitive fields are lowercased at runtime, so
+    // literals they are compared with are lowercased before compilation.
+    fn fold_case(&mut self) {
+        if !self.is_case_insensitive() {
+            return;
+        }
+        match &mut self.op {
+            FieldOp::Ordering {
+                rhs: RhsValue::Bytes(bytes),
+                ..
+            }
+            | FieldOp::Contains(bytes) => *bytes = bytes.to_ascii_lowercase(),
+            FieldOp::Wildcard(wildcard) => {
+                *wildcard = wildcard.pattern().to_ascii_lowercase().into()
+            }
+            FieldOp::Matches(regex) => {
+                // The original regex still works on lowercased values if
+                // the case-insensitive one turns out to be too large.
+                if let Ok(folded) = format!("(?i){}", regex.as_str()).parse() {
+                    *regex = folded;
+                }
+            }
+            FieldOp::OneOf(RhsValues::Bytes(values)) => {
+                for value in values.iter_mut() {
+                    *value = value.to_ascii_lowercase();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns a function call on the left-hand side, if any.
+    pub(crate) fn function_call(&self) -> Option<&FunctionCallExpr<'s>> {
+        match &self.lhs {
+            LhsFieldExpr::Field(_) => None,
+            LhsFieldExpr::FunctionCallExpr(call) => Some(call),
+        }
+    }
+
+    /// Returns the field and an equivalent regular expression for `contains`
+    /// and `matches` comparisons of fields, so that several of them can be
+    /// checked in a single pass.
+    pub(crate) fn field_pattern(&self) -> Option<(Field<'s>, String)> {
+        let field = match self.lhs {
+            LhsFieldExpr::Field(field) => field,
+            LhsFieldExpr::FunctionCallExpr(_) => return None,
+        };
+        if self.quantifier == Quantifier::All {
+            return None;
+        }
+        let case_insensitive = self.is_case_insensitive();
+        let pattern = match &self.op {
+            // Bytes might not be valid UTF-8, so escape each of them.
+            FieldOp::Contains(bytes) => bytes
+                .iter()
+                .map(|b| {
+                    let b = if case_insensitive {
+                        b.to_ascii_lowercase()
+                    } else {
+                        *b
+                    };
+                    format!("\\x{:02x}", b)
+                })
+                .collect(),
+            FieldOp::Matches(regex) if case_insensitive => format!("(?i){}", regex.as_str()),
+            FieldOp::Matches(regex) => regex.as_str().to_owned(),
+            _ => return None,
+        };
+        Some((field, pattern))
+    }
+
+    /// Lexes a chained comparison like `1 <= tcp.port < 1024` into a pair of
+    /// comparisons of the same field, to be combined with `and`.
+    pub(crate) fn lex_chain<'i>(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, (Self, Self)> {
+        // The type of the leading literal is only known from the field that
+        // follows it, so try all types that support ordering.
+        [
+            Type::Ip,
+            Type::Int,
+            Type::Float,
+            Type::Timestamp,
+            Type::Bytes,
+        ]
+        .iter()
+        .map(|&ty| Self::lex_chain_of_type(input, scheme, ty))
+        .find(Result::is_ok)
+        .unwrap_or_else(|| Err((LexErrorKind::ExpectedName("chained comparison"), input)))
+    }
+
+    fn lex_chain_of_type<'i>(
+        input: &'i str,
+        scheme: &'s Scheme,
+        ty: Type,
+    ) -> LexResult<'i, (Self, Self)> {
+        let initial_input = input;
+
+        let (lower, input) = RhsValue::lex_with(input, ty)?;
+        let (lower_op, input) = OrderingOp::lex(skip_space(input))?;
+        let input = skip_space(input);
+        let (lhs, input) = LhsFieldExpr::lex_with(input, scheme)?;
+
+        let lhs_type = lhs.get_type();
+        if lhs_type != ty {
+            return Err((
+                LexErrorKind::UnsupportedOp { lhs_type },
+                span(initial_input, input),
+            ));
+        }
+
+        let (upper_op, input) = OrderingOp::lex(skip_space(input))?;
+        let input = skip_space(input);
+        let (upper, input) = RhsValue::lex_with(input, ty)?;
+
+        let lower = FieldExpr {
+            quantifier: Quantifier::Any,
+            lhs: lhs.clone(),
+            op: FieldOp::Ordering {
+                op: lower_op.reverse(),
+                rhs: lower,
+            },
+        };
+
+        let upper = FieldExpr {
+            quantifier: Quantifier::Any,
+            lhs,
+            op: FieldOp::Ordering {
+                op: upper_op,
+                rhs: upper,
+            },
+        };
+
+        lower
+            .validate_rhs()
+            .and_then(|_| upper.validate_rhs())
+            .map_err(|kind| (kind, span(initial_input, input)))?;
+
+        Ok(((lower, upper), input))
+    }
+
+    fn fmt_comparison(&self, f: &mut Formatter<'_>, style: OperatorStyle) -> fmt::Result {
+        self.lhs.fmt_filter(f)?;
+
+        match &self.op {
+            FieldOp::IsTrue => Ok(()),
+            FieldOp::Ordering { op, .. }
+            | FieldOp::ScopedIp { op, .. }
+            | FieldOp::OrderingExpr { op, .. }
+            | FieldOp::BitwiseOrdering { op, .. } => {
+                if let FieldOp::BitwiseOrdering { bitwise, .. } = &self.op {
+                    f.write_char(' ')?;
+                    bitwise.op.fmt_filter(f, style)?;
+                    write!(f, " {}", bitwise.rhs)?;
+                }
+                let op_str = match op {
+                    OrderingOp::Equal => style.pick("==", "eq"),
+                    OrderingOp::NotEqual => style.pick("!=", "ne"),
+                    OrderingOp::GreaterThanEqual => style.pick(">=", "ge"),
+                    OrderingOp::LessThanEqual => style.pick("<=", "le"),
+                    OrderingOp::GreaterThan => style.pick(">", "gt"),
+                    OrderingOp::LessThan => style.pick("<", "lt"),
+                };
+                write!(f, " {} ", op_str)?;
+                match &self.op {
+                    FieldOp::Ordering { rhs, .. } | FieldOp::BitwiseOrdering { rhs, .. } => {
+                        fmt_rhs_value(f, rhs)
+                    }
+                    FieldOp::ScopedIp { rhs, zone, .. } => {
+                        fmt_rhs_value(f, rhs)?;
+                        write!(f, "%{}", zone)
+                    }
+                    FieldOp::OrderingExpr { rhs, .. } => rhs.fmt_filter(f),
+                    _ => unreachable!(),
+                }
+            }
+            FieldOp::Int { op, rhs } => {
+                f.write_char(' ')?;
+                op.fmt_filter(f, style)?;
+                write!(f, " {}", rhs)
+            }
+            FieldOp::Contains(bytes) => {
+                f.write_str(" contains ")?;
+                fmt_bytes(f, bytes)
+            }
+            FieldOp::Matches(regex) => {
+                write!(f, " {} ", style.pick("~", "matches"))?;
+                fmt_regex(f, regex)
+            }
+            FieldOp::Wildcard(wildcard) => {
+                f.write_str(" wildcard ")?;
+                fmt_bytes(f, wildcard.pattern())
+            }
+            FieldOp::InDomain(domain) => {
+                f.write_str(" in_domain ")?;
+                fmt_bytes(f, domain.name())
+            }
+            FieldOp::OneOf(values) => {
+                f.write_str(" in ")?;
+                fmt_rhs_values(f, values)
+            }
+            FieldOp::InList(list) => write!(f, " in ${}", list.name()),
+        }
+    }
+
+    /// Checks that operators of the comparison are allowed by
+    /// [`ParserSettings::allowed_operators`].
+    fn check_operators(&self, settings: &ParserSettings) -> Result<(), LexErrorKind> {
+        let bitwise = match self.op {
+            FieldOp::BitwiseOrdering { bitwise, .. } => Some(bitwise.op.operator()),
+            _ => None,
+        };
+        for operator in bitwise.into_iter().chain(Some(self.op.operator())) {
+            if !settings.allows_operator(operator) {
+                return Err(LexErrorKind::ForbiddenOperator(operator));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a validator registered in the scheme for the field on the
+    /// right-hand side, if any.
+    fn validate_rhs(&self) -> Result<(), LexErrorKind> {
+        if let LhsFieldExpr::Field(field) = self.lhs {
+            if let (Some(validator), Some(rhs)) =
+                (field.scheme().get_field_validator(field), self.op.rhs())
+            {
+                return validator(rhs).map_err(LexErrorKind::InvalidRhs);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn rebind<'p>(
+        &self,
+        scheme: &'p Scheme,
+        bind_field: BindField<'_, 's, 'p>,
+    ) -> Option<FieldExpr<'p>> {
+        Some(FieldExpr {
+            quantifier: self.quantifier,
+            lhs: self.lhs.rebind(scheme, bind_field)?,
+            op: self.op.rebind(scheme, bind_field)?,
+        })
+    }
+
+    pub(crate) fn anonymize(&mut self, anonymizer: &mut Anonymizer) {
+        self.lhs.anonymize(anonymizer);
+
+        match &mut self.op {
+            FieldOp::IsTrue
+            | FieldOp::Int { .. }
+            | FieldOp::BitwiseOrdering { .. }
+            | FieldOp::InList(_) => {}
+            FieldOp::Ordering { rhs, .. } | FieldOp::ScopedIp { rhs, .. } => {
+                *rhs = anonymizer.rhs_value(rhs)
+            }
+            FieldOp::OrderingExpr { rhs, .. } => rhs.anonymize(anonymizer),
+            FieldOp::Contains(bytes) => *bytes = anonymizer.bytes(bytes),
+            FieldOp::Matches(regex) => *regex = anonymizer.regex(regex),
+            // Metacharacters are punctuation, so they are kept as they are.
+            FieldOp::Wildcard(wildcard) => *wildcard = anonymizer.bytes(wildcard.pattern()).into(),
+            FieldOp::InDomain(domain) => *domain = anonymizer.bytes(domain.name()).into(),
+            FieldOp::OneOf(values) => *values = anonymizer.rhs_values(values),
+        }
+    }
+
+    pub(crate) fn replace_rhs(
+        &mut self,
+        replacement: RhsReplacement,
+    ) -> Result<(), RhsRewriteError> {
+        let lhs_type = self.lhs.get_type();
+        let rhs_type = replacement.get_type();
+
+        if lhs_type != rhs_type {
+            return Err(RhsRewriteError::TypeMismatch(TypeMismatchError {
+                expected: lhs_type,
+                actual: rhs_type,
+            }));
+        }
+
+        self.op = match (&self.op, replacement) {
+            (FieldOp::Ordering { op, .. }, RhsReplacement::Value(rhs)) => {
+                FieldOp::Ordering { op: *op, rhs }
+            }
+            (
+                FieldOp::Ordering {
+                    op: OrderingOp::Equal,
+                    ..
+                },
+                RhsReplacement::Values(values),
+            ) => FieldOp::OneOf(values),
+            (
+                FieldOp::ScopedIp { op, zone, .. },
+                RhsReplacement::Value(rhs @ RhsValue::Ip(IpAddr::V6(_))),
+            ) => FieldOp::ScopedIp {
+                op: *op,
+                rhs,
+                zone: zone.clone(),
+            },
+            (FieldOp::OrderingExpr { op, .. }, RhsReplacement::Value(rhs)) => {
+                FieldOp::Ordering { op: *op, rhs }
+            }
+            (FieldOp::Int { op, .. }, RhsReplacement::Value(RhsValue::Int(rhs))) => {
+                FieldOp::Int { op: *op, rhs }
+            }
+            (FieldOp::BitwiseOrdering { bitwise, op, .. }, RhsReplacement::Value(rhs)) => {
+                FieldOp::BitwiseOrdering {
+                    bitwise: *bitwise,
+                    op: *op,
+                    rhs,
+                }
+            }
+            (FieldOp::Contains(_), RhsReplacement::Value(RhsValue::Bytes(bytes))) => {
+                FieldOp::Contains(bytes)
+            }
+            (FieldOp::Wildcard(_), RhsReplacement::Value(RhsValue::Bytes(bytes))) => {
+                FieldOp::Wildcard(bytes.into())
+            }
+            (FieldOp::InDomain(_), RhsReplacement::Value(RhsValue::Bytes(bytes))) => {
+                FieldOp::InDomain(bytes.into())
+            }
+            (FieldOp::OneOf(_), RhsReplacement::Values(values)) => FieldOp::OneOf(values),
+            _ => {
+                return Err(RhsRewriteError::UnsupportedOp(self.op.operator()));
+            }
+        };
+
+        Ok(())
+    }
+}
+
+impl<'s> Expr<'s> for FieldExpr<'s> {
+    fn uses(&self, field: Field<'s>) -> bool {
+        self.lhs.uses(field) || self.op.uses(field)
+    }
+
+    fn for_each_field_expr<'a>(&'a self, visit: &mut dyn FnMut(&'a FieldExpr<'s>)) {
+        visit(self)
+    }
+
+    fn for_each_field_expr_mut(&mut self, visit: &mut dyn FnMut(&mut FieldExpr<'s>)) {
+        visit(self)
+    }
+
+    fn fmt_filter(&self, f: &mut Formatter<'_>, style: OperatorStyle) -> fmt::Result {
+        match self.quantifier {
+            Quantifier::Any => self.fmt_comparison(f, style),
+            Quantifier::All => {
+                f.write_str("all(")?;
+                self.fmt_comparison(f, style)?;
+                f.write_char(')')
+            }
+        }
+    }
+
+    fn fold_constants(self, constants: Option<&ExecutionContext<'s>>) -> Folded<Self> {
+        let constants = match constants {
+            Some(constants) => constants,
+            None => return Folded::Expr(self),
+        };
+
+        match self.lhs {
+            // Fields on the right-hand side and contents of lists might not
+            // be constant.
+            LhsFieldExpr::Field(_)
+                if matches!(self.op, FieldOp::OrderingExpr { .. } | FieldOp::InList(_))
+                    || self.op.is_time_dependent() =>
+            {
+                Folded::Expr(self)
+            }
+            LhsFieldExpr::Field(field) if constants.has_field_value(field) => {
+                let settings = field.scheme().parser_settings();
+                let compiled = self.compile_with_settings(settings, &CachedCalls::default());
+                Folded::Constant(compiled.execute(constants))
+            }
+            _ => Folded::Expr(self),
+        }
+    }
+
+    fn compile_with_settings(
+        mut self,
+        settings: &ParserSettings,
+        cached_calls: &CachedCalls<'s>,
+    ) -> CompiledExpr<'s> {
+        // Closures below are specialized for checking any of the values, and
+        // fold calls of pure functions even if the right-hand side changes
+        // over time.
+        if self.quantifier == Quantifier::All
+            || self.op.is_time_dependent()
+            || matches!(self.op, FieldOp::ScopedIp { .. })
+        {
+            let comparison = PreparedComparison::new(self, settings, cached_calls);
+            return CompiledExpr::new(move |ctx, calls| comparison.execute(ctx, calls));
+        }
+
+        self.fold_case();
+        let lhs = self.lhs;
+
+        macro_rules! cast_value {
+            ($value:expr, $ty:ident) => {
+                match $value {
+                    LhsValue::$ty(value) => value,
+                    _ => unreachable!(),
+                }
+            };
+        }
+
+        match self.op {
+            FieldOp::IsTrue => lhs.compile_with(cached_calls, move |x| cast_value!(x, Bool)),
+            FieldOp::Ordering { op, rhs } => lhs.compile_with(cached_calls, move |x| {
+                op.matches_opt(x.strict_partial_cmp(&rhs))
+            }),
+            FieldOp::ScopedIp { .. } => unreachable!(),
+            FieldOp::OrderingExpr { op, rhs } => CompiledExpr::new(move |ctx, _| {
+                lhs.any(ctx, |x| {
+                    rhs.any(ctx, |y| op.matches_opt(x.strict_partial_cmp(&y)))
+                })
+            }),
+            FieldOp::InList(list) => {
+                CompiledExpr::new(move |ctx, _| match ctx.get_list_values(list) {
+                    Some(values) => lhs.any(ctx, |x| values.contains(&x)),
+                    None => false,
+                })
+            }
+            FieldOp::Int { op, rhs } => {
+                let overflow = settings.int_overflow;
+                lhs.compile_with(cached_calls, move |x| {
+                    op.apply(cast_value!(x, Int), rhs, overflow)
+                        .is_some_and(|x| x != 0)
+                })
+            }
+            FieldOp::BitwiseOrdering { bitwise, op, rhs } => {
+                let overflow = settings.int_overflow;
+                lhs.compile_with(cached_calls, move |x| {
+                    bitwise
+                        .apply(x, overflow)
+                        .is_some_and(|x| op.matches_opt(x.strict_partial_cmp(&rhs)))
+                })
+            }
+            FieldOp::Contains(bytes) => {
+                let len = bytes.len();
+                let searcher = HeapSearcher::from(bytes);
+
+                match lhs {
+                    LhsFieldExpr::Field(field) => CompiledExpr::new(move |ctx, _| {
+                        ctx.any_field_value_with_offset(field, |x, offset| {
+                            match searcher.search_in(&cast_value!(x, Bytes)) {
+                                Some(start) => {
+                                    if ctx.records_matches() {
+                                        ctx.record_match(field, start..start + len, offset);
+                                    }
+                                    true
+                                }
+                                None => false,
+                            }
+                        })
+                    }),
+                    lhs => lhs.compile_with(cached_calls, move |x| {
+                        searcher.search_in(&cast_value!(x, Bytes)).is_some()
+                    }),
+                }
+            }
+            FieldOp::Matches(regex) => match lhs {
+                LhsFieldExpr::Field(field) => CompiledExpr::new(move |ctx, _| {
+                    ctx.any_field_value_with_offset(field, |x, offset| {
+                        let x = cast_value!(x, Bytes);
+                        if !ctx.records_matches() {
+                            return regex.is_match(&x);
+                        }
+                        match regex.find(&x) {
+                            Some(range) => {
+                                ctx.record_match(field, range, offset);
+                                true
+                            }
+                            None => false,
+                        }
+                    })
+                }),
+                lhs => lhs.compile_with(cached_calls, move |x| {
+                    regex.is_match(&cast_value!(x, Bytes))
+                }),
+            },
+            FieldOp::Wildcard(wildcard) => match lhs {
+                LhsFieldExpr::Field(field) => CompiledExpr::new(move |ctx, _| {
+                    ctx.any_field_value_with_offset(field, |x, offset| {
+                        let x = cast_value!(x, Bytes);
+                        let matched = wildcard.is_match(&x);
+                        // The pattern always covers the whole value.
+                        if matched && ctx.records_matches() {
+                            ctx.record_match(field, 0..x.len(), offset);
+                        }
+                        matched
+                    })
+                }),
+                lhs => lhs.compile_with(cached_calls, move |x| {
+                    wildcard.is_match(&cast_value!(x, Bytes))
+                }),
+            },
+            FieldOp::InDomain(domain) => match lhs {
+                LhsFieldExpr::Field(field) => CompiledExpr::new(move |ctx, _| {
+                    ctx.any_field_value_with_offset(field, |x, offset| {
+                        match domain.find(&cast_value!(x, Bytes)) {
+                            Some(range) => {
+                                if ctx.records_matches() {
+                                    ctx.record_match(field, range, offset);
+                                }
+                                true
+                            }
+                            None => false,
+                        }
+                    })
+                }),
+                lhs => lhs.compile_with(cached_calls, move |x| {
+                    domain.is_match(&cast_value!(x, Bytes))
+                }),
+            },
+            FieldOp::OneOf(values) => match values {
+                RhsValues::Ip(ranges) => {
+                    let ranges: IpRangeSet = ranges.into_iter().map(Into::into).collect();
+
+                    lhs.compile_with(cached_calls, move |x| ranges.contains(&cast_value!(x, Ip)))
+                }
+                RhsValues::Int(values) => match BitSet::from_ranges(&values) {
+                    Some(values) => lhs
+                        .compile_with(cached_calls, move |x| values.contains(cast_value!(x, Int))),
+                    None => {
+                        let values: RangeSet<_> = values.into_iter().collect();
+
+                        lhs.compile_with(cached_calls, move |x| {
+                            values.contains(&cast_value!(x, Int))
+                        })
+                    }
+                },
+                RhsValues::Float(values) => {
+                    let values: RangeSet<_> = values.into_iter().collect();
+
+                    lhs.compile_with(cached_calls, move |x| {
+                        values.contains(&cast_value!(x, Float))
+                    })
+                }
+                RhsValues::Timestamp(values) => {
+                    let values: RangeSet<_> = values.into_iter().collect();
+
+                    lhs.compile_with(cached_calls, move |x| {
+                        values.contains(&cast_value!(x, Timestamp))
+                    })
+                }
+                RhsValues::Bytes(values) => {
+                    let values: IndexSet<Box<[u8]>, FnvBuildHasher> =
+                        values.into_iter().map(Into::into).collect();
+
+                    match settings.bloom_filter_threshold {
+                        Some(threshold) if values.len() >= threshold => {
+                            let bloom = BloomFilter::new(
+                                values.iter().map(|value| &value[..]),
+                                settings.bloom_filter_false_positive_rate,
+                            );
+
+                            lhs.compile_with(cached_calls, move |x| {
+                                let x = &cast_value!(x, Bytes) as &[u8];
+                                bloom.may_contain(x) && values.contains(x)
+                            })
+                        }
+                        _ => lhs.compile_with(cached_calls, move |x| {
+                            values.contains(&cast_value!(x, Bytes) as &[u8])
+                        }),
+                    }
+                }
+                RhsValues::Bool(_) => unreachable!(),
+            },
+        }
+    }
+
+    fn trace(&self, ctx: &ExecutionContext<'s>) -> TraceNode<'s> {
+        let scheme = ctx.scheme();
+
+        // Like `FilterAst::explain_mismatch`, a comparison of a missing
+        // field doesn't match unless the scheme ignores missing fields.
+        let complete = scheme.ignores_missing_fields()
+            || scheme
+                .fields()
+                .all(|field| !self.uses(field) || ctx.has_field_value(field));
+
+        let mut values = Vec::new();
+        let result = complete && {
+            self.lhs.any(ctx, |value| {
+                values.push(LhsValueLiteral(value).to_string());
+                false
+            });
+            self.clone()
+                .compile_with_settings(scheme.parser_settings(), &CachedCalls::default())
+                .execute(ctx)
+        };
+
+        TraceNode::Comparison {
+            filter: Styled(self, OperatorStyle::Symbols).to_string(),
+            field: match self.lhs {
+                LhsFieldExpr::Field(field) => Some(field.name()),
+                LhsFieldExpr::FunctionCallExpr(_) => None,
+            },
+            operator: self.op.operator(),
+            values,
+            result,
+        }
+    }
+
+    fn emit(
+        self,
+        settings: &ParserSettings,
+        cached_calls: &CachedCalls<'s>,
+        program: &mut Program<'s>,
+    ) {
+        if program.shares_comparisons() {
+            // Filter strings round-trip, so they identify comparisons.
+            let key = Styled(&self, OperatorStyle::Symbols).to_string();
+            program.compare_shared(key, || {
+                PreparedComparison::new(self, settings, cached_calls)
+            });
+        } else {
+            program.compare(PreparedComparison::new(self, settings, cached_calls));
+        }
+    }
+}
+
+/// A right-hand side of a comparison prepared for lookups, as used by
+/// [`PreparedComparison`].
+enum PreparedRhs<'s> {
+    IsTrue,
+    Ordering {
+        op: OrderingOp,
+        rhs: RhsValue,
+    },
+    ScopedIp {
+        op: OrderingOp,
+        rhs: RhsValue,
+        zone: String,
+    },
+    OrderingExpr {
+        op: OrderingOp,
+        rhs: LhsFieldExpr<'s>,
+    },
+    List(List<'s>),
+    Int {
+        op: IntOp,
+        rhs: i64,
+        overflow: IntOverflow,
+    },
+    BitwiseOrdering {
+        bitwise: Bitwise,
+        op: OrderingOp,
+        rhs: RhsValue,
+        overflow: IntOverflow,
+    },
+    Contains {
+        searcher: HeapSearcher,
+        len: usize,
+    },
+    Matches(Regex),
+    Wildcard(Wildcard),
+    InDomain(Domain),
+    Ips(IpRangeSet),
+    IntBits(BitSet),
+    Ints(RangeSet<i64>),
+    Floats(RangeSet<Float>),
+    Timestamps(RangeSet<Timestamp>),
+    Bytes {
+        values: IndexSet<Box<[u8]>, FnvBuildHasher>,
+        bloom: Option<BloomFilter>,
+    },
+    Constant(bool),
+}
+
+/// A comparison stored as plain data for the bytecode interpreter, rather
+/// than as a closure like in [`FieldExpr::compile_with_settings`].
+pub(crate) struct PreparedComparison<'s> {
+    quantifier: Quantifier,
+    lhs: LhsFieldExpr<'s>,
+    slot: Option<usize>,
+    rhs: PreparedRhs<'s>,
+}
+
+impl<'s> PreparedComparison<'s> {
+    fn new(
+        mut expr: FieldExpr<'s>,
+        settings: &ParserSettings,
+        cached_calls: &CachedCalls<'s>,
+    ) -> Self {
+        expr.fold_case();
+        let rhs = match expr.op {
+            FieldOp::IsTrue => PreparedRhs::IsTrue,
+            FieldOp::Ordering { op, rhs } => PreparedRhs::Ordering { op, rhs },
+            FieldOp::ScopedIp { op, rhs, zone } => PreparedRhs::ScopedIp { op, rhs, zone },
+            FieldOp::OrderingExpr { op, rhs } => PreparedRhs::OrderingExpr { op, rhs },
+            FieldOp::InList(list) => PreparedRhs::List(list),
+            FieldOp::Int { op, rhs } => PreparedRhs::Int {
+                op,
+                rhs,
+                overflow: settings.int_overflow,
+            },
+            FieldOp::BitwiseOrdering { bitwise, op, rhs } => PreparedRhs::BitwiseOrdering {
+                bitwise,
+                op,
+                rhs,
+                overflow: settings.int_overflow,
+            },
+            FieldOp::Contains(bytes) => PreparedRhs::Contains {
+                len: bytes.len(),
+                searcher: HeapSearcher::from(bytes),
+            },
+            FieldOp::Matches(regex) => PreparedRhs::Matches(regex),
+            FieldOp::Wildcard(wildcard) => PreparedRhs::Wildcard(wildcard),
+            FieldOp::InDomain(domain) => PreparedRhs::InDomain(domain),
+            FieldOp::OneOf(RhsValues::Ip(ranges)) => {
+                PreparedRhs::Ips(ranges.into_iter().map(Into::into).collect())
+            }
+            FieldOp::OneOf(RhsValues::Int(values)) => match BitSet::from_ranges(&values) {
+                Some(values) => PreparedRhs::IntBits(values),
+                None => PreparedRhs::Ints(values.into_iter().collect()),
+            },
+            FieldOp::OneOf(RhsValues::Float(values)) => {
+                PreparedRhs::Floats(values.into_iter().collect())
+            }
+            FieldOp::OneOf(RhsValues::Timestamp(values)) => {
+                PreparedRhs::Timestamps(values.into_iter().collect())
+            }
+            FieldOp::OneOf(RhsValues::Bytes(values)) => {
+                let values: IndexSet<Box<[u8]>, FnvBuildHasher> =
+                    values.into_iter().map(Into::into).collect();
+                let bloom = match settings.bloom_filter_threshold {
+                    Some(threshold) if values.len() >= threshold => Some(BloomFilter::new(
+                        values.iter().map(|value| &value[..]),
+                        settings.bloom_filter_false_positive_rate,
+                    )),
+                    _ => None,
+                };
+                PreparedRhs::Bytes { values, bloom }
+            }
+            FieldOp::OneOf(RhsValues::Bool(_)) => unreachable!(),
+        };
+
+        let mut comparison = PreparedComparison {
+            quantifier: expr.quantifier,
+            slot: match &expr.lhs {
+                LhsFieldExpr::FunctionCallExpr(call) => cached_calls.slot(call),
+                LhsFieldExpr::Field(_) => None,
+            },
+            lhs: expr.lhs,
+            rhs,
+        };
 
-            match (lhs_type, op) {
-                (_, ComparisonOp::In) => {
-                    let (rhs, input) = RhsValues::lex_with(input, lhs_type)?;
-                    (FieldOp::OneOf(rhs), input)
-                }
-                (_, ComparisonOp::Ordering(op)) => {
-                    let (rhs, input) = RhsValue::lex_with(input, lhs_type)?;
-                    (FieldOp::Ordering { op, rhs }, input)
-                }
-                (Type::Int, ComparisonOp::Int(op)) => {
-                    let (rhs, input) = i32::lex(input)?;
-                    (FieldOp::Int { op, rhs }, input)
-                }
-                (Type::Bytes, ComparisonOp::Bytes(op)) => match op {
-                    BytesOp::Contains => {
-                        let (bytes, input) = Bytes::lex(input)?;
-                        (FieldOp::Contains(bytes), input)
-                    }
-                    BytesOp::Matches => {
-                        let (regex, input) = Regex::lex(input)?;
-                        (FieldOp::Matches(regex), input)
+        // Pure functions with constant arguments are folded right away.
+        if let LhsFieldExpr::FunctionCallExpr(call) = &comparison.lhs {
+            match comparison.rhs {
+                PreparedRhs::OrderingExpr { .. } | PreparedRhs::List(_) => {}
+                PreparedRhs::Ordering {
+                    rhs: RhsValue::Timestamp(TimestampLiteral::Relative(_)),
+                    ..
+                } => {}
+                _ if call.is_constant() => {
+                    if let Some(value) = call.execute_constant() {
+                        let result = comparison.matches(value, None);
+                        comparison.rhs = PreparedRhs::Constant(result);
                     }
-                },
-                _ => {
-                    return Err((
-                        LexErrorKind::UnsupportedOp { lhs_type },
-                        span(initial_input, input_after_op),
-                    ));
                 }
+                _ => {}
             }
-        };
-
-        Ok((FieldExpr { lhs, op }, input))
-    }
-}
+        }
 
-impl<'s> Expr<'s> for FieldExpr<'s> {
-    fn uses(&self, field: Field<'s>) -> bool {
-        self.lhs.uses(field)
+        comparison
     }
 
-    fn compile(self) -> CompiledExpr<'s> {
-        let lhs = self.lhs;
-
+    /// Matches a value of the left-hand side, recording the matched span
+    /// into the context, if any.
+    fn matches(
+        &self,
+        x: LhsValue<'_>,
+        record: Option<(&ExecutionContext<'s>, Field<'s>, usize)>,
+    ) -> bool {
         macro_rules! cast_value {
             ($value:expr, $ty:ident) => {
                 match $value {
@@ -254,53 +1989,129 @@ impl<'s> Expr<'s> for FieldExpr<'s> {
             };
         }
 
-        match self.op {
-            FieldOp::IsTrue => lhs.compile_with(move |x| cast_value!(x, Bool)),
-            FieldOp::Ordering { op, rhs } => {
-                lhs.compile_with(move |x| op.matches_opt(x.strict_partial_cmp(&rhs)))
-            }
-            FieldOp::Int {
-                op: IntOp::BitwiseAnd,
+        match &self.rhs {
+            PreparedRhs::IsTrue => cast_value!(x, Bool),
+            PreparedRhs::Ordering { op, rhs } => op.matches_opt(x.strict_partial_cmp(rhs)),
+            // Values of fields are matched in `any`, and values returned
+            // from functions don't have zones.
+            PreparedRhs::ScopedIp { op, .. } => *op == OrderingOp::NotEqual,
+            PreparedRhs::OrderingExpr { .. } | PreparedRhs::List(_) => unreachable!(),
+            PreparedRhs::Int { op, rhs, overflow } => op
+                .apply(cast_value!(x, Int), *rhs, *overflow)
+                .is_some_and(|x| x != 0),
+            PreparedRhs::BitwiseOrdering {
+                bitwise,
+                op,
                 rhs,
-            } => lhs.compile_with(move |x| cast_value!(x, Int) & rhs != 0),
-            FieldOp::Contains(bytes) => {
-                let searcher = HeapSearcher::from(bytes);
-
-                lhs.compile_with(move |x| searcher.search_in(&cast_value!(x, Bytes)).is_some())
-            }
-            FieldOp::Matches(regex) => {
-                lhs.compile_with(move |x| regex.is_match(&cast_value!(x, Bytes)))
+                overflow,
+            } => bitwise
+                .apply(x, *overflow)
+                .is_some_and(|x| op.matches_opt(x.strict_partial_cmp(rhs))),
+            PreparedRhs::Contains { searcher, len } => {
+                match searcher.search_in(&cast_value!(x, Bytes)) {
+                    Some(start) => {
+                        if let Some((ctx, field, offset)) = record {
+                            ctx.record_match(field, start..start + len, offset);
+                        }
+                        true
+                    }
+                    None => false,
+                }
             }
-            FieldOp::OneOf(values) => match values {
-                RhsValues::Ip(ranges) => {
-                    let mut v4 = Vec::new();
-                    let mut v6 = Vec::new();
-                    for range in ranges {
-                        match range.clone().into() {
-                            ExplicitIpRange::V4(range) => v4.push(range),
-                            ExplicitIpRange::V6(range) => v6.push(range),
+            PreparedRhs::Matches(regex) => {
+                let x = cast_value!(x, Bytes);
+                match record {
+                    None => regex.is_match(&x),
+                    Some((ctx, field, offset)) => match regex.find(&x) {
+                        Some(range) => {
+                            ctx.record_match(field, range, offset);
+                            true
                         }
+                        None => false,
+                    },
+                }
+            }
+            PreparedRhs::Wildcard(wildcard) => {
+                let x = cast_value!(x, Bytes);
+                let matched = wildcard.is_match(&x);
+                if let (true, Some((ctx, field, offset))) = (matched, record) {
+                    ctx.record_match(field, 0..x.len(), offset);
+                }
+                matched
+            }
+            PreparedRhs::InDomain(domain) => match domain.find(&cast_value!(x, Bytes)) {
+                Some(range) => {
+                    if let Some((ctx, field, offset)) = record {
+                        ctx.record_match(field, range, offset);
                     }
-                    let v4 = RangeSet::from(v4);
-                    let v6 = RangeSet::from(v6);
-
-                    lhs.compile_with(move |x| match cast_value!(x, Ip) {
-                        IpAddr::V4(addr) => v4.contains(&addr),
-                        IpAddr::V6(addr) => v6.contains(&addr),
-                    })
+                    true
                 }
-                RhsValues::Int(values) => {
-                    let values: RangeSet<_> = values.iter().cloned().collect();
-
-                    lhs.compile_with(move |x| values.contains(&cast_value!(x, Int)))
+                None => false,
+            },
+            PreparedRhs::Ips(ranges) => ranges.contains(&cast_value!(x, Ip)),
+            PreparedRhs::IntBits(values) => values.contains(cast_value!(x, Int)),
+            PreparedRhs::Ints(values) => values.contains(&cast_value!(x, Int)),
+            PreparedRhs::Floats(values) => values.contains(&cast_value!(x, Float)),
+            PreparedRhs::Timestamps(values) => values.contains(&cast_value!(x, Timestamp)),
+            PreparedRhs::Bytes { values, bloom } => {
+                let x = &cast_value!(x, Bytes) as &[u8];
+                match bloom {
+                    Some(bloom) => bloom.may_contain(x) && values.contains(x),
+                    None => values.contains(x),
                 }
-                RhsValues::Bytes(values) => {
-                    let values: IndexSet<Box<[u8]>, FnvBuildHasher> =
-                        values.into_iter().map(Into::into).collect();
+            }
+            PreparedRhs::Constant(result) => *result,
+        }
+    }
 
-                    lhs.compile_with(move |x| values.contains(&cast_value!(x, Bytes) as &[u8]))
-                }
-                RhsValues::Bool(_) => unreachable!(),
+    pub fn execute(&self, ctx: &ExecutionContext<'s>, calls: &CallCache<'_>) -> bool {
+        match self.quantifier {
+            Quantifier::Any => self.any(ctx, calls, false),
+            // Lists that are not set fail the comparison either way.
+            Quantifier::All => match self.rhs {
+                PreparedRhs::List(list) if ctx.get_list_values(list).is_none() => false,
+                _ => !self.any(ctx, calls, true),
+            },
+        }
+    }
+
+    /// Checks whether any value of the left-hand side matches, or doesn't
+    /// match if `negated` is set.
+    fn any(&self, ctx: &ExecutionContext<'s>, calls: &CallCache<'_>, negated: bool) -> bool {
+        match (&self.lhs, &self.rhs) {
+            (_, PreparedRhs::Constant(result)) => *result != negated,
+            (lhs, PreparedRhs::OrderingExpr { op, rhs }) => lhs.any(ctx, |x| {
+                rhs.any(ctx, |y| op.matches_opt(x.strict_partial_cmp(&y))) != negated
+            }),
+            (lhs, PreparedRhs::List(list)) => match ctx.get_list_values(*list) {
+                Some(values) => lhs.any(ctx, |x| values.contains(&x) != negated),
+                None => false,
+            },
+            (LhsFieldExpr::Field(field), PreparedRhs::ScopedIp { op, rhs, zone }) => {
+                // A zone applies to all values of a field.
+                let same_zone = ctx.get_ip_zone(*field) == Some(zone);
+                ctx.any_field_value(*field, |x| {
+                    let equal = same_zone && x.strict_partial_cmp(rhs) == Some(Ordering::Equal);
+                    (equal == (*op == OrderingOp::Equal)) != negated
+                })
+            }
+            (LhsFieldExpr::Field(field), _) => {
+                let records_matches = ctx.records_matches();
+                ctx.any_field_value_with_offset(*field, |x, offset| {
+                    let record = if records_matches {
+                        Some((ctx, *field, offset))
+                    } else {
+                        None
+                    };
+                    self.matches(x, record) != negated
+                })
+            }
+            (LhsFieldExpr::FunctionCallExpr(call), _) => match self.slot {
+                Some(slot) => calls.with_value(slot, |x| self.matches(x, None) != negated),
+                None => match call.execute_isolated(ctx) {
+                    Some(x) => self.matches(x, None) != negated,
+                    None => false,
+                },
             },
         }
     }
@@ -311,15 +2122,21 @@ mod tests {
     use super::*;
     use crate::{
         ast::function_expr::{FunctionCallArgExpr, FunctionCallExpr},
-        execution_context::ExecutionContext,
+        execution_context::{ExecutionContext, SetFromPairsError},
         functions::{
             Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionOptParam, FunctionParam,
         },
-        rhs_types::IpRange,
+        rhs_types::{Float, IpRange, Timestamp, TimestampLiteral},
     };
     use cidr::{Cidr, IpCidr};
     use lazy_static::lazy_static;
-    use std::net::IpAddr;
+    use smallvec::smallvec;
+    use std::{
+        mem,
+        net::IpAddr,
+        sync::{Arc, Mutex},
+        time::SystemTime,
+    };
 
     fn echo_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
         args.next().unwrap()
@@ -366,6 +2183,7 @@ mod tests {
                         opt_params: vec![],
                         return_type: Type::Bytes,
                         implementation: FunctionImpl::new(echo_function),
+                        pure: true,
                     },
                 )
                 .unwrap();
@@ -380,6 +2198,7 @@ mod tests {
                         opt_params: vec![],
                         return_type: Type::Bytes,
                         implementation: FunctionImpl::new(lowercase_function),
+                        pure: true,
                     },
                 )
                 .unwrap();
@@ -397,6 +2216,7 @@ mod tests {
                         }],
                         return_type: Type::Bytes,
                         implementation: FunctionImpl::new(concat_function),
+                        pure: true,
                     },
                 )
                 .unwrap();
@@ -413,6 +2233,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with("ssl", &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::Field(field("ssl")),
                 op: FieldOp::IsTrue
             }
@@ -441,6 +2262,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with("ip.addr <= 10:20:30:40:50:60:70:80", &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::Field(field("ip.addr")),
                 op: FieldOp::Ordering {
                     op: OrderingOp::LessThanEqual,
@@ -493,6 +2315,7 @@ mod tests {
             let expr = assert_ok!(
                 FieldExpr::lex_with("http.host >= 10:20:30:40:50:60:70:80", &SCHEME),
                 FieldExpr {
+                    quantifier: Quantifier::Any,
                     lhs: LhsFieldExpr::Field(field("http.host")),
                     op: FieldOp::Ordering {
                         op: OrderingOp::GreaterThanEqual,
@@ -518,6 +2341,7 @@ mod tests {
             let expr = assert_ok!(
                 FieldExpr::lex_with(r#"http.host < 12"#, &SCHEME),
                 FieldExpr {
+                    quantifier: Quantifier::Any,
                     lhs: LhsFieldExpr::Field(field("http.host")),
                     op: FieldOp::Ordering {
                         op: OrderingOp::LessThan,
@@ -539,6 +2363,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with(r#"http.host == "example.org""#, &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::Field(field("http.host")),
                 op: FieldOp::Ordering {
                     op: OrderingOp::Equal,
@@ -571,6 +2396,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with("tcp.port & 1", &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::Field(field("tcp.port")),
                 op: FieldOp::Int {
                     op: IntOp::BitwiseAnd,
@@ -598,13 +2424,225 @@ mod tests {
         assert_eq!(expr.execute(ctx), true);
     }
 
+    #[test]
+    fn test_bitwise_ordering() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with("tcp.port & 0x12 == 0x12", &SCHEME),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(field("tcp.port")),
+                op: FieldOp::BitwiseOrdering {
+                    bitwise: Bitwise {
+                        op: IntOp::BitwiseAnd,
+                        rhs: 0x12,
+                    },
+                    op: OrderingOp::Equal,
+                    rhs: RhsValue::Int(0x12),
+                }
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "lhs": "tcp.port",
+                "bitwise": {
+                    "op": "BitwiseAnd",
+                    "rhs": 18
+                },
+                "op": "Equal",
+                "rhs": 18
+            }
+        );
+
+        assert_eq!(
+            SCHEME.parse("tcp.port & 0x12 == 0x12").unwrap().to_string(),
+            "tcp.port & 18 == 18"
+        );
+        assert_eq!(
+            ComparisonRef::new(&SCHEME, &expr).bitwise(),
+            Some((ComparisonOperator::BitwiseAnd, 18))
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("tcp.port", 0x13).unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("tcp.port", 0x02).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        // Shifts are not mistaken for `<` and `>`.
+        let expr = assert_ok!(
+            FieldExpr::lex_with("tcp.port >> 4 < 2", &SCHEME),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(field("tcp.port")),
+                op: FieldOp::BitwiseOrdering {
+                    bitwise: Bitwise {
+                        op: IntOp::ShiftRight,
+                        rhs: 4,
+                    },
+                    op: OrderingOp::LessThan,
+                    rhs: RhsValue::Int(2),
+                }
+            }
+        );
+
+        assert_eq!(
+            SCHEME.parse("tcp.port >> 4 < 2").unwrap().to_string(),
+            "tcp.port >> 4 < 2"
+        );
+
+        let expr = expr.compile();
+
+        ctx.set_field_value("tcp.port", 0x1F).unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("tcp.port", 0x20).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        // Other operators without a comparison check for a non-zero result.
+        assert_ok!(
+            FieldExpr::lex_with("tcp.port ^ 80", &SCHEME),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(field("tcp.port")),
+                op: FieldOp::Int {
+                    op: IntOp::BitwiseXor,
+                    rhs: 80,
+                }
+            }
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.port & 1 == x", &SCHEME),
+            LexErrorKind::ExpectedName("digit"),
+            "x"
+        );
+    }
+
+    #[test]
+    fn test_quantifiers() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with("all( tcp.port != 80 )", &SCHEME),
+            FieldExpr {
+                quantifier: Quantifier::All,
+                lhs: LhsFieldExpr::Field(field("tcp.port")),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::NotEqual,
+                    rhs: RhsValue::Int(80),
+                }
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "quantifier": "All",
+                "lhs": "tcp.port",
+                "op": "NotEqual",
+                "rhs": 80
+            }
+        );
+
+        assert_eq!(
+            SCHEME.parse("all( tcp.port != 80 )").unwrap().to_string(),
+            "all(tcp.port != 80)"
+        );
+        assert!(ComparisonRef::new(&SCHEME, &expr).is_all());
+
+        // `any` is the default, so it's not printed.
+        assert_eq!(
+            SCHEME.parse("any(tcp.port != 80)").unwrap().to_string(),
+            "tcp.port != 80"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("all(tcp.port != 80", &SCHEME),
+            LexErrorKind::ExpectedLiteral(")"),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_int_op_apply() {
+        let apply = |op: IntOp, lhs, rhs| op.apply(lhs, rhs, IntOverflow::NoMatch);
+
+        assert_eq!(apply(IntOp::BitwiseAnd, 0b1100, 0b1010), Some(0b1000));
+        assert_eq!(apply(IntOp::BitwiseOr, 0b1100, 0b1010), Some(0b1110));
+        assert_eq!(apply(IntOp::BitwiseXor, 0b1100, 0b1010), Some(0b0110));
+        assert_eq!(apply(IntOp::ShiftLeft, 1, 4), Some(16));
+        assert_eq!(apply(IntOp::ShiftLeft, 1, 40), Some(1 << 40));
+        assert_eq!(apply(IntOp::ShiftLeft, -1, 63), Some(i64::MIN));
+        assert_eq!(apply(IntOp::ShiftLeft, 0, 70), Some(0));
+        assert_eq!(apply(IntOp::ShiftRight, 16, 4), Some(1));
+        assert_eq!(apply(IntOp::ShiftRight, -16, 70), Some(-1));
+        assert_eq!(apply(IntOp::ShiftRight, 16, 70), Some(0));
+
+        for &(lhs, rhs, wrapping, saturating) in &[
+            (1, 64, 0, i64::MAX),
+            (1, -1, 0, i64::MAX),
+            (1, 63, i64::MIN, i64::MAX),
+            (0x4000_0000_0000_0001, 2, 4, i64::MAX),
+            (-0x4000_0000_0000_0001, 2, -4, i64::MIN),
+        ] {
+            let apply = |overflow| IntOp::ShiftLeft.apply(lhs, rhs, overflow);
+            assert_eq!(apply(IntOverflow::Wrapping), Some(wrapping));
+            assert_eq!(apply(IntOverflow::Saturating), Some(saturating));
+            assert_eq!(apply(IntOverflow::NoMatch), None);
+        }
+    }
+
+    #[test]
+    fn test_int_overflow() {
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+        ctx.set_field_value("tcp.port", 0x4000_0000_0000_0001_i64)
+            .unwrap();
+
+        for &(overflow, ordering, int) in &[
+            (IntOverflow::Wrapping, true, true),
+            (IntOverflow::Saturating, false, true),
+            (IntOverflow::NoMatch, false, false),
+        ] {
+            let settings = ParserSettings {
+                int_overflow: overflow,
+                ..Default::default()
+            };
+
+            for &(source, expected) in &[("tcp.port << 2 < 1000", ordering), ("tcp.port << 2", int)]
+            {
+                let expr = FieldExpr::lex_with(source, &SCHEME).unwrap().0;
+
+                let prepared =
+                    PreparedComparison::new(expr.clone(), &settings, &CachedCalls::default());
+                let calls = CallCache::empty(ctx);
+                assert_eq!(
+                    prepared.execute(ctx, &calls),
+                    expected,
+                    "{:?} {}",
+                    overflow,
+                    source
+                );
+
+                let compiled = expr.compile_with_settings(&settings, &CachedCalls::default());
+                assert_eq!(compiled.execute(ctx), expected, "{:?} {}", overflow, source);
+            }
+        }
+
+        // Overflows don't match by default.
+        assert_eq!(ParserSettings::default().int_overflow, IntOverflow::NoMatch);
+    }
+
     #[test]
     fn test_int_in() {
         let expr = assert_ok!(
             FieldExpr::lex_with(r#"tcp.port in { 80 443 2082..2083 }"#, &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::Field(field("tcp.port")),
-                op: FieldOp::OneOf(RhsValues::Int(vec![80..=80, 443..=443, 2082..=2083])),
+                op: FieldOp::OneOf(RhsValues::Int(smallvec![80..=80, 443..=443, 2082..=2083])),
             }
         );
 
@@ -644,6 +2682,43 @@ mod tests {
 
         ctx.set_field_value("tcp.port", 2084).unwrap();
         assert_eq!(expr.execute(ctx), false);
+
+        // Lists are normalized only when compiled, so the AST still
+        // reflects the original order and duplicates.
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"tcp.port in { 100000 -1 80 80 }"#, &SCHEME),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(field("tcp.port")),
+                op: FieldOp::OneOf(RhsValues::Int(smallvec![
+                    100_000..=100_000,
+                    -1..=-1,
+                    80..=80,
+                    80..=80
+                ])),
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "lhs": "tcp.port",
+                "op": "OneOf",
+                "rhs": [
+                    { "start": 100_000, "end": 100_000 },
+                    { "start": -1, "end": -1 },
+                    { "start": 80, "end": 80 },
+                    { "start": 80, "end": 80 },
+                ]
+            }
+        );
+
+        let expr = expr.compile();
+
+        for &(value, expected) in &[(-1, true), (0, false), (80, true), (100_000, true)] {
+            ctx.set_field_value("tcp.port", value).unwrap();
+            assert_eq!(expr.execute(ctx), expected);
+        }
     }
 
     #[test]
@@ -651,6 +2726,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with(r#"http.host in { "example.org" "example.com" }"#, &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::Field(field("http.host")),
                 op: FieldOp::OneOf(RhsValues::Bytes(
                     ["example.org", "example.com",]
@@ -673,17 +2749,35 @@ mod tests {
             }
         );
 
+        let with_bloom_filter = expr.clone().compile_with_settings(
+            &ParserSettings {
+                bloom_filter_threshold: Some(2),
+                ..Default::default()
+            },
+            &CachedCalls::default(),
+        );
+        // A rate of `0` would need an infinitely large bloom filter.
+        let with_exact_bloom_filter = expr.clone().compile_with_settings(
+            &ParserSettings {
+                bloom_filter_threshold: Some(2),
+                bloom_filter_false_positive_rate: 0.0,
+                ..Default::default()
+            },
+            &CachedCalls::default(),
+        );
         let expr = expr.compile();
         let ctx = &mut ExecutionContext::new(&SCHEME);
 
-        ctx.set_field_value("http.host", "example.com").unwrap();
-        assert_eq!(expr.execute(ctx), true);
+        for expr in &[expr, with_bloom_filter, with_exact_bloom_filter] {
+            ctx.set_field_value("http.host", "example.com").unwrap();
+            assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value("http.host", "example.org").unwrap();
-        assert_eq!(expr.execute(ctx), true);
+            ctx.set_field_value("http.host", "example.org").unwrap();
+            assert_eq!(expr.execute(ctx), true);
 
-        ctx.set_field_value("http.host", "example.net").unwrap();
-        assert_eq!(expr.execute(ctx), false);
+            ctx.set_field_value("http.host", "example.net").unwrap();
+            assert_eq!(expr.execute(ctx), false);
+        }
     }
 
     #[test]
@@ -694,8 +2788,9 @@ mod tests {
                 &SCHEME
             ),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::Field(field("ip.addr")),
-                op: FieldOp::OneOf(RhsValues::Ip(vec![
+                op: FieldOp::OneOf(RhsValues::Ip(smallvec![
                     IpRange::Cidr(IpCidr::new([127, 0, 0, 0].into(), 8).unwrap()),
                     IpRange::Cidr(IpCidr::new_host([0, 0, 0, 0, 0, 0, 0, 1].into())),
                     IpRange::Explicit(ExplicitIpRange::V4(
@@ -747,6 +2842,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with(r#"http.host contains "abc""#, &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::Field(field("http.host")),
                 op: FieldOp::Contains("abc".to_owned().into())
             }
@@ -776,6 +2872,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with(r#"http.host contains 6F:72:67"#, &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::Field(field("http.host")),
                 op: FieldOp::Contains(vec![0x6F, 0x72, 0x67].into()),
             }
@@ -790,14 +2887,135 @@ mod tests {
             }
         );
 
-        let expr = expr.compile();
-        let ctx = &mut ExecutionContext::new(&SCHEME);
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        ctx.set_field_value("http.host", "example.com").unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("http.host", "example.org").unwrap();
+        assert_eq!(expr.execute(ctx), true);
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"http.host wildcard "*.example.?rg""#, &SCHEME),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(field("http.host")),
+                op: FieldOp::Wildcard(Bytes::from("*.example.?rg".to_owned()).into()),
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "lhs": "http.host",
+                "op": "Wildcard",
+                "rhs": "*.example.?rg",
+            }
+        );
+
+        assert_eq!(
+            FieldExpr::lex_with(r#"http.host like "*.example.?rg""#, &SCHEME),
+            Ok((expr.clone(), ""))
+        );
+
+        assert_err!(
+            FieldExpr::lex_with(r#"tcp.port like "8*""#, &SCHEME),
+            LexErrorKind::UnsupportedOp {
+                lhs_type: Type::Int
+            },
+            "tcp.port like"
+        );
+
+        let ast = SCHEME.parse(r#"http.host like "*.example.?rg""#).unwrap();
+        assert_eq!(ast.to_string(), r#"http.host wildcard "*.example.?rg""#);
+        assert_eq!(
+            SCHEME
+                .parse_from_ast(serde_json::to_value(&ast).unwrap())
+                .unwrap(),
+            ast
+        );
+        let bytecode = ast.compile_to_bytecode();
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+
+        for (host, matches) in &[
+            ("www.example.org", true),
+            ("a.b.example.org", true),
+            ("example.org", false),
+            ("www.example.org.au", false),
+        ] {
+            ctx.set_field_value("http.host", *host).unwrap();
+            assert_eq!(expr.execute(ctx), *matches, "{}", host);
+            assert_eq!(bytecode.execute(ctx), Ok(*matches), "{}", host);
+        }
+    }
+
+    #[test]
+    fn test_in_domain() {
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"http.host in_domain "example.org""#, &SCHEME),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(field("http.host")),
+                op: FieldOp::InDomain(Bytes::from("example.org".to_owned()).into()),
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "lhs": "http.host",
+                "op": "InDomain",
+                "rhs": "example.org",
+            }
+        );
+
+        assert_err!(
+            FieldExpr::lex_with(r#"tcp.port in_domain "example.org""#, &SCHEME),
+            LexErrorKind::UnsupportedOp {
+                lhs_type: Type::Int
+            },
+            "tcp.port in_domain"
+        );
 
-        ctx.set_field_value("http.host", "example.com").unwrap();
-        assert_eq!(expr.execute(ctx), false);
+        let ast = SCHEME
+            .parse(r#"http.host in_domain "example.org""#)
+            .unwrap();
+        assert_eq!(ast.to_string(), r#"http.host in_domain "example.org""#);
+        assert_eq!(
+            SCHEME
+                .parse_from_ast(serde_json::to_value(&ast).unwrap())
+                .unwrap(),
+            ast
+        );
+        let bytecode = ast.compile_to_bytecode();
 
-        ctx.set_field_value("http.host", "example.org").unwrap();
-        assert_eq!(expr.execute(ctx), true);
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&SCHEME);
+        ctx.set_record_matches(true);
+
+        for (host, span) in &[
+            ("example.org", Some(0..11)),
+            ("WWW.Example.Org.", Some(4..15)),
+            ("a.b.example.org", Some(4..15)),
+            ("badexample.org", None),
+            ("example.org.au", None),
+        ] {
+            ctx.set_field_value("http.host", *host).unwrap();
+            assert_eq!(expr.execute(ctx), span.is_some(), "{}", host);
+            assert_eq!(bytecode.execute(ctx), Ok(span.is_some()), "{}", host);
+            let spans: Vec<_> = ctx
+                .matched_spans()
+                .into_iter()
+                .map(|span| span.range)
+                .collect();
+            assert_eq!(spans, span.iter().cloned().collect::<Vec<_>>(), "{}", host);
+        }
     }
 
     #[test]
@@ -805,6 +3023,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with(r#"tcp.port < 8000"#, &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::Field(field("tcp.port")),
                 op: FieldOp::Ordering {
                     op: OrderingOp::LessThan,
@@ -837,6 +3056,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with(r#"echo(http.host) == "example.org""#, &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
                     name: String::from("echo"),
                     function: SCHEME.get_function("echo").unwrap(),
@@ -883,6 +3103,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with(r#"lowercase(http.host) == "example.org""#, &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
                     name: String::from("lowercase"),
                     function: SCHEME.get_function("lowercase").unwrap(),
@@ -929,6 +3150,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with(r#"concat(http.host) == "example.org""#, &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
                     name: String::from("concat"),
                     function: SCHEME.get_function("concat").unwrap(),
@@ -972,6 +3194,7 @@ mod tests {
         let expr = assert_ok!(
             FieldExpr::lex_with(r#"concat(http.host, ".org") == "example.org""#, &SCHEME),
             FieldExpr {
+                quantifier: Quantifier::Any,
                 lhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
                     name: String::from("concat"),
                     function: SCHEME.get_function("concat").unwrap(),
@@ -1019,4 +3242,595 @@ mod tests {
         ctx.set_field_value("http.host", "cloudflare").unwrap();
         assert_eq!(expr.execute(ctx), false);
     }
+
+    #[test]
+    fn test_isolate_function_panics() {
+        fn panic_function<'a>(_: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+            panic!("function panicked");
+        }
+
+        let mut scheme = Scheme! { http.host: Bytes };
+
+        scheme
+            .add_function(
+                "panic".into(),
+                Function {
+                    params: vec![FunctionParam {
+                        arg_kind: FunctionArgKind::Field,
+                        val_type: Type::Bytes,
+                    }],
+                    opt_params: vec![],
+                    return_type: Type::Bytes,
+                    implementation: FunctionImpl::new(panic_function),
+                    pure: false,
+                },
+            )
+            .unwrap();
+        scheme
+            .add_function(
+                "pure_panic".into(),
+                Function {
+                    params: vec![FunctionParam {
+                        arg_kind: FunctionArgKind::Literal,
+                        val_type: Type::Bytes,
+                    }],
+                    opt_params: vec![],
+                    return_type: Type::Bytes,
+                    implementation: FunctionImpl::new(panic_function),
+                    pure: true,
+                },
+            )
+            .unwrap();
+
+        let panics = Arc::new(Mutex::new(Vec::new()));
+        let take_panics = || mem::take(&mut *panics.lock().unwrap());
+
+        scheme.set_isolate_function_panics(true);
+        scheme.set_function_panic_hook({
+            let panics = Arc::clone(&panics);
+            move |function, message| {
+                panics
+                    .lock()
+                    .unwrap()
+                    .push((function.to_owned(), message.to_owned()))
+            }
+        });
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"panic(http.host) != "example.org""#, &scheme),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
+                    name: String::from("panic"),
+                    function: scheme.get_function("panic").unwrap(),
+                    args: vec![FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(
+                        scheme.get_field_index("http.host").unwrap()
+                    ))],
+                }),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::NotEqual,
+                    rhs: RhsValue::Bytes("example.org".to_owned().into())
+                }
+            }
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&scheme);
+
+        ctx.set_field_value("http.host", "example.com").unwrap();
+        assert_eq!(expr.execute(ctx), false);
+        let panic = |function: &str| (function.to_owned(), "function panicked".to_owned());
+        assert_eq!(take_panics(), [panic("panic")]);
+
+        // Pure calls with constant arguments that panic aren't folded, but
+        // are isolated on execution.
+        let ast = scheme
+            .parse(r#"pure_panic("a") != "b" or http.host == "example.com""#)
+            .unwrap();
+        // Only panics on execution are reported, none when compiling.
+        let filter = ast.clone().compile();
+        let bytecode = ast.compile_to_bytecode();
+        assert_eq!(take_panics(), []);
+        assert_eq!(filter.execute(ctx), Ok(true));
+        assert_eq!(take_panics(), [panic("pure_panic")]);
+        assert_eq!(bytecode.execute(ctx), Ok(true));
+        assert_eq!(take_panics(), [panic("pure_panic")]);
+
+        let ast = scheme
+            .parse(r#"pure_panic("a") != "b" or panic(http.host) == "a""#)
+            .unwrap();
+        assert_eq!(ast.clone().compile().execute(ctx), Ok(false));
+        assert_eq!(take_panics(), [panic("pure_panic"), panic("panic")]);
+        assert_eq!(ast.compile_to_bytecode().execute(ctx), Ok(false));
+        assert_eq!(take_panics(), [panic("pure_panic"), panic("panic")]);
+    }
+
+    #[test]
+    fn test_ignore_missing_fields() {
+        fn echo_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+            args.next().unwrap()
+        }
+
+        let mut scheme = Scheme! {
+            http.host: Bytes,
+            http.referer: Bytes,
+            tcp.port: Int,
+        };
+
+        scheme
+            .add_function(
+                "echo".into(),
+                Function {
+                    params: vec![FunctionParam {
+                        arg_kind: FunctionArgKind::Field,
+                        val_type: Type::Bytes,
+                    }],
+                    opt_params: vec![],
+                    return_type: Type::Bytes,
+                    implementation: FunctionImpl::new(echo_function),
+                    pure: false,
+                },
+            )
+            .unwrap();
+
+        scheme.set_ignore_missing_fields(true);
+
+        let ctx = &mut ExecutionContext::new(&scheme);
+        ctx.set_field_value("http.host", "example.org").unwrap();
+
+        let execute = |filter: &str| {
+            scheme
+                .parse(filter)
+                .unwrap()
+                .compile()
+                .execute(ctx)
+                .unwrap()
+        };
+
+        assert_eq!(execute(r#"http.referer == "example.org""#), false);
+        assert_eq!(execute(r#"http.referer != "example.org""#), false);
+        assert_eq!(execute(r#"http.referer contains "example""#), false);
+        assert_eq!(execute(r#"http.referer matches "^example""#), false);
+        assert_eq!(execute(r#"echo(http.referer) == "example.org""#), false);
+        assert_eq!(execute("http.host == http.referer"), false);
+        assert_eq!(execute("tcp.port > 0"), false);
+        assert_eq!(
+            execute(r#"http.referer contains "a" or http.referer matches "^b""#),
+            false
+        );
+        assert_eq!(execute(r#"not http.referer == "example.org""#), true);
+        assert_eq!(
+            execute(r#"http.referer == "example.org" or http.host == "example.org""#),
+            true
+        );
+    }
+
+    #[test]
+    fn test_float() {
+        let scheme = Scheme! { bot.score: Float };
+        let float = |value| RhsValue::Float(Float::new(value).unwrap());
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with("bot.score >= 2.5e-1", &scheme),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(scheme.get_field_index("bot.score").unwrap()),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::GreaterThanEqual,
+                    rhs: float(0.25),
+                }
+            }
+        );
+        assert_eq!(
+            scheme.parse("bot.score >= 2.5e-1").unwrap().to_string(),
+            "bot.score >= 0.25"
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&scheme);
+
+        ctx.set_field_value("bot.score", Float::new(0.25).unwrap())
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("bot.score", Float::new(-0.0).unwrap())
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with("bot.score in { 0.1..0.5 1 }", &scheme),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(scheme.get_field_index("bot.score").unwrap()),
+                op: FieldOp::OneOf(RhsValues::Float(smallvec![
+                    Float::new(0.1).unwrap()..=Float::new(0.5).unwrap(),
+                    Float::new(1.0).unwrap()..=Float::new(1.0).unwrap(),
+                ])),
+            }
+        );
+        assert_eq!(
+            scheme
+                .parse("bot.score in {0.1..0.5, 1.0}")
+                .unwrap()
+                .to_string(),
+            "bot.score in { 0.1..0.5 1 }"
+        );
+
+        let expr = expr.compile();
+
+        for &(value, matches) in &[(0.1, true), (0.3, true), (0.75, false), (1.0, true)] {
+            ctx.set_field_value("bot.score", Float::new(value).unwrap())
+                .unwrap();
+            assert_eq!(expr.execute(ctx), matches, "{}", value);
+        }
+
+        assert_eq!(
+            scheme.parse("0.1 < bot.score <= 0.5").unwrap().to_string(),
+            "(bot.score > 0.1 && bot.score <= 0.5)"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("bot.score & 1", &scheme),
+            LexErrorKind::UnsupportedOp {
+                lhs_type: Type::Float
+            },
+            "bot.score &"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("bot.score == 1e999", &scheme),
+            LexErrorKind::FloatOutOfRange,
+            "1e999"
+        );
+
+        assert_eq!(
+            ctx.set_from_pairs(vec![("bot.score", "NaN")]),
+            Err(SetFromPairsError::InvalidValue {
+                name: "bot.score".to_owned(),
+                ty: Type::Float,
+            })
+        );
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let scheme = Scheme! { http.request.timestamp: Timestamp };
+        let timestamp = |s| Timestamp::parse(s).unwrap();
+        let hour = 3600 * 1_000_000_000;
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with(
+                r#"http.request.timestamp > "2023-06-01T02:00:00+02:00""#,
+                &scheme
+            ),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(scheme.get_field_index("http.request.timestamp").unwrap()),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::GreaterThan,
+                    rhs: RhsValue::Timestamp(TimestampLiteral::Absolute(timestamp(
+                        "2023-06-01T00:00:00Z"
+                    ))),
+                }
+            }
+        );
+        assert_eq!(
+            scheme
+                .parse(r#"http.request.timestamp > "2023-06-01T02:00:00+02:00""#)
+                .unwrap()
+                .to_string(),
+            r#"http.request.timestamp > "2023-06-01T00:00:00Z""#
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&scheme);
+
+        ctx.set_field_value("http.request.timestamp", SystemTime::now())
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value(
+            "http.request.timestamp",
+            timestamp("2023-05-31T23:59:59.999Z"),
+        )
+        .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with("http.request.timestamp >= now() - 1h", &scheme),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(scheme.get_field_index("http.request.timestamp").unwrap()),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::GreaterThanEqual,
+                    rhs: RhsValue::Timestamp(TimestampLiteral::Relative(-hour)),
+                }
+            }
+        );
+        assert_eq!(
+            scheme
+                .parse("http.request.timestamp >= now()-60m")
+                .unwrap()
+                .to_string(),
+            "http.request.timestamp >= now() - 1h"
+        );
+
+        // Relative timestamps are resolved on every execution, even when
+        // the field is known upfront.
+        let recent = Timestamp::from_nanos(Timestamp::now().as_nanos() - hour / 2);
+        ctx.set_field_value("http.request.timestamp", recent)
+            .unwrap();
+        assert!(matches!(
+            expr.clone().fold_constants(Some(ctx)),
+            Folded::Expr(_)
+        ));
+        assert_eq!(expr.compile().execute(ctx), true);
+
+        let old = Timestamp::from_nanos(Timestamp::now().as_nanos() - 2 * hour);
+        ctx.set_field_value("http.request.timestamp", old).unwrap();
+        assert_eq!(
+            scheme
+                .parse("http.request.timestamp >= now() - 1h")
+                .unwrap()
+                .compile()
+                .execute(ctx),
+            Ok(false)
+        );
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with(
+                r#"http.request.timestamp in { "2023-01-01T00:00:00Z".."2023-01-31T23:59:59Z" "2023-06-01T00:00:00Z" }"#,
+                &scheme
+            ),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(scheme.get_field_index("http.request.timestamp").unwrap()),
+                op: FieldOp::OneOf(RhsValues::Timestamp(smallvec![
+                    timestamp("2023-01-01T00:00:00Z")..=timestamp("2023-01-31T23:59:59Z"),
+                    timestamp("2023-06-01T00:00:00Z")..=timestamp("2023-06-01T00:00:00Z"),
+                ])),
+            }
+        );
+
+        let expr = expr.compile();
+
+        for &(value, matches) in &[
+            ("2023-01-15T12:00:00Z", true),
+            ("2023-02-01T00:00:00Z", false),
+            ("2023-06-01T00:00:00Z", true),
+            ("2023-06-01T00:00:00.5Z", false),
+        ] {
+            ctx.set_field_value("http.request.timestamp", timestamp(value))
+                .unwrap();
+            assert_eq!(expr.execute(ctx), matches, "{}", value);
+        }
+
+        assert_eq!(
+            scheme
+                .parse(r#""2023-01-01T00:00:00Z" <= http.request.timestamp < now()"#)
+                .unwrap()
+                .to_string(),
+            r#"(http.request.timestamp >= "2023-01-01T00:00:00Z" && http.request.timestamp < now())"#
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("http.request.timestamp == \"2023-06-01\"", &scheme),
+            LexErrorKind::InvalidTimestamp,
+            "2023-06-01"
+        );
+
+        assert_err!(
+            FieldExpr::lex_with("http.request.timestamp in { now() }", &scheme),
+            LexErrorKind::InvalidListElement {
+                position: 1,
+                kind: Box::new(LexErrorKind::ExpectedLiteral("\"")),
+            },
+            "now() }"
+        );
+
+        assert_eq!(
+            ctx.set_from_pairs(vec![("http.request.timestamp", "2023-06-01T00:00:00Z")]),
+            Ok(())
+        );
+        assert_eq!(
+            ctx.set_from_pairs(vec![("http.request.timestamp", "yesterday")]),
+            Err(SetFromPairsError::InvalidValue {
+                name: "http.request.timestamp".to_owned(),
+                ty: Type::Timestamp,
+            })
+        );
+    }
+
+    #[test]
+    fn test_pure_function_folding() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+        fn counting_lowercase_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+            CALLS.fetch_add(1, Ordering::SeqCst);
+            lowercase_function(args)
+        }
+
+        let mut scheme = Scheme! { http.host: Bytes };
+
+        scheme
+            .add_function(
+                "lower".into(),
+                Function {
+                    params: vec![FunctionParam {
+                        arg_kind: FunctionArgKind::Literal,
+                        val_type: Type::Bytes,
+                    }],
+                    opt_params: vec![],
+                    return_type: Type::Bytes,
+                    implementation: FunctionImpl::new(counting_lowercase_function),
+                    pure: true,
+                },
+            )
+            .unwrap();
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with(r#"lower("FOO") == "foo""#, &scheme),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
+                    name: String::from("lower"),
+                    function: scheme.get_function("lower").unwrap(),
+                    args: vec![FunctionCallArgExpr::Literal(RhsValue::Bytes(
+                        "FOO".to_owned().into()
+                    ))],
+                }),
+                op: FieldOp::Ordering {
+                    op: OrderingOp::Equal,
+                    rhs: RhsValue::Bytes("foo".to_owned().into())
+                }
+            }
+        );
+
+        let expr = expr.compile();
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+        let ctx = &mut ExecutionContext::new(&scheme);
+        ctx.set_field_value("http.host", "example.org").unwrap();
+
+        assert_eq!(expr.execute(ctx), true);
+        assert_eq!(expr.execute(ctx), true);
+        assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_compare_with_rhs_expr() {
+        fn join_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+            let mut joined = Vec::new();
+            for arg in args {
+                match arg {
+                    LhsValue::Bytes(bytes) => joined.extend_from_slice(&bytes),
+                    arg => panic!("Invalid type: expected Bytes, got {:?}", arg),
+                }
+            }
+            LhsValue::Bytes(joined.into())
+        }
+
+        let mut scheme = Scheme! {
+            http.host: Bytes,
+            http.referer: Bytes,
+            tcp.srcport: Int,
+            tcp.dstport: Int,
+        };
+
+        scheme
+            .add_function(
+                "concat".into(),
+                Function {
+                    params: vec![
+                        FunctionParam {
+                            arg_kind: FunctionArgKind::Literal,
+                            val_type: Type::Bytes,
+                        },
+                        FunctionParam {
+                            arg_kind: FunctionArgKind::Field,
+                            val_type: Type::Bytes,
+                        },
+                        FunctionParam {
+                            arg_kind: FunctionArgKind::Literal,
+                            val_type: Type::Bytes,
+                        },
+                    ],
+                    opt_params: vec![],
+                    return_type: Type::Bytes,
+                    implementation: FunctionImpl::new(join_function),
+                    pure: true,
+                },
+            )
+            .unwrap();
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with(
+                r#"http.referer == concat("https://", http.host, "/")"#,
+                &scheme
+            ),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(scheme.get_field_index("http.referer").unwrap()),
+                op: FieldOp::OrderingExpr {
+                    op: OrderingOp::Equal,
+                    rhs: LhsFieldExpr::FunctionCallExpr(FunctionCallExpr {
+                        name: String::from("concat"),
+                        function: scheme.get_function("concat").unwrap(),
+                        args: vec![
+                            FunctionCallArgExpr::Literal(RhsValue::Bytes(
+                                "https://".to_owned().into()
+                            )),
+                            FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(
+                                scheme.get_field_index("http.host").unwrap()
+                            )),
+                            FunctionCallArgExpr::Literal(RhsValue::Bytes("/".to_owned().into())),
+                        ],
+                    }),
+                }
+            }
+        );
+
+        assert!(expr.uses(scheme.get_field_index("http.host").unwrap()));
+
+        let ast = scheme
+            .parse(r#"http.referer == concat("https://", http.host, "/")"#)
+            .unwrap();
+        assert_eq!(
+            ast.to_string(),
+            r#"http.referer == concat("https://", http.host, "/")"#
+        );
+
+        let expr = expr.compile();
+        let ctx = &mut ExecutionContext::new(&scheme);
+
+        ctx.set_field_value("http.host", "example.org").unwrap();
+        ctx.set_field_value("http.referer", "https://example.org/")
+            .unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        ctx.set_field_value("http.referer", "https://example.com/")
+            .unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        let expr = assert_ok!(
+            FieldExpr::lex_with("tcp.srcport < tcp.dstport", &scheme),
+            FieldExpr {
+                quantifier: Quantifier::Any,
+                lhs: LhsFieldExpr::Field(scheme.get_field_index("tcp.srcport").unwrap()),
+                op: FieldOp::OrderingExpr {
+                    op: OrderingOp::LessThan,
+                    rhs: LhsFieldExpr::Field(scheme.get_field_index("tcp.dstport").unwrap()),
+                }
+            }
+        );
+
+        assert_json!(
+            expr,
+            {
+                "lhs": "tcp.srcport",
+                "op": "LessThan",
+                "rhs_expr": "tcp.dstport"
+            }
+        );
+
+        let expr = expr.compile();
+
+        ctx.set_field_value("tcp.srcport", 1024).unwrap();
+        ctx.set_field_value("tcp.dstport", 443).unwrap();
+        assert_eq!(expr.execute(ctx), false);
+
+        ctx.set_field_value("tcp.dstport", 8080).unwrap();
+        assert_eq!(expr.execute(ctx), true);
+
+        assert_err!(
+            FieldExpr::lex_with("tcp.srcport == http.host", &scheme),
+            LexErrorKind::InvalidRhsType(TypeMismatchError {
+                expected: Type::Int,
+                actual: Type::Bytes,
+            }),
+            "http.host"
+        );
+    }
 }
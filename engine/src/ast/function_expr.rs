@@ -1,5 +1,9 @@
-use super::field_expr::LhsFieldExpr;
+use super::{
+    field_expr::{fmt_rhs_value, LhsFieldExpr},
+    BindField,
+};
 use crate::{
+    anonymizer::Anonymizer,
     execution_context::ExecutionContext,
     functions::{Function, FunctionArgKind, FunctionParam},
     lex::{expect, skip_space, span, take, take_while, LexError, LexErrorKind, LexResult, LexWith},
@@ -7,6 +11,12 @@ use crate::{
     types::{GetType, LhsValue, RhsValue, TypeMismatchError},
 };
 use serde::Serialize;
+use std::{
+    any::Any,
+    cell::OnceCell,
+    fmt::{self, Formatter},
+    panic::{self, AssertUnwindSafe},
+};
 
 #[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 #[serde(tag = "kind", content = "value")]
@@ -32,6 +42,46 @@ impl<'s> FunctionCallArgExpr<'s> {
             FunctionCallArgExpr::Literal(literal) => literal.into(),
         }
     }
+
+    pub fn is_constant(&self) -> bool {
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(_)) => false,
+            FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::FunctionCallExpr(call)) => {
+                call.is_constant()
+            }
+            FunctionCallArgExpr::Literal(_) => true,
+        }
+    }
+
+    pub fn execute_constant(&self) -> LhsValue<'_> {
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::FunctionCallExpr(call)) => {
+                call.evaluate_constant()
+            }
+            FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(_)) => unreachable!(),
+            FunctionCallArgExpr::Literal(literal) => literal.into(),
+        }
+    }
+
+    pub fn fmt_filter(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => lhs.fmt_filter(f),
+            FunctionCallArgExpr::Literal(literal) => fmt_rhs_value(f, literal),
+        }
+    }
+
+    pub fn rebind<'p>(
+        &self,
+        scheme: &'p Scheme,
+        bind_field: BindField<'_, 's, 'p>,
+    ) -> Option<FunctionCallArgExpr<'p>> {
+        Some(match self {
+            FunctionCallArgExpr::LhsFieldExpr(lhs) => {
+                FunctionCallArgExpr::LhsFieldExpr(lhs.rebind(scheme, bind_field)?)
+            }
+            FunctionCallArgExpr::Literal(literal) => FunctionCallArgExpr::Literal(literal.clone()),
+        })
+    }
 }
 
 struct SchemeFunctionParam<'s, 'a> {
@@ -91,15 +141,241 @@ impl<'s> FunctionCallExpr<'s> {
         self.args.iter().any(|arg| arg.uses(field))
     }
 
-    pub fn execute(&self, ctx: &'s ExecutionContext<'s>) -> LhsValue<'_> {
+    pub fn fmt_filter(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}(", self.name)?;
+        for (i, arg) in self.args.iter().enumerate() {
+            if i != 0 {
+                f.write_str(", ")?;
+            }
+            arg.fmt_filter(f)?;
+        }
+        f.write_str(")")
+    }
+
+    pub fn anonymize(&mut self, anonymizer: &mut Anonymizer) {
+        for arg in &mut self.args {
+            match arg {
+                FunctionCallArgExpr::LhsFieldExpr(lhs) => lhs.anonymize(anonymizer),
+                FunctionCallArgExpr::Literal(value) => *value = anonymizer.rhs_value(value),
+            }
+        }
+    }
+
+    pub fn rebind<'p>(
+        &self,
+        scheme: &'p Scheme,
+        bind_field: BindField<'_, 's, 'p>,
+    ) -> Option<FunctionCallExpr<'p>> {
+        let function = scheme
+            .get_function(&self.name)
+            .ok()
+            .filter(|function| *function == self.function)?;
+
+        Some(FunctionCallExpr {
+            name: self.name.clone(),
+            function,
+            args: self
+                .args
+                .iter()
+                .map(|arg| arg.rebind(scheme, bind_field))
+                .collect::<Option<_>>()?,
+        })
+    }
+
+    /// Checks whether this is a call to a pure function with arguments known
+    /// at compile time, so that it can be evaluated only once.
+    pub fn is_constant(&self) -> bool {
+        self.function.pure && self.args.iter().all(FunctionCallArgExpr::is_constant)
+    }
+
+    /// Evaluates a call for which [`is_constant`](Self::is_constant) holds,
+    /// returning `None` if the function panicked.
+    ///
+    /// Panics are always caught here, since constant calls are evaluated
+    /// while compiling a filter: such calls aren't folded and are left for
+    /// execution, where the scheme decides whether to
+    /// [isolate](::Scheme::set_isolate_function_panics) them.
+    pub fn execute_constant(&self) -> Option<LhsValue<'_>> {
+        panic::catch_unwind(AssertUnwindSafe(|| self.evaluate_constant())).ok()
+    }
+
+    fn evaluate_constant(&self) -> LhsValue<'_> {
         self.function.implementation.execute(
+            self.args
+                .iter()
+                .map(FunctionCallArgExpr::execute_constant)
+                .chain(
+                    self.function.opt_params[self.args.len() - self.function.params.len()..]
+                        .iter()
+                        .map(|opt_arg| opt_arg.default_value.as_ref()),
+                ),
+        )
+    }
+
+    pub fn execute(&self, ctx: &'s ExecutionContext<'s>) -> LhsValue<'_> {
+        self.function.implementation.execute_with_deadline(
             self.args.iter().map(|arg| arg.execute(ctx)).chain(
                 self.function.opt_params[self.args.len() - self.function.params.len()..]
                     .iter()
                     .map(|opt_arg| opt_arg.default_value.as_ref()),
             ),
+            ctx.deadline(),
         )
     }
+
+    /// Estimates the cost of a call in the units of
+    /// [`FilterAst::cost`](::FilterAst::cost), including its arguments.
+    pub fn cost(&self) -> u64 {
+        if self.is_constant() {
+            // Folded right away during compilation.
+            return 0;
+        }
+
+        10 + self
+            .args
+            .iter()
+            .map(|arg| match arg {
+                FunctionCallArgExpr::LhsFieldExpr(lhs) => lhs.cost(),
+                FunctionCallArgExpr::Literal(_) => 0,
+            })
+            .sum::<u64>()
+    }
+
+    fn has_arg_values(&self, ctx: &ExecutionContext<'s>) -> bool {
+        self.args.iter().all(|arg| match arg {
+            FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::Field(field)) => {
+                ctx.has_field_value(*field)
+            }
+            FunctionCallArgExpr::LhsFieldExpr(LhsFieldExpr::FunctionCallExpr(call)) => {
+                call.has_arg_values(ctx)
+            }
+            FunctionCallArgExpr::Literal(_) => true,
+        })
+    }
+
+    /// Executes a call, returning `None` if the function panicked and the
+    /// scheme [isolates panics](::Scheme::set_isolate_function_panics), or
+    /// if an argument is missing and the scheme
+    /// [ignores missing fields](::Scheme::set_ignore_missing_fields).
+    pub fn execute_isolated(&self, ctx: &'s ExecutionContext<'s>) -> Option<LhsValue<'_>> {
+        if ctx.scheme().ignores_missing_fields() && !self.has_arg_values(ctx) {
+            None
+        } else if ctx.scheme().isolates_function_panics() {
+            match panic::catch_unwind(AssertUnwindSafe(|| self.execute(ctx))) {
+                Ok(value) => Some(value),
+                Err(payload) => {
+                    if let Some(hook) = ctx.scheme().get_function_panic_hook() {
+                        hook(&self.name, panic_message(&*payload));
+                    }
+                    None
+                }
+            }
+        } else {
+            Some(self.execute(ctx))
+        }
+    }
+}
+
+// Extracts a message passed to `panic!`, if any.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload.downcast_ref::<String>().map_or("", String::as_str),
+    }
+}
+
+/// Calls to pure functions that appear more than once on the left-hand side
+/// of comparisons in a filter.
+///
+/// Each of them gets a slot in a [`CallCache`] of every execution, so that
+/// the work is done only once per execution.
+#[derive(Default)]
+pub(crate) struct CachedCalls<'s>(Vec<FunctionCallExpr<'s>>);
+
+impl<'s> CachedCalls<'s> {
+    pub fn new<'a>(calls: impl IntoIterator<Item = &'a FunctionCallExpr<'s>>) -> Self
+    where
+        's: 'a,
+    {
+        let mut seen = Vec::new();
+        let mut repeated = Vec::new();
+
+        for call in calls {
+            if !call.function.pure || call.is_constant() {
+                continue;
+            }
+            if !seen.contains(&call) {
+                seen.push(call);
+            } else if !repeated.contains(call) {
+                repeated.push(call.clone());
+            }
+        }
+
+        CachedCalls(repeated)
+    }
+
+    pub fn slot(&self, call: &FunctionCallExpr<'s>) -> Option<usize> {
+        self.0.iter().position(|cached| cached == call)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Values of [`CachedCalls`] computed during a single execution.
+///
+/// It's owned by the execution rather than by the context, so that values
+/// can borrow from the context without being copied, and executions sharing
+/// a context don't see each other's values.
+pub(crate) struct CallCache<'a> {
+    calls: &'a [FunctionCallExpr<'a>],
+    ctx: &'a ExecutionContext<'a>,
+    // Filters rarely repeat more than a few calls, so values are kept
+    // inline to avoid allocating on every execution. Neither an array nor a
+    // `Vec` keeps the context borrowed when the cache is dropped, unlike
+    // `SmallVec`.
+    inline: [OnceCell<Option<LhsValue<'a>>>; INLINE_CACHED_CALLS],
+    spilled: Vec<OnceCell<Option<LhsValue<'a>>>>,
+}
+
+const INLINE_CACHED_CALLS: usize = 4;
+
+impl<'a> CallCache<'a> {
+    pub fn new(calls: &'a CachedCalls<'a>, ctx: &'a ExecutionContext<'a>) -> Self {
+        CallCache {
+            calls: &calls.0,
+            ctx,
+            inline: Default::default(),
+            spilled: (INLINE_CACHED_CALLS..calls.len())
+                .map(|_| OnceCell::new())
+                .collect(),
+        }
+    }
+
+    /// Creates a cache for expressions compiled without cached calls.
+    pub fn empty(ctx: &'a ExecutionContext<'a>) -> Self {
+        CallCache {
+            calls: &[],
+            ctx,
+            inline: Default::default(),
+            spilled: Vec::new(),
+        }
+    }
+
+    /// Passes a value of a cached call to `func`, calling the function only
+    /// if it wasn't called yet in this execution.
+    pub fn with_value(&self, slot: usize, func: impl FnOnce(LhsValue<'_>) -> bool) -> bool {
+        let value = match slot.checked_sub(INLINE_CACHED_CALLS) {
+            Some(index) => &self.spilled[index],
+            None => &self.inline[slot],
+        };
+        self.ctx.count_cached_call(value.get().is_some());
+        match value.get_or_init(|| self.calls[slot].execute_isolated(self.ctx)) {
+            Some(value) => func(value.as_ref()),
+            None => false,
+        }
+    }
 }
 
 fn invalid_args_count<'i>(function: &Function, input: &'i str) -> LexError<'i> {
@@ -234,6 +510,7 @@ fn test_function() {
                         }],
                         return_type: Type::Bytes,
                         implementation: FunctionImpl::new(echo_function),
+                        pure: true,
                     },
                 )
                 .unwrap();
@@ -0,0 +1,292 @@
+use super::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    simple_expr::SimpleExpr,
+    FilterAst,
+};
+use crate::{
+    lex::{LexErrorKind, LexWith},
+    scheme::{ParseError, Scheme},
+};
+use std::ops::Range;
+
+// An operand of the top-level combination of a filter, followed by a
+// combining operator unless it's the last one.
+struct Operand<'s> {
+    // Spans from the start of the operand to the start of the next one.
+    span: Range<usize>,
+    expr: SimpleExpr<'s>,
+    op: Option<CombiningOp>,
+}
+
+/// A filter being edited, e.g. in a web editor, which is parsed again
+/// after every edit without starting from scratch.
+///
+/// A filter is kept as a list of operands of its top-level `and`, `or`
+/// and `xor` operators, and only the operands touched by an edit are lexed
+/// again, so the cost of validating a keystroke doesn't grow with the size
+/// of large rule sets written as long combinations. The results are always
+/// the same as of [`Scheme::parse`](::Scheme::parse) on the whole input.
+pub struct IncrementalParse<'s> {
+    scheme: &'s Scheme,
+    input: String,
+    operands: Vec<Operand<'s>>,
+    error: Option<(LexErrorKind, Range<usize>)>,
+    relexed: Range<usize>,
+}
+
+impl<'s> IncrementalParse<'s> {
+    /// Parses a filter from scratch.
+    pub fn new(scheme: &'s Scheme, input: String) -> Self {
+        let mut parse = IncrementalParse {
+            scheme,
+            input,
+            operands: Vec::new(),
+            error: None,
+            relexed: 0..0,
+        };
+        parse.relex(0, Vec::new());
+        parse
+    }
+
+    /// Returns the current input.
+    pub fn input(&self) -> &str {
+        &self.input
+    }
+
+    /// Replaces a byte range of the input and parses the result.
+    ///
+    /// # Panics
+    ///
+    /// Panics under the same conditions as [`String::replace_range`], i.e.
+    /// if the range is out of bounds or doesn't lie on character
+    /// boundaries.
+    pub fn edit(&mut self, range: Range<usize>, replacement: &str) {
+        self.input.replace_range(range.clone(), replacement);
+        let new_end = range.start + replacement.len();
+
+        // An operator is lexed together with the operand before it, so
+        // the operand before the first one touched by the edit is lexed
+        // again as well.
+        let keep = self
+            .operands
+            .iter()
+            .take_while(|operand| operand.span.end < range.start)
+            .count()
+            .saturating_sub(1);
+
+        // Operands after the edit are reused once lexing reaches one of
+        // them again.
+        let tail = self
+            .operands
+            .drain(keep..)
+            .filter(|operand| operand.span.start > range.end)
+            .map(|operand| Operand {
+                span: operand.span.start - range.end + new_end
+                    ..operand.span.end - range.end + new_end,
+                ..operand
+            })
+            .collect();
+
+        self.relex(keep, tail);
+    }
+
+    /// Returns the byte range of the input lexed again by the last edit,
+    /// e.g. to update syntax highlighting.
+    pub fn relexed(&self) -> Range<usize> {
+        self.relexed.clone()
+    }
+
+    /// Returns the parsed filter or the parsing error.
+    pub fn result(&self) -> Result<FilterAst<'s>, ParseError<'_>> {
+        match &self.error {
+            Some((kind, span)) => Err(ParseError::new(
+                &self.input,
+                (kind.clone(), &self.input[span.clone()]),
+            )),
+            None => Ok(FilterAst {
+                scheme: self.scheme,
+                op: combine(
+                    &self.operands,
+                    &[CombiningOp::Or, CombiningOp::Xor, CombiningOp::And],
+                ),
+            }),
+        }
+    }
+
+    fn relex(&mut self, keep: usize, tail: Vec<Operand<'s>>) {
+        // Like `Scheme::parse`, ignore surrounding whitespace. Trimming
+        // whitespace-only input leaves an empty string at its start, so
+        // errors point there rather than at its end.
+        let end = self.input.trim_end().len();
+        let start = match end {
+            0 => 0,
+            _ => self.input.len() - self.input.trim_start().len(),
+        };
+
+        self.operands.truncate(keep);
+        self.error = None;
+
+        let mut pos = match self.operands.last() {
+            Some(operand) => operand.span.end,
+            None => start,
+        };
+        let relexed_start = pos;
+        let mut tail = tail.into_iter().peekable();
+
+        loop {
+            while tail.next_if(|operand| operand.span.start < pos).is_some() {}
+            if tail.peek().map(|operand| operand.span.start) == Some(pos) {
+                self.relexed = relexed_start..pos;
+                self.operands.extend(tail);
+                return;
+            }
+
+            match self.lex_operand(pos, end) {
+                Ok(operand) => {
+                    pos = operand.span.end;
+                    let last = operand.op.is_none();
+                    self.operands.push(operand);
+                    if last {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    self.error = Some(err);
+                    break;
+                }
+            }
+        }
+
+        self.relexed = relexed_start..pos.max(end);
+    }
+
+    fn lex_operand(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> Result<Operand<'s>, (LexErrorKind, Range<usize>)> {
+        let input = &self.input[..end];
+        let offset = |rest: &str| rest.as_ptr() as usize - input.as_ptr() as usize;
+
+        let (expr, rest) = SimpleExpr::lex_with(&input[start..], self.scheme)
+            .map_err(|(kind, span)| (kind, offset(span)..offset(span) + span.len()))?;
+        let (op, rest) = CombinedExpr::lex_combining_op(rest);
+
+        if op.is_none() && !rest.is_empty() {
+            return Err((LexErrorKind::EOF, offset(rest)..end));
+        }
+
+        Ok(Operand {
+            span: start..offset(rest),
+            expr,
+            op,
+        })
+    }
+}
+
+// Combines operands the same way as the parser does, from the operator
+// with the lowest precedence to the highest one.
+fn combine<'s>(operands: &[Operand<'s>], ops: &[CombiningOp]) -> CombinedExpr<'s> {
+    match ops.split_first() {
+        None => CombinedExpr::Simple(operands[0].expr.clone()),
+        Some((&op, ops)) => {
+            let mut items = operands
+                .split_inclusive(|operand| operand.op == Some(op))
+                .map(|operands| combine(operands, ops))
+                .collect::<Vec<_>>();
+            if items.len() == 1 {
+                items.pop().unwrap()
+            } else {
+                CombinedExpr::Combining { op, items }
+            }
+        }
+    }
+}
+
+#[test]
+fn test_incremental_parse() {
+    let scheme = &Scheme! {
+        http.host: Bytes,
+        tcp.port: Int,
+        ssl: Bool,
+    };
+
+    let check = |parse: &IncrementalParse<'_>| {
+        let expected = scheme.parse(parse.input());
+        match (parse.result(), expected) {
+            (Ok(actual), Ok(expected)) => assert_eq!(actual, expected, "{:?}", parse.input()),
+            (Err(actual), Err(expected)) => {
+                assert_eq!(actual, expected, "{:?}", parse.input())
+            }
+            (actual, expected) => panic!(
+                "{:?}: expected {:?}, got {:?}",
+                parse.input(),
+                expected,
+                actual
+            ),
+        }
+    };
+
+    let mut parse = IncrementalParse::new(
+        scheme,
+        r#" ssl and tcp.port == 80 or http.host == "a" xor not ssl and (tcp.port > 1 or ssl) "#
+            .to_owned(),
+    );
+    check(&parse);
+
+    // Only the edited operand and the one before it are lexed again.
+    parse.edit(21..23, "443");
+    assert_eq!(
+        parse.input()[parse.relexed()].trim(),
+        "ssl and tcp.port == 443 or"
+    );
+    check(&parse);
+
+    let replace = |parse: &mut IncrementalParse<'_>, from: &str, to: &str| {
+        let start = parse.input().find(from).unwrap();
+        parse.edit(start..start + from.len(), to);
+        check(parse);
+    };
+
+    // An operator changes precedence.
+    replace(&mut parse, "443 or", "443 and");
+    // An operand becomes invalid and valid again.
+    replace(&mut parse, r#""a""#, r#""a"#);
+    replace(&mut parse, r#""a"#, r#""b""#);
+    // Operands get merged into a parenthesized one and split again.
+    replace(&mut parse, "ssl and tcp", "(ssl and tcp");
+    replace(&mut parse, "443", "443)");
+    replace(&mut parse, "(ssl", "ssl");
+    replace(&mut parse, "443)", "443");
+    // The start and the end of the input change.
+    parse.edit(0..0, "not ");
+    check(&parse);
+    replace(&mut parse, "ssl) ", "ssl) or");
+    replace(&mut parse, "ssl) or", "ssl) or ssl  ");
+    // The whole input is replaced.
+    let len = parse.input().len();
+    parse.edit(0..len, "ssl");
+    check(&parse);
+    assert_eq!(parse.input(), "ssl");
+
+    // Operands after an error are lexed once it's fixed.
+    let mut parse = IncrementalParse::new(scheme, "ssl or tcp.port ==  or ssl".to_owned());
+    check(&parse);
+    assert_eq!(parse.result().unwrap_err().span(), 20..26);
+    parse.edit(26..26, " or ssl");
+    check(&parse);
+    parse.edit(19..19, "1");
+    check(&parse);
+    assert!(parse.result().is_ok());
+
+    // Whitespace-only input fails at its start.
+    let mut parse = IncrementalParse::new(scheme, String::new());
+    check(&parse);
+    parse.edit(0..0, " ");
+    check(&parse);
+    assert_eq!(parse.result().unwrap_err().span(), 0..0);
+    parse.edit(1..1, "\n ssl");
+    check(&parse);
+    parse.edit(1..5, "  ");
+    check(&parse);
+}
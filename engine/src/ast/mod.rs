@@ -1,20 +1,123 @@
+mod builder;
 mod combined_expr;
 mod field_expr;
 mod function_expr;
+mod incremental;
+mod repr;
 mod simple_expr;
 
-use self::combined_expr::CombinedExpr;
+pub use self::{
+    builder::{ExprBuildError, ExprBuilder, FieldExprBuilder, Literal},
+    field_expr::{
+        ComparisonOperator, ComparisonRef, ComparisonRhs, RhsReplacement, RhsRewriteError,
+    },
+    incremental::IncrementalParse,
+};
+
+pub(crate) use self::{
+    field_expr::PreparedComparison,
+    function_expr::{CachedCalls, CallCache},
+};
+
+use self::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    field_expr::{fmt_lhs_value, FieldExpr},
+    repr::ExprRepr,
+    simple_expr::SimpleExpr,
+};
 use crate::{
-    filter::{CompiledExpr, Filter},
+    anonymizer::Anonymizer,
+    execution_context::ExecutionContext,
+    filter::{CompiledExpr, Filter, SchemeMismatchError},
     lex::{LexResult, LexWith},
+    migration::{Migration, MigrationError},
+    parser_settings::ParserSettings,
     scheme::{Field, Scheme, UnknownFieldError},
+    types::{GetType, LhsValue},
+    vm::{BytecodeFilter, Instr, Program},
+};
+use failure::Fail;
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{
+    fmt::{self, Debug, Display},
+    ops::{Not, Range},
 };
-use serde::Serialize;
-use std::fmt::{self, Debug};
 
 trait Expr<'s>: Sized + Eq + Debug + for<'i> LexWith<'i, &'s Scheme> + Serialize {
     fn uses(&self, field: Field<'s>) -> bool;
-    fn compile(self) -> CompiledExpr<'s>;
+    fn for_each_field_expr<'a>(&'a self, visit: &mut dyn FnMut(&'a FieldExpr<'s>));
+    fn for_each_field_expr_mut(&mut self, visit: &mut dyn FnMut(&mut FieldExpr<'s>));
+    // Folds literals and, if a context is given, comparisons of fields with
+    // values in it.
+    fn fold_constants(self, constants: Option<&ExecutionContext<'s>>) -> Folded<Self>;
+    fn fmt_filter(&self, f: &mut fmt::Formatter<'_>, style: OperatorStyle) -> fmt::Result;
+    fn compile_with_settings(
+        self,
+        settings: &ParserSettings,
+        cached_calls: &CachedCalls<'s>,
+    ) -> CompiledExpr<'s>;
+    fn emit(
+        self,
+        settings: &ParserSettings,
+        cached_calls: &CachedCalls<'s>,
+        program: &mut Program<'s>,
+    );
+    // Evaluates an expression node by node, see `Filter::execute_with_trace`.
+    fn trace(&self, ctx: &ExecutionContext<'s>) -> TraceNode<'s>;
+
+    #[cfg(test)]
+    fn compile(self) -> CompiledExpr<'s> {
+        self.compile_with_settings(&ParserSettings::default(), &CachedCalls::default())
+    }
+}
+
+// Maps a field onto another scheme when rebinding expressions.
+type BindField<'a, 's, 'p> = &'a dyn Fn(Field<'s>) -> Option<Field<'p>>;
+
+/// A spelling of operators used when printing a [`FilterAst`] back into a
+/// string with [`FilterAst::to_filter_string`](FilterAst::to_filter_string).
+///
+/// The parser accepts both of them everywhere.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum OperatorStyle {
+    /// Symbolic operators like `==`, `>=`, `~` and `&&`.
+    Symbols,
+    /// Wireshark-style word operators like `eq`, `ge`, `matches` and `and`.
+    Words,
+}
+
+impl OperatorStyle {
+    fn pick(self, symbol: &'static str, word: &'static str) -> &'static str {
+        match self {
+            OperatorStyle::Symbols => symbol,
+            OperatorStyle::Words => word,
+        }
+    }
+}
+
+// Prints an expression back into a string using a given spelling of
+// operators.
+struct Styled<'a, E>(&'a E, OperatorStyle);
+
+impl<'a, 's, E: Expr<'s>> Display for Styled<'a, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt_filter(f, self.1)
+    }
+}
+
+/// An expression after folding comparisons on constant fields.
+enum Folded<T> {
+    Expr(T),
+    Constant(bool),
+}
+
+impl<T> Folded<T> {
+    fn map<U>(self, f: impl FnOnce(T) -> U) -> Folded<U> {
+        match self {
+            Folded::Expr(expr) => Folded::Expr(f(expr)),
+            Folded::Constant(value) => Folded::Constant(value),
+        }
+    }
 }
 
 /// A parsed filter AST.
@@ -37,6 +140,157 @@ impl<'s> Debug for FilterAst<'s> {
     }
 }
 
+/// Prints a filter back into a string with symbolic operators.
+impl<'s> Display for FilterAst<'s> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.op.fmt_filter(f, OperatorStyle::Symbols)
+    }
+}
+
+/// An operand of a top-level `&&` that didn't match, as reported by
+/// [`FilterAst::explain_mismatch`](FilterAst::explain_mismatch).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct FailedConjunct<'s> {
+    /// A byte range of the operand in the filter printed with symbolic
+    /// operators, i.e. with [`Display`].
+    pub span: Range<usize>,
+    /// The operand printed back into a filter.
+    pub filter: String,
+    /// Fields used by the operand and their values printed as literals, or
+    /// `None` for fields without a value.
+    pub values: Vec<(&'s str, Option<String>)>,
+}
+
+/// A node of a trace of a filter execution, as returned by
+/// [`Filter::execute_with_trace`](::Filter::execute_with_trace).
+///
+/// Operands skipped by short-circuiting of `&&` and `||` are left out, so a
+/// trace contains exactly the nodes the result was decided by.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TraceNode<'s> {
+    /// A single comparison.
+    Comparison {
+        /// The comparison printed back into a filter.
+        filter: String,
+        /// The compared field, or `None` if the left-hand side is a function
+        /// call.
+        field: Option<&'s str>,
+        /// The operator of the comparison.
+        operator: ComparisonOperator,
+        /// Values of the left-hand side printed as literals: one per value of
+        /// a multi-valued field, and none if the value is missing.
+        values: Vec<String>,
+        /// Whether the comparison matched.
+        result: bool,
+    },
+    /// Operands combined with `&&`, `||` or `^^`.
+    Combination {
+        /// The combining operator.
+        operator: &'static str,
+        /// Traces of the evaluated operands, in order.
+        operands: Vec<TraceNode<'s>>,
+        /// Whether the combination matched.
+        result: bool,
+    },
+    /// A negated expression.
+    Not {
+        /// A trace of the negated expression.
+        operand: Box<TraceNode<'s>>,
+        /// Whether the negation matched.
+        result: bool,
+    },
+    /// A literal `true` or `false`, including parts of a filter folded into
+    /// constants during compilation.
+    Literal {
+        /// The value of the literal.
+        result: bool,
+    },
+}
+
+impl<'s> TraceNode<'s> {
+    /// Returns whether the node matched.
+    pub fn result(&self) -> bool {
+        match *self {
+            TraceNode::Comparison { result, .. }
+            | TraceNode::Combination { result, .. }
+            | TraceNode::Not { result, .. }
+            | TraceNode::Literal { result } => result,
+        }
+    }
+}
+
+/// Prints an operand followed by the values it was evaluated with, e.g.
+/// `tcp.port == 80 [tcp.port = 443]`.
+impl<'s> Display for FailedConjunct<'s> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.filter)?;
+
+        for (i, (name, value)) in self.values.iter().enumerate() {
+            f.write_str(if i == 0 { " [" } else { ", " })?;
+            match value {
+                Some(value) => write!(f, "{} = {}", name, value)?,
+                None => write!(f, "{} is missing", name)?,
+            }
+        }
+
+        if self.values.is_empty() {
+            Ok(())
+        } else {
+            f.write_str("]")
+        }
+    }
+}
+
+/// A construct of a filter that a backend can't handle, as reported by
+/// [`FilterAst::check_support`](FilterAst::check_support).
+#[derive(Debug, PartialEq, Eq, Clone, Fail)]
+#[fail(display = "unsupported {} at {:?}: {}", construct, span, reason)]
+pub struct Unsupported {
+    /// The construct printed back into a filter.
+    pub construct: String,
+    /// A byte range of the construct in the filter printed with symbolic
+    /// operators, i.e. with [`Display`].
+    pub span: Range<usize>,
+    /// Why the construct is unsupported.
+    pub reason: String,
+}
+
+/// An error that occurs when a normal form of a filter produced by
+/// [`FilterAst::to_dnf`](FilterAst::to_dnf) or
+/// [`FilterAst::to_cnf`](FilterAst::to_cnf) would be too large.
+#[derive(Debug, PartialEq, Eq, Clone, Fail)]
+#[fail(display = "normal form would have more than {} terms", max_terms)]
+pub struct NormalFormSizeError {
+    /// The maximum number of terms that was requested.
+    pub max_terms: usize,
+}
+
+/// A result of splitting a [`FilterAst`] with
+/// [`FilterAst::restrict_to`](FilterAst::restrict_to).
+///
+/// The original filter matches only if both of the parts match, and a
+/// missing part is considered to always match.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SplitFilterAst<'p, 's> {
+    /// A part of the filter that can be evaluated with the projected scheme.
+    pub projected: Option<FilterAst<'p>>,
+    /// A remaining part of the filter that needs the original scheme.
+    pub residual: Option<FilterAst<'s>>,
+}
+
+impl<'s> Not for FilterAst<'s> {
+    type Output = FilterAst<'s>;
+
+    /// Negates a filter into `not self`.
+    fn not(self) -> FilterAst<'s> {
+        FilterAst {
+            scheme: self.scheme,
+            op: self.op.negate(),
+        }
+    }
+}
+
 impl<'i, 's> LexWith<'i, &'s Scheme> for FilterAst<'s> {
     fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
         let (op, input) = CombinedExpr::lex_with(input, scheme)?;
@@ -45,6 +299,16 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for FilterAst<'s> {
 }
 
 impl<'s> FilterAst<'s> {
+    pub(crate) fn deserialize_with<'de, D: Deserializer<'de>>(
+        deserializer: D,
+        scheme: &'s Scheme,
+    ) -> Result<Self, D::Error> {
+        let op = ExprRepr::deserialize(deserializer)?
+            .bind(scheme)
+            .map_err(de::Error::custom)?;
+        Ok(FilterAst { scheme, op })
+    }
+
     /// Recursively checks whether a [`FilterAst`] uses a given field name.
     ///
     /// This is useful to lazily initialise expensive fields only if necessary.
@@ -54,8 +318,1405 @@ impl<'s> FilterAst<'s> {
             .map(|field| self.op.uses(field))
     }
 
-    /// Compiles a [`FilterAst`] into a [`Filter`].
+    /// Returns fields used by a [`FilterAst`] in the order of the scheme.
+    pub(crate) fn used_fields<'a>(&'a self) -> impl Iterator<Item = Field<'s>> + 'a {
+        self.scheme
+            .fields()
+            .filter(move |field| self.op.uses(*field))
+    }
+
+    /// Returns an iterator over all comparisons in a [`FilterAst`], in the
+    /// order they appear in the filter.
+    ///
+    /// This is useful for auditing rules without walking the AST manually,
+    /// e.g. to find every rule referencing a deprecated field.
+    pub fn comparisons<'a>(&'a self) -> impl Iterator<Item = ComparisonRef<'a, 's>> {
+        let mut comparisons = Vec::new();
+        self.op.for_each_field_expr(&mut |expr| {
+            comparisons.push(ComparisonRef::new(self.scheme, expr))
+        });
+        comparisons.into_iter()
+    }
+
+    /// Returns all comparisons in a [`FilterAst`] together with their
+    /// [estimated costs](ComparisonRef::cost).
+    ///
+    /// This helps to find out which parts of an expensive filter to blame.
+    pub fn annotate_costs<'a>(&'a self) -> impl Iterator<Item = (ComparisonRef<'a, 's>, u64)> {
+        self.comparisons()
+            .map(|comparison| (comparison, comparison.cost()))
+    }
+
+    /// Returns an estimated cost of executing a [`FilterAst`] in relative
+    /// units, assuming that none of the comparisons are short-circuited.
+    ///
+    /// A comparison of a field with a single value costs 2, while regular
+    /// expressions, large lists and function calls cost more. Platforms
+    /// accepting filters from users can reject the ones above a threshold.
+    pub fn cost(&self) -> u64 {
+        self.annotate_costs().map(|(_, cost)| cost).sum()
+    }
+
+    /// Rewrites right-hand sides of comparisons in a [`FilterAst`].
+    ///
+    /// The callback is invoked for every comparison and can return a
+    /// replacement for its right-hand side, e.g. to update an IP literal that
+    /// changed or to expand a shorthand literal into a full list.
+    ///
+    /// Replacements are type-checked against the left-hand side, and if any
+    /// of them is invalid, an error is returned and the AST is left intact.
+    pub fn rewrite_rhs<F>(&mut self, mut rewrite: F) -> Result<(), RhsRewriteError>
+    where
+        F: FnMut(ComparisonRef<'_, 's>) -> Option<RhsReplacement>,
+    {
+        let scheme = self.scheme;
+        let mut op = self.op.clone();
+        let mut result = Ok(());
+
+        op.for_each_field_expr_mut(&mut |expr| {
+            if result.is_ok() {
+                if let Some(replacement) = rewrite(ComparisonRef::new(scheme, expr)) {
+                    result = expr.replace_rhs(replacement);
+                }
+            }
+        });
+
+        result.map(|()| self.op = op)
+    }
+
+    fn combine(
+        self,
+        op: CombiningOp,
+        other: FilterAst<'s>,
+    ) -> Result<FilterAst<'s>, SchemeMismatchError> {
+        if self.scheme != other.scheme {
+            return Err(SchemeMismatchError::new(self.scheme, other.scheme));
+        }
+
+        Ok(FilterAst {
+            scheme: self.scheme,
+            op: self.op.combine(op, other.op),
+        })
+    }
+
+    /// Combines two filters parsed with the same scheme into `self && other`.
+    ///
+    /// This is equivalent to parsing both filters joined with `&&`, but
+    /// doesn't require to concatenate and reparse strings.
+    pub fn and(self, other: FilterAst<'s>) -> Result<FilterAst<'s>, SchemeMismatchError> {
+        self.combine(CombiningOp::And, other)
+    }
+
+    /// Combines two filters parsed with the same scheme into `self || other`.
+    pub fn or(self, other: FilterAst<'s>) -> Result<FilterAst<'s>, SchemeMismatchError> {
+        self.combine(CombiningOp::Or, other)
+    }
+
+    /// Binds a [`FilterAst`] to another scheme, e.g. a newer version of its
+    /// scheme, by looking up all the fields, functions and lists by name.
+    ///
+    /// Returns `None` if any of them is missing or has a different type,
+    /// see [`Scheme::check_compatibility`](::Scheme::check_compatibility)
+    /// to find out which fields differ.
+    pub fn rebind<'p>(&self, scheme: &'p Scheme) -> Option<FilterAst<'p>> {
+        Some(FilterAst {
+            scheme,
+            op: self.op.rebind(scheme)?,
+        })
+    }
+
+    /// Splits a [`FilterAst`] into a part that can be evaluated against a
+    /// [projection](::Scheme::project) of its scheme and a
+    /// residual part that needs the original scheme.
+    ///
+    /// Top-level `&&` operands that only use fields and functions available
+    /// in the projection are moved into the projected part, and everything
+    /// else is kept in the residual one. This allows to evaluate a filter
+    /// partially on one tier (e.g. the edge) and leave the rest to another
+    /// (e.g. the origin).
+    pub fn restrict_to<'p>(&self, projection: &'p Scheme) -> SplitFilterAst<'p, 's> {
+        let items = match &self.op {
+            CombinedExpr::Combining {
+                op: CombiningOp::And,
+                items,
+            } => items.clone(),
+            op => vec![op.clone()],
+        };
+
+        let mut projected = Vec::new();
+        let mut residual = Vec::new();
+
+        for item in items {
+            match item.rebind(projection) {
+                Some(item) => projected.push(item),
+                None => residual.push(item),
+            }
+        }
+
+        fn conjunction<'s>(
+            scheme: &'s Scheme,
+            mut items: Vec<CombinedExpr<'s>>,
+        ) -> Option<FilterAst<'s>> {
+            let op = match items.len() {
+                0 => return None,
+                1 => items.pop().unwrap(),
+                _ => CombinedExpr::Combining {
+                    op: CombiningOp::And,
+                    items,
+                },
+            };
+            Some(FilterAst { scheme, op })
+        }
+
+        SplitFilterAst {
+            projected: conjunction(projection, projected),
+            residual: conjunction(self.scheme, residual),
+        }
+    }
+
+    /// Prints a filter back into a string using a given spelling of
+    /// operators.
+    ///
+    /// The result parses into the same AST, but original whitespace and
+    /// redundant parentheses are not preserved.
+    pub fn to_filter_string(&self, style: OperatorStyle) -> String {
+        Styled(&self.op, style).to_string()
+    }
+
+    /// Migrates a filter to a new version of its scheme.
+    ///
+    /// Fields are renamed and converted as described by the [`Migration`],
+    /// and everything else is looked up by name in the new scheme.
+    pub fn migrate<'t>(
+        &self,
+        migration: &Migration,
+        to: &'t Scheme,
+    ) -> Result<FilterAst<'t>, MigrationError> {
+        if let Some(field) = self
+            .used_fields()
+            .find(|field| migration.is_dropped(field.name()))
+        {
+            return Err(MigrationError::DroppedField(field.name().to_owned()));
+        }
+
+        // Convert comparisons while the original right-hand sides are still
+        // typed as the old fields.
+        let mut replacements = Vec::new();
+        for comparison in self.comparisons() {
+            let mut replacement = None;
+
+            for field in self.used_fields() {
+                let name = field.name();
+                let converter = match migration.get_converter(name) {
+                    Some(converter) if comparison.uses(name) => converter,
+                    _ => continue,
+                };
+
+                replacement = match (comparison.field(), comparison.rhs()) {
+                    // Results of bitwise operations are not values of the
+                    // field, so they can't be converted.
+                    (Some(lhs), Some(rhs)) if lhs == name && comparison.bitwise().is_none() => {
+                        converter(rhs)
+                    }
+                    _ => None,
+                }
+                .map(|replacement| (name, replacement));
+
+                if replacement.is_none() {
+                    return Err(MigrationError::Conversion(name.to_owned()));
+                }
+            }
+
+            replacements.push(replacement);
+        }
+
+        let mut op = self
+            .op
+            .rebind_with(to, &|field| {
+                let new_field = to.get_field_index(migration.new_name(field.name())).ok()?;
+                if new_field.get_type() == field.get_type()
+                    || migration.get_converter(field.name()).is_some()
+                {
+                    Some(new_field)
+                } else {
+                    None
+                }
+            })
+            .ok_or(MigrationError::SchemeMismatch)?;
+
+        let mut replacements = replacements.into_iter();
+        let mut result = Ok(());
+
+        op.for_each_field_expr_mut(&mut |expr| {
+            if let Some((name, replacement)) = replacements.next().unwrap() {
+                if result.is_ok() {
+                    result = expr.replace_rhs(replacement).map_err(|err| {
+                        MigrationError::InvalidConversion {
+                            name: name.to_owned(),
+                            err,
+                        }
+                    });
+                }
+            }
+        });
+
+        result.map(|()| FilterAst { scheme: to, op })
+    }
+
+    /// Replaces bytes, regex and IP literals in a filter with placeholders,
+    /// so that it can be shared without leaking hostnames or addresses.
+    ///
+    /// The structure of the filter is preserved: strings keep their lengths
+    /// and punctuation, lists keep their sizes, and networks keep their
+    /// prefix lengths. The same address is always replaced with the same
+    /// placeholder from `10.0.0.0/8` or `2001:db8::/32`.
+    pub fn anonymize(&self) -> FilterAst<'s> {
+        let mut anonymizer = Anonymizer::default();
+        let mut op = self.op.clone();
+
+        op.for_each_field_expr_mut(&mut |expr| expr.anonymize(&mut anonymizer));
+
+        FilterAst {
+            scheme: self.scheme,
+            op,
+        }
+    }
+
+    /// Converts a filter into a disjunctive normal form, i.e. a top-level
+    /// `||` of `&&`s of comparisons, each of them possibly negated.
+    ///
+    /// This is useful for offloading filters to systems that only support
+    /// flat structures. The conversion can grow a filter exponentially, so
+    /// it fails if the result would have more than `max_terms` operands of
+    /// the top-level `||`.
+    pub fn to_dnf(&self, max_terms: usize) -> Result<FilterAst<'s>, NormalFormSizeError> {
+        let terms = self.op.dnf_terms(false, max_terms)?;
+
+        Ok(FilterAst {
+            scheme: self.scheme,
+            op: CombinedExpr::from_terms(terms, CombiningOp::Or),
+        })
+    }
+
+    /// Returns comparisons of a disjunctive normal form of a filter, see
+    /// [`to_dnf`](FilterAst::to_dnf), each of them paired with a flag that is
+    /// set if the comparison is negated.
+    pub(crate) fn dnf_comparisons<'a>(
+        &'a self,
+        max_terms: usize,
+    ) -> Result<Vec<Vec<(bool, ComparisonRef<'a, 's>)>>, NormalFormSizeError> {
+        Ok(self
+            .op
+            .dnf_terms(false, max_terms)?
+            .into_iter()
+            .map(|term| {
+                term.into_iter()
+                    .map(|(negated, expr)| (negated, ComparisonRef::new(self.scheme, expr)))
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Converts a filter into a conjunctive normal form, i.e. a top-level
+    /// `&&` of `||`s of comparisons, each of them possibly negated.
+    ///
+    /// Just like with [`to_dnf`](FilterAst::to_dnf), it fails if the result
+    /// would have more than `max_terms` operands of the top-level `&&`.
+    pub fn to_cnf(&self, max_terms: usize) -> Result<FilterAst<'s>, NormalFormSizeError> {
+        // A negation of a DNF of a negated filter is its CNF.
+        let mut terms = self.op.dnf_terms(true, max_terms)?;
+
+        for term in &mut terms {
+            for (negated, _) in term {
+                *negated = !*negated;
+            }
+        }
+
+        Ok(FilterAst {
+            scheme: self.scheme,
+            op: CombinedExpr::from_terms(terms, CombiningOp::And),
+        })
+    }
+
+    /// Checks whether every comparison of a filter is supported by a
+    /// backend, so that users know in advance whether the filter can be
+    /// offloaded to it.
+    ///
+    /// The callback returns a reason for every comparison the backend can't
+    /// handle, and all of them are reported together.
+    pub fn check_support<F>(&self, mut check: F) -> Result<(), Vec<Unsupported>>
+    where
+        F: FnMut(ComparisonRef<'_, 's>) -> Option<String>,
+    {
+        let mut spans = Vec::new();
+        comparison_spans(&self.op, &mut 0, &mut spans);
+
+        let unsupported = self
+            .comparisons()
+            .zip(spans)
+            .filter_map(|(comparison, span)| {
+                let reason = check(comparison)?;
+                Some(Unsupported {
+                    construct: Styled(comparison.expr(), OperatorStyle::Symbols).to_string(),
+                    span,
+                    reason,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        if unsupported.is_empty() {
+            Ok(())
+        } else {
+            Err(unsupported)
+        }
+    }
+
+    /// Explains why a filter doesn't match a given context.
+    ///
+    /// The filter is viewed as top-level `||` of disjuncts, each of them being
+    /// a top-level `&&` of conjuncts, and the result contains failed
+    /// conjuncts of every disjunct, in order. Nested expressions are reported
+    /// as a whole. Operands using fields without a value are considered
+    /// failed instead of panicking like an execution would.
+    ///
+    /// The result is empty if the filter matches.
+    pub fn explain_mismatch(
+        &self,
+        ctx: &ExecutionContext<'s>,
+    ) -> Result<Vec<Vec<FailedConjunct<'s>>>, SchemeMismatchError> {
+        if self.scheme != ctx.scheme() {
+            return Err(SchemeMismatchError::new(self.scheme, ctx.scheme()));
+        }
+
+        fn operands<'a, 's>(expr: &'a CombinedExpr<'s>, op: CombiningOp) -> &'a [CombinedExpr<'s>] {
+            match expr {
+                CombinedExpr::Combining { op: expr_op, items } if *expr_op == op => items,
+                expr => std::slice::from_ref(expr),
+            }
+        }
+
+        // Both ` || ` and ` && ` separators are printed as four bytes.
+        const SEPARATOR_LEN: usize = 4;
+
+        let mut explanation = Vec::new();
+        let mut offset = 0;
+
+        for disjunct in operands(&self.op, CombiningOp::Or) {
+            let mut failed = Vec::new();
+
+            for conjunct in operands(disjunct, CombiningOp::And) {
+                let filter = Styled(conjunct, OperatorStyle::Symbols).to_string();
+                let span = offset..offset + filter.len();
+                offset = span.end + SEPARATOR_LEN;
+
+                let values = self
+                    .scheme
+                    .fields()
+                    .filter(|field| conjunct.uses(*field))
+                    .map(|field| {
+                        let value = if ctx.has_field_value(field) {
+                            Some(LhsValueLiteral(ctx.get_field_value_unchecked(field)).to_string())
+                        } else {
+                            None
+                        };
+                        (field.name(), value)
+                    })
+                    .collect::<Vec<_>>();
+
+                let matched = values.iter().all(|(_, value)| value.is_some())
+                    && conjunct
+                        .clone()
+                        .compile_with_settings(
+                            self.scheme.parser_settings(),
+                            &CachedCalls::default(),
+                        )
+                        .execute(ctx);
+
+                if !matched {
+                    failed.push(FailedConjunct {
+                        span,
+                        filter,
+                        values,
+                    });
+                }
+            }
+
+            if failed.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            explanation.push(failed);
+        }
+
+        Ok(explanation)
+    }
+
+    /// Compiles a [`FilterAst`] into a [`Filter`] using
+    /// [`ParserSettings`](::ParserSettings) of its scheme.
+    ///
+    /// Calls to pure functions that are repeated across comparisons, like
+    /// `lower(http.host)`, are evaluated only once per execution.
+    ///
+    /// Literal `true` and `false`, e.g. left in templated rules, are folded
+    /// away together with operands they make redundant.
     pub fn compile(self) -> Filter<'s> {
-        Filter::new(self.op.compile(), self.scheme)
+        Self::compile_folded(self.scheme, self.op.fold_constants(None))
     }
+
+    // Compiles a filter after folding constants, keeping the folded AST for
+    // `Filter::execute_with_trace`.
+    fn compile_folded(scheme: &'s Scheme, op: Folded<CombinedExpr<'s>>) -> Filter<'s> {
+        let op = match op {
+            Folded::Expr(op) => op,
+            Folded::Constant(value) => {
+                let op = CombinedExpr::Simple(SimpleExpr::Literal(value));
+                return Filter::new(
+                    CompiledExpr::new(move |_, _| value),
+                    FilterAst { scheme, op },
+                    CachedCalls::default(),
+                    0,
+                );
+            }
+        };
+
+        let cached_calls = cached_calls(&op);
+        let cost = worst_case_cost(scheme, &op, &cached_calls);
+        let root_expr = op
+            .clone()
+            .compile_with_settings(scheme.parser_settings(), &cached_calls);
+
+        Filter::new(root_expr, FilterAst { scheme, op }, cached_calls, cost)
+    }
+
+    // Traces an execution of a filter, see `Filter::execute_with_trace`.
+    pub(crate) fn trace(&self, ctx: &ExecutionContext<'s>) -> TraceNode<'s> {
+        self.op.trace(ctx)
+    }
+
+    /// Compiles a [`FilterAst`] into a [`BytecodeFilter`], an alternative to
+    /// [`Filter`] that is cheaper to compile and more compact in memory.
+    pub fn compile_to_bytecode(self) -> BytecodeFilter<'s> {
+        let mut program = Program::default();
+        let cached_calls = match self.op.fold_constants(None) {
+            Folded::Expr(op) => {
+                let cached_calls = cached_calls(&op);
+                op.emit(self.scheme.parser_settings(), &cached_calls, &mut program);
+                cached_calls
+            }
+            Folded::Constant(value) => {
+                program.push(Instr::Set(value));
+                CachedCalls::default()
+            }
+        };
+        BytecodeFilter::new(program, self.scheme, cached_calls)
+    }
+
+    pub(crate) fn scheme(&self) -> &'s Scheme {
+        self.scheme
+    }
+
+    // Emits bytecode of filters one after another into a program, which
+    // can share comparisons between them, and returns instructions of each
+    // filter together with the function calls cached across all of them.
+    pub(crate) fn emit_all(
+        filters: Vec<FilterAst<'s>>,
+        program: &mut Program<'s>,
+    ) -> (Vec<Box<[Instr]>>, CachedCalls<'s>) {
+        let filters = filters
+            .into_iter()
+            .map(|filter| (filter.scheme, filter.op.fold_constants(None)))
+            .collect::<Vec<_>>();
+
+        let mut calls = Vec::new();
+        for (_, op) in &filters {
+            if let Folded::Expr(op) = op {
+                op.for_each_field_expr(&mut |expr| calls.extend(expr.function_call()));
+            }
+        }
+        let cached_calls = CachedCalls::new(calls);
+
+        let code = filters
+            .into_iter()
+            .map(|(scheme, op)| {
+                match op {
+                    Folded::Expr(op) => op.emit(scheme.parser_settings(), &cached_calls, program),
+                    Folded::Constant(value) => {
+                        program.push(Instr::Set(value));
+                    }
+                }
+                program.take_instructions()
+            })
+            .collect();
+
+        (code, cached_calls)
+    }
+
+    /// Compiles a [`FilterAst`] into a [`Filter`], evaluating comparisons on
+    /// fields that already have values in a given context right away.
+    ///
+    /// This is useful for fields that are fixed for a deployment target,
+    /// like zone identifiers: subexpressions that become constant are
+    /// dropped, and the resulting filter ignores values of those fields in
+    /// contexts it's executed against later. Note that fields with a
+    /// [default](::Scheme::set_field_default) are always treated as
+    /// constants, since they have a value in every context.
+    pub fn compile_with_constants(
+        self,
+        constants: &ExecutionContext<'s>,
+    ) -> Result<Filter<'s>, SchemeMismatchError> {
+        if self.scheme != constants.scheme() {
+            return Err(SchemeMismatchError::new(self.scheme, constants.scheme()));
+        }
+
+        Ok(Self::compile_folded(
+            self.scheme,
+            self.op.fold_constants(Some(constants)),
+        ))
+    }
+}
+
+// Prints a runtime value as a filter literal.
+struct LhsValueLiteral<'v>(LhsValue<'v>);
+
+impl<'v> Display for LhsValueLiteral<'v> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt_lhs_value(f, &self.0)
+    }
+}
+
+// Collects byte ranges of comparisons in an expression printed with symbolic
+// operators, in the order they are visited by `for_each_field_expr`.
+fn comparison_spans(expr: &CombinedExpr<'_>, offset: &mut usize, spans: &mut Vec<Range<usize>>) {
+    fn simple_spans(expr: &SimpleExpr<'_>, offset: &mut usize, spans: &mut Vec<Range<usize>>) {
+        match expr {
+            SimpleExpr::Field(expr) => {
+                let len = Styled(expr, OperatorStyle::Symbols).to_string().len();
+                spans.push(*offset..*offset + len);
+                *offset += len;
+            }
+            SimpleExpr::Parenthesized(expr) => {
+                *offset += "(".len();
+                comparison_spans(expr, offset, spans);
+                *offset += ")".len();
+            }
+            SimpleExpr::Unary { arg, .. } => {
+                *offset += "!".len();
+                simple_spans(arg, offset, spans);
+            }
+            SimpleExpr::Literal(value) => *offset += value.to_string().len(),
+        }
+    }
+
+    match expr {
+        CombinedExpr::Simple(expr) => simple_spans(expr, offset, spans),
+        CombinedExpr::Combining { items, .. } => {
+            for (i, item) in items.iter().enumerate() {
+                if i != 0 {
+                    // All of ` && `, ` || ` and ` ^^ ` are four bytes long.
+                    *offset += 4;
+                }
+                comparison_spans(item, offset, spans);
+            }
+        }
+    }
+}
+
+fn cached_calls<'s>(op: &CombinedExpr<'s>) -> CachedCalls<'s> {
+    let mut calls = Vec::new();
+    op.for_each_field_expr(&mut |expr| calls.extend(expr.function_call()));
+    CachedCalls::new(calls)
+}
+
+// Estimates the cost of executing a compiled expression when none of its
+// comparisons are short-circuited. Unlike `FilterAst::cost`, it accounts
+// for repeated function calls that are cached and done only once.
+fn worst_case_cost<'s>(
+    scheme: &'s Scheme,
+    op: &CombinedExpr<'s>,
+    cached_calls: &CachedCalls<'s>,
+) -> u64 {
+    let mut cost = 0;
+    let mut done = vec![false; cached_calls.len()];
+    op.for_each_field_expr(&mut |expr| {
+        cost += ComparisonRef::new(scheme, expr).cost();
+        if let Some(call) = expr.function_call() {
+            if let Some(slot) = cached_calls.slot(call) {
+                if done[slot] {
+                    cost -= call.cost();
+                }
+                done[slot] = true;
+            }
+        }
+    });
+    cost
+}
+
+#[test]
+fn test_comparisons() {
+    use crate::types::RhsValue;
+
+    let scheme = &Scheme! {
+        http.host: Bytes,
+        tcp.port: Int,
+        ssl: Bool,
+    };
+
+    let ast = scheme
+        .parse(r#"ssl and (tcp.port == 443 or not http.host contains "example")"#)
+        .unwrap();
+
+    let comparisons = ast.comparisons().collect::<Vec<_>>();
+
+    assert_eq!(comparisons.len(), 3);
+
+    assert_eq!(comparisons[0].field(), Some("ssl"));
+    assert_eq!(comparisons[0].op(), ComparisonOperator::IsTrue);
+    assert_eq!(comparisons[0].rhs(), None);
+
+    assert_eq!(comparisons[1].field(), Some("tcp.port"));
+    assert!(comparisons[1].uses("tcp.port"));
+    assert!(!comparisons[1].uses("http.host"));
+    assert_eq!(comparisons[1].op(), ComparisonOperator::Equal);
+    assert_eq!(
+        comparisons[1].rhs(),
+        Some(ComparisonRhs::Value(&RhsValue::Int(443)))
+    );
+
+    assert_eq!(comparisons[2].field(), Some("http.host"));
+    assert_eq!(comparisons[2].function(), None);
+    assert_eq!(comparisons[2].op(), ComparisonOperator::Contains);
+    match comparisons[2].rhs() {
+        Some(ComparisonRhs::Bytes(bytes)) => assert_eq!(&**bytes, b"example"),
+        rhs => panic!("expected bytes, got {:?}", rhs),
+    }
+}
+
+#[test]
+fn test_cost() {
+    use crate::{
+        functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+        types::{LhsValue, Type},
+    };
+
+    fn echo<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+        args.next().unwrap()
+    }
+
+    let mut scheme = Scheme! {
+        http.host: Bytes,
+        tcp.port: Int,
+        ssl: Bool,
+    };
+    scheme
+        .add_function(
+            "echo".into(),
+            Function {
+                params: vec![FunctionParam {
+                    arg_kind: FunctionArgKind::Field,
+                    val_type: Type::Bytes,
+                }],
+                opt_params: vec![],
+                return_type: Type::Bytes,
+                implementation: FunctionImpl::new(echo),
+                pure: true,
+            },
+        )
+        .unwrap();
+
+    let ast = scheme
+        .parse(
+            r#"ssl and tcp.port in { 80 443 8080 } or http.host matches "^www\." or echo(echo(http.host)) contains "a""#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        ast.annotate_costs()
+            .map(|(comparison, cost)| (comparison.op(), cost))
+            .collect::<Vec<_>>(),
+        vec![
+            (ComparisonOperator::IsTrue, 2),
+            (ComparisonOperator::In, 4),
+            (ComparisonOperator::Matches, 17),
+            (ComparisonOperator::Contains, 25),
+        ]
+    );
+    assert_eq!(ast.cost(), 48);
+}
+
+#[test]
+fn test_rewrite_rhs() {
+    use crate::{
+        rhs_types::IpRange,
+        types::{RhsValue, RhsValues, Type, TypeMismatchError},
+    };
+    use cidr::{Cidr, IpCidr};
+    use smallvec::smallvec;
+    use std::net::IpAddr;
+
+    let scheme = &Scheme! {
+        ip.src: Ip,
+        tcp.port: Int,
+    };
+
+    let mut ast = scheme
+        .parse("ip.src == 10.0.0.1 and tcp.port == 80")
+        .unwrap();
+
+    let host = |addr: [u8; 4]| IpRange::Cidr(IpCidr::new_host(IpAddr::from(addr)));
+
+    ast.rewrite_rhs(|comparison| match comparison.field() {
+        Some("ip.src") => Some(RhsReplacement::Value(RhsValue::Ip(IpAddr::from([
+            10, 0, 0, 2,
+        ])))),
+        Some("tcp.port") => Some(RhsReplacement::Values(RhsValues::Int(smallvec![
+            80..=80,
+            8080..=8080,
+        ]))),
+        _ => None,
+    })
+    .unwrap();
+
+    assert_eq!(
+        ast,
+        scheme
+            .parse("ip.src == 10.0.0.2 and tcp.port in { 80 8080 }")
+            .unwrap()
+    );
+
+    let original = ast.clone();
+
+    assert_eq!(
+        ast.rewrite_rhs(|comparison| match comparison.field() {
+            Some("ip.src") => Some(RhsReplacement::Values(RhsValues::Ip(smallvec![host([
+                10, 0, 0, 3
+            ])]))),
+            Some("tcp.port") => Some(RhsReplacement::Value(RhsValue::Ip(IpAddr::from([
+                10, 0, 0, 4,
+            ])))),
+            _ => None,
+        }),
+        Err(RhsRewriteError::TypeMismatch(TypeMismatchError {
+            expected: Type::Int,
+            actual: Type::Ip,
+        }))
+    );
+
+    assert_eq!(ast, original);
+
+    assert_eq!(
+        ast.rewrite_rhs(|comparison| match comparison.field() {
+            Some("tcp.port") => Some(RhsReplacement::Value(RhsValue::Int(443))),
+            _ => None,
+        }),
+        Err(RhsRewriteError::UnsupportedOp(ComparisonOperator::In))
+    );
+}
+
+#[test]
+fn test_explain_mismatch() {
+    let scheme = &Scheme! {
+        http.host: Bytes,
+        tcp.port: Int,
+        ssl: Bool,
+    };
+
+    let ast = scheme
+        .parse(r#"ssl and tcp.port == 443 or http.host == "example.org" and (tcp.port == 80 or tcp.port == 8080)"#)
+        .unwrap();
+
+    let mut ctx = ExecutionContext::new(scheme);
+    ctx.set_field_value("ssl", true).unwrap();
+    ctx.set_field_value("tcp.port", 8443).unwrap();
+
+    let explanation = ast.explain_mismatch(&ctx).unwrap();
+
+    assert_eq!(
+        explanation,
+        vec![
+            vec![FailedConjunct {
+                span: 7..22,
+                filter: "tcp.port == 443".to_owned(),
+                values: vec![("tcp.port", Some("8443".to_owned()))],
+            }],
+            vec![
+                FailedConjunct {
+                    span: 26..52,
+                    filter: r#"http.host == "example.org""#.to_owned(),
+                    values: vec![("http.host", None)],
+                },
+                FailedConjunct {
+                    span: 56..92,
+                    filter: "(tcp.port == 80 || tcp.port == 8080)".to_owned(),
+                    values: vec![("tcp.port", Some("8443".to_owned()))],
+                },
+            ],
+        ]
+    );
+
+    let filter = ast.to_string();
+    for conjunct in explanation.iter().flatten() {
+        assert_eq!(&filter[conjunct.span.clone()], conjunct.filter);
+    }
+
+    assert_eq!(
+        explanation[1][0].to_string(),
+        r#"http.host == "example.org" [http.host is missing]"#
+    );
+    assert_eq!(
+        explanation[1][1].to_string(),
+        "(tcp.port == 80 || tcp.port == 8080) [tcp.port = 8443]"
+    );
+
+    ctx.set_field_value("tcp.port", 443).unwrap();
+    assert_eq!(ast.explain_mismatch(&ctx), Ok(vec![]));
+
+    let other = Scheme! { ssl: Bool };
+    assert_eq!(
+        ast.explain_mismatch(&ExecutionContext::new(&other)),
+        Err(SchemeMismatchError::new(ast.scheme(), &other))
+    );
+}
+
+#[test]
+fn test_check_support() {
+    let scheme = &Scheme! {
+        http.host: Bytes,
+        tcp.port: Int,
+        ssl: Bool,
+    };
+
+    let ast = scheme
+        .parse(
+            r#"not ssl and (http.host matches "^a+$" or tcp.port in {80 443}) xor http.host ~ "b""#,
+        )
+        .unwrap();
+
+    assert_eq!(ast.check_support(|_| None), Ok(()));
+
+    let unsupported = ast
+        .check_support(|comparison| match comparison.op() {
+            ComparisonOperator::Matches => Some("regular expressions are not supported".to_owned()),
+            _ => None,
+        })
+        .unwrap_err();
+
+    assert_eq!(
+        unsupported,
+        vec![
+            Unsupported {
+                construct: r#"http.host ~ "^a+$""#.to_owned(),
+                span: 9..27,
+                reason: "regular expressions are not supported".to_owned(),
+            },
+            Unsupported {
+                construct: r#"http.host ~ "b""#.to_owned(),
+                span: 58..73,
+                reason: "regular expressions are not supported".to_owned(),
+            },
+        ]
+    );
+
+    let filter = ast.to_string();
+    for item in &unsupported {
+        assert_eq!(&filter[item.span.clone()], item.construct);
+    }
+
+    let spans = ast
+        .check_support(|_| Some(String::new()))
+        .unwrap_err()
+        .into_iter()
+        .map(|item| item.construct)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        spans,
+        [
+            "ssl",
+            r#"http.host ~ "^a+$""#,
+            "tcp.port in { 80 443 }",
+            r#"http.host ~ "b""#
+        ]
+    );
+}
+
+#[test]
+fn test_normal_forms() {
+    let scheme = &Scheme! { a: Bool, b: Bool, c: Bool, d: Bool };
+
+    let parse = |filter: &str| scheme.parse(filter).unwrap();
+
+    assert_eq!(
+        parse("a and (b or c)").to_dnf(10).unwrap().to_string(),
+        "a && b || a && c"
+    );
+    assert_eq!(
+        parse("a or b and c").to_cnf(10).unwrap().to_string(),
+        "(a || b) && (a || c)"
+    );
+    assert_eq!(
+        parse("not (a or not b)").to_dnf(10).unwrap().to_string(),
+        "!a && b"
+    );
+    assert_eq!(
+        parse("a xor b").to_dnf(10).unwrap().to_string(),
+        "a && !b || !a && b"
+    );
+    assert_eq!(
+        parse("a xor b").to_cnf(10).unwrap().to_string(),
+        "(!a || !b) && (a || b)"
+    );
+
+    let filters = [
+        "a and (b or c)",
+        "not (a and b) or c and d",
+        "a xor b xor c",
+        "not (a xor (b and not c)) and (d or a)",
+    ];
+
+    for filter in &filters {
+        let ast = parse(filter);
+        let dnf = ast.to_dnf(100).unwrap().compile();
+        let cnf = ast.to_cnf(100).unwrap().compile();
+        let ast = ast.compile();
+
+        for bits in 0..16 {
+            let mut ctx = ExecutionContext::new(scheme);
+            for (i, name) in ["a", "b", "c", "d"].iter().enumerate() {
+                ctx.set_field_value(name, bits & (1 << i) != 0).unwrap();
+            }
+
+            let expected = ast.execute(&ctx).unwrap();
+            assert_eq!(
+                dnf.execute(&ctx),
+                Ok(expected),
+                "{} with {:04b}",
+                filter,
+                bits
+            );
+            assert_eq!(
+                cnf.execute(&ctx),
+                Ok(expected),
+                "{} with {:04b}",
+                filter,
+                bits
+            );
+        }
+    }
+
+    let ast = parse("(a or b) and (c or d) and (a or c)");
+    assert_eq!(ast.to_dnf(8).unwrap().to_string().matches("||").count(), 7);
+    assert_eq!(ast.to_dnf(7), Err(NormalFormSizeError { max_terms: 7 }));
+    assert_eq!(ast.to_cnf(3).unwrap(), ast);
+}
+
+#[test]
+fn test_anonymize() {
+    let scheme = &Scheme! {
+        http.host: Bytes,
+        ip.src: Ip,
+        ip.dst: Ip,
+        port: Int,
+    };
+
+    let ast = scheme
+        .parse(
+            r#"http.host == "Internal-01.Example.org" && port == 443
+            && ip.src in { 192.168.1.0/24 172.16.0.1..172.16.0.9 2001:67c::1 }
+            && ip.dst == 192.168.1.0 && ip.src != 172.16.0.1
+            && http.host contains 6d:79 && http.host ~ "^db\d{2}\.corp$""#,
+        )
+        .unwrap()
+        .anonymize();
+
+    assert_eq!(
+        ast.to_string(),
+        concat!(
+            r#"http.host == "Xxxxxxxx-00.Xxxxxxx.xxx" && port == 443"#,
+            " && ip.src in { 10.0.0.0/24 10.0.1.0..10.0.1.1 2001:db8::/128 }",
+            " && ip.dst == 10.0.1.2 && ip.src != 10.0.1.0",
+            r#" && http.host contains 78:78 && http.host ~ "^xx\d{2}\.xxxx$""#,
+        )
+    );
+    assert_eq!(scheme.parse(&ast.to_string()), Ok(ast));
+}
+
+#[test]
+fn test_combinators() {
+    let scheme = &Scheme! {
+        a: Bool,
+        b: Bool,
+        c: Bool,
+        d: Bool,
+    };
+
+    let parse = |s| scheme.parse(s).unwrap();
+
+    assert_eq!(parse("a").and(parse("b")).unwrap(), parse("a and b"));
+
+    assert_eq!(
+        parse("a and b").and(parse("c && d")).unwrap(),
+        parse("a and b and c and d")
+    );
+
+    assert_eq!(
+        parse("a or b").and(parse("c")).unwrap(),
+        parse("(a or b) and c")
+    );
+
+    assert_eq!(
+        parse("a and b").or(parse("c xor d")).unwrap(),
+        parse("a and b or c xor d")
+    );
+
+    assert_eq!(!parse("a"), parse("not a"));
+
+    assert_eq!(!parse("a or b"), parse("not (a or b)"));
+
+    assert_eq!(
+        !parse("a").and(!parse("b")).unwrap(),
+        parse("not (a and not b)")
+    );
+
+    let other_scheme = &Scheme! { a: Bool };
+
+    assert_eq!(
+        parse("a").and(other_scheme.parse("a").unwrap()),
+        Err(SchemeMismatchError::new(scheme, other_scheme))
+    );
+}
+
+#[test]
+fn test_restrict_to() {
+    let scheme = &Scheme! {
+        ip.src: Ip,
+        tcp.port: Int,
+        http.host: Bytes,
+    };
+
+    let projection = &scheme.project(vec!["ip.src", "tcp.port"]).unwrap();
+
+    assert!(scheme.project(vec!["ip.dst"]).is_err());
+
+    let split = scheme
+        .parse(r#"ip.src in { 10.0.0.0/8 } and http.host == "example.org" and tcp.port == 443"#)
+        .unwrap()
+        .restrict_to(projection);
+
+    assert_eq!(
+        split,
+        SplitFilterAst {
+            projected: Some(
+                projection
+                    .parse("ip.src in { 10.0.0.0/8 } and tcp.port == 443")
+                    .unwrap()
+            ),
+            residual: Some(scheme.parse(r#"http.host == "example.org""#).unwrap()),
+        }
+    );
+
+    let split = scheme
+        .parse(r#"ip.src == 10.0.0.1 or http.host == "example.org""#)
+        .unwrap()
+        .restrict_to(projection);
+
+    assert_eq!(
+        split,
+        SplitFilterAst {
+            projected: None,
+            residual: Some(
+                scheme
+                    .parse(r#"ip.src == 10.0.0.1 or http.host == "example.org""#)
+                    .unwrap()
+            ),
+        }
+    );
+
+    let split = scheme
+        .parse("(ip.src == 10.0.0.1 or tcp.port == 80)")
+        .unwrap()
+        .restrict_to(projection);
+
+    assert_eq!(
+        split,
+        SplitFilterAst {
+            projected: Some(
+                projection
+                    .parse("(ip.src == 10.0.0.1 or tcp.port == 80)")
+                    .unwrap()
+            ),
+            residual: None,
+        }
+    );
+}
+
+#[test]
+fn test_literals() {
+    use crate::types::Type;
+
+    let scheme = &Scheme! {
+        ssl: Bool,
+        tcp.port: Int,
+    };
+    let parse = |filter: &str| scheme.parse(filter).unwrap();
+
+    let ctx = &mut ExecutionContext::new(scheme);
+    ctx.set_field_value("ssl", true).unwrap();
+    ctx.set_field_value("tcp.port", 80).unwrap();
+
+    for (filter, folded, matches) in &[
+        ("true && ssl", Some("ssl"), true),
+        ("ssl and false", None, false),
+        ("false || tcp.port == 80", Some("tcp.port == 80"), true),
+        ("(true or ssl) and not false", None, true),
+        ("ssl ^^ true", Some("!ssl"), false),
+        ("!true", None, false),
+    ] {
+        let ast = parse(filter);
+        assert_eq!(parse(&ast.to_filter_string(OperatorStyle::Symbols)), ast);
+
+        let json = serde_json::to_string(&ast).unwrap();
+        assert_eq!(
+            scheme
+                .parse_from_ast(&mut serde_json::Deserializer::from_str(&json))
+                .unwrap(),
+            ast
+        );
+
+        let filter = ast.clone().compile();
+        assert_eq!(filter.execute(ctx), Ok(*matches), "{}", json);
+        assert_eq!(ast.clone().compile_to_bytecode().execute(ctx), Ok(*matches));
+
+        // Literals are folded away during compilation.
+        let cost = folded.map_or(0, |folded| parse(folded).compile().worst_case_cost());
+        assert_eq!(filter.worst_case_cost(), cost);
+    }
+
+    assert_eq!(
+        parse("ssl and false or tcp.port == 80 and true")
+            .to_dnf(8)
+            .unwrap()
+            .to_filter_string(OperatorStyle::Symbols),
+        "tcp.port == 80"
+    );
+    assert_eq!(
+        parse("ssl && false")
+            .to_dnf(8)
+            .unwrap()
+            .to_filter_string(OperatorStyle::Symbols),
+        "false"
+    );
+    assert_eq!(
+        parse("ssl || !ssl || true")
+            .to_cnf(8)
+            .unwrap()
+            .to_filter_string(OperatorStyle::Symbols),
+        "true"
+    );
+
+    // Fields named like literals take precedence.
+    let scheme = &Scheme::try_from_iter(vec![("true".to_owned(), Type::Int)]).unwrap();
+    let ctx = &mut ExecutionContext::new(scheme);
+    ctx.set_field_value("true", 1).unwrap();
+    assert_eq!(
+        scheme
+            .parse("true == 1 && !false")
+            .unwrap()
+            .compile()
+            .execute(ctx),
+        Ok(true)
+    );
+}
+
+#[test]
+fn test_compile_with_constants() {
+    let scheme = &Scheme! {
+        zone.id: Int,
+        tcp.port: Int,
+        ssl: Bool,
+    };
+
+    let constants = &mut ExecutionContext::new(scheme);
+    constants.set_field_value("zone.id", 1).unwrap();
+
+    let fold = |filter: &str| match scheme
+        .parse(filter)
+        .unwrap()
+        .op
+        .fold_constants(Some(constants))
+    {
+        Folded::Expr(op) => Ok(op),
+        Folded::Constant(value) => Err(value),
+    };
+    let parse = |filter: &str| Ok(scheme.parse(filter).unwrap().op);
+
+    assert_eq!(fold("zone.id == 1"), Err(true));
+    assert_eq!(fold("zone.id == 2 and ssl"), Err(false));
+    assert_eq!(fold("zone.id == 1 and ssl"), parse("ssl"));
+    assert_eq!(fold("zone.id == 1 or ssl"), Err(true));
+    assert_eq!(
+        fold("zone.id != 1 or ssl and tcp.port == 443"),
+        parse("ssl and tcp.port == 443")
+    );
+    assert_eq!(
+        fold("zone.id == 1 xor ssl xor ssl"),
+        parse("not (ssl xor ssl)")
+    );
+    assert_eq!(fold("not (zone.id == 1 and ssl)"), parse("not (ssl)"));
+
+    let filter = scheme
+        .parse("zone.id == 1 and tcp.port == 443")
+        .unwrap()
+        .compile_with_constants(constants)
+        .unwrap();
+
+    let ctx = &mut ExecutionContext::new(scheme);
+    ctx.set_field_value("tcp.port", 443).unwrap();
+
+    // `zone.id` was folded, so it's not needed at runtime anymore.
+    assert_eq!(filter.execute(ctx), Ok(true));
+
+    let other_scheme = &Scheme! { zone.id: Int };
+
+    assert!(other_scheme
+        .parse("zone.id == 1")
+        .unwrap()
+        .compile_with_constants(constants)
+        .is_err());
+}
+
+#[test]
+fn test_to_filter_string() {
+    let scheme = &Scheme! {
+        ip.addr: Ip,
+        tcp.port: Int,
+        http.host: Bytes,
+        ssl: Bool,
+    };
+
+    let ast = scheme
+        .parse(
+            r#"not ssl and (tcp.port >= 1024 || tcp.port & 0x1) xor http.host in { "a\"b" 01:02 }
+                or ip.addr in { 10.0.0.0/8 ::1 192.168.0.1..192.168.0.255 } && tcp.port in { 80 8000..8080 }
+                or http.host matches "^[a"]\\d\"$" and http.host contains "x""#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        ast.to_string(),
+        r#"!ssl && (tcp.port >= 1024 || tcp.port & 1) ^^ http.host in { "a\"b" 01:02 } || ip.addr in { 10.0.0.0/8 ::1/128 192.168.0.1..192.168.0.255 } && tcp.port in { 80 8000..8080 } || http.host ~ "^[a"]\\d\"$" && http.host contains "x""#
+    );
+
+    assert_eq!(
+        ast.to_filter_string(OperatorStyle::Words),
+        r#"not ssl and (tcp.port ge 1024 or tcp.port bitwise_and 1) xor http.host in { "a\"b" 01:02 } or ip.addr in { 10.0.0.0/8 ::1/128 192.168.0.1..192.168.0.255 } and tcp.port in { 80 8000..8080 } or http.host matches "^[a"]\\d\"$" and http.host contains "x""#
+    );
+
+    for style in &[OperatorStyle::Symbols, OperatorStyle::Words] {
+        assert_eq!(scheme.parse(&ast.to_filter_string(*style)), Ok(ast.clone()));
+    }
+}
+
+#[test]
+fn test_golden_encoding() {
+    use crate::{
+        functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+        types::Type,
+    };
+
+    fn echo<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+        args.next().unwrap()
+    }
+
+    let mut scheme: Scheme =
+        serde_json::from_str(include_str!("../../tests/golden/scheme.json")).unwrap();
+    scheme
+        .add_function(
+            "echo".into(),
+            Function {
+                params: vec![FunctionParam {
+                    arg_kind: FunctionArgKind::Field,
+                    val_type: Type::Bytes,
+                }],
+                opt_params: vec![],
+                return_type: Type::Bytes,
+                implementation: FunctionImpl::new(echo),
+                pure: true,
+            },
+        )
+        .unwrap();
+
+    let ast = scheme
+        .parse(include_str!("../../tests/golden/filter.txt"))
+        .unwrap();
+
+    // The encoding must not change across versions and platforms, so that
+    // ASTs can be exchanged between them.
+    assert_eq!(
+        serde_json::to_value(&ast).unwrap(),
+        serde_json::from_str::<serde_json::Value>(include_str!("../../tests/golden/ast.json"))
+            .unwrap()
+    );
+
+    assert_eq!(
+        scheme
+            .parse_from_ast(&mut serde_json::Deserializer::from_str(include_str!(
+                "../../tests/golden/ast.json"
+            )))
+            .unwrap(),
+        ast
+    );
+}
+
+#[test]
+fn test_parse_from_ast() {
+    let scheme = Scheme! {
+        http.host: Bytes,
+        ip.src: Ip,
+        tcp.port: Int,
+        ratio: Float,
+        ssl: Bool,
+    };
+
+    let from_json =
+        |json: &str| scheme.parse_from_ast(&mut serde_json::Deserializer::from_str(json));
+
+    for filter in &[
+        r#"ssl && (http.host == "example.org" || http.host contains 65:78) && !(ssl || tcp.port in { 80 443..444 })"#,
+        r#"(ssl ^^ ssl) ^^ ssl || ip.src in { 10.0.0.0/8 ::1 127.0.0.1..127.0.0.9 } && ratio in { 0.5..1.5 }"#,
+        r#"tcp.port & 1 && ratio < 0.25 && ip.src != ::1 && http.host matches "^a.*$""#,
+        r#"tcp.port >> 8 == 1 || tcp.port ^ 3 != 0 || tcp.port | 1"#,
+        r#"all(http.host contains "a") && !all(tcp.port in { 80 443 })"#,
+    ] {
+        let ast = scheme.parse(filter).unwrap();
+        let json = serde_json::to_string(&ast).unwrap();
+        assert_eq!(from_json(&json).unwrap(), ast);
+    }
+
+    let error = |json: &str| from_json(json).unwrap_err().to_string();
+
+    assert_eq!(
+        error(r#"{"lhs": "http.path", "op": "IsTrue"}"#),
+        "unknown field http.path"
+    );
+    assert_eq!(
+        error(r#"{"lhs": "tcp.port", "op": "Equal", "rhs": "80"}"#),
+        "invalid literal of type Int"
+    );
+    assert_eq!(
+        error(r#"{"lhs": "http.host", "op": "BitwiseAnd", "rhs": 1}"#),
+        "unsupported operator BitwiseAnd for type Bytes"
+    );
+    assert_eq!(
+        error(
+            r#"{"lhs": "tcp.port", "bitwise": {"op": "BitwiseAnd", "rhs": 1}, "op": "OneOf", "rhs": [{"start": 1, "end": 1}]}"#
+        ),
+        "bitwise operations are not supported with operator OneOf"
+    );
+    assert_eq!(
+        error(r#"{"lhs": "tcp.port", "op": "OneOf", "rhs": [{"start": 2, "end": 1}]}"#),
+        "invalid range of type Int"
+    );
+    assert_eq!(
+        error(r#"{"op": "And", "items": [{"lhs": "ssl", "op": "IsTrue"}]}"#),
+        "And must have at least two operands"
+    );
 }
@@ -0,0 +1,358 @@
+//! A scheme-independent representation of serialized ASTs.
+//!
+//! ASTs are serialized with names of fields and functions and with untyped
+//! literals, so they are first deserialized into the types below, and then
+//! bound to a scheme, which resolves names and checks types just like the
+//! parser does.
+
+use super::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    field_expr::{Bitwise, FieldExpr, LhsFieldExpr, Quantifier},
+    function_expr::{FunctionCallArgExpr, FunctionCallExpr},
+    simple_expr::{SimpleExpr, UnaryOp},
+};
+use crate::{
+    functions::{FunctionArgKind, FunctionParam},
+    lex::LexErrorKind,
+    rhs_types::{Bytes, ExplicitIpRange, Float, IpRange, Timestamp, TimestampLiteral},
+    scheme::Scheme,
+    types::{GetType, RhsValue, RhsValues, Type},
+};
+use cidr::IpCidr;
+use serde::Deserialize;
+use std::{convert::TryFrom, net::IpAddr, ops::RangeInclusive};
+
+pub(crate) type BindResult<T> = Result<T, String>;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ExprRepr {
+    Combining {
+        op: CombiningOp,
+        items: Vec<ExprRepr>,
+    },
+    Unary {
+        op: UnaryOp,
+        arg: Box<ExprRepr>,
+    },
+    Field(FieldExprRepr),
+    Literal(bool),
+}
+
+impl ExprRepr {
+    pub fn bind<'s>(&self, scheme: &'s Scheme) -> BindResult<CombinedExpr<'s>> {
+        match self {
+            ExprRepr::Combining { op, items } => {
+                if items.len() < 2 {
+                    return Err(format!("{:?} must have at least two operands", op));
+                }
+
+                Ok(CombinedExpr::Combining {
+                    op: *op,
+                    items: items
+                        .iter()
+                        .map(|item| match item {
+                            // Parentheses are not serialized, so restore
+                            // them where the parser would require them.
+                            ExprRepr::Combining { op: item_op, .. } if item_op <= op => {
+                                Ok(CombinedExpr::Simple(SimpleExpr::Parenthesized(Box::new(
+                                    item.bind(scheme)?,
+                                ))))
+                            }
+                            item => item.bind(scheme),
+                        })
+                        .collect::<BindResult<_>>()?,
+                })
+            }
+            _ => Ok(CombinedExpr::Simple(self.bind_simple(scheme)?)),
+        }
+    }
+
+    fn bind_simple<'s>(&self, scheme: &'s Scheme) -> BindResult<SimpleExpr<'s>> {
+        Ok(match self {
+            ExprRepr::Combining { .. } => SimpleExpr::Parenthesized(Box::new(self.bind(scheme)?)),
+            ExprRepr::Unary { op, arg } => SimpleExpr::Unary {
+                op: *op,
+                arg: Box::new(arg.bind_simple(scheme)?),
+            },
+            ExprRepr::Field(field) => SimpleExpr::Field(FieldExpr::bind(field, scheme)?),
+            ExprRepr::Literal(value) => SimpleExpr::Literal(*value),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct FieldExprRepr {
+    pub lhs: LhsRepr,
+    pub op: String,
+    #[serde(default)]
+    pub rhs: Option<ValueRepr>,
+    #[serde(default)]
+    pub rhs_expr: Option<LhsRepr>,
+    #[serde(default)]
+    pub zone: Option<String>,
+    #[serde(default)]
+    pub bitwise: Option<Bitwise>,
+    #[serde(default)]
+    pub quantifier: Option<Quantifier>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum LhsRepr {
+    Field(String),
+    FunctionCall { name: String, args: Vec<ArgRepr> },
+}
+
+impl LhsRepr {
+    pub fn bind<'s>(&self, scheme: &'s Scheme) -> BindResult<LhsFieldExpr<'s>> {
+        Ok(match self {
+            LhsRepr::Field(name) => LhsFieldExpr::Field(
+                scheme
+                    .get_field_index(name)
+                    .map_err(|_| format!("unknown field {}", name))?,
+            ),
+            LhsRepr::FunctionCall { .. } if !scheme.parser_settings().allow_function_calls => {
+                return Err(LexErrorKind::ForbiddenFunctionCall.to_string());
+            }
+            LhsRepr::FunctionCall { name, args } => {
+                LhsFieldExpr::FunctionCallExpr(bind_function_call(name, args, scheme)?)
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", content = "value")]
+pub(crate) enum ArgRepr {
+    LhsFieldExpr(LhsRepr),
+    Literal(ValueRepr),
+}
+
+fn bind_function_call<'s>(
+    name: &str,
+    args: &[ArgRepr],
+    scheme: &'s Scheme,
+) -> BindResult<FunctionCallExpr<'s>> {
+    let function = scheme
+        .get_function(name)
+        .map_err(|_| format!("unknown function {}", name))?;
+
+    let max_args = function.params.len() + function.opt_params.len();
+    if args.len() < function.params.len() || args.len() > max_args {
+        return Err(format!("invalid number of arguments of function {}", name));
+    }
+
+    let mut call = FunctionCallExpr::new(name, function);
+
+    for (index, arg) in args.iter().enumerate() {
+        let param = match function.params.get(index) {
+            Some(param) => param.clone(),
+            None => {
+                let opt_param = &function.opt_params[index - function.params.len()];
+                FunctionParam {
+                    arg_kind: opt_param.arg_kind.clone(),
+                    val_type: opt_param.default_value.get_type(),
+                }
+            }
+        };
+
+        let arg = match (param.arg_kind, arg) {
+            (FunctionArgKind::Field, ArgRepr::LhsFieldExpr(lhs)) => {
+                let lhs = lhs.bind(scheme)?;
+                if lhs.get_type() != param.val_type {
+                    return Err(format!(
+                        "argument {} of function {} must be of type {:?}",
+                        index, name, param.val_type
+                    ));
+                }
+                FunctionCallArgExpr::LhsFieldExpr(lhs)
+            }
+            (FunctionArgKind::Literal, ArgRepr::Literal(value)) => {
+                FunctionCallArgExpr::Literal(value.to_rhs_value(param.val_type)?)
+            }
+            _ => {
+                return Err(format!(
+                    "argument {} of function {} has an invalid kind",
+                    index, name
+                ));
+            }
+        };
+
+        call.args.push(arg);
+    }
+
+    Ok(call)
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum ValueRepr {
+    Int(i64),
+    Float(f64),
+    String(String),
+    Range {
+        start: Box<ValueRepr>,
+        end: Box<ValueRepr>,
+    },
+    List(Vec<ValueRepr>),
+}
+
+fn invalid_literal(ty: Type) -> String {
+    format!("invalid literal of type {:?}", ty)
+}
+
+impl ValueRepr {
+    fn ip(&self) -> BindResult<IpAddr> {
+        match self {
+            ValueRepr::String(s) => s.parse().map_err(|_| invalid_literal(Type::Ip)),
+            _ => Err(invalid_literal(Type::Ip)),
+        }
+    }
+
+    pub fn int(&self) -> BindResult<i64> {
+        match self {
+            ValueRepr::Int(int) => Ok(*int),
+            _ => Err(invalid_literal(Type::Int)),
+        }
+    }
+
+    fn float(&self) -> BindResult<Float> {
+        let value = match self {
+            ValueRepr::Int(int) => *int as f64,
+            ValueRepr::Float(float) => *float,
+            _ => return Err(invalid_literal(Type::Float)),
+        };
+        Float::new(value)
+            .filter(|value| value.get().is_finite())
+            .ok_or_else(|| invalid_literal(Type::Float))
+    }
+
+    fn timestamp_literal(&self) -> BindResult<TimestampLiteral> {
+        match self {
+            ValueRepr::String(s) => {
+                TimestampLiteral::parse(s).ok_or_else(|| invalid_literal(Type::Timestamp))
+            }
+            _ => Err(invalid_literal(Type::Timestamp)),
+        }
+    }
+
+    fn timestamp(&self) -> BindResult<Timestamp> {
+        match self {
+            ValueRepr::String(s) => {
+                Timestamp::parse(s).map_err(|_| invalid_literal(Type::Timestamp))
+            }
+            _ => Err(invalid_literal(Type::Timestamp)),
+        }
+    }
+
+    pub fn bytes(&self) -> BindResult<Bytes> {
+        match self {
+            ValueRepr::String(s) => Ok(s.clone().into()),
+            ValueRepr::List(items) => items
+                .iter()
+                .map(|item| match item {
+                    ValueRepr::Int(b) => u8::try_from(*b).ok(),
+                    _ => None,
+                })
+                .collect::<Option<Vec<u8>>>()
+                .map(Bytes::from)
+                .ok_or_else(|| invalid_literal(Type::Bytes)),
+            _ => Err(invalid_literal(Type::Bytes)),
+        }
+    }
+
+    fn range<T: Ord>(
+        &self,
+        ty: Type,
+        bound: impl Fn(&Self) -> BindResult<T>,
+    ) -> BindResult<RangeInclusive<T>> {
+        match self {
+            ValueRepr::Range { start, end } => {
+                let (start, end) = (bound(start)?, bound(end)?);
+                if start <= end {
+                    Ok(start..=end)
+                } else {
+                    Err(format!("invalid range of type {:?}", ty))
+                }
+            }
+            _ => Err(invalid_literal(ty)),
+        }
+    }
+
+    fn ip_range(&self) -> BindResult<IpRange> {
+        match self {
+            ValueRepr::String(s) => s
+                .parse::<IpCidr>()
+                .map(IpRange::Cidr)
+                .map_err(|_| invalid_literal(Type::Ip)),
+            _ => match self.range(Type::Ip, Self::ip)?.into_inner() {
+                (IpAddr::V4(start), IpAddr::V4(end)) => {
+                    Ok(IpRange::Explicit(ExplicitIpRange::V4(start..=end)))
+                }
+                (IpAddr::V6(start), IpAddr::V6(end)) => {
+                    Ok(IpRange::Explicit(ExplicitIpRange::V6(start..=end)))
+                }
+                _ => Err(format!("invalid range of type {:?}", Type::Ip)),
+            },
+        }
+    }
+
+    pub fn to_rhs_value(&self, ty: Type) -> BindResult<RhsValue> {
+        Ok(match ty {
+            Type::Ip => RhsValue::Ip(self.ip()?),
+            Type::Bytes => RhsValue::Bytes(self.bytes()?),
+            Type::Int => RhsValue::Int(self.int()?),
+            Type::Float => RhsValue::Float(self.float()?),
+            Type::Timestamp => RhsValue::Timestamp(self.timestamp_literal()?),
+            Type::Bool => return Err(invalid_literal(Type::Bool)),
+        })
+    }
+
+    pub fn to_rhs_values(&self, ty: Type, max_elements: Option<usize>) -> BindResult<RhsValues> {
+        let items = match self {
+            ValueRepr::List(items) => items,
+            _ => return Err(format!("expected a list of type {:?}", ty)),
+        };
+
+        if let Some(max_elements) = max_elements {
+            if items.len() > max_elements {
+                return Err(format!("list has more than {} elements", max_elements));
+            }
+        }
+
+        Ok(match ty {
+            Type::Ip => RhsValues::Ip(
+                items
+                    .iter()
+                    .map(Self::ip_range)
+                    .collect::<BindResult<_>>()?,
+            ),
+            Type::Bytes => {
+                RhsValues::Bytes(items.iter().map(Self::bytes).collect::<BindResult<_>>()?)
+            }
+            Type::Int => RhsValues::Int(
+                items
+                    .iter()
+                    .map(|item| item.range(ty, Self::int))
+                    .collect::<BindResult<_>>()?,
+            ),
+            Type::Float => RhsValues::Float(
+                items
+                    .iter()
+                    .map(|item| item.range(ty, Self::float))
+                    .collect::<BindResult<_>>()?,
+            ),
+            Type::Timestamp => RhsValues::Timestamp(
+                items
+                    .iter()
+                    .map(|item| match item {
+                        ValueRepr::String(_) => item.timestamp().map(|value| value..=value),
+                        _ => item.range(ty, Self::timestamp),
+                    })
+                    .collect::<BindResult<_>>()?,
+            ),
+            Type::Bool => return Err(invalid_literal(Type::Bool)),
+        })
+    }
+}
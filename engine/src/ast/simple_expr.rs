@@ -1,14 +1,26 @@
-use super::{combined_expr::CombinedExpr, field_expr::FieldExpr, CompiledExpr, Expr};
+use super::{
+    combined_expr::{CombinedExpr, CombiningOp},
+    field_expr::FieldExpr,
+    function_expr::CachedCalls,
+    BindField, CompiledExpr, Expr, Folded, OperatorStyle, TraceNode,
+};
 use crate::{
+    execution_context::ExecutionContext,
     lex::{expect, skip_space, Lex, LexResult, LexWith},
+    parser_settings::ParserSettings,
     scheme::{Field, Scheme},
+    vm::{Instr, Program},
 };
 use serde::Serialize;
+use std::fmt;
 
 lex_enum!(UnaryOp {
     "not" | "!" => Not,
 });
 
+// Comparisons are by far the most common expressions, so they are stored
+// inline rather than boxed.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, PartialEq, Eq, Clone, Serialize)]
 #[serde(untagged)]
 pub enum SimpleExpr<'s> {
@@ -18,6 +30,24 @@ pub enum SimpleExpr<'s> {
         op: UnaryOp,
         arg: Box<SimpleExpr<'s>>,
     },
+    Literal(bool),
+}
+
+// Lexes `true` or `false`, unless the scheme has a field with that name.
+fn lex_literal<'i>(input: &'i str, scheme: &Scheme) -> Option<(bool, &'i str)> {
+    let len = input
+        .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '.')
+        .unwrap_or(input.len());
+    let (word, rest) = input.split_at(len);
+    let value = match word {
+        "true" => true,
+        "false" => false,
+        _ => return None,
+    };
+    if scheme.contains(word) {
+        return None;
+    }
+    Some((value, rest))
 }
 
 impl<'i, 's> LexWith<'i, &'s Scheme> for SimpleExpr<'s> {
@@ -38,6 +68,19 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for SimpleExpr<'s> {
                 },
                 input,
             )
+        } else if let Some((value, input)) = lex_literal(input, scheme) {
+            (SimpleExpr::Literal(value), input)
+        } else if let Ok(((lower, upper), input)) = FieldExpr::lex_chain(input, scheme) {
+            // Chained comparisons are desugared into a parenthesized
+            // conjunction so that they bind tighter than any other operator.
+            let op = CombinedExpr::Combining {
+                op: CombiningOp::And,
+                items: vec![
+                    CombinedExpr::Simple(SimpleExpr::Field(lower)),
+                    CombinedExpr::Simple(SimpleExpr::Field(upper)),
+                ],
+            };
+            (SimpleExpr::Parenthesized(Box::new(op)), input)
         } else {
             let (op, input) = FieldExpr::lex_with(input, scheme)?;
             (SimpleExpr::Field(op), input)
@@ -45,25 +88,148 @@ impl<'i, 's> LexWith<'i, &'s Scheme> for SimpleExpr<'s> {
     }
 }
 
+impl<'s> SimpleExpr<'s> {
+    pub(crate) fn rebind<'p>(
+        &self,
+        scheme: &'p Scheme,
+        bind_field: BindField<'_, 's, 'p>,
+    ) -> Option<SimpleExpr<'p>> {
+        Some(match self {
+            SimpleExpr::Field(op) => SimpleExpr::Field(op.rebind(scheme, bind_field)?),
+            SimpleExpr::Parenthesized(op) => {
+                SimpleExpr::Parenthesized(Box::new(op.rebind_with(scheme, bind_field)?))
+            }
+            SimpleExpr::Unary { op, arg } => SimpleExpr::Unary {
+                op: *op,
+                arg: Box::new(arg.rebind(scheme, bind_field)?),
+            },
+            SimpleExpr::Literal(value) => SimpleExpr::Literal(*value),
+        })
+    }
+}
+
 impl<'s> Expr<'s> for SimpleExpr<'s> {
     fn uses(&self, field: Field<'s>) -> bool {
         match self {
             SimpleExpr::Field(op) => op.uses(field),
             SimpleExpr::Parenthesized(op) => op.uses(field),
             SimpleExpr::Unary { arg, .. } => arg.uses(field),
+            SimpleExpr::Literal(_) => false,
+        }
+    }
+
+    fn for_each_field_expr<'a>(&'a self, visit: &mut dyn FnMut(&'a FieldExpr<'s>)) {
+        match self {
+            SimpleExpr::Field(op) => op.for_each_field_expr(visit),
+            SimpleExpr::Parenthesized(op) => op.for_each_field_expr(visit),
+            SimpleExpr::Unary { arg, .. } => arg.for_each_field_expr(visit),
+            SimpleExpr::Literal(_) => {}
         }
     }
 
-    fn compile(self) -> CompiledExpr<'s> {
+    fn for_each_field_expr_mut(&mut self, visit: &mut dyn FnMut(&mut FieldExpr<'s>)) {
         match self {
-            SimpleExpr::Field(op) => op.compile(),
-            SimpleExpr::Parenthesized(op) => op.compile(),
+            SimpleExpr::Field(op) => op.for_each_field_expr_mut(visit),
+            SimpleExpr::Parenthesized(op) => op.for_each_field_expr_mut(visit),
+            SimpleExpr::Unary { arg, .. } => arg.for_each_field_expr_mut(visit),
+            SimpleExpr::Literal(_) => {}
+        }
+    }
+
+    fn fmt_filter(&self, f: &mut fmt::Formatter<'_>, style: OperatorStyle) -> fmt::Result {
+        match self {
+            SimpleExpr::Field(op) => op.fmt_filter(f, style),
+            SimpleExpr::Parenthesized(op) => {
+                f.write_str("(")?;
+                op.fmt_filter(f, style)?;
+                f.write_str(")")
+            }
             SimpleExpr::Unary {
                 op: UnaryOp::Not,
                 arg,
             } => {
-                let arg = arg.compile();
-                CompiledExpr::new(move |ctx| !arg.execute(ctx))
+                f.write_str(style.pick("!", "not "))?;
+                arg.fmt_filter(f, style)
+            }
+            SimpleExpr::Literal(value) => write!(f, "{}", value),
+        }
+    }
+
+    fn fold_constants(self, constants: Option<&ExecutionContext<'s>>) -> Folded<Self> {
+        match self {
+            SimpleExpr::Field(op) => op.fold_constants(constants).map(SimpleExpr::Field),
+            SimpleExpr::Parenthesized(op) => op
+                .fold_constants(constants)
+                .map(|op| SimpleExpr::Parenthesized(Box::new(op))),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => match arg.fold_constants(constants) {
+                Folded::Expr(arg) => Folded::Expr(SimpleExpr::Unary {
+                    op: UnaryOp::Not,
+                    arg: Box::new(arg),
+                }),
+                Folded::Constant(value) => Folded::Constant(!value),
+            },
+            SimpleExpr::Literal(value) => Folded::Constant(value),
+        }
+    }
+
+    fn compile_with_settings(
+        self,
+        settings: &ParserSettings,
+        cached_calls: &CachedCalls<'s>,
+    ) -> CompiledExpr<'s> {
+        match self {
+            SimpleExpr::Field(op) => op.compile_with_settings(settings, cached_calls),
+            SimpleExpr::Parenthesized(op) => op.compile_with_settings(settings, cached_calls),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => {
+                let arg = arg.compile_with_settings(settings, cached_calls);
+                CompiledExpr::new(move |ctx, calls| !arg.execute_with(ctx, calls))
+            }
+            SimpleExpr::Literal(value) => CompiledExpr::new(move |_, _| value),
+        }
+    }
+
+    fn trace(&self, ctx: &ExecutionContext<'s>) -> TraceNode<'s> {
+        match self {
+            SimpleExpr::Field(op) => op.trace(ctx),
+            SimpleExpr::Parenthesized(op) => op.trace(ctx),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => {
+                let operand = arg.trace(ctx);
+                TraceNode::Not {
+                    result: !operand.result(),
+                    operand: Box::new(operand),
+                }
+            }
+            SimpleExpr::Literal(value) => TraceNode::Literal { result: *value },
+        }
+    }
+
+    fn emit(
+        self,
+        settings: &ParserSettings,
+        cached_calls: &CachedCalls<'s>,
+        program: &mut Program<'s>,
+    ) {
+        match self {
+            SimpleExpr::Field(op) => op.emit(settings, cached_calls, program),
+            SimpleExpr::Parenthesized(op) => op.emit(settings, cached_calls, program),
+            SimpleExpr::Unary {
+                op: UnaryOp::Not,
+                arg,
+            } => {
+                arg.emit(settings, cached_calls, program);
+                program.push(Instr::Not);
+            }
+            SimpleExpr::Literal(value) => {
+                program.push(Instr::Set(value));
             }
         }
     }
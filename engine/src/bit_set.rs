@@ -0,0 +1,68 @@
+use std::ops::RangeInclusive;
+
+/// Values up to this one (inclusive) are stored in a [`BitSet`], which
+/// covers common small domains like ports while using at most 8 KiB.
+const MAX_VALUE: i64 = 0xFFFF;
+
+const WORD_BITS: usize = 64;
+
+/// BitSet provides a constant-time membership check for sets of small
+/// non-negative integers.
+pub struct BitSet {
+    words: Box<[u64]>,
+}
+
+impl BitSet {
+    /// Builds a set from inclusive ranges, or returns `None` if any of them
+    /// doesn't fit into a small domain.
+    pub fn from_ranges(ranges: &[RangeInclusive<i64>]) -> Option<Self> {
+        let max = ranges.iter().map(|range| *range.end()).max().unwrap_or(0);
+
+        if max > MAX_VALUE || ranges.iter().any(|range| *range.start() < 0) {
+            return None;
+        }
+
+        let mut words = vec![0u64; max as usize / WORD_BITS + 1];
+
+        for range in ranges {
+            for value in range.clone() {
+                let value = value as usize;
+                words[value / WORD_BITS] |= 1 << (value % WORD_BITS);
+            }
+        }
+
+        Some(BitSet {
+            words: words.into_boxed_slice(),
+        })
+    }
+
+    /// Checks whether a value is in the set.
+    pub fn contains(&self, value: i64) -> bool {
+        if value < 0 {
+            return false;
+        }
+
+        let value = value as usize;
+
+        match self.words.get(value / WORD_BITS) {
+            Some(word) => word & (1 << (value % WORD_BITS)) != 0,
+            None => false,
+        }
+    }
+}
+
+#[test]
+fn test() {
+    let set = BitSet::from_ranges(&[443..=443, 80..=80, 63..=65, 80..=80]).unwrap();
+
+    for value in &[63, 64, 65, 80, 443] {
+        assert!(set.contains(*value));
+    }
+
+    for value in &[-1, 0, 62, 66, 81, 442, 444, 0xFFFF, i64::MAX] {
+        assert!(!set.contains(*value));
+    }
+
+    assert!(BitSet::from_ranges(&[0..=0x10000]).is_none());
+    assert!(BitSet::from_ranges(&[-1..=80]).is_none());
+}
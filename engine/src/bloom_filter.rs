@@ -0,0 +1,121 @@
+use fnv::FnvHasher;
+use std::{f64::consts::LN_2, hash::Hasher};
+
+const WORD_BITS: u64 = 64;
+
+// Bounds of false positive rates. Lower rates than the minimum take over
+// 40 bits per item, more than the exact set behind the filter saves, and
+// `0` would need infinitely many. Higher rates than the maximum let most
+// non-members through.
+const MIN_FALSE_POSITIVE_RATE: f64 = 1e-9;
+const MAX_FALSE_POSITIVE_RATE: f64 = 0.5;
+
+/// BloomFilter provides a probabilistic set-like interface for byte strings
+/// which never gives false negatives, but can give false positives with a
+/// configured rate.
+///
+/// It's used as a cheap pre-check in front of large exact sets.
+pub struct BloomFilter {
+    words: Box<[u64]>,
+    bit_count: u64,
+    hash_count: u32,
+}
+
+// Double hashing scheme from "Less Hashing, Same Performance: Building a
+// Better Bloom Filter" by Kirsch and Mitzenmacher: two halves of a single
+// 64-bit hash are combined to simulate any number of hash functions.
+fn hash_pair(item: &[u8]) -> (u64, u64) {
+    let mut hasher = FnvHasher::default();
+    hasher.write(item);
+    let hash = hasher.finish();
+    (hash & 0xFFFF_FFFF, (hash >> 32) | 1)
+}
+
+impl BloomFilter {
+    /// Builds a filter for given items with a target false positive rate.
+    ///
+    /// The rate is clamped to `1e-9..=0.5`, and NaN is treated as `0.5`.
+    pub fn new<'a, I>(items: I, false_positive_rate: f64) -> Self
+    where
+        I: ExactSizeIterator<Item = &'a [u8]>,
+    {
+        let item_count = items.len().max(1) as f64;
+        let false_positive_rate = if false_positive_rate.is_nan() {
+            MAX_FALSE_POSITIVE_RATE
+        } else {
+            false_positive_rate.clamp(MIN_FALSE_POSITIVE_RATE, MAX_FALSE_POSITIVE_RATE)
+        };
+
+        // Optimal number of bits and hash functions for a given rate.
+        let bit_count = (-item_count * false_positive_rate.ln() / (LN_2 * LN_2))
+            .ceil()
+            .max(WORD_BITS as f64) as u64;
+        let hash_count = ((bit_count as f64 / item_count) * LN_2).round().max(1.0) as u32;
+
+        let mut filter = BloomFilter {
+            words: vec![0; (bit_count / WORD_BITS + 1) as usize].into(),
+            bit_count,
+            hash_count,
+        };
+
+        for item in items {
+            let (h1, h2) = hash_pair(item);
+            for i in 0..u64::from(filter.hash_count) {
+                let bit = h1.wrapping_add(i.wrapping_mul(h2)) % filter.bit_count;
+                filter.words[(bit / WORD_BITS) as usize] |= 1 << (bit % WORD_BITS);
+            }
+        }
+
+        filter
+    }
+
+    /// Checks whether an item might be in the set.
+    pub fn may_contain(&self, item: &[u8]) -> bool {
+        let (h1, h2) = hash_pair(item);
+        (0..u64::from(self.hash_count)).all(|i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % self.bit_count;
+            self.words[(bit / WORD_BITS) as usize] & (1 << (bit % WORD_BITS)) != 0
+        })
+    }
+}
+
+#[test]
+fn test() {
+    let items = (0..1000)
+        .map(|i| format!("item{}", i).into_bytes())
+        .collect::<Vec<_>>();
+
+    let filter = BloomFilter::new(items.iter().map(|item| &item[..]), 0.01);
+
+    for item in &items {
+        assert!(filter.may_contain(item));
+    }
+
+    let false_positives = (0..10_000)
+        .filter(|i| filter.may_contain(format!("other{}", i).as_bytes()))
+        .count();
+
+    // Allow some slack over the configured 1% rate.
+    assert!(false_positives < 300, "{} false positives", false_positives);
+}
+
+#[test]
+fn test_out_of_range_rates() {
+    let items = (0..10_000)
+        .map(|i| format!("item{}", i).into_bytes())
+        .collect::<Vec<_>>();
+
+    for &rate in &[0.0, -1.0, 1.0, 2.0, f64::NAN, f64::INFINITY] {
+        let filter = BloomFilter::new(items.iter().map(|item| &item[..]), rate);
+
+        assert!(
+            filter.bit_count <= 10_000 * 44,
+            "{}: {}",
+            rate,
+            filter.bit_count
+        );
+        for item in &items {
+            assert!(filter.may_contain(item));
+        }
+    }
+}
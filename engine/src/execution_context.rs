@@ -1,7 +1,212 @@
 use crate::{
-    scheme::{Field, Scheme},
-    types::{GetType, LhsValue, TypeMismatchError},
+    ast::FilterAst,
+    filter::SchemeMismatchError,
+    functions::FunctionImpl,
+    list::ListValues,
+    parser_settings::Ipv6Zones,
+    rhs_types::{unmap_ipv4, Float, Timestamp},
+    scheme::{ComparisonMode, Field, FieldRef, List, Scheme, UnknownFieldError, UnknownListError},
+    types::{GetType, LhsValue, Type, TypeMismatchError},
 };
+use failure::Fail;
+use std::{
+    borrow::Cow,
+    mem,
+    net::IpAddr,
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+/// A point in time after which an execution should be abandoned.
+///
+/// It's set on an [`ExecutionContext`] and passed down to functions
+/// registered with [`FunctionImpl::with_deadline`](::FunctionImpl::with_deadline),
+/// so that embedders can enforce an overall budget per execution even when
+/// filters call into custom code.
+///
+/// The default value never expires.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Deadline(Option<Instant>);
+
+impl Deadline {
+    /// Creates a deadline at a given point in time.
+    pub fn at(instant: Instant) -> Self {
+        Deadline(Some(instant))
+    }
+
+    /// Creates a deadline after a given duration from now.
+    pub fn after(duration: Duration) -> Self {
+        Deadline::at(Instant::now() + duration)
+    }
+
+    /// Returns the time left before the deadline, or `None` if there is no
+    /// deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.0.map(|instant| {
+            let now = Instant::now();
+            if instant > now {
+                instant - now
+            } else {
+                Duration::from_secs(0)
+            }
+        })
+    }
+
+    /// Checks whether the deadline has already passed.
+    pub fn is_expired(&self) -> bool {
+        self.remaining() == Some(Duration::from_secs(0))
+    }
+}
+
+/// An error that occurs when setting a runtime value of a field in an
+/// [`ExecutionContext`].
+#[derive(Debug, PartialEq, Fail)]
+pub enum SetFieldValueError {
+    /// The field is not registered in the scheme.
+    #[fail(display = "{}", _0)]
+    UnknownField(#[cause] UnknownFieldError),
+
+    /// The value has a different type than the field.
+    #[fail(display = "{}", _0)]
+    TypeMismatch(#[cause] TypeMismatchError),
+
+    /// The field accepts exactly one value, see
+    /// [`Scheme::set_field_multi_valued`](::Scheme::set_field_multi_valued).
+    #[fail(display = "field accepts exactly one value")]
+    NotMultiValued,
+
+    /// Storing the value would exceed the memory limit of the context.
+    #[fail(display = "memory limit of {} bytes exceeded", limit)]
+    MemoryLimitExceeded {
+        /// The configured limit in bytes.
+        limit: usize,
+    },
+}
+
+/// An error that occurs when providing contents of a list with
+/// [`ExecutionContext::set_list_values`].
+#[derive(Debug, PartialEq, Fail)]
+pub enum SetListValuesError {
+    /// The list is not registered in the scheme.
+    #[fail(display = "{}", _0)]
+    UnknownList(#[cause] UnknownListError),
+
+    /// The values have a different type than elements of the list.
+    #[fail(display = "{}", _0)]
+    TypeMismatch(#[cause] TypeMismatchError),
+}
+
+/// An error that occurs when setting field values from strings with
+/// [`ExecutionContext::set_from_pairs`].
+#[derive(Debug, PartialEq, Fail)]
+pub enum SetFromPairsError {
+    /// The field is not registered in the scheme.
+    #[fail(display = "unknown field {}", _0)]
+    UnknownField(String),
+
+    /// The string can't be parsed as a value of the field's type.
+    #[fail(display = "invalid value for field {} of type {:?}", name, ty)]
+    InvalidValue {
+        /// A name of the field.
+        name: String,
+        /// A type of the field.
+        ty: Type,
+    },
+
+    /// The parsed value couldn't be set.
+    #[fail(display = "field {}: {}", name, err)]
+    SetFieldValue {
+        /// A name of the field.
+        name: String,
+        /// An underlying error.
+        #[cause]
+        err: SetFieldValueError,
+    },
+}
+
+/// A field used by a filter that doesn't have a value in an
+/// [`ExecutionContext`], as reported by
+/// [`ExecutionContext::validate_for`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MissingField<'s> {
+    /// A name of the field.
+    pub name: &'s str,
+    /// A type of the field expected by the filter.
+    pub ty: Type,
+}
+
+/// Counters of the cache for function calls repeated across comparisons of a
+/// filter, see [`FilterAst::compile`](::FilterAst::compile).
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct CallCacheStats {
+    /// Number of times a cached value was reused.
+    pub hits: usize,
+    /// Number of times a function had to be called.
+    pub misses: usize,
+}
+
+/// A byte range matched by a `contains` or `matches` comparison, recorded
+/// when [`ExecutionContext::set_record_matches`] is enabled.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct MatchedSpan<'s> {
+    /// A name of the compared field.
+    pub field: &'s str,
+    /// Offsets of the matched bytes in the value of the field.
+    pub range: Range<usize>,
+}
+
+// Number of bytes a value holds outside of the values slice.
+fn value_size(value: &LhsValue<'_>) -> usize {
+    match value {
+        LhsValue::Bytes(bytes) => bytes.len(),
+        _ => 0,
+    }
+}
+
+// Number of bytes taken by additional values of a multi-valued field.
+fn extra_values_size(values: &[LhsValue<'_>]) -> usize {
+    values
+        .iter()
+        .map(|value| mem::size_of_val(value) + value_size(value))
+        .sum()
+}
+
+// Applies a comparison mode to a value, returning the number of bytes trimmed
+// from its start.
+fn normalize_bytes(bytes: Cow<'_, [u8]>, mode: ComparisonMode) -> (usize, Cow<'_, [u8]>) {
+    let (start, end) = if mode.trim {
+        let start = bytes
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or_else(|| bytes.len());
+        let end = bytes
+            .iter()
+            .rposition(|b| !b.is_ascii_whitespace())
+            .map_or(start, |pos| pos + 1);
+        (start, end)
+    } else {
+        (0, bytes.len())
+    };
+
+    let bytes = if mode.case_insensitive && bytes[start..end].iter().any(u8::is_ascii_uppercase) {
+        Cow::Owned(bytes[start..end].to_ascii_lowercase())
+    } else {
+        match bytes {
+            Cow::Borrowed(bytes) => Cow::Borrowed(&bytes[start..end]),
+            Cow::Owned(mut bytes) => {
+                bytes.truncate(end);
+                bytes.drain(..start);
+                Cow::Owned(bytes)
+            }
+        }
+    };
+
+    (start, bytes)
+}
 
 /// An execution context stores an associated [`Scheme`](struct@Scheme) and a
 /// set of runtime values to execute [`Filter`](::Filter) against.
@@ -11,40 +216,545 @@ use crate::{
 pub struct ExecutionContext<'e> {
     scheme: &'e Scheme,
     values: Box<[Option<LhsValue<'e>>]>,
+    extra_values: Box<[Vec<LhsValue<'e>>]>,
+    ip_zones: Box<[Option<Box<str>>]>,
+    values_size: usize,
+    memory_limit: Option<usize>,
+    deadline: Deadline,
+    // Values computed during executions are kept in thread-safe cells, so
+    // that a context can be shared between threads executing filters.
+    call_cache_hits: AtomicUsize,
+    call_cache_misses: AtomicUsize,
+    virtual_values: Box<[OnceLock<LhsValue<'static>>]>,
+    record_matches: bool,
+    matched_spans: Mutex<Vec<(usize, Range<usize>)>>,
+    lists: Box<[Option<Arc<ListValues>>]>,
 }
 
 impl<'e> ExecutionContext<'e> {
     /// Creates an execution context associated with a given scheme.
     ///
     /// This scheme will be used for resolving any field names and indices.
+    /// Fields that have a [default](::Scheme::set_field_default) in the
+    /// scheme start with that value.
     pub fn new<'s: 'e>(scheme: &'s Scheme) -> Self {
+        let values = Self::default_values(scheme);
         ExecutionContext {
             scheme,
-            values: vec![None; scheme.get_field_count()].into(),
+            values_size: Self::values_size(&values),
+            values,
+            extra_values: vec![Vec::new(); scheme.get_field_count()].into(),
+            ip_zones: vec![None; scheme.get_field_count()].into(),
+            memory_limit: None,
+            deadline: Deadline::default(),
+            call_cache_hits: AtomicUsize::new(0),
+            call_cache_misses: AtomicUsize::new(0),
+            virtual_values: (0..scheme.get_virtual_field_count())
+                .map(|_| OnceLock::new())
+                .collect(),
+            record_matches: false,
+            matched_spans: Default::default(),
+            lists: vec![None; scheme.get_list_count()].into(),
+        }
+    }
+
+    fn default_values(scheme: &'e Scheme) -> Box<[Option<LhsValue<'e>>]> {
+        let mut values = vec![None; scheme.get_field_count()];
+        for (index, value) in scheme.get_field_defaults() {
+            values[index] = Some(value.as_ref());
+        }
+        values.into()
+    }
+
+    fn values_size(values: &[Option<LhsValue<'e>>]) -> usize {
+        values.iter().flatten().map(value_size).sum()
+    }
+
+    /// Restores all fields to their defaults from the scheme, removing any
+    /// other values, so that the context can be reused for another
+    /// execution. Contents of lists are kept.
+    ///
+    /// Allocations are kept as well, so contexts can be pooled, e.g. one
+    /// per worker, instead of being created for every request.
+    pub fn reset(&mut self) {
+        for value in self.values.iter_mut() {
+            *value = None;
+        }
+        for (index, value) in self.scheme.get_field_defaults() {
+            self.values[index] = Some(value.as_ref());
+        }
+        self.values_size = Self::values_size(&self.values);
+        for values in self.extra_values.iter_mut() {
+            values.clear();
+        }
+        for zone in self.ip_zones.iter_mut() {
+            *zone = None;
+        }
+        *self.call_cache_hits.get_mut() = 0;
+        *self.call_cache_misses.get_mut() = 0;
+        self.clear_virtual_values();
+        self.clear_matched_spans();
+    }
+
+    /// Restores a field to its default from the scheme, or removes its
+    /// value if there is none, including all values of a multi-valued
+    /// field.
+    pub fn clear_field(&mut self, name: &str) -> Result<(), UnknownFieldError> {
+        let index = self.scheme.get_field_index(name)?.index();
+        let value = self.scheme.get_field_default(index).map(LhsValue::as_ref);
+
+        let old_size = self.values[index].as_ref().map_or(0, value_size)
+            + extra_values_size(&self.extra_values[index]);
+        let new_size = value.as_ref().map_or(0, value_size);
+        self.values_size = self.values_size - old_size + new_size;
+
+        self.values[index] = value;
+        self.extra_values[index].clear();
+        self.ip_zones[index] = None;
+        self.clear_virtual_values();
+        Ok(())
+    }
+
+    // Forgets derived values of virtual fields after any field changes.
+    fn clear_virtual_values(&mut self) {
+        for value in self.virtual_values.iter_mut() {
+            value.take();
         }
     }
 
+    /// Returns an approximate number of bytes used by this context and the
+    /// values stored in it, including borrowed ones.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of_val(&*self.values) + self.values_size
+    }
+
+    /// Returns a memory limit of this context in bytes, if any.
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    /// Sets a limit on [`memory_usage`](ExecutionContext::memory_usage) of
+    /// this context in bytes.
+    ///
+    /// Once set, [`set_field_value`](ExecutionContext::set_field_value)
+    /// rejects values that would make the context exceed this limit, which
+    /// protects shared evaluators from unexpectedly large inputs.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
     /// Returns an associated scheme.
     pub fn scheme(&self) -> &'e Scheme {
         self.scheme
     }
 
+    /// Returns a value of a field, or `None` if it wasn't set and has no
+    /// default in the scheme.
+    ///
+    /// For a [multi-valued](::Scheme::set_field_multi_valued) field, this is
+    /// the first of its values. Virtual fields are never set, since their
+    /// values are derived only during execution.
+    pub fn get_field_value(&self, name: &str) -> Result<Option<&LhsValue<'e>>, UnknownFieldError> {
+        let field = self.scheme.get_field_index(name)?;
+        Ok(self.values[field.index()].as_ref())
+    }
+
+    /// Checks whether a field has a value, either set explicitly or a
+    /// default from the scheme.
+    pub fn is_set(&self, name: &str) -> Result<bool, UnknownFieldError> {
+        self.get_field_value(name).map(|value| value.is_some())
+    }
+
+    /// Iterates over fields that have values, in the order of the scheme,
+    /// e.g. to log or snapshot the inputs of an execution.
+    ///
+    /// A multi-valued field is yielded once per value.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (FieldRef<'e>, &'a LhsValue<'e>)> + 'a {
+        self.scheme
+            .iter()
+            .zip(self.values.iter().zip(self.extra_values.iter()))
+            .flat_map(|(field, (value, extra_values))| {
+                value
+                    .iter()
+                    .chain(extra_values)
+                    .map(move |value| (field, value))
+            })
+    }
+
+    /// Returns a deadline for executions with this context.
+    pub fn deadline(&self) -> Deadline {
+        self.deadline
+    }
+
+    /// Sets a deadline for executions with this context.
+    ///
+    /// The deadline is passed to functions that opted into it, which can
+    /// then cut their work short; the caller can check
+    /// [`Deadline::is_expired`] after execution to find out whether the
+    /// budget was exceeded.
+    pub fn set_deadline(&mut self, deadline: Deadline) {
+        self.deadline = deadline;
+    }
+
+    /// Checks that every field used by a filter has a value of the right
+    /// type in this context.
+    ///
+    /// Executing a filter with a missing value panics, so services can call
+    /// this first to fail fast with a report of all the missing fields.
+    /// Fields of a filter parsed with another scheme are matched by name and
+    /// type.
+    pub fn validate_for<'s>(&self, ast: &FilterAst<'s>) -> Result<(), Vec<MissingField<'s>>> {
+        let missing = ast
+            .used_fields()
+            .filter(|field| match field.rebind(self.scheme) {
+                Some(field) => !self.has_field_value(field),
+                None => true,
+            })
+            .map(|field| MissingField {
+                name: field.name(),
+                ty: field.get_type(),
+            })
+            .collect::<Vec<_>>();
+
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(missing)
+        }
+    }
+
+    /// Returns counters of the function call cache accumulated by
+    /// executions with this context since it was created or
+    /// [reset](ExecutionContext::reset).
+    pub fn call_cache_stats(&self) -> CallCacheStats {
+        CallCacheStats {
+            hits: self.call_cache_hits.load(Ordering::Relaxed),
+            misses: self.call_cache_misses.load(Ordering::Relaxed),
+        }
+    }
+
+    // Counts a lookup of a cached function call, see `CallCache`.
+    pub(crate) fn count_cached_call(&self, hit: bool) {
+        let counter = if hit {
+            &self.call_cache_hits
+        } else {
+            &self.call_cache_misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Enables or disables recording of byte ranges matched by `contains`
+    /// and `matches` comparisons of fields during executions with this
+    /// context.
+    ///
+    /// This allows to highlight or redact the offending parts of the input,
+    /// e.g. in logs. Comparisons of function calls are not recorded, since
+    /// their results are not visible to the caller. Executions that share
+    /// a context at the same time, e.g. from several threads, record their
+    /// spans into the same list.
+    pub fn set_record_matches(&mut self, enabled: bool) {
+        self.record_matches = enabled;
+    }
+
+    /// Returns byte ranges matched during the last execution with this
+    /// context, in the order they were evaluated.
+    ///
+    /// Only comparisons actually evaluated are recorded, including the ones
+    /// under a `not`, so a span doesn't necessarily mean that the filter as a
+    /// whole matched.
+    pub fn matched_spans(&self) -> Vec<MatchedSpan<'e>> {
+        self.matched_spans
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(index, range)| MatchedSpan {
+                field: self.scheme.field_at(*index).name(),
+                range: range.clone(),
+            })
+            .collect()
+    }
+
+    pub(crate) fn records_matches(&self) -> bool {
+        self.record_matches
+    }
+
+    // Records a span matched in a value that starts at a given offset of
+    // the original one, see `any_field_value_with_offset`.
+    pub(crate) fn record_match(&self, field: Field<'_>, range: Range<usize>, offset: usize) {
+        self.matched_spans
+            .lock()
+            .unwrap()
+            .push((field.index(), range.start + offset..range.end + offset));
+    }
+
+    pub(crate) fn clear_matched_spans(&self) {
+        self.matched_spans.lock().unwrap().clear();
+    }
+
+    pub(crate) fn has_field_value(&self, field: Field<'_>) -> bool {
+        self.values[field.index()].is_some()
+            || match self.scheme.get_virtual_field(field) {
+                Some((_, virtual_field)) => virtual_field
+                    .sources
+                    .iter()
+                    .all(|&index| self.has_field_value(self.scheme.field_at(index))),
+                None => false,
+            }
+    }
+
+    /// Checks whether any value of a field satisfies a predicate, see
+    /// [`set_field_values`](ExecutionContext::set_field_values).
+    ///
+    /// Values are normalized according to the
+    /// [comparison mode](::Scheme::set_field_comparison_mode) of the field
+    /// first.
+    pub(crate) fn any_field_value(
+        &'e self,
+        field: Field<'e>,
+        mut func: impl FnMut(LhsValue<'e>) -> bool,
+    ) -> bool {
+        self.any_field_value_with_offset(field, |value, _| func(value))
+    }
+
+    /// Like [`any_field_value`](ExecutionContext::any_field_value), but also
+    /// passes the offset at which a normalized value starts in the original
+    /// one, so that matched spans can be shifted back to point into it.
+    pub(crate) fn any_field_value_with_offset(
+        &'e self,
+        field: Field<'e>,
+        mut func: impl FnMut(LhsValue<'e>, usize) -> bool,
+    ) -> bool {
+        let mode = self.scheme.get_field_comparison_mode(field);
+        if mode == ComparisonMode::default() {
+            return self.any_field_value_inner(field, |value| func(value, 0));
+        }
+
+        self.any_field_value_inner(field, |value| match value {
+            LhsValue::Bytes(bytes) => {
+                let (offset, bytes) = normalize_bytes(bytes, mode);
+                func(LhsValue::Bytes(bytes), offset)
+            }
+            value => func(value, 0),
+        })
+    }
+
+    fn any_field_value_inner(
+        &'e self,
+        field: Field<'e>,
+        mut func: impl FnMut(LhsValue<'e>) -> bool,
+    ) -> bool {
+        // Multi-valued fields can legitimately have no values at all.
+        if self.values[field.index()].is_none() && self.scheme.is_field_multi_valued(field) {
+            return false;
+        }
+
+        match self.lookup_field_value(field) {
+            Some(value) => {
+                func(value)
+                    || self.extra_values[field.index()]
+                        .iter()
+                        .any(|value| func(value.as_ref()))
+            }
+            None => false,
+        }
+    }
+
+    /// Returns a value of a field, or `None` if it's missing and the scheme
+    /// [ignores missing fields](::Scheme::set_ignore_missing_fields).
+    pub(crate) fn lookup_field_value(&'e self, field: Field<'e>) -> Option<LhsValue<'e>> {
+        if self.scheme.ignores_missing_fields() && !self.has_field_value(field) {
+            None
+        } else {
+            Some(self.get_field_value_unchecked(field))
+        }
+    }
+
     pub(crate) fn get_field_value_unchecked(&'e self, field: Field<'e>) -> LhsValue<'e> {
-        // This is safe because this code is reachable only from Filter::execute
-        // which already performs the scheme compatibility check, but check that
-        // invariant holds in the future at least in the debug mode.
-        debug_assert!(self.scheme() == field.scheme());
-
-        // For now we panic in this, but later we are going to align behaviour
-        // with wireshark: resolve all subexpressions that don't have RHS value
-        // to `false`.
-        let lhs_value = self.values[field.index()].as_ref().unwrap_or_else(|| {
+        // This is reachable only from APIs that already return
+        // `SchemeMismatchError` for other schemes, but a field of another
+        // scheme would silently read a value at the same index, so the
+        // invariant is checked in release builds too. Comparing schemes is a
+        // single pointer comparison, and fingerprints are only computed for
+        // the panic message.
+        if self.scheme != field.scheme() {
+            let err = SchemeMismatchError::new(field.scheme(), self.scheme);
             panic!(
-                "Field {} was registered but not given a value",
-                field.name()
+                "Field {} was read from an execution context of another scheme \
+                 (fingerprints {:016x} and {:016x})",
+                field.name(),
+                err.expected,
+                err.actual
             );
-        });
-        lhs_value.as_ref()
+        }
+
+        if let Some(value) = &self.values[field.index()] {
+            return value.as_ref();
+        }
+
+        if let Some((slot, virtual_field)) = self.scheme.get_virtual_field(field) {
+            return self.virtual_values[slot]
+                .get_or_init(|| {
+                    self.derive_value(field, &virtual_field.sources, &virtual_field.implementation)
+                })
+                .as_ref();
+        }
+
+        // Schemes that ignore missing fields resolve such comparisons to
+        // `false` before getting here, see `lookup_field_value`.
+        panic!(
+            "Field {} was registered but not given a value",
+            field.name()
+        );
+    }
+
+    fn derive_value(
+        &'e self,
+        field: Field<'e>,
+        sources: &[usize],
+        implementation: &FunctionImpl,
+    ) -> LhsValue<'static> {
+        let value = implementation.execute_with_deadline(
+            sources
+                .iter()
+                .map(|&index| self.get_field_value_unchecked(self.scheme.field_at(index))),
+            self.deadline,
+        );
+
+        assert_eq!(
+            value.get_type(),
+            field.get_type(),
+            "Virtual field {} derived a value of a wrong type",
+            field.name()
+        );
+
+        value.into_owned()
+    }
+
+    /// Sets runtime values from pairs of field names and strings, parsing
+    /// each string according to the type of the field.
+    ///
+    /// IP addresses and integers are parsed in their usual textual form,
+    /// booleans are `true` or `false`, and bytes are taken as-is. This is
+    /// handy for inputs like environment variables or form fields.
+    ///
+    /// Values are set in order, so on error the preceding ones stay set.
+    pub fn set_from_pairs<'n, 'v: 'e>(
+        &mut self,
+        pairs: impl IntoIterator<Item = (&'n str, &'v str)>,
+    ) -> Result<(), SetFromPairsError> {
+        let zones = self.scheme.parser_settings().ipv6_zones;
+
+        for (name, value) in pairs {
+            let field = self
+                .scheme
+                .get_field_index(name)
+                .map_err(|_| SetFromPairsError::UnknownField(name.to_owned()))?;
+            let ty = field.get_type();
+
+            let (value, zone) = match value.split_once('%') {
+                Some((addr, zone)) if ty == Type::Ip && zones != Ipv6Zones::Reject => {
+                    (addr, Some(zone))
+                }
+                _ => (value, None),
+            };
+
+            let value = match ty {
+                Type::Ip => match (value.parse::<IpAddr>(), zone) {
+                    (Ok(addr @ IpAddr::V6(_)), Some(_)) | (Ok(addr), None) => Some(addr.into()),
+                    _ => None,
+                },
+                Type::Int => value.parse::<i64>().ok().map(LhsValue::from),
+                Type::Float => value
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(Float::new)
+                    .map(LhsValue::from),
+                Type::Timestamp => Timestamp::parse(value).ok().map(LhsValue::from),
+                Type::Bool => value.parse::<bool>().ok().map(LhsValue::from),
+                Type::Bytes => Some(LhsValue::from(value)),
+            }
+            .ok_or_else(|| SetFromPairsError::InvalidValue {
+                name: name.to_owned(),
+                ty,
+            })?;
+
+            self.set_field_value(name, value)
+                .map_err(|err| SetFromPairsError::SetFieldValue {
+                    name: name.to_owned(),
+                    err,
+                })?;
+
+            if zones == Ipv6Zones::Compare {
+                self.ip_zones[field.index()] = zone.map(Into::into);
+            }
+        }
+        Ok(())
+    }
+
+    /// Provides contents of a list [registered](::Scheme::add_list) in the
+    /// scheme, replacing any previous ones.
+    ///
+    /// Contents are shared, so a single [`ListValues`] can be built whenever
+    /// a list changes and then given to every context. Comparisons with a
+    /// list without contents are `false`.
+    pub fn set_list_values(
+        &mut self,
+        name: &str,
+        values: Arc<ListValues>,
+    ) -> Result<(), SetListValuesError> {
+        let list = self
+            .scheme
+            .get_list(name)
+            .map_err(SetListValuesError::UnknownList)?;
+
+        let list_type = list.get_type();
+        let values_type = values.get_type();
+
+        if list_type != values_type {
+            return Err(SetListValuesError::TypeMismatch(TypeMismatchError {
+                expected: list_type,
+                actual: values_type,
+            }));
+        }
+
+        self.lists[list.index()] = Some(values);
+        Ok(())
+    }
+
+    /// Sets a zone identifier of IPv6 values of a field, like the name of the
+    /// interface they were received on, which is compared with scoped
+    /// literals like `fe80::1%eth0` when parser settings
+    /// [compare zones](::Ipv6Zones::Compare).
+    ///
+    /// The zone applies to all values of the field, and is removed when they
+    /// are replaced.
+    pub fn set_ip_zone(
+        &mut self,
+        name: &str,
+        zone: Option<&str>,
+    ) -> Result<(), SetFieldValueError> {
+        let field = self
+            .scheme
+            .get_field_index(name)
+            .map_err(SetFieldValueError::UnknownField)?;
+
+        if field.get_type() != Type::Ip {
+            return Err(SetFieldValueError::TypeMismatch(TypeMismatchError {
+                expected: field.get_type(),
+                actual: Type::Ip,
+            }));
+        }
+
+        self.ip_zones[field.index()] = zone.map(Into::into);
+        Ok(())
+    }
+
+    pub(crate) fn get_ip_zone(&self, field: Field<'_>) -> Option<&str> {
+        self.ip_zones[field.index()].as_deref()
+    }
+
+    pub(crate) fn get_list_values(&self, list: List<'_>) -> Option<&ListValues> {
+        self.lists[list.index()].as_deref()
     }
 
     /// Sets a runtime value for a given field name.
@@ -52,25 +762,154 @@ impl<'e> ExecutionContext<'e> {
         &mut self,
         name: &str,
         value: V,
-    ) -> Result<(), TypeMismatchError> {
-        let field = self.scheme.get_field_index(name).unwrap();
-        let value = value.into();
+    ) -> Result<(), SetFieldValueError> {
+        let field = self
+            .scheme
+            .get_field_index(name)
+            .map_err(SetFieldValueError::UnknownField)?;
+        let value = self.check_value(field, value.into())?;
+
+        let index = field.index();
+        let old_size = self.values[index].as_ref().map_or(0, value_size)
+            + extra_values_size(&self.extra_values[index]);
+        self.replace_values(index, old_size, value_size(&value))?;
+
+        // Clearing keeps the capacity for later multi-valued sets.
+        self.values[index] = Some(value);
+        self.extra_values[index].clear();
+        Ok(())
+    }
+
+    /// Sets all runtime values of a field that
+    /// [accepts multiple values](::Scheme::set_field_multi_valued), like a
+    /// repeated header, replacing any previous ones.
+    ///
+    /// A comparison with such a field is `true` if it's `true` for any of
+    /// the values, and `false` if there are none. Other fields accept
+    /// exactly one value.
+    pub fn set_field_values<'v: 'e, V: Into<LhsValue<'v>>>(
+        &mut self,
+        name: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> Result<(), SetFieldValueError> {
+        let field = self
+            .scheme
+            .get_field_index(name)
+            .map_err(SetFieldValueError::UnknownField)?;
+        let index = field.index();
+
+        // New values are appended after the old ones in the same vector, so
+        // that the context is left unchanged on errors without allocating.
+        let mut extra_values = mem::take(&mut self.extra_values[index]);
+        let old_len = extra_values.len();
+        let result = self.append_values(field, &mut extra_values, values);
+
+        match result {
+            Ok(()) => {
+                // The first new value is stored separately.
+                self.values[index] = if extra_values.len() > old_len {
+                    extra_values.drain(..=old_len).next_back()
+                } else {
+                    extra_values.clear();
+                    None
+                };
+            }
+            Err(_) => extra_values.truncate(old_len),
+        }
+
+        self.extra_values[index] = extra_values;
+        result
+    }
+
+    // Appends new values of a field to its old extra values and accounts
+    // for replacing the old values with them.
+    fn append_values<'v: 'e, V: Into<LhsValue<'v>>>(
+        &mut self,
+        field: Field<'_>,
+        extra_values: &mut Vec<LhsValue<'e>>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Result<(), SetFieldValueError> {
+        let old_len = extra_values.len();
+        for value in values {
+            extra_values.push(self.check_value(field, value.into())?);
+        }
 
+        let new_values = &extra_values[old_len..];
+        if new_values.len() != 1 && !self.scheme.is_field_multi_valued(field) {
+            return Err(SetFieldValueError::NotMultiValued);
+        }
+
+        let index = field.index();
+        let old_size = self.values[index].as_ref().map_or(0, value_size)
+            + extra_values_size(&extra_values[..old_len]);
+        let new_size = match new_values.split_first() {
+            Some((value, rest)) => value_size(value) + extra_values_size(rest),
+            None => 0,
+        };
+        self.replace_values(index, old_size, new_size)
+    }
+
+    // Checks the type of a value of a field and normalizes it.
+    fn check_value<'v: 'e>(
+        &self,
+        field: Field<'_>,
+        value: LhsValue<'v>,
+    ) -> Result<LhsValue<'e>, SetFieldValueError> {
         let field_type = field.get_type();
         let value_type = value.get_type();
 
-        if field_type == value_type {
-            self.values[field.index()] = Some(value);
-            Ok(())
-        } else {
-            Err(TypeMismatchError {
+        if field_type != value_type {
+            return Err(SetFieldValueError::TypeMismatch(TypeMismatchError {
                 expected: field_type,
                 actual: value_type,
-            })
+            }));
+        }
+
+        Ok(match value {
+            LhsValue::Ip(addr) if self.scheme.parser_settings().ipv4_mapped_ipv6_interop => {
+                LhsValue::Ip(unmap_ipv4(addr))
+            }
+            value => value,
+        })
+    }
+
+    // Checks the memory limit before values of a field are replaced, given
+    // the sizes of the old and new ones, and forgets state derived from the
+    // old values.
+    fn replace_values(
+        &mut self,
+        index: usize,
+        old_size: usize,
+        new_size: usize,
+    ) -> Result<(), SetFieldValueError> {
+        let values_size = self.values_size - old_size + new_size;
+
+        if let Some(limit) = self.memory_limit {
+            if mem::size_of_val(&*self.values) + values_size > limit {
+                return Err(SetFieldValueError::MemoryLimitExceeded { limit });
+            }
         }
+
+        self.ip_zones[index] = None;
+        self.values_size = values_size;
+        self.clear_virtual_values();
+        Ok(())
     }
 }
 
+#[test]
+#[should_panic(expected = "Field foo was read from an execution context of another scheme")]
+fn test_field_of_another_scheme() {
+    let scheme = Scheme! { foo: Int };
+    let other_scheme = Scheme! { foo: Int };
+
+    let mut ctx = ExecutionContext::new(&other_scheme);
+    ctx.set_field_value("foo", 1).unwrap();
+
+    let field = scheme.get_field_index("foo").unwrap();
+    ctx.get_field_value_unchecked(field);
+}
+
 #[test]
 fn test_field_value_type_mismatch() {
     use crate::types::Type;
@@ -81,9 +920,564 @@ fn test_field_value_type_mismatch() {
 
     assert_eq!(
         ctx.set_field_value("foo", LhsValue::Bool(false)),
-        Err(TypeMismatchError {
+        Err(SetFieldValueError::TypeMismatch(TypeMismatchError {
             expected: Type::Int,
             actual: Type::Bool
+        }))
+    );
+
+    assert_eq!(
+        ctx.set_field_value("bar", 1),
+        Err(SetFieldValueError::UnknownField(UnknownFieldError))
+    );
+}
+
+#[test]
+fn test_memory_limit() {
+    let scheme = Scheme! { foo: Bytes, bar: Int };
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    let base = ctx.memory_usage();
+
+    ctx.set_field_value("foo", "abcd").unwrap();
+    ctx.set_field_value("bar", 42).unwrap();
+    assert_eq!(ctx.memory_usage(), base + 4);
+
+    ctx.set_memory_limit(Some(base + 8));
+    assert_eq!(ctx.memory_limit(), Some(base + 8));
+
+    // Replaced values are not accounted for anymore.
+    ctx.set_field_value("foo", "abcdefgh").unwrap();
+    assert_eq!(ctx.memory_usage(), base + 8);
+
+    assert_eq!(
+        ctx.set_field_value("foo", "abcdefghi"),
+        Err(SetFieldValueError::MemoryLimitExceeded { limit: base + 8 })
+    );
+    assert_eq!(ctx.memory_usage(), base + 8);
+
+    ctx.set_field_value("foo", "abcd").unwrap();
+    ctx.clear_field("foo").unwrap();
+    assert_eq!(ctx.memory_usage(), base);
+
+    // Contexts keep their allocations across resets.
+    ctx.set_field_value("foo", "abcd").unwrap();
+    let values = ctx.values.as_ptr();
+    ctx.reset();
+    assert_eq!(ctx.memory_usage(), base);
+    assert_eq!(ctx.values.as_ptr(), values);
+}
+
+#[test]
+fn test_deadline() {
+    use crate::{
+        functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+        types::Type,
+    };
+
+    fn is_expired<'a>(_: FunctionArgs<'_, 'a>, deadline: Deadline) -> LhsValue<'a> {
+        LhsValue::Bool(deadline.is_expired())
+    }
+
+    assert_eq!(Deadline::default().remaining(), None);
+    assert!(!Deadline::default().is_expired());
+    assert!(!Deadline::after(Duration::from_secs(60)).is_expired());
+    assert!(Deadline::at(Instant::now()).is_expired());
+
+    let mut scheme = Scheme! { foo: Int };
+
+    scheme
+        .add_function(
+            "is_expired".into(),
+            Function {
+                params: vec![FunctionParam {
+                    arg_kind: FunctionArgKind::Field,
+                    val_type: Type::Int,
+                }],
+                opt_params: vec![],
+                return_type: Type::Bool,
+                implementation: FunctionImpl::with_deadline(is_expired),
+                pure: false,
+            },
+        )
+        .unwrap();
+
+    let filter = scheme.parse("is_expired(foo)").unwrap().compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("foo", 42).unwrap();
+
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.set_deadline(Deadline::at(Instant::now()));
+
+    assert_eq!(filter.execute(&ctx), Ok(true));
+}
+
+#[test]
+fn test_field_defaults() {
+    use crate::{scheme::FieldDefaultError, types::Type};
+
+    let mut scheme = Scheme! { foo: Int, bar: Int };
+
+    assert_eq!(
+        scheme.set_field_default("foo", LhsValue::Bool(true)),
+        Err(FieldDefaultError::TypeMismatch(TypeMismatchError {
+            expected: Type::Int,
+            actual: Type::Bool,
+        }))
+    );
+    assert!(scheme.set_field_default("baz", 0).is_err());
+    scheme.set_field_default("foo", 42).unwrap();
+
+    let filter = scheme.parse("foo == 42").unwrap().compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    ctx.set_field_value("foo", 1).unwrap();
+    ctx.set_field_value("bar", 1).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.reset();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert!(ctx.values[scheme.get_field_index("bar").unwrap().index()].is_none());
+
+    ctx.set_field_value("foo", 1).unwrap();
+    ctx.set_field_value("bar", 1).unwrap();
+    ctx.clear_field("foo").unwrap();
+    ctx.clear_field("bar").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert!(ctx.values[scheme.get_field_index("bar").unwrap().index()].is_none());
+    assert_eq!(ctx.clear_field("baz"), Err(UnknownFieldError));
+}
+
+#[test]
+fn test_context_is_sync() {
+    // A context can be shared between threads executing filters.
+    fn assert_sync<T: Send + Sync>() {}
+    assert_sync::<ExecutionContext<'_>>();
+}
+
+#[test]
+fn test_call_cache() {
+    use crate::{
+        functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+        types::Type,
+    };
+
+    fn lower_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+        match args.next().unwrap() {
+            LhsValue::Bytes(bytes) => LhsValue::Bytes(bytes.to_ascii_lowercase().into()),
+            _ => unreachable!(),
+        }
+    }
+
+    let mut scheme = Scheme! { foo: Bytes, bar: Bytes };
+
+    for (name, pure) in &[("lower", true), ("impure_lower", false)] {
+        scheme
+            .add_function(
+                name.to_string(),
+                Function {
+                    params: vec![FunctionParam {
+                        arg_kind: FunctionArgKind::Field,
+                        val_type: Type::Bytes,
+                    }],
+                    opt_params: vec![],
+                    return_type: Type::Bytes,
+                    implementation: FunctionImpl::new(lower_function),
+                    pure: *pure,
+                },
+            )
+            .unwrap();
+    }
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("foo", "ABC").unwrap();
+    ctx.set_field_value("bar", "DEF").unwrap();
+
+    let filter = scheme
+        .parse(
+            r#"lower(foo) == "a" or lower(bar) == "def" and lower(foo) contains "b" and lower(foo) == "abc""#,
+        )
+        .unwrap()
+        .compile();
+
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(
+        ctx.call_cache_stats(),
+        CallCacheStats { hits: 2, misses: 1 }
+    );
+
+    // The cache doesn't outlive an execution.
+    ctx.set_field_value("foo", "XYZ").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+    assert_eq!(
+        ctx.call_cache_stats(),
+        CallCacheStats { hits: 3, misses: 2 }
+    );
+
+    // Calls to impure functions are always executed.
+    let filter = scheme
+        .parse(r#"impure_lower(foo) == "a" or impure_lower(foo) == "xyz""#)
+        .unwrap()
+        .compile();
+
+    ctx.reset();
+    ctx.set_field_value("foo", "XYZ").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(ctx.call_cache_stats(), CallCacheStats::default());
+}
+
+#[test]
+fn test_set_from_pairs() {
+    let scheme = Scheme! { ip: Ip, port: Int, ssl: Bool, host: Bytes };
+
+    let mut ctx = ExecutionContext::new(&scheme);
+
+    ctx.set_from_pairs(vec![
+        ("ip", "::1"),
+        ("port", "443"),
+        ("ssl", "true"),
+        ("host", "example.org"),
+    ])
+    .unwrap();
+
+    let filter = scheme
+        .parse(r#"ip == ::1 && port == 443 && ssl && host == "example.org""#)
+        .unwrap()
+        .compile();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    assert_eq!(
+        ctx.set_from_pairs(vec![("unknown", "1")]),
+        Err(SetFromPairsError::UnknownField("unknown".to_owned()))
+    );
+
+    assert_eq!(
+        ctx.set_from_pairs(vec![("port", "80"), ("ssl", "yes")]),
+        Err(SetFromPairsError::InvalidValue {
+            name: "ssl".to_owned(),
+            ty: Type::Bool,
+        })
+    );
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.set_memory_limit(Some(ctx.memory_usage()));
+    assert_eq!(
+        ctx.set_from_pairs(vec![("host", "example.com.")]),
+        Err(SetFromPairsError::SetFieldValue {
+            name: "host".to_owned(),
+            err: SetFieldValueError::MemoryLimitExceeded {
+                limit: ctx.memory_usage()
+            },
         })
     );
 }
+
+#[test]
+fn test_validate_for() {
+    let scheme = Scheme! { foo: Int, bar: Bytes, baz: Bool };
+    let ast = scheme.parse(r#"baz || foo == 1 && bar == "x""#).unwrap();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("foo", 1).unwrap();
+
+    assert_eq!(
+        ctx.validate_for(&ast),
+        Err(vec![
+            MissingField {
+                name: "bar",
+                ty: Type::Bytes,
+            },
+            MissingField {
+                name: "baz",
+                ty: Type::Bool,
+            },
+        ])
+    );
+
+    ctx.set_field_value("bar", "x").unwrap();
+    ctx.set_field_value("baz", false).unwrap();
+    assert_eq!(ctx.validate_for(&ast), Ok(()));
+
+    // Fields of another scheme are matched by name and type.
+    let other = Scheme! { foo: Int, bar: Int, baz: Bool };
+    let mut ctx = ExecutionContext::new(&other);
+    ctx.set_field_value("foo", 1).unwrap();
+    ctx.set_field_value("bar", 1).unwrap();
+    ctx.set_field_value("baz", true).unwrap();
+
+    assert_eq!(
+        ctx.validate_for(&ast),
+        Err(vec![MissingField {
+            name: "bar",
+            ty: Type::Bytes,
+        }])
+    );
+}
+
+#[test]
+fn test_virtual_fields() {
+    use crate::{functions::FunctionArgs, scheme::VirtualFieldError};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    fn concat<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+        CALLS.fetch_add(1, Ordering::SeqCst);
+        let mut result = Vec::new();
+        for arg in args {
+            match arg {
+                LhsValue::Bytes(bytes) => result.extend_from_slice(&bytes),
+                _ => unreachable!(),
+            }
+        }
+        LhsValue::Bytes(result.into())
+    }
+
+    let mut scheme = Scheme! { host: Bytes, path: Bytes, ssl: Bool };
+
+    assert_eq!(
+        scheme.add_virtual_field(
+            "uri".into(),
+            Type::Bytes,
+            &["host", "query"],
+            FunctionImpl::new(concat)
+        ),
+        Err(VirtualFieldError::UnknownField(
+            crate::scheme::UnknownFieldError
+        ))
+    );
+    scheme
+        .add_virtual_field(
+            "uri".into(),
+            Type::Bytes,
+            &["host", "path"],
+            FunctionImpl::new(concat),
+        )
+        .unwrap();
+
+    let filter = scheme
+        .parse(r#"ssl or uri == "example.org/" or uri contains "/index""#)
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("ssl", true).unwrap();
+    ctx.set_field_value("host", "example.org").unwrap();
+
+    // Not derived when not needed, even though the path is missing.
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(CALLS.load(Ordering::SeqCst), 0);
+
+    let ast = scheme.parse(r#"uri == "example.org/""#).unwrap();
+    assert_eq!(
+        ctx.validate_for(&ast),
+        Err(vec![MissingField {
+            name: "uri",
+            ty: Type::Bytes,
+        }])
+    );
+
+    ctx.set_field_value("ssl", false).unwrap();
+    ctx.set_field_value("path", "/index.html").unwrap();
+    assert_eq!(ctx.validate_for(&ast), Ok(()));
+
+    // Derived once and reused until fields change.
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+    ctx.set_field_value("path", "/").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+
+    // Explicit values take precedence.
+    ctx.set_field_value("uri", "other").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_matched_spans() {
+    let scheme = Scheme! { http.uri: Bytes, http.ua: Bytes };
+    let filter = scheme
+        .parse(r#"http.uri contains "union" and http.ua matches "sql[a-z]+" and not http.uri contains "admin""#)
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.uri", "/?id=1 union select")
+        .unwrap();
+    ctx.set_field_value("http.ua", "x-sqlmap/1.0").unwrap();
+
+    // Nothing is recorded by default.
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(ctx.matched_spans(), vec![]);
+
+    ctx.set_record_matches(true);
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(
+        ctx.matched_spans(),
+        vec![
+            MatchedSpan {
+                field: "http.uri",
+                range: 7..12,
+            },
+            MatchedSpan {
+                field: "http.ua",
+                range: 2..8,
+            },
+        ]
+    );
+
+    // Spans are cleared before each execution.
+    ctx.set_field_value("http.ua", "curl/7.64").unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+    assert_eq!(
+        ctx.matched_spans(),
+        vec![MatchedSpan {
+            field: "http.uri",
+            range: 7..12,
+        }]
+    );
+}
+
+#[test]
+fn test_multi_valued_fields() {
+    let mut scheme = Scheme! { xff: Ip, ua: Bytes, port: Int };
+    scheme.set_field_multi_valued("xff", true).unwrap();
+    scheme.set_field_multi_valued("ua", true).unwrap();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    let base = ctx.memory_usage();
+
+    ctx.set_field_values(
+        "xff",
+        vec![IpAddr::from([10, 0, 0, 1]), IpAddr::from([1, 2, 3, 4])],
+    )
+    .unwrap();
+    ctx.set_field_values("ua", vec!["curl", "wget"]).unwrap();
+    ctx.set_field_value("port", 443).unwrap();
+    assert_eq!(
+        ctx.memory_usage(),
+        base + 4 + mem::size_of::<LhsValue<'_>>() * 2 + 4
+    );
+
+    // A failed set leaves all values in place.
+    let usage = ctx.memory_usage();
+    assert!(ctx
+        .set_field_values("ua", vec![LhsValue::from("curl"), LhsValue::from(true)])
+        .is_err());
+    assert_eq!(ctx.memory_usage(), usage);
+
+    let filters = [
+        ("xff == 1.2.3.4", true),
+        ("xff != 1.2.3.4", true),
+        ("not xff == 1.2.3.4", false),
+        ("xff in { 192.168.0.0/16 }", false),
+        ("xff in { 10.0.0.0/8 }", true),
+        (r#"ua contains "wg""#, true),
+        (r#"ua ~ "^c" and ua ~ "t$""#, true),
+        (r#"ua ~ "^x" or ua ~ "^w""#, true),
+        (r#"ua == "curl" and port == 443"#, true),
+        ("all(xff != 1.2.3.4)", false),
+        ("all(xff in { 0.0.0.0/0 })", true),
+        ("not all(xff in { 10.0.0.0/8 })", true),
+        (r#"any(ua == "wget")"#, true),
+        (r#"all(ua ~ "^[a-z]+$")"#, true),
+        (r#"all(ua contains "c") or all(ua contains "w")"#, false),
+        ("all(port == 443)", true),
+    ];
+
+    for (filter, expected) in &filters {
+        let ast = scheme.parse(filter).unwrap();
+        assert_eq!(
+            ast.clone().compile().execute(&ctx),
+            Ok(*expected),
+            "{}",
+            filter
+        );
+        assert_eq!(
+            ast.compile_to_bytecode().execute(&ctx),
+            Ok(*expected),
+            "{}",
+            filter
+        );
+    }
+
+    // A single value replaces all of them.
+    ctx.set_field_value("xff", IpAddr::from([10, 0, 0, 1]))
+        .unwrap();
+    let filter = scheme.parse("xff == 1.2.3.4").unwrap().compile();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    // Without values, comparisons are false.
+    ctx.set_field_values("xff", Vec::<IpAddr>::new()).unwrap();
+    let filter = scheme.parse("xff != 1.2.3.4").unwrap().compile();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+    let filter = scheme.parse("all(xff == 1.2.3.4)").unwrap().compile();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    assert_eq!(
+        ctx.set_field_values("port", vec![80, 443]),
+        Err(SetFieldValueError::NotMultiValued)
+    );
+    assert_eq!(
+        ctx.set_field_values("port", Vec::<i32>::new()),
+        Err(SetFieldValueError::NotMultiValued)
+    );
+
+    ctx.reset();
+    assert_eq!(ctx.memory_usage(), base);
+}
+
+#[test]
+fn test_field_values() {
+    let mut scheme = Scheme! { host: Bytes, xff: Ip, port: Int, tls: Bool };
+    scheme.set_field_multi_valued("xff", true).unwrap();
+    scheme.set_field_default("tls", false).unwrap();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("host", "example.com").unwrap();
+    ctx.set_field_values(
+        "xff",
+        vec![IpAddr::from([10, 0, 0, 1]), IpAddr::from([1, 2, 3, 4])],
+    )
+    .unwrap();
+
+    assert_eq!(
+        ctx.get_field_value("host"),
+        Ok(Some(&LhsValue::from("example.com")))
+    );
+    assert_eq!(
+        ctx.get_field_value("xff"),
+        Ok(Some(&LhsValue::Ip(IpAddr::from([10, 0, 0, 1]))))
+    );
+    assert_eq!(ctx.get_field_value("port"), Ok(None));
+    assert_eq!(ctx.get_field_value("tls"), Ok(Some(&LhsValue::Bool(false))));
+    assert_eq!(ctx.get_field_value("path"), Err(UnknownFieldError));
+
+    assert_eq!(ctx.is_set("host"), Ok(true));
+    assert_eq!(ctx.is_set("port"), Ok(false));
+    assert_eq!(ctx.is_set("tls"), Ok(true));
+    assert_eq!(ctx.is_set("path"), Err(UnknownFieldError));
+
+    assert_eq!(
+        ctx.iter()
+            .map(|(field, value)| (field.name(), value.clone()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("host", LhsValue::from("example.com")),
+            ("xff", LhsValue::Ip(IpAddr::from([10, 0, 0, 1]))),
+            ("xff", LhsValue::Ip(IpAddr::from([1, 2, 3, 4]))),
+            ("tls", LhsValue::Bool(false)),
+        ]
+    );
+
+    ctx.clear_field("host").unwrap();
+    assert_eq!(ctx.is_set("host"), Ok(false));
+    assert_eq!(ctx.iter().count(), 3);
+}
@@ -1,11 +1,37 @@
-use crate::{execution_context::ExecutionContext, scheme::Scheme};
+use crate::{
+    ast::{CachedCalls, CallCache, FilterAst, TraceNode},
+    execution_context::{ExecutionContext, MissingField},
+    scheme::Scheme,
+    vm::{BytecodeFilter, YieldingExecution},
+};
 use failure::Fail;
+use std::{cell::OnceCell, time::Duration};
 
 /// An error that occurs if filter and provided [`ExecutionContext`] have
 /// different [schemes](struct@Scheme).
+///
+/// It's also returned when combining filters parsed with different schemes.
+/// Both schemes are identified by their
+/// [fingerprints](::Scheme::fingerprint): equal ones mean that the same
+/// scheme was created more than once, rather than that unrelated schemes
+/// were mixed up.
 #[derive(Debug, PartialEq, Fail)]
 #[fail(display = "execution context doesn't match the scheme with which filter was parsed")]
-pub struct SchemeMismatchError;
+pub struct SchemeMismatchError {
+    /// The fingerprint of the scheme of the filter.
+    pub expected: u64,
+    /// The fingerprint of the other scheme.
+    pub actual: u64,
+}
+
+impl SchemeMismatchError {
+    pub(crate) fn new(expected: &Scheme, actual: &Scheme) -> Self {
+        SchemeMismatchError {
+            expected: expected.fingerprint(),
+            actual: actual.fingerprint(),
+        }
+    }
+}
 
 // Each AST expression node gets compiled into CompiledExpr. Therefore, Filter
 // essentialy is a public API facade for a tree of CompiledExprs. When filter
@@ -13,20 +39,46 @@ pub struct SchemeMismatchError;
 // under the hood propagates field values to its leafs by recursively calling
 // their `execute` methods and aggregating results into a single boolean value
 // as recursion unwinds.
-pub(crate) struct CompiledExpr<'s>(Box<dyn 's + Fn(&ExecutionContext<'s>) -> bool>);
+//
+// Values of repeated function calls are passed down alongside the context,
+// see `CallCache`.
+pub(crate) struct CompiledExpr<'s>(Box<CompiledFn<'s>>);
+
+type CompiledFn<'s> = dyn 's + Fn(&ExecutionContext<'s>, &CallCache<'_>) -> bool;
 
 impl<'s> CompiledExpr<'s> {
     /// Creates a compiled expression IR from a generic closure.
-    pub(crate) fn new(closure: impl 's + Fn(&ExecutionContext<'s>) -> bool) -> Self {
+    pub(crate) fn new(
+        closure: impl 's + Fn(&ExecutionContext<'s>, &CallCache<'_>) -> bool,
+    ) -> Self {
         CompiledExpr(Box::new(closure))
     }
 
     /// Executes a filter against a provided context with values.
+    pub fn execute_with(&self, ctx: &ExecutionContext<'s>, calls: &CallCache<'_>) -> bool {
+        self.0(ctx, calls)
+    }
+
+    /// Executes a filter compiled without cached function calls.
     pub fn execute(&self, ctx: &ExecutionContext<'s>) -> bool {
-        self.0(ctx)
+        self.execute_with(ctx, &CallCache::empty(ctx))
     }
 }
 
+/// Latencies of a machine used to turn [estimated costs](::FilterAst::cost)
+/// into worst-case latencies of filters with
+/// [`Filter::estimated_worst_case`].
+///
+/// Both depend on hardware and on the workload, so they are best measured
+/// once per deployment, e.g. by timing a rule set of known cost.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LatencyModel {
+    /// Nanoseconds taken by a single unit of cost.
+    pub ns_per_unit: f64,
+    /// Nanoseconds of fixed overhead of every execution.
+    pub ns_per_execution: f64,
+}
+
 /// An IR for a compiled filter expression.
 ///
 /// Currently it works by creating and combining boxed untyped closures and
@@ -44,37 +96,601 @@ impl<'s> CompiledExpr<'s> {
 /// and execution.
 pub struct Filter<'s> {
     root_expr: CompiledExpr<'s>,
-    scheme: &'s Scheme,
+    // The AST after constant folding, kept for tracing executions.
+    ast: FilterAst<'s>,
+    // Bytecode compiled on the first yielding execution.
+    bytecode: OnceCell<BytecodeFilter<'s>>,
+    cached_calls: CachedCalls<'s>,
+    cost: u64,
 }
 
 impl<'s> Filter<'s> {
     /// Creates a compiled expression IR from a generic closure.
-    pub(crate) fn new(root_expr: CompiledExpr<'s>, scheme: &'s Scheme) -> Self {
-        Filter { root_expr, scheme }
+    pub(crate) fn new(
+        root_expr: CompiledExpr<'s>,
+        ast: FilterAst<'s>,
+        cached_calls: CachedCalls<'s>,
+        cost: u64,
+    ) -> Self {
+        Filter {
+            root_expr,
+            ast,
+            bytecode: OnceCell::new(),
+            cached_calls,
+            cost,
+        }
+    }
+
+    /// Returns an estimated cost of executing a filter in the units of
+    /// [`FilterAst::cost`](::FilterAst::cost), assuming that none of the
+    /// comparisons are short-circuited.
+    ///
+    /// Unlike the cost of the AST, it reflects how the filter was compiled:
+    /// repeated calls of pure functions are counted once, since their
+    /// results are cached, and comparisons folded into constants are free.
+    pub fn worst_case_cost(&self) -> u64 {
+        self.cost
+    }
+
+    /// Estimates the worst-case latency of executing a filter on a machine
+    /// described by a [`LatencyModel`].
+    ///
+    /// This bounds the overhead of a rule set per request without
+    /// measuring it after every change. Note that functions are assumed to
+    /// take time proportional to their cost too, which their
+    /// implementations can't guarantee.
+    pub fn estimated_worst_case(&self, model: &LatencyModel) -> Duration {
+        let ns = model.ns_per_execution + self.cost as f64 * model.ns_per_unit;
+        Duration::from_nanos(ns.max(0.0).ceil() as u64)
+    }
+
+    /// Checks that a context has values of all fields the filter reads, like
+    /// [`ExecutionContext::validate_for`](::ExecutionContext::validate_for)
+    /// does for an AST.
+    ///
+    /// Executing a filter with a missing value panics, so callers that only
+    /// keep the compiled filter, like the FFI bindings, can call this first
+    /// to report an error instead. Fields of comparisons that were folded
+    /// into constants aren't needed.
+    pub fn validate_context(
+        &self,
+        ctx: &ExecutionContext<'s>,
+    ) -> Result<(), Vec<MissingField<'s>>> {
+        ctx.validate_for(&self.ast)
     }
 
     /// Executes a filter against a provided context with values.
     pub fn execute(&self, ctx: &ExecutionContext<'s>) -> Result<bool, SchemeMismatchError> {
-        if self.scheme == ctx.scheme() {
-            Ok(self.root_expr.execute(ctx))
+        if self.ast.scheme() == ctx.scheme() {
+            ctx.clear_matched_spans();
+            let calls = CallCache::new(&self.cached_calls, ctx);
+            Ok(self.root_expr.execute_with(ctx, &calls))
+        } else {
+            Err(SchemeMismatchError::new(self.ast.scheme(), ctx.scheme()))
+        }
+    }
+
+    /// Executes a filter against many contexts, e.g. records of a log, and
+    /// returns results in the same order.
+    ///
+    /// Schemes of all contexts are checked upfront, so either all of them
+    /// are executed or none.
+    pub fn execute_batch(
+        &self,
+        ctxs: &[&ExecutionContext<'s>],
+    ) -> Result<Vec<bool>, SchemeMismatchError> {
+        if let Some(ctx) = ctxs.iter().find(|ctx| self.ast.scheme() != ctx.scheme()) {
+            return Err(SchemeMismatchError::new(self.ast.scheme(), ctx.scheme()));
+        }
+
+        Ok(ctxs
+            .iter()
+            .map(|ctx| {
+                ctx.clear_matched_spans();
+                let calls = CallCache::new(&self.cached_calls, ctx);
+                self.root_expr.execute_with(ctx, &calls)
+            })
+            .collect())
+    }
+
+    /// Starts an execution that can be suspended after every
+    /// `yield_every` comparisons, so that evaluating a very large filter
+    /// doesn't block an async executor for too long, see
+    /// [`BytecodeFilter::execute_yielding`].
+    ///
+    /// A tree of closures can't be suspended, so the filter is compiled into
+    /// bytecode on the first call, which is kept for later ones. Filters
+    /// that are only ever executed this way are cheaper to compile with
+    /// [`FilterAst::compile_to_bytecode`](::FilterAst::compile_to_bytecode)
+    /// directly.
+    pub fn execute_yielding<'a>(
+        &'a self,
+        ctx: &'a ExecutionContext<'s>,
+        yield_every: usize,
+    ) -> Result<YieldingExecution<'a, 's>, SchemeMismatchError> {
+        if self.ast.scheme() != ctx.scheme() {
+            return Err(SchemeMismatchError::new(self.ast.scheme(), ctx.scheme()));
+        }
+
+        self.bytecode
+            .get_or_init(|| self.ast.clone().compile_to_bytecode())
+            .execute_yielding(ctx, yield_every)
+    }
+
+    /// Executes a filter against a provided context and returns a trace of
+    /// every comparison together with the values it was evaluated with,
+    /// e.g. to show in a debugging UI why a filter did or didn't match.
+    ///
+    /// The trace is serializable to JSON and its result is always the same
+    /// as of [`Filter::execute`], but evaluating it is much slower, since
+    /// every comparison is compiled and executed on its own.
+    pub fn execute_with_trace(
+        &self,
+        ctx: &ExecutionContext<'s>,
+    ) -> Result<TraceNode<'s>, SchemeMismatchError> {
+        if self.ast.scheme() == ctx.scheme() {
+            ctx.clear_matched_spans();
+            Ok(self.ast.trace(ctx))
         } else {
-            Err(SchemeMismatchError)
+            Err(SchemeMismatchError::new(self.ast.scheme(), ctx.scheme()))
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::SchemeMismatchError;
-    use crate::execution_context::ExecutionContext;
+    use super::{LatencyModel, SchemeMismatchError};
+    use crate::{
+        ast::{ComparisonOperator, TraceNode},
+        execution_context::{ExecutionContext, MissingField},
+        functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+        types::{LhsValue, Type},
+    };
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+        net::IpAddr,
+        time::Duration,
+    };
+
+    // Counts allocations made by the current thread, so that tests running
+    // in parallel don't affect each other.
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+        let before = ALLOCATIONS.with(Cell::get);
+        let result = f();
+        (result, ALLOCATIONS.with(Cell::get) - before)
+    }
 
     #[test]
     fn test_scheme_mismatch() {
         let scheme1 = Scheme! { foo: Int };
         let scheme2 = Scheme! { foo: Int, bar: Int };
+        let scheme3 = Scheme! { foo: Int };
         let filter = scheme1.parse("foo == 42").unwrap().compile();
         let ctx = ExecutionContext::new(&scheme2);
 
-        assert_eq!(filter.execute(&ctx), Err(SchemeMismatchError));
+        let err = SchemeMismatchError::new(&scheme1, &scheme2);
+        assert_eq!(filter.execute(&ctx), Err(err));
+
+        let other_ctx = ExecutionContext::new(&scheme1);
+        assert_eq!(
+            filter.execute_batch(&[&other_ctx, &ctx]),
+            Err(SchemeMismatchError::new(&scheme1, &scheme2))
+        );
+
+        // The same scheme created twice is still a different scheme, but
+        // fingerprints tell it apart from an unrelated one.
+        let err = filter
+            .execute(&ExecutionContext::new(&scheme3))
+            .unwrap_err();
+        assert_eq!(err.expected, err.actual);
+        assert_ne!(scheme1.fingerprint(), scheme2.fingerprint());
+        assert_ne!(Scheme! { foo: Bytes }.fingerprint(), scheme1.fingerprint());
+    }
+
+    #[test]
+    fn test_validate_context() {
+        let scheme = Scheme! { foo: Int, bar: Bytes, baz: Bool };
+        let filter = scheme
+            .parse(r#"foo > 1 and (bar == "a" or true) and not baz"#)
+            .unwrap()
+            .compile();
+
+        let mut ctx = ExecutionContext::new(&scheme);
+        assert_eq!(
+            filter.validate_context(&ctx),
+            Err(vec![
+                MissingField {
+                    name: "foo",
+                    ty: Type::Int
+                },
+                MissingField {
+                    name: "baz",
+                    ty: Type::Bool
+                },
+            ])
+        );
+
+        // `bar` is only used by a comparison folded into `true`.
+        ctx.set_field_value("foo", 2).unwrap();
+        ctx.set_field_value("baz", false).unwrap();
+        assert_eq!(filter.validate_context(&ctx), Ok(()));
+        assert_eq!(filter.execute(&ctx), Ok(true));
+    }
+
+    #[test]
+    fn test_execute_batch() {
+        let scheme = Scheme! { foo: Int };
+        let filter = scheme.parse("foo > 1").unwrap().compile();
+
+        let ctxs = (0..4)
+            .map(|value| {
+                let mut ctx = ExecutionContext::new(&scheme);
+                ctx.set_field_value("foo", value).unwrap();
+                ctx
+            })
+            .collect::<Vec<_>>();
+        let ctxs = ctxs.iter().collect::<Vec<_>>();
+
+        assert_eq!(
+            filter.execute_batch(&ctxs),
+            Ok(vec![false, false, true, true])
+        );
+        assert_eq!(filter.execute_batch(&[]), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_execute_yielding() {
+        let scheme = Scheme! { a: Bool, b: Bool, port: Int };
+        let other_scheme = Scheme! { a: Bool };
+        let filter = scheme
+            .parse("(a || port == 80) && (b ^^ port > 1024) && !b")
+            .unwrap()
+            .compile();
+
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_field_value("a", true).unwrap();
+        ctx.set_field_value("b", false).unwrap();
+        ctx.set_field_value("port", 8080).unwrap();
+
+        // `port == 80` is skipped, so there are four comparisons to evaluate.
+        let mut execution = filter.execute_yielding(&ctx, 2).unwrap();
+        assert_eq!(execution.resume(), None);
+        assert_eq!(execution.resume(), Some(true));
+
+        // Later executions reuse the same bytecode.
+        ctx.set_field_value("port", 80).unwrap();
+        let mut execution = filter.execute_yielding(&ctx, 100).unwrap();
+        assert_eq!(execution.resume(), Some(false));
+        assert_eq!(filter.execute(&ctx), Ok(false));
+
+        assert_eq!(
+            filter
+                .execute_yielding(&ExecutionContext::new(&other_scheme), 1)
+                .unwrap_err(),
+            SchemeMismatchError::new(&scheme, &other_scheme)
+        );
+    }
+
+    #[test]
+    fn test_estimated_worst_case() {
+        fn echo_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+            args.next().unwrap()
+        }
+
+        let mut scheme = Scheme! {
+            http.host: Bytes,
+            tcp.port: Int,
+            ssl: Bool,
+        };
+        scheme
+            .add_function(
+                "echo".into(),
+                Function {
+                    params: vec![FunctionParam {
+                        arg_kind: FunctionArgKind::Field,
+                        val_type: Type::Bytes,
+                    }],
+                    opt_params: vec![],
+                    return_type: Type::Bytes,
+                    implementation: FunctionImpl::new(echo_function),
+                    pure: true,
+                },
+            )
+            .unwrap();
+        let model = LatencyModel {
+            ns_per_unit: 2.5,
+            ns_per_execution: 40.0,
+        };
+
+        let ast = scheme
+            .parse(r#"echo(http.host) == "a" or echo(http.host) contains "b""#)
+            .unwrap();
+        assert_eq!(ast.cost(), 27);
+        // The second call is served from the cache.
+        let filter = ast.compile();
+        assert_eq!(filter.worst_case_cost(), 16);
+        assert_eq!(
+            filter.estimated_worst_case(&model),
+            Duration::from_nanos(80)
+        );
+
+        let mut constants = ExecutionContext::new(&scheme);
+        constants.set_field_value("ssl", true).unwrap();
+        let ast = scheme.parse("ssl and tcp.port == 443").unwrap();
+        assert_eq!(ast.cost(), 4);
+        let filter = ast.compile_with_constants(&constants).unwrap();
+        assert_eq!(filter.worst_case_cost(), 2);
+        let filter = scheme
+            .parse("ssl")
+            .unwrap()
+            .compile_with_constants(&constants)
+            .unwrap();
+        assert_eq!(filter.worst_case_cost(), 0);
+        assert_eq!(
+            filter.estimated_worst_case(&model),
+            Duration::from_nanos(40)
+        );
+    }
+
+    #[test]
+    fn test_execute_does_not_allocate() {
+        fn echo_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+            args.next().unwrap()
+        }
+
+        let mut scheme = Scheme! {
+            http.host: Bytes,
+            http.ua: Bytes,
+            ip.addr: Ip,
+            ssl: Bool,
+            tcp.port: Int,
+        };
+        scheme
+            .add_function(
+                "echo".into(),
+                Function {
+                    params: vec![FunctionParam {
+                        arg_kind: FunctionArgKind::Field,
+                        val_type: Type::Bytes,
+                    }],
+                    opt_params: vec![],
+                    return_type: Type::Bytes,
+                    implementation: FunctionImpl::new(echo_function),
+                    pure: true,
+                },
+            )
+            .unwrap();
+        scheme.set_field_multi_valued("http.ua", true).unwrap();
+
+        let user_agents = ["curl/7.64.1", "Mozilla/5.0 (X11; Linux x86_64)"];
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_field_value("http.host", "www.example.org").unwrap();
+        ctx.set_field_value("http.ua", "Mozilla/5.0 (X11; Linux x86_64)")
+            .unwrap();
+        ctx.set_field_value("ip.addr", "10.0.0.1".parse::<IpAddr>().unwrap())
+            .unwrap();
+        ctx.set_field_value("ssl", true).unwrap();
+        ctx.set_field_value("tcp.port", 443).unwrap();
+
+        for source in &[
+            r#"ssl"#,
+            r#"http.host == "www.example.org""#,
+            r#"http.host >= "www" && http.host < "xyz""#,
+            r#"http.host contains "example""#,
+            r#"http.ua matches "(?i)linux""#,
+            r#"http.host in { "a.org" "www.example.org" }"#,
+            r#"tcp.port in { 80 443 8000..8080 }"#,
+            r#"tcp.port & 1 && tcp.port != 80"#,
+            r#"ip.addr in { 10.0.0.0/8 ::1 }"#,
+            r#"not ssl || (tcp.port == 443 ^^ ip.addr == 127.0.0.1)"#,
+            r#"echo(http.host) == "www.example.org""#,
+            r#"echo(http.host) == "a.org" || echo(http.host) contains "example""#,
+        ] {
+            let filter = scheme.parse(source).unwrap().compile();
+
+            // Warm up any lazily initialised state, such as regex caches.
+            filter.execute(&ctx).unwrap();
+
+            let (result, allocations) = count_allocations(|| filter.execute(&ctx));
+            assert!(result.is_ok());
+            assert_eq!(allocations, 0, "{} allocated on execution", source);
+        }
+
+        // Neither does reusing a context for many executions, once its
+        // vectors of multiple values have grown.
+        let filter = scheme
+            .parse(
+                r#"http.host contains "example" && tcp.port == 443 && http.ua matches "(?i)linux""#,
+            )
+            .unwrap()
+            .compile();
+        let mut cycle = || {
+            ctx.set_field_value("http.host", "www.example.org").unwrap();
+            ctx.set_field_value("tcp.port", 443).unwrap();
+            ctx.set_field_values("http.ua", user_agents.iter().copied())
+                .unwrap();
+            let result = filter.execute(&ctx).unwrap();
+            ctx.reset();
+            result
+        };
+        assert!(cycle());
+
+        let (results, allocations) = count_allocations(|| (0..3).all(|_| cycle()));
+        assert!(results);
+        assert_eq!(allocations, 0, "allocated on set, execute and reset");
+    }
+
+    #[test]
+    fn test_execute_with_trace() {
+        fn echo_function<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+            args.next().unwrap()
+        }
+
+        let mut scheme = Scheme! {
+            http.cookie: Bytes,
+            http.host: Bytes,
+            tcp.port: Int,
+            ssl: Bool,
+        };
+        scheme.set_field_multi_valued("http.cookie", true).unwrap();
+        scheme
+            .add_function(
+                "echo".into(),
+                Function {
+                    params: vec![FunctionParam {
+                        arg_kind: FunctionArgKind::Field,
+                        val_type: Type::Bytes,
+                    }],
+                    opt_params: vec![],
+                    return_type: Type::Bytes,
+                    implementation: FunctionImpl::new(echo_function),
+                    pure: true,
+                },
+            )
+            .unwrap();
+        let other_scheme = Scheme! { ssl: Bool };
+
+        let filter = scheme
+            .parse(
+                r#"(ssl or tcp.port == 80) and not http.cookie contains "b" and echo(http.host) == "a.org""#,
+            )
+            .unwrap()
+            .compile();
+
+        let mut ctx = ExecutionContext::new(&scheme);
+        ctx.set_field_value("ssl", true).unwrap();
+        ctx.set_field_values("http.cookie", vec!["a", "c"]).unwrap();
+        ctx.set_field_value("http.host", "a.org").unwrap();
+
+        let trace = filter.execute_with_trace(&ctx).unwrap();
+        assert!(trace.result());
+        assert_eq!(filter.execute(&ctx), Ok(true));
+
+        // The comparison of `tcp.port` is short-circuited, and the function
+        // call doesn't have a field.
+        assert_eq!(
+            serde_json::to_value(&trace).unwrap(),
+            serde_json::json!({
+                "kind": "combination",
+                "operator": "&&",
+                "operands": [
+                    {
+                        "kind": "combination",
+                        "operator": "||",
+                        "operands": [
+                            {
+                                "kind": "comparison",
+                                "filter": "ssl",
+                                "field": "ssl",
+                                "operator": "IsTrue",
+                                "values": ["true"],
+                                "result": true,
+                            },
+                        ],
+                        "result": true,
+                    },
+                    {
+                        "kind": "not",
+                        "operand": {
+                            "kind": "comparison",
+                            "filter": "http.cookie contains \"b\"",
+                            "field": "http.cookie",
+                            "operator": "Contains",
+                            "values": ["\"a\"", "\"c\""],
+                            "result": false,
+                        },
+                        "result": true,
+                    },
+                    {
+                        "kind": "comparison",
+                        "filter": "echo(http.host) == \"a.org\"",
+                        "field": null,
+                        "operator": "Equal",
+                        "values": ["\"a.org\""],
+                        "result": true,
+                    },
+                ],
+                "result": true,
+            })
+        );
+
+        // Unlike on execution, a missing field doesn't panic, but doesn't
+        // match and has no values.
+        ctx.set_field_value("ssl", false).unwrap();
+        let trace = filter.execute_with_trace(&ctx).unwrap();
+        match trace {
+            TraceNode::Combination {
+                operands, result, ..
+            } => {
+                assert!(!result);
+                assert_eq!(operands.len(), 1);
+                assert_eq!(
+                    operands[0],
+                    TraceNode::Combination {
+                        operator: "||",
+                        operands: vec![
+                            TraceNode::Comparison {
+                                filter: "ssl".to_owned(),
+                                field: Some("ssl"),
+                                operator: ComparisonOperator::IsTrue,
+                                values: vec!["false".to_owned()],
+                                result: false,
+                            },
+                            TraceNode::Comparison {
+                                filter: "tcp.port == 80".to_owned(),
+                                field: Some("tcp.port"),
+                                operator: ComparisonOperator::Equal,
+                                values: vec![],
+                                result: false,
+                            },
+                        ],
+                        result: false,
+                    }
+                );
+            }
+            trace => panic!("unexpected trace {:?}", trace),
+        }
+
+        // Filters folded into constants are traced as literals.
+        let filter = scheme
+            .parse("ssl and tcp.port == 80")
+            .unwrap()
+            .compile_with_constants(&ctx)
+            .unwrap();
+        assert_eq!(
+            filter.execute_with_trace(&ctx),
+            Ok(TraceNode::Literal { result: false })
+        );
+
+        assert_eq!(
+            filter.execute_with_trace(&ExecutionContext::new(&other_scheme)),
+            Err(SchemeMismatchError::new(&scheme, &other_scheme))
+        );
     }
 }
@@ -1,4 +1,7 @@
-use crate::types::{LhsValue, Type};
+use crate::{
+    execution_context::Deadline,
+    types::{LhsValue, Type},
+};
 use std::fmt;
 
 /// An iterator over function arguments as [`LhsValue`]s.
@@ -6,33 +9,74 @@ pub type FunctionArgs<'i, 'a> = &'i mut dyn Iterator<Item = LhsValue<'a>>;
 
 type FunctionPtr = for<'a> fn(FunctionArgs<'_, 'a>) -> LhsValue<'a>;
 
+type FunctionWithDeadlinePtr = for<'a> fn(FunctionArgs<'_, 'a>, Deadline) -> LhsValue<'a>;
+
+#[derive(Clone, Copy)]
+enum FunctionPtrKind {
+    Plain(FunctionPtr),
+    WithDeadline(FunctionWithDeadlinePtr),
+}
+
+impl FunctionPtrKind {
+    fn as_ptr(self) -> *const () {
+        match self {
+            FunctionPtrKind::Plain(func) => func as *const (),
+            FunctionPtrKind::WithDeadline(func) => func as *const (),
+        }
+    }
+}
+
 /// Wrapper around a function pointer providing the runtime implemetation.
 #[derive(Clone)]
-pub struct FunctionImpl(FunctionPtr);
+pub struct FunctionImpl(FunctionPtrKind);
 
 impl FunctionImpl {
     /// Creates a new wrapper around a function pointer.
     pub fn new(func: FunctionPtr) -> Self {
-        Self(func)
+        Self(FunctionPtrKind::Plain(func))
+    }
+
+    /// Creates a new wrapper around a function pointer that also receives
+    /// the [`Deadline`] of the current execution.
+    ///
+    /// This allows expensive functions to give up early once the
+    /// per-execution budget set via
+    /// [`ExecutionContext::set_deadline`](::ExecutionContext::set_deadline)
+    /// is exhausted.
+    pub fn with_deadline(func: FunctionWithDeadlinePtr) -> Self {
+        Self(FunctionPtrKind::WithDeadline(func))
     }
 
     /// Calls the wrapped function pointer.
     pub fn execute<'a>(&self, args: impl IntoIterator<Item = LhsValue<'a>>) -> LhsValue<'a> {
-        (self.0)(&mut args.into_iter())
+        self.execute_with_deadline(args, Deadline::default())
+    }
+
+    /// Calls the wrapped function pointer with a given deadline.
+    pub fn execute_with_deadline<'a>(
+        &self,
+        args: impl IntoIterator<Item = LhsValue<'a>>,
+        deadline: Deadline,
+    ) -> LhsValue<'a> {
+        let args = &mut args.into_iter();
+        match self.0 {
+            FunctionPtrKind::Plain(func) => func(args),
+            FunctionPtrKind::WithDeadline(func) => func(args, deadline),
+        }
     }
 }
 
 impl fmt::Debug for FunctionImpl {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
         fmt.debug_tuple("FunctionImpl")
-            .field(&(self.0 as *const ()))
+            .field(&self.0.as_ptr())
             .finish()
     }
 }
 
 impl PartialEq for FunctionImpl {
     fn eq(&self, other: &FunctionImpl) -> bool {
-        self.0 as *const () == other.0 as *const ()
+        self.0.as_ptr() == other.0.as_ptr()
     }
 }
 
@@ -76,4 +120,10 @@ pub struct Function {
     pub return_type: Type,
     /// Actual implementation that will be called at runtime.
     pub implementation: FunctionImpl,
+    /// Whether the function always returns the same result for the same
+    /// arguments and has no side effects.
+    ///
+    /// Calls to pure functions with only literal arguments are evaluated
+    /// once when a filter is compiled instead of on every execution.
+    pub pure: bool,
 }
@@ -1,13 +1,17 @@
 use crate::{
+    ast::ComparisonOperator,
     rhs_types::RegexError,
-    scheme::{UnknownFieldError, UnknownFunctionError},
+    scheme::{UnknownFieldError, UnknownFunctionError, UnknownListError},
     types::{Type, TypeMismatchError},
 };
 use cidr::NetworkParseError;
 use failure::Fail;
-use std::num::ParseIntError;
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    num::ParseIntError,
+};
 
-#[derive(Debug, PartialEq, Fail)]
+#[derive(Debug, PartialEq, Clone, Fail)]
 pub enum LexErrorKind {
     #[fail(display = "expected {}", _0)]
     ExpectedName(&'static str),
@@ -15,6 +19,9 @@ pub enum LexErrorKind {
     #[fail(display = "expected literal {:?}", _0)]
     ExpectedLiteral(&'static str),
 
+    #[fail(display = "expected {}", _0)]
+    ExpectedOneOf(TokenSet),
+
     #[fail(display = "{} while parsing with radix {}", err, radix)]
     ParseInt {
         #[cause]
@@ -22,6 +29,18 @@ pub enum LexErrorKind {
         radix: u32,
     },
 
+    #[fail(display = "integer is out of the valid range {}..{}", min, max)]
+    IntOutOfRange { min: i64, max: i64 },
+
+    #[fail(display = "float is out of the valid range")]
+    FloatOutOfRange,
+
+    #[fail(display = "expected an RFC 3339 timestamp")]
+    InvalidTimestamp,
+
+    #[fail(display = "timestamp is out of the valid range")]
+    TimestampOutOfRange,
+
     #[fail(display = "{}", _0)]
     ParseNetwork(#[cause] NetworkParseError),
 
@@ -47,12 +66,46 @@ pub enum LexErrorKind {
     #[fail(display = "{}", _0)]
     UnknownFunction(#[cause] UnknownFunctionError),
 
+    #[fail(display = "{}", _0)]
+    UnknownList(#[cause] UnknownListError),
+
     #[fail(display = "cannot use this operation type {:?}", lhs_type)]
     UnsupportedOp { lhs_type: Type },
 
+    #[fail(display = "{}", _0)]
+    InvalidRhs(String),
+
+    #[fail(display = "invalid type of right-hand side: {}", _0)]
+    InvalidRhsType(#[cause] TypeMismatchError),
+
+    // Positions of elements start from 1.
+    #[fail(display = "invalid element #{} of the list: {}", position, kind)]
+    InvalidListElement {
+        position: usize,
+        kind: Box<LexErrorKind>,
+    },
+
+    #[fail(display = "list has more than {} elements", max)]
+    TooManyListElements { max: usize },
+
+    #[fail(display = "operator {:?} is not allowed", _0)]
+    ForbiddenOperator(ComparisonOperator),
+
+    #[fail(display = "function calls are not allowed")]
+    ForbiddenFunctionCall,
+
+    #[fail(display = "octets with leading zeros are ambiguous")]
+    LeadingZeroOctet,
+
     #[fail(display = "incompatible range bounds")]
     IncompatibleRangeBounds,
 
+    #[fail(display = "IPv6 zone identifiers are not allowed")]
+    ForbiddenIpZone,
+
+    #[fail(display = "zone identifiers can only follow IPv6 addresses compared with == or !=")]
+    InvalidIpZone,
+
     #[fail(display = "unrecognised input")]
     EOF,
 
@@ -70,6 +123,69 @@ pub enum LexErrorKind {
     },
 }
 
+impl LexErrorKind {
+    /// Returns literal tokens that would have been accepted instead, if
+    /// they are known.
+    pub fn expected_tokens(&self) -> Vec<&'static str> {
+        match self {
+            LexErrorKind::ExpectedLiteral(literal) => vec![literal],
+            LexErrorKind::ExpectedOneOf(tokens) => tokens.to_vec(),
+            LexErrorKind::InvalidListElement { kind, .. } => kind.expected_tokens(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Alternatives of an enum generated with `lex_enum!`.
+pub trait Tokens {
+    fn tokens(out: &mut Vec<&'static str>);
+}
+
+/// A set of tokens one of which was expected, named after the enum which
+/// they make up, like `OrderingOp`.
+///
+/// Tokens are collected only when needed, so that failed attempts to lex
+/// an enum, which are common while backtracking, stay cheap.
+#[derive(Clone, Copy)]
+pub struct TokenSet {
+    name: &'static str,
+    collect: fn(&mut Vec<&'static str>),
+}
+
+impl TokenSet {
+    pub fn of<T: Tokens>(name: &'static str) -> Self {
+        TokenSet {
+            name,
+            collect: T::tokens,
+        }
+    }
+
+    pub fn to_vec(self) -> Vec<&'static str> {
+        let mut tokens = Vec::new();
+        (self.collect)(&mut tokens);
+        tokens
+    }
+}
+
+// Names of enums are unique, unlike addresses of functions.
+impl PartialEq for TokenSet {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Debug for TokenSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl Display for TokenSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
 pub type LexError<'i> = (LexErrorKind, &'i str);
 
 pub type LexResult<'i, T> = Result<(T, &'i str), LexError<'i>>;
@@ -111,7 +227,8 @@ pub fn skip_space(input: &str) -> &str {
 /// This macro generates enum declaration + lexer implementation.
 ///
 /// It works by recursively processing variants one by one, while passing
-/// around intermediate state (partial declaration and lexer bodies).
+/// around intermediate state (partial declaration, lexer and token list
+/// bodies).
 macro_rules! lex_enum {
     // Branch for handling `SomeType => VariantName`.
     //
@@ -119,11 +236,11 @@ macro_rules! lex_enum {
     //
     // On the parser side, tries to parse `SomeType` and wraps into the variant
     // on success.
-    (@decl $preamble:tt $name:ident $input:ident { $($decl:tt)* } { $($expr:tt)* } {
+    (@decl $preamble:tt $name:ident $input:ident $out:ident { $($decl:tt)* } { $($expr:tt)* } { $($tokens:tt)* } {
         $ty:ty => $item:ident,
         $($rest:tt)*
     }) => {
-        lex_enum!(@decl $preamble $name $input {
+        lex_enum!(@decl $preamble $name $input $out {
             $($decl)*
             $item($ty),
         } {
@@ -131,6 +248,9 @@ macro_rules! lex_enum {
             if let Ok((res, $input)) = $crate::lex::Lex::lex($input) {
                 return Ok(($name::$item(res), $input));
             }
+        } {
+            $($tokens)*
+            <$ty as $crate::lex::Tokens>::tokens($out);
         } { $($rest)* });
     };
 
@@ -141,11 +261,11 @@ macro_rules! lex_enum {
     //
     // On the parser side, tries to parse either of the given string values,
     // and returns the variant if any of them succeeded.
-    (@decl $preamble:tt $name:ident $input:ident { $($decl:tt)* } { $($expr:tt)* } {
+    (@decl $preamble:tt $name:ident $input:ident $out:ident { $($decl:tt)* } { $($expr:tt)* } { $($tokens:tt)* } {
         $($s:tt)|+ => $item:ident $(= $value:expr)*,
         $($rest:tt)*
     }) => {
-        lex_enum!(@decl $preamble $name $input {
+        lex_enum!(@decl $preamble $name $input $out {
             $($decl)*
             $item $(= $value)*,
         } {
@@ -153,6 +273,9 @@ macro_rules! lex_enum {
             $(if let Ok($input) = $crate::lex::expect($input, $s) {
                 return Ok(($name::$item, $input));
             })+
+        } {
+            $($tokens)*
+            $out.extend_from_slice(&[$($s),+]);
         } { $($rest)* });
     };
 
@@ -160,8 +283,8 @@ macro_rules! lex_enum {
     //
     // This is invoked when no more variants are left to process.
     // At this point declaration and lexer body are considered complete.
-    (@decl { $($preamble:tt)* } $name:ident $input:ident $decl:tt { $($expr:stmt)* } {}) => {
-        #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize)]
+    (@decl { $($preamble:tt)* } $name:ident $input:ident $out:ident $decl:tt { $($expr:stmt)* } { $($tokens:tt)* } {}) => {
+        #[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, serde::Deserialize)]
         $($preamble)*
         pub enum $name $decl
 
@@ -169,18 +292,26 @@ macro_rules! lex_enum {
             fn lex($input: &'i str) -> $crate::lex::LexResult<'_, Self> {
                 $($expr)*
                 Err((
-                    $crate::lex::LexErrorKind::ExpectedName(stringify!($name)),
+                    $crate::lex::LexErrorKind::ExpectedOneOf(
+                        $crate::lex::TokenSet::of::<$name>(stringify!($name))
+                    ),
                     $input
                 ))
             }
         }
+
+        impl $crate::lex::Tokens for $name {
+            fn tokens($out: &mut Vec<&'static str>) {
+                $($tokens)*
+            }
+        }
     };
 
     // The public entry point to the macro.
     ($(# $attrs:tt)* $name:ident $items:tt) => {
         lex_enum!(@decl {
             $(# $attrs)*
-        } $name input {} {} $items);
+        } $name input out {} {} {} $items);
     };
 }
 
@@ -53,6 +53,19 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! # Serialization
+//!
+//! Schemes can be serialized and deserialized with serde, filter ASTs can be
+//! serialized, and runtime values can be deserialized as [`LhsValue`]s. The
+//! encoding refers to fields and functions by name and consists only of
+//! strings, booleans, 64-bit integers, 64-bit floats and sequences of them,
+//! with timestamps encoded as strings, so it doesn't depend on the pointer
+//! width or endianness of the platform it was produced on.
+//!
+//! Integers beyond ±2^53 lose precision in JSON parsers that read all
+//! numbers as floats, like `JSON.parse` in JavaScript, so such encodings
+//! have to be read with a parser that keeps integers exact.
 #![warn(missing_docs)]
 
 #[macro_use]
@@ -61,23 +74,57 @@ mod lex;
 #[macro_use]
 mod scheme;
 
+mod anonymizer;
 mod ast;
+mod bit_set;
+mod bloom_filter;
 mod execution_context;
 mod filter;
 mod functions;
 mod heap_searcher;
+mod list;
+mod migration;
+mod nftables;
+mod parser_settings;
 mod range_set;
 mod rhs_types;
 mod strict_partial_ord;
+pub mod testing;
 mod types;
+mod vm;
+mod wireshark;
 
 pub use self::{
-    ast::FilterAst,
-    execution_context::ExecutionContext,
-    filter::{Filter, SchemeMismatchError},
+    ast::{
+        ComparisonOperator, ComparisonRef, ComparisonRhs, ExprBuildError, ExprBuilder,
+        FailedConjunct, FieldExprBuilder, FilterAst, IncrementalParse, Literal,
+        NormalFormSizeError, OperatorStyle, RhsReplacement, RhsRewriteError, SplitFilterAst,
+        TraceNode, Unsupported,
+    },
+    execution_context::{
+        CallCacheStats, Deadline, ExecutionContext, MatchedSpan, MissingField, SetFieldValueError,
+        SetFromPairsError, SetListValuesError,
+    },
+    filter::{Filter, LatencyModel, SchemeMismatchError},
     functions::{
         Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionOptParam, FunctionParam,
     },
-    scheme::{FieldRedefinitionError, ParseError, Scheme, UnknownFieldError},
-    types::{GetType, LhsValue, Type, TypeMismatchError},
+    list::{ListDefinition, ListValues},
+    migration::{Migration, MigrationError, RhsConverter},
+    nftables::{NftablesExportError, NftablesExporter},
+    parser_settings::{IntOverflow, Ipv6Zones, ParserSettings},
+    rhs_types::{
+        Bytes, Domain, ExplicitIpRange, Float, IpRange, Regex, RegexError, Timestamp,
+        TimestampLiteral, UninhabitedBool, Wildcard,
+    },
+    scheme::{
+        ComparisonMode, FieldComparisonModeError, FieldDefaultError, FieldDefinitionError,
+        FieldRedefinitionError, FieldRef, FunctionPanicHook, FunctionRedefinitionError,
+        ItemRedefinitionError, ListRedefinitionError, ParseError, RhsValidator, RuleParseError,
+        Scheme, SchemeBuilder, SchemeIncompatibility, UnknownFieldError, UnknownListError,
+        VirtualFieldError,
+    },
+    types::{GetType, LhsValue, RhsValue, RhsValues, Type, TypeMismatchError},
+    vm::{BytecodeFilter, FilterSet, YieldingExecution},
+    wireshark::WiresharkFieldsError,
 };
@@ -0,0 +1,215 @@
+use crate::{
+    range_set::{IpRangeSet, RangeSet},
+    rhs_types::{Float, Timestamp},
+    types::{GetType, LhsValue, RhsValues, Type},
+};
+use fnv::FnvBuildHasher;
+use indexmap::IndexSet;
+use std::fmt::{self, Debug, Formatter};
+
+/// A declaration of a named list that filters refer to as `$name`, see
+/// [`Scheme::add_list`](::Scheme::add_list).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ListDefinition {
+    /// Type of elements of the list.
+    pub val_type: Type,
+}
+
+enum ListMatcher {
+    Ip(IpRangeSet),
+    Int(RangeSet<i64>),
+    Float(RangeSet<Float>),
+    Timestamp(RangeSet<Timestamp>),
+    Bytes(IndexSet<Box<[u8]>, FnvBuildHasher>),
+}
+
+/// Contents of a named list, prepared for lookups.
+///
+/// Contents are built once whenever a list changes and can be shared between
+/// any number of [execution contexts](::ExecutionContext), see
+/// [`ExecutionContext::set_list_values`](::ExecutionContext::set_list_values).
+/// Filters referring to the list don't need to be parsed or compiled again.
+pub struct ListValues {
+    matcher: ListMatcher,
+    len: usize,
+}
+
+impl ListValues {
+    /// Prepares given values for lookups.
+    pub fn new(values: RhsValues) -> Self {
+        let len = values.len();
+
+        let matcher = match values {
+            RhsValues::Ip(ranges) => ListMatcher::Ip(ranges.into_iter().map(Into::into).collect()),
+            RhsValues::Int(values) => ListMatcher::Int(values.into_iter().collect()),
+            RhsValues::Float(values) => ListMatcher::Float(values.into_iter().collect()),
+            RhsValues::Timestamp(values) => ListMatcher::Timestamp(values.into_iter().collect()),
+            RhsValues::Bytes(values) => {
+                ListMatcher::Bytes(values.into_iter().map(Into::into).collect())
+            }
+            RhsValues::Bool(_) => unreachable!(),
+        };
+
+        ListValues { matcher, len }
+    }
+
+    /// Returns the number of values the list was built from.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks whether the list was built without any values.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn contains(&self, value: &LhsValue<'_>) -> bool {
+        match (&self.matcher, value) {
+            (ListMatcher::Ip(ranges), LhsValue::Ip(addr)) => ranges.contains(addr),
+            (ListMatcher::Int(values), LhsValue::Int(value)) => values.contains(value),
+            (ListMatcher::Float(values), LhsValue::Float(value)) => values.contains(value),
+            (ListMatcher::Timestamp(values), LhsValue::Timestamp(value)) => values.contains(value),
+            (ListMatcher::Bytes(values), LhsValue::Bytes(value)) => values.contains(&**value),
+            _ => false,
+        }
+    }
+}
+
+impl GetType for ListValues {
+    fn get_type(&self) -> Type {
+        match self.matcher {
+            ListMatcher::Ip(_) => Type::Ip,
+            ListMatcher::Int(_) => Type::Int,
+            ListMatcher::Float(_) => Type::Float,
+            ListMatcher::Timestamp(_) => Type::Timestamp,
+            ListMatcher::Bytes(_) => Type::Bytes,
+        }
+    }
+}
+
+impl Debug for ListValues {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ListValues")
+            .field("type", &self.get_type())
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+#[test]
+fn test_lists() {
+    use crate::{
+        execution_context::{ExecutionContext, SetListValuesError},
+        lex::LexErrorKind,
+        rhs_types::IpRange,
+        scheme::UnknownListError,
+        types::TypeMismatchError,
+    };
+    use smallvec::smallvec;
+    use std::sync::Arc;
+
+    let mut scheme = Scheme! { ip.src: Ip, host: Bytes };
+    scheme
+        .add_list("bad_ips".into(), ListDefinition { val_type: Type::Ip })
+        .unwrap();
+    scheme
+        .add_list(
+            "host".into(),
+            ListDefinition {
+                val_type: Type::Bytes,
+            },
+        )
+        .unwrap();
+    assert!(scheme
+        .add_list(
+            "host".into(),
+            ListDefinition {
+                val_type: Type::Bytes,
+            },
+        )
+        .is_err());
+
+    let ast = scheme
+        .parse(r#"ip.src in $bad_ips or host in $host"#)
+        .unwrap();
+    assert_eq!(ast.to_string(), "ip.src in $bad_ips || host in $host");
+
+    let json = serde_json::to_value(&ast).unwrap();
+    assert_eq!(
+        json["items"][0],
+        serde_json::json!({ "lhs": "ip.src", "op": "InList", "rhs": "bad_ips" })
+    );
+    assert_eq!(
+        scheme.parse_from_ast(json).unwrap().to_string(),
+        ast.to_string()
+    );
+
+    let err = scheme.parse("ip.src in $good_ips").unwrap_err();
+    assert_eq!(err.reason(), "unknown list");
+    assert_eq!((err.span_start(), err.span_len()), (10, 9));
+    assert_eq!(
+        scheme.parse("host in $bad_ips").unwrap_err().reason(),
+        LexErrorKind::InvalidRhsType(TypeMismatchError {
+            expected: Type::Bytes,
+            actual: Type::Ip,
+        })
+        .to_string()
+    );
+
+    let filter = ast.clone().compile();
+    let bytecode = ast.compile_to_bytecode();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("ip.src", std::net::IpAddr::from([10, 1, 2, 3]))
+        .unwrap();
+    ctx.set_field_value("host", "example.org").unwrap();
+
+    // Lists without contents don't match anything.
+    assert_eq!(filter.execute(&ctx), Ok(false));
+    assert_eq!(bytecode.execute(&ctx), Ok(false));
+
+    let bad_ips = Arc::new(ListValues::new(RhsValues::Ip(smallvec![IpRange::Cidr(
+        "10.0.0.0/8".parse().unwrap(),
+    )])));
+    assert_eq!(bad_ips.len(), 1);
+    ctx.set_list_values("bad_ips", bad_ips).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(bytecode.execute(&ctx), Ok(true));
+
+    // Contents are swapped without compiling the filter again.
+    ctx.set_list_values(
+        "bad_ips",
+        Arc::new(ListValues::new(RhsValues::Ip(smallvec![]))),
+    )
+    .unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+
+    ctx.set_list_values(
+        "host",
+        Arc::new(ListValues::new(RhsValues::Bytes(smallvec![
+            "example.com".to_owned().into(),
+            "example.org".to_owned().into(),
+        ]))),
+    )
+    .unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(bytecode.execute(&ctx), Ok(true));
+
+    assert_eq!(
+        ctx.set_list_values(
+            "host",
+            Arc::new(ListValues::new(RhsValues::Int(smallvec![])))
+        ),
+        Err(SetListValuesError::TypeMismatch(TypeMismatchError {
+            expected: Type::Bytes,
+            actual: Type::Int,
+        }))
+    );
+    assert_eq!(
+        ctx.set_list_values(
+            "other",
+            Arc::new(ListValues::new(RhsValues::Int(smallvec![])))
+        ),
+        Err(SetListValuesError::UnknownList(UnknownListError))
+    );
+}
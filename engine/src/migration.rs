@@ -0,0 +1,156 @@
+use crate::ast::{ComparisonRhs, RhsReplacement, RhsRewriteError};
+use failure::Fail;
+use fnv::FnvBuildHasher;
+use indexmap::{IndexMap, IndexSet};
+
+/// A function that converts a right-hand side of a comparison of a field
+/// whose type is changed by a [`Migration`].
+///
+/// Returning `None` means that the comparison can't be converted.
+pub type RhsConverter = fn(ComparisonRhs<'_>) -> Option<RhsReplacement>;
+
+/// A set of changes between two versions of a scheme, applied to stored
+/// filters with [`FilterAst::migrate`](::FilterAst::migrate).
+///
+/// Fields are referred to by their names in the old version of the scheme.
+/// Fields and functions that aren't mentioned are expected to be present in
+/// the new version under the same name and with the same type.
+#[derive(Default)]
+pub struct Migration {
+    renames: IndexMap<String, String, FnvBuildHasher>,
+    converters: IndexMap<String, RhsConverter, FnvBuildHasher>,
+    dropped: IndexSet<String, FnvBuildHasher>,
+}
+
+impl Migration {
+    /// Creates an empty migration.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Renames a field.
+    pub fn rename_field(&mut self, from: String, to: String) {
+        self.renames.insert(from, to);
+    }
+
+    /// Changes a type of a field, converting right-hand sides of its
+    /// comparisons with a given function.
+    ///
+    /// Comparisons of the field are converted with
+    /// [`RhsReplacement`]s, just like with
+    /// [`FilterAst::rewrite_rhs`](::FilterAst::rewrite_rhs), so the
+    /// comparison operator must accept the replacement. Filters that use the
+    /// field in any other way, e.g. as a function argument, can't be
+    /// migrated.
+    pub fn change_field_type(&mut self, name: String, converter: RhsConverter) {
+        self.converters.insert(name, converter);
+    }
+
+    /// Drops a field. Filters using it can't be migrated.
+    pub fn drop_field(&mut self, name: String) {
+        self.dropped.insert(name);
+    }
+
+    pub(crate) fn new_name<'a>(&'a self, name: &'a str) -> &'a str {
+        self.renames.get(name).map_or(name, String::as_str)
+    }
+
+    pub(crate) fn get_converter(&self, name: &str) -> Option<RhsConverter> {
+        self.converters.get(name).copied()
+    }
+
+    pub(crate) fn is_dropped(&self, name: &str) -> bool {
+        self.dropped.contains(name)
+    }
+}
+
+/// An error that occurs when a filter can't be migrated with
+/// [`FilterAst::migrate`](::FilterAst::migrate).
+#[derive(Debug, PartialEq, Fail)]
+pub enum MigrationError {
+    /// The filter uses a dropped field.
+    #[fail(display = "field {} was dropped", _0)]
+    DroppedField(String),
+
+    /// A comparison of a field that changes type couldn't be converted.
+    #[fail(display = "cannot convert a comparison of field {}", _0)]
+    Conversion(String),
+
+    /// A converted right-hand side can't be used in the comparison.
+    #[fail(display = "invalid conversion of field {}: {}", name, err)]
+    InvalidConversion {
+        /// A name of the field.
+        name: String,
+        /// An underlying error.
+        #[cause]
+        err: RhsRewriteError,
+    },
+
+    /// A field or a function is missing in the new scheme or has a
+    /// different type.
+    #[fail(display = "filter doesn't match the new scheme")]
+    SchemeMismatch,
+}
+
+#[test]
+fn test_migrate() {
+    use crate::types::RhsValue;
+
+    fn int_to_bytes(rhs: ComparisonRhs<'_>) -> Option<RhsReplacement> {
+        match rhs {
+            ComparisonRhs::Value(RhsValue::Int(int)) => Some(RhsReplacement::Value(
+                RhsValue::Bytes(int.to_string().into()),
+            )),
+            _ => None,
+        }
+    }
+
+    let v1 = Scheme! {
+        http.host: Bytes,
+        port: Int,
+        ssl: Bool,
+        legacy: Bool,
+    };
+
+    let v2 = Scheme! {
+        http.request.host: Bytes,
+        port: Bytes,
+        ssl: Bool,
+    };
+
+    let mut migration = Migration::new();
+    migration.rename_field("http.host".into(), "http.request.host".into());
+    migration.change_field_type("port".into(), int_to_bytes);
+    migration.drop_field("legacy".into());
+
+    let ast = v1
+        .parse(r#"http.host == "example.org" && (port == 443 || not ssl)"#)
+        .unwrap();
+
+    assert_eq!(
+        ast.migrate(&migration, &v2),
+        Ok(v2
+            .parse(r#"http.request.host == "example.org" && (port == "443" || not ssl)"#)
+            .unwrap())
+    );
+
+    assert_eq!(
+        v1.parse("ssl || legacy").unwrap().migrate(&migration, &v2),
+        Err(MigrationError::DroppedField("legacy".into()))
+    );
+
+    assert_eq!(
+        v1.parse("port & 1").unwrap().migrate(&migration, &v2),
+        Err(MigrationError::Conversion("port".into()))
+    );
+
+    let v3 = Scheme! {
+        http.request.host: Bytes,
+        port: Bytes,
+    };
+
+    assert_eq!(
+        ast.migrate(&migration, &v3),
+        Err(MigrationError::SchemeMismatch)
+    );
+}
@@ -0,0 +1,336 @@
+use crate::{
+    ast::{
+        ComparisonOperator, ComparisonRef, ComparisonRhs, FilterAst, NormalFormSizeError,
+        Unsupported,
+    },
+    rhs_types::{ExplicitIpRange, IpRange},
+    types::{GetType, RhsValue, RhsValues},
+};
+use cidr::Cidr;
+use failure::Fail;
+use fnv::FnvBuildHasher;
+use indexmap::IndexMap;
+use std::fmt::Write;
+
+/// An error that occurs when exporting a filter with
+/// [`NftablesExporter::export`].
+#[derive(Debug, PartialEq, Fail)]
+pub enum NftablesExportError {
+    /// The filter has comparisons that can't be expressed in nftables.
+    #[fail(display = "filter has unsupported comparisons")]
+    Unsupported(Vec<Unsupported>),
+
+    /// The filter would need too many rules.
+    #[fail(display = "{}", _0)]
+    TooManyRules(#[cause] NormalFormSizeError),
+}
+
+enum Selector {
+    Value(String),
+    Flag { selector: String, value: String },
+}
+
+/// Exports simple filters as nftables rules, so that the same filters can
+/// program both userland filtering and kernel firewalls.
+///
+/// Only comparisons of IP and integer fields with literals are supported,
+/// plus boolean fields that stand for protocol matches. Every used field has
+/// to be mapped onto an nftables expression first.
+#[derive(Default)]
+pub struct NftablesExporter {
+    selectors: IndexMap<String, Selector, FnvBuildHasher>,
+}
+
+impl NftablesExporter {
+    /// Creates an exporter without any fields.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Maps an IP or an integer field onto an nftables expression like
+    /// `ip saddr` or `tcp dport`.
+    pub fn add_field(&mut self, name: String, selector: String) {
+        self.selectors.insert(name, Selector::Value(selector));
+    }
+
+    /// Maps a boolean field onto a match of an nftables expression against a
+    /// value, like `meta l4proto` and `tcp`.
+    pub fn add_flag(&mut self, name: String, selector: String, value: String) {
+        self.selectors
+            .insert(name, Selector::Flag { selector, value });
+    }
+
+    /// Checks whether a filter can be exported, reporting all the
+    /// comparisons that can't.
+    pub fn supports(&self, ast: &FilterAst<'_>) -> Result<(), Vec<Unsupported>> {
+        ast.check_support(|comparison| self.check(comparison))
+    }
+
+    /// Exports a filter as bodies of nftables rules, without verdicts.
+    ///
+    /// The filter is converted into a
+    /// [disjunctive normal form](::FilterAst::to_dnf), and each of its terms
+    /// becomes a separate rule, so a packet matches the filter if it matches
+    /// any of the rules. Fails if the filter has unsupported comparisons or
+    /// would need more than `max_rules` rules.
+    pub fn export(
+        &self,
+        ast: &FilterAst<'_>,
+        max_rules: usize,
+    ) -> Result<Vec<String>, NftablesExportError> {
+        self.supports(ast)
+            .map_err(NftablesExportError::Unsupported)?;
+
+        let terms = ast
+            .dnf_comparisons(max_rules)
+            .map_err(NftablesExportError::TooManyRules)?;
+
+        Ok(terms
+            .into_iter()
+            .map(|term| {
+                let mut rule = String::new();
+                for (negated, comparison) in term {
+                    if !rule.is_empty() {
+                        rule.push(' ');
+                    }
+                    self.write_match(&mut rule, comparison, negated);
+                }
+                rule
+            })
+            .collect())
+    }
+
+    fn check(&self, comparison: ComparisonRef<'_, '_>) -> Option<String> {
+        let name = match comparison.field() {
+            Some(name) => name,
+            None => return Some("function calls are not supported".to_owned()),
+        };
+
+        let op = comparison.op();
+
+        match (self.selectors.get(name), op) {
+            (None, _) => return Some(format!("field {} is not mapped", name)),
+            (Some(Selector::Flag { .. }), ComparisonOperator::IsTrue) => return None,
+            (Some(Selector::Flag { .. }), _) => {
+                return Some(format!(
+                    "field {} is mapped as a flag, but is not boolean",
+                    name
+                ));
+            }
+            (Some(Selector::Value(_)), ComparisonOperator::IsTrue) => {
+                return Some(format!("boolean field {} must be mapped as a flag", name));
+            }
+            (Some(Selector::Value(_)), _) => {}
+        }
+
+        if comparison.is_all() {
+            return Some("quantifier all is not supported".to_owned());
+        }
+
+        if let Some((op, _)) = comparison.bitwise() {
+            return Some(format!("operator {:?} is not supported", op));
+        }
+
+        let negative = match comparison.rhs() {
+            Some(ComparisonRhs::Value(RhsValue::Ip(_)))
+            | Some(ComparisonRhs::Values(RhsValues::Ip(_))) => false,
+            Some(ComparisonRhs::Value(RhsValue::Int(int))) => *int < 0,
+            Some(ComparisonRhs::Values(RhsValues::Int(ranges))) => {
+                ranges.iter().any(|range| *range.start() < 0)
+            }
+            Some(ComparisonRhs::Value(value)) => {
+                return Some(format!(
+                    "values of type {:?} are not supported",
+                    value.get_type()
+                ));
+            }
+            Some(ComparisonRhs::Values(values)) => {
+                return Some(format!(
+                    "values of type {:?} are not supported",
+                    values.get_type()
+                ));
+            }
+            Some(ComparisonRhs::List(_)) => return Some("lists are not supported".to_owned()),
+            Some(_) => return Some(format!("operator {:?} is not supported", op)),
+            None => {
+                return Some(
+                    "comparisons with fields or function calls are not supported".to_owned(),
+                );
+            }
+        };
+
+        if negative {
+            Some("negative integers are not supported".to_owned())
+        } else {
+            None
+        }
+    }
+
+    fn write_match(&self, rule: &mut String, comparison: ComparisonRef<'_, '_>, negated: bool) {
+        // Comparisons are checked with `supports` beforehand.
+        let selector = &self.selectors[comparison.field().unwrap()];
+
+        let (selector, value) = match selector {
+            Selector::Flag { selector, value } => {
+                let op = if negated { "!= " } else { "" };
+                write!(rule, "{} {}{}", selector, op, value).unwrap();
+                return;
+            }
+            Selector::Value(selector) => (selector, comparison.rhs().unwrap()),
+        };
+
+        // nftables has no negation, so operators are inverted instead.
+        let op = match (comparison.op(), negated) {
+            (ComparisonOperator::Equal, false)
+            | (ComparisonOperator::NotEqual, true)
+            | (ComparisonOperator::In, false) => "",
+            (ComparisonOperator::Equal, true)
+            | (ComparisonOperator::NotEqual, false)
+            | (ComparisonOperator::In, true) => "!= ",
+            (ComparisonOperator::GreaterThanEqual, false)
+            | (ComparisonOperator::LessThan, true) => ">= ",
+            (ComparisonOperator::LessThanEqual, false)
+            | (ComparisonOperator::GreaterThan, true) => "<= ",
+            (ComparisonOperator::GreaterThan, false)
+            | (ComparisonOperator::LessThanEqual, true) => "> ",
+            (ComparisonOperator::LessThan, false)
+            | (ComparisonOperator::GreaterThanEqual, true) => "< ",
+            _ => unreachable!(),
+        };
+
+        write!(rule, "{} {}", selector, op).unwrap();
+
+        match value {
+            ComparisonRhs::Value(RhsValue::Ip(ip)) => write!(rule, "{}", ip).unwrap(),
+            ComparisonRhs::Value(RhsValue::Int(int)) => write!(rule, "{}", int).unwrap(),
+            ComparisonRhs::Values(values) => {
+                rule.push_str("{ ");
+                match values {
+                    RhsValues::Ip(ranges) => {
+                        for (i, range) in ranges.iter().enumerate() {
+                            if i != 0 {
+                                rule.push_str(", ");
+                            }
+                            match range {
+                                IpRange::Cidr(cidr) => write!(
+                                    rule,
+                                    "{}/{}",
+                                    cidr.first_address(),
+                                    cidr.network_length()
+                                ),
+                                IpRange::Explicit(ExplicitIpRange::V4(range)) => {
+                                    write!(rule, "{}-{}", range.start(), range.end())
+                                }
+                                IpRange::Explicit(ExplicitIpRange::V6(range)) => {
+                                    write!(rule, "{}-{}", range.start(), range.end())
+                                }
+                            }
+                            .unwrap();
+                        }
+                    }
+                    RhsValues::Int(ranges) => {
+                        for (i, range) in ranges.iter().enumerate() {
+                            if i != 0 {
+                                rule.push_str(", ");
+                            }
+                            if range.start() == range.end() {
+                                write!(rule, "{}", range.start()).unwrap();
+                            } else {
+                                write!(rule, "{}-{}", range.start(), range.end()).unwrap();
+                            }
+                        }
+                    }
+                    _ => unreachable!(),
+                }
+                rule.push_str(" }");
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+#[test]
+fn test_export() {
+    let scheme = Scheme! {
+        ip.src: Ip,
+        tcp: Bool,
+        tcp.dstport: Int,
+        http.host: Bytes,
+    };
+
+    let mut exporter = NftablesExporter::new();
+    exporter.add_field("ip.src".into(), "ip saddr".into());
+    exporter.add_flag("tcp".into(), "meta l4proto".into(), "tcp".into());
+    exporter.add_field("tcp.dstport".into(), "tcp dport".into());
+
+    let ast = scheme
+        .parse(
+            "tcp and ip.src in { 10.0.0.0/8 192.168.0.1..192.168.0.9 } \
+             and not (tcp.dstport == 22 or tcp.dstport >= 1024)",
+        )
+        .unwrap();
+
+    assert_eq!(
+        exporter.export(&ast, 10),
+        Ok(vec![
+            "meta l4proto tcp ip saddr { 10.0.0.0/8, 192.168.0.1-192.168.0.9 } \
+             tcp dport != 22 tcp dport < 1024"
+                .to_owned()
+        ])
+    );
+
+    let ast = scheme
+        .parse("not tcp and (tcp.dstport in { 80 443 8000..8080 } or ip.src == 127.0.0.1)")
+        .unwrap();
+
+    assert_eq!(
+        exporter.export(&ast, 10),
+        Ok(vec![
+            "meta l4proto != tcp tcp dport { 80, 443, 8000-8080 }".to_owned(),
+            "meta l4proto != tcp ip saddr 127.0.0.1".to_owned(),
+        ])
+    );
+
+    assert_eq!(
+        exporter.export(&ast, 1),
+        Err(NftablesExportError::TooManyRules(NormalFormSizeError {
+            max_terms: 1
+        }))
+    );
+
+    let ast = scheme
+        .parse(r#"tcp.dstport & 1 or http.host == "example.org" or tcp.dstport > -1"#)
+        .unwrap();
+
+    assert_eq!(
+        exporter.supports(&ast),
+        Err(vec![
+            Unsupported {
+                construct: "tcp.dstport & 1".to_owned(),
+                span: 0..15,
+                reason: "operator BitwiseAnd is not supported".to_owned(),
+            },
+            Unsupported {
+                construct: r#"http.host == "example.org""#.to_owned(),
+                span: 19..45,
+                reason: "field http.host is not mapped".to_owned(),
+            },
+            Unsupported {
+                construct: "tcp.dstport > -1".to_owned(),
+                span: 49..65,
+                reason: "negative integers are not supported".to_owned(),
+            },
+        ])
+    );
+
+    let ast = scheme.parse("tcp.dstport >> 8 == 1").unwrap();
+
+    assert_eq!(
+        exporter.supports(&ast),
+        Err(vec![Unsupported {
+            construct: "tcp.dstport >> 8 == 1".to_owned(),
+            span: 0..21,
+            reason: "operator ShiftRight is not supported".to_owned(),
+        }])
+    );
+}
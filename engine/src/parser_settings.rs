@@ -0,0 +1,116 @@
+use crate::ast::ComparisonOperator;
+
+/// Settings that control how filters are parsed and compiled.
+///
+/// These are stored on a [`Scheme`](struct@::Scheme) and apply to all
+/// filters parsed with it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParserSettings {
+    /// Minimum number of elements in a `bytes in { ... }` list for which a
+    /// bloom filter is built in front of the exact set, so that most
+    /// non-members are rejected with a single hash.
+    ///
+    /// `None` disables bloom filters altogether.
+    pub bloom_filter_threshold: Option<usize>,
+
+    /// Target false positive rate of bloom filters built for large lists.
+    ///
+    /// Rates are clamped to `1e-9..=0.5`, since a rate of `0` would need an
+    /// infinitely large filter, and lower ones take more memory than the
+    /// list itself. NaN is treated as `0.5`.
+    pub bloom_filter_false_positive_rate: f64,
+
+    /// Maximum number of elements in a `field in { ... }` list.
+    ///
+    /// Parsing fails as soon as a longer list is encountered, so that
+    /// machine-generated filters can't make the parser consume unbounded
+    /// memory. `None` means no limit.
+    pub max_list_elements: Option<usize>,
+
+    /// Comparison operators that filters are allowed to use.
+    ///
+    /// Parsing fails on the first comparison with any other operator, so
+    /// that platforms can offer a restricted language to untrusted tenants,
+    /// for example one without `matches`. `None` allows all operators.
+    pub allowed_operators: Option<Vec<ComparisonOperator>>,
+
+    /// Whether filters are allowed to call functions.
+    pub allow_function_calls: bool,
+
+    /// Whether IPv4-mapped IPv6 addresses like `::ffff:1.2.3.4` are treated
+    /// as the IPv4 addresses they stand for.
+    ///
+    /// When enabled, such addresses are converted to IPv4 both in literals
+    /// (including networks and ranges that lie entirely within
+    /// `::ffff:0:0/96`) and in values of fields as they are set, so that
+    /// `::ffff:1.2.3.4` and `1.2.3.4` compare as equal on either side.
+    /// Values returned from functions are left as they are.
+    pub ipv4_mapped_ipv6_interop: bool,
+
+    /// What happens when an integer operation in a filter overflows, like
+    /// `len << 24` for a large `len`.
+    pub int_overflow: IntOverflow,
+
+    /// Whether IPv6 addresses can be scoped with a zone identifier, like
+    /// `fe80::1%eth0`, and what it means.
+    pub ipv6_zones: Ipv6Zones,
+}
+
+/// Handling of zone identifiers of scoped IPv6 addresses like
+/// `fe80::1%eth0`.
+///
+/// Link-local addresses are only unique within a network interface, which
+/// is what a zone identifier names. Zones can follow IPv6 literals compared
+/// with `==` or `!=`, and IPv6 values given to
+/// [`ExecutionContext::set_from_pairs`](::ExecutionContext::set_from_pairs).
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Ipv6Zones {
+    /// Zone identifiers are rejected.
+    Reject,
+    /// Zone identifiers are accepted and dropped, so addresses are compared
+    /// regardless of the interface.
+    Ignore,
+    /// A literal with a zone identifier only equals a value with the same
+    /// zone, see [`ExecutionContext::set_ip_zone`](::ExecutionContext::set_ip_zone).
+    /// Literals without a zone match values regardless of their zone.
+    Compare,
+}
+
+/// Behaviour of integer operations whose results don't fit into an `Int`.
+///
+/// Values of fields often come from untrusted inputs, so by default an
+/// overflow makes a comparison `false` instead of producing a small or
+/// negative number that could slip past a check.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum IntOverflow {
+    /// Bits that don't fit are dropped, like in C.
+    Wrapping,
+    /// The result is clamped to the smallest or the largest `Int`.
+    Saturating,
+    /// The comparison doesn't match.
+    NoMatch,
+}
+
+impl Default for ParserSettings {
+    fn default() -> Self {
+        ParserSettings {
+            bloom_filter_threshold: Some(10_000),
+            bloom_filter_false_positive_rate: 0.01,
+            max_list_elements: None,
+            allowed_operators: None,
+            allow_function_calls: true,
+            ipv4_mapped_ipv6_interop: false,
+            int_overflow: IntOverflow::NoMatch,
+            ipv6_zones: Ipv6Zones::Reject,
+        }
+    }
+}
+
+impl ParserSettings {
+    pub(crate) fn allows_operator(&self, op: ComparisonOperator) -> bool {
+        match &self.allowed_operators {
+            Some(allowed) => allowed.contains(&op),
+            None => true,
+        }
+    }
+}
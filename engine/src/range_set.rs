@@ -1,4 +1,11 @@
-use std::{borrow::Borrow, cmp::Ordering, iter::FromIterator, ops::RangeInclusive};
+use crate::rhs_types::ExplicitIpRange;
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    iter::FromIterator,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::RangeInclusive,
+};
 
 /// RangeSet provides a set-like interface that allows to search for items while
 /// being constructed from and storing inclusive ranges in a compact fashion.
@@ -54,3 +61,73 @@ impl<T> RangeSet<T> {
             .is_ok()
     }
 }
+
+/// IpRangeSet keeps IPv4 and IPv6 ranges in separate [`RangeSet`]s, so that
+/// even lists of many thousands of CIDRs are matched with a binary search
+/// over merged ranges rather than by checking each CIDR in turn.
+pub struct IpRangeSet {
+    v4: RangeSet<Ipv4Addr>,
+    v6: RangeSet<Ipv6Addr>,
+}
+
+impl FromIterator<ExplicitIpRange> for IpRangeSet {
+    fn from_iter<I: IntoIterator<Item = ExplicitIpRange>>(ranges: I) -> Self {
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        for range in ranges {
+            match range {
+                ExplicitIpRange::V4(range) => v4.push(range),
+                ExplicitIpRange::V6(range) => v6.push(range),
+            }
+        }
+        IpRangeSet {
+            v4: RangeSet::from(v4),
+            v6: RangeSet::from(v6),
+        }
+    }
+}
+
+impl IpRangeSet {
+    /// Checks whether an address is in any of the ranges.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match addr {
+            IpAddr::V4(addr) => self.v4.contains(addr),
+            IpAddr::V6(addr) => self.v6.contains(addr),
+        }
+    }
+}
+
+#[test]
+fn test_ip_range_set() {
+    use crate::rhs_types::IpRange;
+    use cidr::{Cidr, IpCidr};
+
+    // Every other /24 of 10.0.0.0/8 and every other /48 of 2001:db8::/32,
+    // so that no ranges get merged.
+    let set = (0..0x8000_u32)
+        .map(|i| IpCidr::new(Ipv4Addr::from(0x0a00_0000 | i << 9).into(), 24))
+        .chain(
+            (0..0x8000_u128)
+                .map(|i| IpCidr::new(Ipv6Addr::from(0x2001_0db8_u128 << 96 | i << 81).into(), 48)),
+        )
+        .map(|cidr| IpRange::Cidr(cidr.unwrap()).into())
+        .collect::<IpRangeSet>();
+
+    assert_eq!(set.v4.ranges.len(), 0x8000);
+    assert_eq!(set.v6.ranges.len(), 0x8000);
+
+    for &(addr, expected) in &[
+        ("10.0.0.1", true),
+        ("10.0.1.1", false),
+        ("10.255.254.255", true),
+        ("10.255.255.0", false),
+        ("11.0.0.0", false),
+        ("2001:db8::1", true),
+        ("2001:db8:1::1", false),
+        ("2001:db8:fffe:ffff::", true),
+        ("2001:db9::", false),
+        ("::ffff:10.0.0.1", false),
+    ] {
+        assert_eq!(set.contains(&addr.parse().unwrap()), expected, "{}", addr);
+    }
+}
@@ -11,10 +11,16 @@ use std::{
     str,
 };
 
+/// A bytes literal parsed from a filter.
+///
+/// It remembers whether it was written as a string or as a raw sequence of
+/// hex bytes, but otherwise behaves as a byte slice.
 #[derive(PartialEq, Eq, Clone, Serialize)]
 #[serde(untagged)]
 pub enum Bytes {
+    /// A quoted string literal.
     Str(Box<str>),
+    /// A `:`, `-` or `.` separated hex bytes literal.
     Raw(Box<[u8]>),
 }
 
@@ -78,6 +84,17 @@ impl Deref for Bytes {
     }
 }
 
+impl Bytes {
+    /// Returns a copy with ASCII letters lowercased, keeping the kind of the
+    /// literal.
+    pub(crate) fn to_ascii_lowercase(&self) -> Self {
+        match self {
+            Bytes::Str(s) => Bytes::Str(s.to_ascii_lowercase().into_boxed_str()),
+            Bytes::Raw(b) => Bytes::Raw(b.to_ascii_lowercase().into_boxed_slice()),
+        }
+    }
+}
+
 impl Borrow<[u8]> for Bytes {
     fn borrow(&self) -> &[u8] {
         self
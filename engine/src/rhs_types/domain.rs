@@ -0,0 +1,137 @@
+use crate::{
+    lex::{Lex, LexResult},
+    rhs_types::Bytes,
+};
+use serde::{Serialize, Serializer};
+use std::{
+    fmt::{self, Debug, Formatter},
+    ops::Range,
+};
+
+/// A domain name for `in_domain` comparisons, which match the name itself
+/// and all of its subdomains.
+///
+/// Names are compared label by label and case-insensitively, and trailing
+/// dots of fully qualified names are ignored on both sides. Unlike
+/// `contains` or a suffix check, `"example.com"` doesn't match
+/// `"badexample.com"`.
+pub struct Domain {
+    name: Bytes,
+    // Lowercased name without the trailing dot.
+    normalized: Box<[u8]>,
+}
+
+impl Domain {
+    /// Creates a domain from its name.
+    pub fn new(name: Bytes) -> Self {
+        let normalized = strip_root(&name).to_ascii_lowercase().into();
+        Domain { name, normalized }
+    }
+
+    /// Returns the name as it was written in a filter.
+    pub fn name(&self) -> &Bytes {
+        &self.name
+    }
+
+    /// Returns the range of the value that matched the domain, if the value
+    /// is the domain itself or one of its subdomains.
+    pub fn find(&self, value: &[u8]) -> Option<Range<usize>> {
+        let value = strip_root(value);
+        let domain = &self.normalized[..];
+
+        // The root matches any name.
+        if domain.is_empty() {
+            return Some(0..value.len());
+        }
+
+        let start = value.len().checked_sub(domain.len())?;
+        if !value[start..].eq_ignore_ascii_case(domain) {
+            return None;
+        }
+
+        // A match has to start at a label boundary.
+        if start == 0 || value[start - 1] == b'.' {
+            Some(start..value.len())
+        } else {
+            None
+        }
+    }
+
+    /// Checks whether a value is the domain itself or one of its
+    /// subdomains.
+    pub fn is_match(&self, value: &[u8]) -> bool {
+        self.find(value).is_some()
+    }
+}
+
+fn strip_root(name: &[u8]) -> &[u8] {
+    match name.split_last() {
+        Some((b'.', rest)) => rest,
+        _ => name,
+    }
+}
+
+impl From<Bytes> for Domain {
+    fn from(name: Bytes) -> Self {
+        Domain::new(name)
+    }
+}
+
+impl Clone for Domain {
+    fn clone(&self) -> Self {
+        Domain::new(self.name.clone())
+    }
+}
+
+impl PartialEq for Domain {
+    fn eq(&self, other: &Domain) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for Domain {}
+
+impl Debug for Domain {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.name.fmt(f)
+    }
+}
+
+impl<'i> Lex<'i> for Domain {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let (name, input) = Bytes::lex(input)?;
+        Ok((Domain::new(name), input))
+    }
+}
+
+impl Serialize for Domain {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.name.serialize(ser)
+    }
+}
+
+#[test]
+fn test() {
+    let domain = |name: &str| Domain::new(name.to_owned().into());
+
+    let example = domain("example.com");
+    assert_eq!(example.find(b"example.com"), Some(0..11));
+    assert_eq!(example.find(b"www.EXAMPLE.com."), Some(4..15));
+    assert!(example.is_match(b"a.b.example.com"));
+    assert!(!example.is_match(b"badexample.com"));
+    assert!(!example.is_match(b"example.com.evil"));
+    assert!(!example.is_match(b"com"));
+    assert!(!example.is_match(b""));
+
+    assert!(domain("EXAMPLE.com").is_match(b"www.example.COM"));
+
+    let fqdn = domain("example.com.");
+    assert!(fqdn.is_match(b"example.com"));
+    assert!(fqdn.is_match(b"www.example.com."));
+
+    let root = domain(".");
+    assert!(root.is_match(b"example.com"));
+    assert!(root.is_match(b""));
+
+    assert_eq!(Domain::lex(r#""example.com" rest"#), Ok((example, " rest")));
+}
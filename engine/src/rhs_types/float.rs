@@ -0,0 +1,164 @@
+use crate::{
+    lex::{expect, span, take_while, Lex, LexErrorKind, LexResult},
+    strict_partial_ord::StrictPartialOrd,
+};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::{
+    cmp::Ordering,
+    fmt::{self, Debug, Display, Formatter},
+    ops::RangeInclusive,
+};
+
+/// A 64-bit floating point number that is never NaN.
+///
+/// Unlike `f64`, it's totally ordered, so it can be compared and used in
+/// ranges just like integers.
+#[derive(Clone, Copy, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Float(f64);
+
+impl Float {
+    /// Creates a number, or returns `None` for NaN.
+    pub fn new(value: f64) -> Option<Self> {
+        if value.is_nan() {
+            None
+        } else {
+            Some(Float(value))
+        }
+    }
+
+    /// Returns the number as `f64`.
+    pub fn get(self) -> f64 {
+        self.0
+    }
+}
+
+impl From<Float> for f64 {
+    fn from(value: Float) -> Self {
+        value.0
+    }
+}
+
+impl Eq for Float {}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // NaN is rejected on construction, so all the values are comparable.
+        self.0.partial_cmp(&other.0).unwrap()
+    }
+}
+
+impl StrictPartialOrd for Float {}
+
+impl Debug for Float {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&self.0, f)
+    }
+}
+
+impl Display for Float {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(&self.0, f)
+    }
+}
+
+impl<'de> Deserialize<'de> for Float {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Float::new(f64::deserialize(deserializer)?)
+            .ok_or_else(|| de::Error::custom("NaN is not a valid float"))
+    }
+}
+
+fn lex_digits(input: &str) -> LexResult<'_, &str> {
+    take_while(input, "digit", |c| c.is_ascii_digit())
+}
+
+// Lexes an optional part of a number, like a fraction or an exponent, only
+// if it's followed by digits. This keeps `1..2` a range rather than `1.`
+// followed by garbage.
+fn lex_suffix<'i>(input: &'i str, prefixes: &[&'static str]) -> &'i str {
+    prefixes
+        .iter()
+        .filter_map(|prefix| expect(input, prefix).ok())
+        .filter_map(|rest| lex_digits(rest).ok())
+        .map(|(_, rest)| rest)
+        .next()
+        .unwrap_or(input)
+}
+
+impl<'i> Lex<'i> for Float {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let initial_input = input;
+        let input = expect(input, "-").unwrap_or(input);
+        let (_, input) = lex_digits(input)?;
+        let input = lex_suffix(input, &["."]);
+        let input = lex_suffix(input, &["e-", "e+", "e", "E-", "E+", "E"]);
+
+        let number = span(initial_input, input);
+
+        // The syntax above can't produce NaN, but large exponents overflow
+        // into infinities.
+        match number.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok((Float(value), input)),
+            _ => Err((LexErrorKind::FloatOutOfRange, number)),
+        }
+    }
+}
+
+impl<'i> Lex<'i> for RangeInclusive<Float> {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let initial_input = input;
+        let (first, input) = Float::lex(input)?;
+        let (last, input) = if let Ok(input) = expect(input, "..") {
+            Float::lex(input)?
+        } else {
+            (first, input)
+        };
+        if last < first {
+            return Err((
+                LexErrorKind::IncompatibleRangeBounds,
+                span(initial_input, input),
+            ));
+        }
+        Ok((first..=last, input))
+    }
+}
+
+#[test]
+fn test() {
+    let float = |value| Float::new(value).unwrap();
+
+    assert_ok!(Float::lex("0"), float(0.0), "");
+    assert_ok!(Float::lex("0.5;"), float(0.5), ";");
+    assert_ok!(Float::lex("-12.25-"), float(-12.25), "-");
+    assert_ok!(Float::lex("1e3!"), float(1000.0), "!");
+    assert_ok!(Float::lex("2.5E-2 "), float(0.025), " ");
+    assert_ok!(Float::lex("3.x"), float(3.0), ".x");
+    assert_ok!(Float::lex("4ex"), float(4.0), "ex");
+    assert_err!(Float::lex("x"), LexErrorKind::ExpectedName("digit"), "x");
+    assert_err!(
+        Float::lex("-1e400"),
+        LexErrorKind::FloatOutOfRange,
+        "-1e400"
+    );
+    assert_ok!(RangeInclusive::lex("0.5!"), float(0.5)..=float(0.5), "!");
+    assert_ok!(RangeInclusive::lex("0.1..0.5"), float(0.1)..=float(0.5));
+    assert_ok!(RangeInclusive::lex("1..2e1"), float(1.0)..=float(20.0));
+    assert_err!(
+        <RangeInclusive<Float>>::lex("0.5..0.1"),
+        LexErrorKind::IncompatibleRangeBounds,
+        "0.5..0.1"
+    );
+
+    assert_eq!(Float::new(f64::NAN), None);
+    assert!(float(-0.0) == float(0.0));
+    assert!(float(-1.5) < float(0.25));
+    assert_eq!(float(0.1).to_string(), "0.1");
+    assert_eq!(serde_json::from_str::<Float>("0.75").unwrap(), float(0.75));
+}
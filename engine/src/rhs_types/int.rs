@@ -9,14 +9,29 @@ fn lex_digits(input: &str) -> LexResult<'_, &str> {
     take_while(input, "digit", |c| c.is_digit(16))
 }
 
-fn parse_number<'i>((input, rest): (&'i str, &'i str), radix: u32) -> LexResult<'_, i32> {
-    match i32::from_str_radix(input, radix) {
+fn parse_number<'i>((input, rest): (&'i str, &'i str), radix: u32) -> LexResult<'_, i64> {
+    match i64::from_str_radix(input, radix) {
         Ok(res) => Ok((res, rest)),
-        Err(err) => Err((LexErrorKind::ParseInt { err, radix }, input)),
+        Err(err) => {
+            let digits = input.trim_start_matches('-');
+
+            // If all the digits are valid, the only reason for failure is an
+            // overflow, so report the valid range instead of a generic error.
+            let kind = if digits.chars().all(|c| c.is_digit(radix)) {
+                LexErrorKind::IntOutOfRange {
+                    min: i64::MIN,
+                    max: i64::MAX,
+                }
+            } else {
+                LexErrorKind::ParseInt { err, radix }
+            };
+
+            Err((kind, input))
+        }
     }
 }
 
-impl<'i> Lex<'i> for i32 {
+impl<'i> Lex<'i> for i64 {
     fn lex(input: &str) -> LexResult<'_, Self> {
         if let Ok(input) = expect(input, "0x") {
             parse_number(lex_digits(input)?, 16)
@@ -36,12 +51,12 @@ impl<'i> Lex<'i> for i32 {
     }
 }
 
-impl<'i> Lex<'i> for RangeInclusive<i32> {
+impl<'i> Lex<'i> for RangeInclusive<i64> {
     fn lex(input: &str) -> LexResult<'_, Self> {
         let initial_input = input;
-        let (first, input) = i32::lex(input)?;
+        let (first, input) = i64::lex(input)?;
         let (last, input) = if let Ok(input) = expect(input, "..") {
-            i32::lex(input)?
+            i64::lex(input)?
         } else {
             (first, input)
         };
@@ -55,49 +70,64 @@ impl<'i> Lex<'i> for RangeInclusive<i32> {
     }
 }
 
-impl StrictPartialOrd for i32 {}
+impl StrictPartialOrd for i64 {}
 
 #[test]
 fn test() {
     use std::str::FromStr;
 
-    assert_ok!(i32::lex("0"), 0i32, "");
-    assert_ok!(i32::lex("0-"), 0i32, "-");
-    assert_ok!(i32::lex("0x1f5+"), 501i32, "+");
-    assert_ok!(i32::lex("0123;"), 83i32, ";");
-    assert_ok!(i32::lex("78!"), 78i32, "!");
-    assert_ok!(i32::lex("0xefg"), 239i32, "g");
-    assert_ok!(i32::lex("-12-"), -12i32, "-");
+    assert_ok!(i64::lex("0"), 0i64, "");
+    assert_ok!(i64::lex("0-"), 0i64, "-");
+    assert_ok!(i64::lex("0x1f5+"), 501i64, "+");
+    assert_ok!(i64::lex("0123;"), 83i64, ";");
+    assert_ok!(i64::lex("78!"), 78i64, "!");
+    assert_ok!(i64::lex("0xefg"), 239i64, "g");
+    assert_ok!(i64::lex("-12-"), -12i64, "-");
+    assert_ok!(i64::lex("4294967296!"), 1i64 << 32, "!");
+    assert_ok!(i64::lex("0xFFFFFFFF"), 0xFFFF_FFFFi64, "");
+    assert_ok!(i64::lex("-9223372036854775808!"), i64::MIN, "!");
     assert_err!(
-        i32::lex("-2147483649!"),
-        LexErrorKind::ParseInt {
-            err: i32::from_str("-2147483649").unwrap_err(),
-            radix: 10
+        i64::lex("-9223372036854775809!"),
+        LexErrorKind::IntOutOfRange {
+            min: i64::MIN,
+            max: i64::MAX
         },
-        "-2147483649"
+        "-9223372036854775809"
     );
     assert_err!(
-        i32::lex("2147483648!"),
-        LexErrorKind::ParseInt {
-            err: i32::from_str("2147483648").unwrap_err(),
-            radix: 10
+        i64::lex("9223372036854775808!"),
+        LexErrorKind::IntOutOfRange {
+            min: i64::MIN,
+            max: i64::MAX
+        },
+        "9223372036854775808"
+    );
+    assert_err!(
+        i64::lex("0x8000000000000000"),
+        LexErrorKind::IntOutOfRange {
+            min: i64::MIN,
+            max: i64::MAX
         },
-        "2147483648"
+        "8000000000000000"
     );
     assert_err!(
-        i32::lex("10fex"),
+        i64::lex("10fex"),
         LexErrorKind::ParseInt {
-            err: i32::from_str("10fe").unwrap_err(),
+            err: i64::from_str("10fe").unwrap_err(),
             radix: 10
         },
         "10fe"
     );
-    assert_ok!(RangeInclusive::lex("78!"), 78i32..=78i32, "!");
-    assert_ok!(RangeInclusive::lex("0..10"), 0i32..=10i32);
-    assert_ok!(RangeInclusive::lex("0123..0xefg"), 83i32..=239i32, "g");
-    assert_ok!(RangeInclusive::lex("-20..-10"), -20i32..=-10i32);
+    assert_ok!(RangeInclusive::lex("78!"), 78i64..=78i64, "!");
+    assert_ok!(RangeInclusive::lex("0..10"), 0i64..=10i64);
+    assert_ok!(RangeInclusive::lex("0123..0xefg"), 83i64..=239i64, "g");
+    assert_ok!(RangeInclusive::lex("-20..-10"), -20i64..=-10i64);
+    assert_ok!(
+        RangeInclusive::lex("2147483648..0x1FFFFFFFF"),
+        (1i64 << 31)..=0x1_FFFF_FFFFi64
+    );
     assert_err!(
-        <RangeInclusive<i32>>::lex("10..0"),
+        <RangeInclusive<i64>>::lex("10..0"),
         LexErrorKind::IncompatibleRangeBounds,
         "10..0"
     );
@@ -1,5 +1,5 @@
 use crate::{
-    lex::{take_while, Lex, LexError, LexErrorKind, LexResult},
+    lex::{expect, take_while, Lex, LexError, LexErrorKind, LexResult},
     strict_partial_ord::StrictPartialOrd,
 };
 use cidr::{Cidr, IpCidr, Ipv4Cidr, Ipv6Cidr, NetworkParseError};
@@ -18,7 +18,29 @@ fn match_addr_or_cidr(input: &str) -> LexResult<'_, &str> {
     })
 }
 
+// Octets like `010` are read as octal by some tools and as decimal by
+// others, so they are rejected rather than silently picking one meaning.
+fn check_octets(input: &str) -> Result<(), LexError<'_>> {
+    let ipv4_part = match input.rfind(':') {
+        Some(pos) => &input[pos + 1..],
+        None => input,
+    };
+
+    if !ipv4_part.contains('.') {
+        return Ok(());
+    }
+
+    match ipv4_part
+        .split('.')
+        .find(|octet| octet.len() > 1 && octet.starts_with('0'))
+    {
+        Some(octet) => Err((LexErrorKind::LeadingZeroOctet, octet)),
+        None => Ok(()),
+    }
+}
+
 fn parse_addr(input: &str) -> Result<IpAddr, LexError<'_>> {
+    check_octets(input)?;
     IpAddr::from_str(input).map_err(|err| {
         (
             LexErrorKind::ParseNetwork(NetworkParseError::AddrParseError(err)),
@@ -34,17 +56,30 @@ impl<'i> Lex<'i> for IpAddr {
     }
 }
 
+/// Lexes a zone identifier of a scoped IPv6 address, like `%eth0`.
+pub(crate) fn lex_ip_zone(input: &str) -> LexResult<'_, &str> {
+    take_while(expect(input, "%")?, "zone identifier character", |c| {
+        c.is_ascii_alphanumeric() || "-._~".contains(c)
+    })
+}
+
+/// An inclusive range of IP addresses of the same family.
 #[derive(PartialEq, Eq, Clone, Serialize, Debug)]
 #[serde(untagged)]
 pub enum ExplicitIpRange {
+    /// A range of IPv4 addresses.
     V4(RangeInclusive<Ipv4Addr>),
+    /// A range of IPv6 addresses.
     V6(RangeInclusive<Ipv6Addr>),
 }
 
+/// An IP range literal, as used in `ip in { ... }` lists.
 #[derive(PartialEq, Eq, Clone, Serialize, Debug)]
 #[serde(untagged)]
 pub enum IpRange {
+    /// An explicit `first..last` range.
     Explicit(ExplicitIpRange),
+    /// A CIDR network (or a single address).
     Cidr(IpCidr),
 }
 
@@ -69,8 +104,9 @@ impl<'i> Lex<'i> for IpRange {
                 }
             })
         } else {
+            let split_pos = chunk.find('/').unwrap_or_else(|| chunk.len());
+            check_octets(&chunk[..split_pos])?;
             IpRange::Cidr(cidr::IpCidr::from_str(chunk).map_err(|err| {
-                let split_pos = chunk.find('/').unwrap_or_else(|| chunk.len());
                 let err_span = match err {
                     NetworkParseError::AddrParseError(_) | NetworkParseError::InvalidHostPart => {
                         &chunk[..split_pos]
@@ -123,6 +159,43 @@ impl From<IpRange> for ExplicitIpRange {
     }
 }
 
+/// Converts an IPv4-mapped IPv6 address like `::ffff:1.2.3.4` into the
+/// IPv4 address it stands for, leaving other addresses as they are.
+pub(crate) fn unmap_ipv4(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => addr,
+        },
+        addr => addr,
+    }
+}
+
+impl IpRange {
+    /// Converts a range that lies entirely within `::ffff:0:0/96` into the
+    /// equivalent IPv4 range, see [`unmap_ipv4`].
+    pub(crate) fn unmap_ipv4(self) -> Self {
+        match self {
+            IpRange::Cidr(IpCidr::V6(cidr)) if cidr.network_length() >= 96 => {
+                match cidr.first_address().to_ipv4_mapped() {
+                    Some(addr) => IpRange::Cidr(IpCidr::V4(
+                        Ipv4Cidr::new(addr, cidr.network_length() - 96)
+                            .expect("host bits of a mapped network are zero"),
+                    )),
+                    None => IpRange::Cidr(IpCidr::V6(cidr)),
+                }
+            }
+            IpRange::Explicit(ExplicitIpRange::V6(range)) => {
+                match (range.start().to_ipv4_mapped(), range.end().to_ipv4_mapped()) {
+                    (Some(start), Some(end)) => IpRange::Explicit(ExplicitIpRange::V4(start..=end)),
+                    _ => IpRange::Explicit(ExplicitIpRange::V6(range)),
+                }
+            }
+            range => range,
+        }
+    }
+}
+
 impl StrictPartialOrd for IpAddr {
     fn strict_partial_cmp(&self, other: &Self) -> Option<Ordering> {
         match (self, other) {
@@ -233,6 +306,60 @@ fn test_lex() {
     );
 }
 
+#[test]
+fn test_normalization() {
+    assert_err!(
+        IpRange::lex("10.01.0.1"),
+        LexErrorKind::LeadingZeroOctet,
+        "01"
+    );
+    assert_err!(
+        IpRange::lex("010.0.0.0/8"),
+        LexErrorKind::LeadingZeroOctet,
+        "010"
+    );
+    assert_err!(
+        IpRange::lex("10.0.0.1..10.0.0.010"),
+        LexErrorKind::LeadingZeroOctet,
+        "010"
+    );
+    assert_err!(
+        IpAddr::lex("::ffff:1.2.3.04"),
+        LexErrorKind::LeadingZeroOctet,
+        "04"
+    );
+    assert_ok!(IpAddr::lex("10.0.0.0"), IpAddr::from([10, 0, 0, 0]));
+
+    // Hex digits are case-insensitive and are always printed in lowercase.
+    let (addr, _) = IpAddr::lex("2001:DB8::AbCd").unwrap();
+    assert_eq!(addr.to_string(), "2001:db8::abcd");
+
+    let mapped = IpAddr::lex("::FFFF:1.2.3.4").unwrap().0;
+    assert_eq!(mapped.to_string(), "::ffff:1.2.3.4");
+    assert_eq!(unmap_ipv4(mapped), IpAddr::from([1, 2, 3, 4]));
+    assert_eq!(
+        unmap_ipv4(IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1])),
+        IpAddr::from([0, 0, 0, 0, 0, 0, 0, 1])
+    );
+
+    let unmapped = |s| IpRange::lex(s).unwrap().0.unmap_ipv4();
+    assert_eq!(
+        unmapped("::ffff:10.0.0.0/104"),
+        IpRange::Cidr(IpCidr::new([10, 0, 0, 0].into(), 8).unwrap())
+    );
+    assert_eq!(
+        unmapped("::ffff:10.0.0.1..::ffff:10.0.0.9"),
+        IpRange::Explicit(ExplicitIpRange::V4(
+            [10, 0, 0, 1].into()..=[10, 0, 0, 9].into()
+        ))
+    );
+    assert_eq!(unmapped("::/64"), IpRange::lex("::/64").unwrap().0);
+    assert_eq!(
+        unmapped("::1..::ffff:1.2.3.4"),
+        IpRange::lex("::1..::ffff:1.2.3.4").unwrap().0
+    );
+}
+
 #[test]
 fn test_strict_partial_ord() {
     let ips = &[
@@ -1,12 +1,22 @@
 mod bool;
 mod bytes;
+mod domain;
+mod float;
 mod int;
 mod ip;
 mod regex;
+mod timestamp;
+mod wildcard;
+
+pub(crate) use self::ip::{lex_ip_zone, unmap_ipv4};
 
 pub use self::{
     bool::UninhabitedBool,
     bytes::Bytes,
+    domain::Domain,
+    float::Float,
     ip::{ExplicitIpRange, IpRange},
-    regex::{Error as RegexError, Regex},
+    regex::{Error as RegexError, Regex, RegexSet},
+    timestamp::{Timestamp, TimestampLiteral},
+    wildcard::Wildcard,
 };
@@ -1,7 +1,8 @@
-use std::str::FromStr;
+use std::{ops::Range, str::FromStr};
 
 pub use regex::Error;
 
+/// A compiled regular expression parsed from a filter.
 #[derive(Clone)]
 pub struct Regex(regex::bytes::Regex);
 
@@ -17,11 +18,38 @@ impl FromStr for Regex {
 }
 
 impl Regex {
+    /// Checks whether the regex matches given bytes.
     pub fn is_match(&self, text: &[u8]) -> bool {
         self.0.is_match(text)
     }
 
+    /// Returns the range of the leftmost match in given bytes, if any.
+    pub fn find(&self, text: &[u8]) -> Option<Range<usize>> {
+        self.0.find(text).map(|m| m.start()..m.end())
+    }
+
+    /// Returns the original regex source.
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
 }
+
+/// A set of regular expressions matched in a single pass over the input.
+pub struct RegexSet(regex::bytes::RegexSet);
+
+impl RegexSet {
+    /// Compiles a set of patterns, or returns `None` if they can't be
+    /// compiled together, e.g. because the set is too large.
+    pub fn new<I: IntoIterator<Item = S>, S: AsRef<str>>(patterns: I) -> Option<Self> {
+        ::regex::bytes::RegexSetBuilder::new(patterns)
+            .unicode(false)
+            .build()
+            .map(RegexSet)
+            .ok()
+    }
+
+    /// Checks whether any of the patterns matches given bytes.
+    pub fn is_match(&self, text: &[u8]) -> bool {
+        self.0.is_match(text)
+    }
+}
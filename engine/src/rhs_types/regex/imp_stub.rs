@@ -1,8 +1,11 @@
-use std::fmt;
 use failure::Fail;
+use std::fmt;
+use std::ops::Range;
 use std::str::FromStr;
 
-#[derive(Debug, PartialEq, Fail)]
+/// An uninhabited regex error used when the engine is built without regex
+/// support.
+#[derive(Debug, PartialEq, Clone, Fail)]
 pub enum Error {}
 
 impl fmt::Display for Error {
@@ -11,6 +14,8 @@ impl fmt::Display for Error {
     }
 }
 
+/// A stub regular expression used when the engine is built without regex
+/// support.
 #[derive(Clone)]
 pub struct Regex(String);
 
@@ -23,11 +28,34 @@ impl FromStr for Regex {
 }
 
 impl Regex {
+    /// Always panics as regexes are not supported in this build.
     pub fn is_match(&self, _text: &[u8]) -> bool {
         unimplemented!("Engine was built without regex support")
     }
 
+    /// Always panics as regexes are not supported in this build.
+    pub fn find(&self, _text: &[u8]) -> Option<Range<usize>> {
+        unimplemented!("Engine was built without regex support")
+    }
+
+    /// Returns the original regex source.
     pub fn as_str(&self) -> &str {
         self.0.as_str()
     }
 }
+
+/// A stub set of regular expressions used when the engine is built without
+/// regex support.
+pub enum RegexSet {}
+
+impl RegexSet {
+    /// Always returns `None` as regexes are not supported in this build.
+    pub fn new<I: IntoIterator<Item = S>, S: AsRef<str>>(_patterns: I) -> Option<Self> {
+        None
+    }
+
+    /// Never called as a stub set can't be created.
+    pub fn is_match(&self, _text: &[u8]) -> bool {
+        match *self {}
+    }
+}
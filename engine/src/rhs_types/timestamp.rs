@@ -0,0 +1,558 @@
+use crate::{
+    lex::{expect, skip_space, span, take_while, Lex, LexErrorKind, LexResult},
+    strict_partial_ord::StrictPartialOrd,
+};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    cmp::Ordering,
+    convert::TryFrom,
+    fmt::{self, Debug, Display, Formatter},
+    ops::RangeInclusive,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+const SECS_PER_DAY: i64 = 86_400;
+
+/// A point in time, stored as nanoseconds since the Unix epoch.
+///
+/// This covers years 1677 to 2262. Timestamps are written in filters as
+/// quoted RFC 3339 strings, like `"2023-06-01T00:00:00Z"`.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp(i64);
+
+impl Timestamp {
+    /// Creates a timestamp from nanoseconds since the Unix epoch.
+    pub fn from_nanos(nanos: i64) -> Self {
+        Timestamp(nanos)
+    }
+
+    /// Returns the number of nanoseconds since the Unix epoch.
+    pub fn as_nanos(self) -> i64 {
+        self.0
+    }
+
+    /// Returns the current time.
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    // Parses an RFC 3339 timestamp, like `2023-06-01T12:30:00.5+02:00`.
+    pub(crate) fn parse(s: &str) -> Result<Self, LexErrorKind> {
+        let mut parser = DateTimeParser(s.as_bytes());
+        let nanos = parser
+            .parse()
+            .ok_or(LexErrorKind::InvalidTimestamp)?
+            .ok_or(LexErrorKind::TimestampOutOfRange)?;
+        Ok(Timestamp(nanos))
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    /// Converts a system time, saturating at the bounds of the supported
+    /// range.
+    fn from(time: SystemTime) -> Self {
+        fn nanos(duration: Duration) -> i64 {
+            i64::try_from(duration.as_nanos()).unwrap_or(i64::MAX)
+        }
+
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => Timestamp(nanos(after)),
+            Err(before) => Timestamp(nanos(before.duration()).saturating_neg()),
+        }
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(timestamp: Timestamp) -> Self {
+        let offset = Duration::from_nanos(timestamp.0.unsigned_abs());
+        if timestamp.0 < 0 {
+            UNIX_EPOCH - offset
+        } else {
+            UNIX_EPOCH + offset
+        }
+    }
+}
+
+impl StrictPartialOrd for Timestamp {}
+
+// Converts days since the Unix epoch to a (year, month, day) triple in the
+// proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+// The inverse of `civil_from_days`.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+struct DateTimeParser<'a>(&'a [u8]);
+
+impl<'a> DateTimeParser<'a> {
+    fn digits(&mut self, count: usize) -> Option<i64> {
+        if self.0.len() < count || !self.0[..count].iter().all(u8::is_ascii_digit) {
+            return None;
+        }
+        let (digits, rest) = self.0.split_at(count);
+        self.0 = rest;
+        Some(
+            digits
+                .iter()
+                .fold(0, |acc, digit| acc * 10 + i64::from(digit - b'0')),
+        )
+    }
+
+    fn separator(&mut self, separators: &[u8]) -> Option<u8> {
+        let (&first, rest) = self.0.split_first()?;
+        if separators.contains(&first) {
+            self.0 = rest;
+            Some(first)
+        } else {
+            None
+        }
+    }
+
+    // Returns `None` for invalid syntax and `Some(None)` for valid dates
+    // that don't fit into the supported range.
+    fn parse(&mut self) -> Option<Option<i64>> {
+        let year = self.digits(4)?;
+        self.separator(b"-")?;
+        let month = self.digits(2)?;
+        self.separator(b"-")?;
+        let day = self.digits(2)?;
+        self.separator(b"Tt")?;
+        let hour = self.digits(2)?;
+        self.separator(b":")?;
+        let minute = self.digits(2)?;
+        self.separator(b":")?;
+        let second = self.digits(2)?;
+
+        if !(1..=12).contains(&month)
+            || !(1..=days_in_month(year, month)).contains(&day)
+            || hour > 23
+            || minute > 59
+            || second > 59
+        {
+            return None;
+        }
+
+        let mut nanos = 0;
+        if self.separator(b".").is_some() {
+            let len = self.0.iter().take_while(|c| c.is_ascii_digit()).count();
+            if len == 0 || len > 9 {
+                return None;
+            }
+            nanos = self.digits(len)? * 10_i64.pow(9 - len as u32);
+        }
+
+        let offset = match self.separator(b"Zz+-")? {
+            b'Z' | b'z' => 0,
+            sign => {
+                let hours = self.digits(2)?;
+                self.separator(b":")?;
+                let minutes = self.digits(2)?;
+                if hours > 23 || minutes > 59 {
+                    return None;
+                }
+                let offset = hours * 3600 + minutes * 60;
+                if sign == b'-' {
+                    -offset
+                } else {
+                    offset
+                }
+            }
+        };
+
+        if !self.0.is_empty() {
+            return None;
+        }
+
+        let secs =
+            days_from_civil(year, month, day) * SECS_PER_DAY + hour * 3600 + minute * 60 + second
+                - offset;
+
+        Some(
+            secs.checked_mul(NANOS_PER_SEC)
+                .and_then(|secs| secs.checked_add(nanos)),
+        )
+    }
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let secs = self.0.div_euclid(NANOS_PER_SEC);
+        let nanos = self.0.rem_euclid(NANOS_PER_SEC);
+        let (year, month, day) = civil_from_days(secs.div_euclid(SECS_PER_DAY));
+        let secs_of_day = secs.rem_euclid(SECS_PER_DAY);
+
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            secs_of_day / 3600,
+            secs_of_day / 60 % 60,
+            secs_of_day % 60
+        )?;
+
+        if nanos != 0 {
+            let fraction = format!("{:09}", nanos);
+            write!(f, ".{}", fraction.trim_end_matches('0'))?;
+        }
+
+        f.write_str("Z")
+    }
+}
+
+impl Debug for Timestamp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <&str>::deserialize(deserializer)?;
+        Timestamp::parse(s).map_err(de::Error::custom)
+    }
+}
+
+impl<'i> Lex<'i> for Timestamp {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let input = expect(input, "\"")?;
+        let len = input
+            .find('"')
+            .ok_or((LexErrorKind::MissingEndingQuote, input))?;
+        let (s, rest) = input.split_at(len);
+        match Timestamp::parse(s) {
+            Ok(timestamp) => Ok((timestamp, &rest[1..])),
+            Err(kind) => Err((kind, s)),
+        }
+    }
+}
+
+impl<'i> Lex<'i> for RangeInclusive<Timestamp> {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let initial_input = input;
+        let (first, input) = Timestamp::lex(input)?;
+        let (last, input) = if let Ok(input) = expect(input, "..") {
+            Timestamp::lex(input)?
+        } else {
+            (first, input)
+        };
+        if last < first {
+            return Err((
+                LexErrorKind::IncompatibleRangeBounds,
+                span(initial_input, input),
+            ));
+        }
+        Ok((first..=last, input))
+    }
+}
+
+// Units of relative offsets, from the largest to the smallest one, so that
+// offsets are printed in a canonical form.
+const UNITS: &[(&str, i64)] = &[
+    ("d", SECS_PER_DAY * NANOS_PER_SEC),
+    ("h", 3600 * NANOS_PER_SEC),
+    ("m", 60 * NANOS_PER_SEC),
+    ("s", NANOS_PER_SEC),
+    ("ms", NANOS_PER_SEC / 1000),
+];
+
+/// A timestamp on the right-hand side of a comparison.
+///
+/// Besides absolute timestamps, filters can compare against the time of
+/// execution, like `timestamp > now() - 1h30m`. Relative timestamps are
+/// resolved on every comparison, so compiled filters never go stale.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimestampLiteral {
+    /// A fixed point in time.
+    Absolute(Timestamp),
+    /// An offset in nanoseconds from the current time.
+    Relative(i64),
+}
+
+impl TimestampLiteral {
+    /// Returns the point in time this literal refers to at the moment.
+    pub fn resolve(self) -> Timestamp {
+        match self {
+            TimestampLiteral::Absolute(timestamp) => timestamp,
+            TimestampLiteral::Relative(offset) => {
+                Timestamp(Timestamp::now().0.saturating_add(offset))
+            }
+        }
+    }
+
+    // Parses a literal the way it's serialized, i.e. either an unquoted
+    // RFC 3339 timestamp or a relative one.
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match Timestamp::parse(s) {
+            Ok(timestamp) => Some(TimestampLiteral::Absolute(timestamp)),
+            Err(_) => match TimestampLiteral::lex(s) {
+                Ok((literal, "")) => Some(literal),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl From<Timestamp> for TimestampLiteral {
+    fn from(timestamp: Timestamp) -> Self {
+        TimestampLiteral::Absolute(timestamp)
+    }
+}
+
+impl PartialEq<TimestampLiteral> for Timestamp {
+    fn eq(&self, other: &TimestampLiteral) -> bool {
+        *self == other.resolve()
+    }
+}
+
+impl PartialOrd<TimestampLiteral> for Timestamp {
+    fn partial_cmp(&self, other: &TimestampLiteral) -> Option<Ordering> {
+        self.partial_cmp(&other.resolve())
+    }
+}
+
+impl StrictPartialOrd<TimestampLiteral> for Timestamp {}
+
+fn lex_duration(input: &str) -> LexResult<'_, i64> {
+    let initial_input = input;
+    let mut total: i64 = 0;
+    let mut input = input;
+    loop {
+        let (digits, rest) = match take_while(input, "digit", |c| c.is_ascii_digit()) {
+            Ok(res) => res,
+            Err(err) if input == initial_input => return Err(err),
+            Err(_) => return Ok((total, input)),
+        };
+        // Prefer the longest unit name, so that `ms` isn't lexed as `m`.
+        let (unit, rest) = UNITS
+            .iter()
+            .filter_map(|&(name, unit)| expect(rest, name).ok().map(|rest| (unit, rest)))
+            .min_by_key(|&(_, rest)| rest.len())
+            .ok_or((LexErrorKind::ExpectedName("duration unit"), rest))?;
+        total = digits
+            .parse::<i64>()
+            .ok()
+            .and_then(|value| value.checked_mul(unit))
+            .and_then(|value| total.checked_add(value))
+            .ok_or((LexErrorKind::TimestampOutOfRange, span(initial_input, rest)))?;
+        input = rest;
+    }
+}
+
+impl<'i> Lex<'i> for TimestampLiteral {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let rest = match expect(input, "now()") {
+            Ok(rest) => rest,
+            Err(_) => {
+                let (timestamp, rest) = Timestamp::lex(input)?;
+                return Ok((TimestampLiteral::Absolute(timestamp), rest));
+            }
+        };
+
+        let after_now = skip_space(rest);
+        let (negative, rest) = match after_now.chars().next() {
+            Some('+') => (false, skip_space(&after_now[1..])),
+            Some('-') => (true, skip_space(&after_now[1..])),
+            _ => return Ok((TimestampLiteral::Relative(0), rest)),
+        };
+
+        let (offset, rest) = lex_duration(rest)?;
+        let offset = if negative { -offset } else { offset };
+        Ok((TimestampLiteral::Relative(offset), rest))
+    }
+}
+
+impl Display for TimestampLiteral {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let offset = match *self {
+            TimestampLiteral::Absolute(timestamp) => return write!(f, "\"{}\"", timestamp),
+            TimestampLiteral::Relative(offset) => offset,
+        };
+
+        f.write_str("now()")?;
+        if offset == 0 {
+            return Ok(());
+        }
+
+        f.write_str(if offset < 0 { " - " } else { " + " })?;
+
+        // Lexed offsets are always whole milliseconds, but ones created
+        // by hand are rounded towards zero.
+        let mut rest = offset.unsigned_abs();
+        let mut written = false;
+        for &(name, unit) in UNITS {
+            let count = rest / unit as u64;
+            if count > 0 {
+                write!(f, "{}{}", count, name)?;
+                rest %= unit as u64;
+                written = true;
+            }
+        }
+        if !written {
+            f.write_str("0ms")?;
+        }
+        Ok(())
+    }
+}
+
+impl Debug for TimestampLiteral {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Display::fmt(self, f)
+    }
+}
+
+impl Serialize for TimestampLiteral {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            TimestampLiteral::Absolute(timestamp) => timestamp.serialize(serializer),
+            TimestampLiteral::Relative(_) => serializer.collect_str(self),
+        }
+    }
+}
+
+#[test]
+fn test() {
+    let timestamp = |s| Timestamp::parse(s).unwrap();
+
+    assert_eq!(timestamp("1970-01-01T00:00:00Z").as_nanos(), 0);
+    assert_eq!(
+        timestamp("2023-06-01T00:00:00Z").as_nanos(),
+        1_685_577_600 * NANOS_PER_SEC
+    );
+    assert_eq!(
+        timestamp("2023-06-01T02:30:00.25+02:30"),
+        Timestamp::from_nanos(1_685_577_600 * NANOS_PER_SEC + NANOS_PER_SEC / 4)
+    );
+    assert_eq!(timestamp("1969-12-31T23:59:59.999999999Z").as_nanos(), -1);
+    assert_eq!(
+        timestamp("2000-02-29t12:00:00z").to_string(),
+        "2000-02-29T12:00:00Z"
+    );
+    assert_eq!(
+        timestamp("1960-03-01T00:00:00.12-00:00").to_string(),
+        "1960-03-01T00:00:00.12Z"
+    );
+
+    for invalid in &[
+        "2023-06-01",
+        "2023-06-01T00:00:00",
+        "2023-13-01T00:00:00Z",
+        "2023-02-29T00:00:00Z",
+        "2023-06-01T24:00:00Z",
+        "2023-06-01T00:00:00.Z",
+        "2023-06-01T00:00:00Z ",
+    ] {
+        assert_eq!(
+            Timestamp::parse(invalid),
+            Err(LexErrorKind::InvalidTimestamp),
+            "{}",
+            invalid
+        );
+    }
+    assert_eq!(
+        Timestamp::parse("2300-01-01T00:00:00Z"),
+        Err(LexErrorKind::TimestampOutOfRange)
+    );
+
+    let time = SystemTime::from(timestamp("1950-01-01T00:00:00.5Z"));
+    assert_eq!(Timestamp::from(time), timestamp("1950-01-01T00:00:00.5Z"));
+
+    assert_ok!(
+        Timestamp::lex(r#""2023-06-01T00:00:00Z";"#),
+        timestamp("2023-06-01T00:00:00Z"),
+        ";"
+    );
+    assert_err!(
+        Timestamp::lex(r#""2023-06-01T00:00:00";"#),
+        LexErrorKind::InvalidTimestamp,
+        "2023-06-01T00:00:00"
+    );
+    assert_err!(
+        Timestamp::lex(r#""2023"#),
+        LexErrorKind::MissingEndingQuote,
+        "2023"
+    );
+    assert_err!(
+        RangeInclusive::<Timestamp>::lex(r#""2023-06-02T00:00:00Z".."2023-06-01T00:00:00Z""#),
+        LexErrorKind::IncompatibleRangeBounds,
+        r#""2023-06-02T00:00:00Z".."2023-06-01T00:00:00Z""#
+    );
+
+    assert_ok!(
+        TimestampLiteral::lex("now()!"),
+        TimestampLiteral::Relative(0),
+        "!"
+    );
+    assert_ok!(
+        TimestampLiteral::lex("now() - 1h30m;"),
+        TimestampLiteral::Relative(-90 * 60 * NANOS_PER_SEC),
+        ";"
+    );
+    assert_ok!(
+        TimestampLiteral::lex("now()+1d500ms"),
+        TimestampLiteral::Relative(SECS_PER_DAY * NANOS_PER_SEC + NANOS_PER_SEC / 2),
+        ""
+    );
+    assert_err!(
+        TimestampLiteral::lex("now() - 1y"),
+        LexErrorKind::ExpectedName("duration unit"),
+        "y"
+    );
+    assert_err!(
+        TimestampLiteral::lex("now() - h"),
+        LexErrorKind::ExpectedName("digit"),
+        "h"
+    );
+    assert_err!(
+        TimestampLiteral::lex("now() - 99999999999d"),
+        LexErrorKind::TimestampOutOfRange,
+        "99999999999d"
+    );
+
+    for literal in &["now()", "now() - 1h30m", "now() + 1d1s500ms"] {
+        let (parsed, _) = TimestampLiteral::lex(literal).unwrap();
+        assert_eq!(parsed.to_string(), *literal);
+        assert_eq!(TimestampLiteral::parse(literal), Some(parsed));
+    }
+
+    let now = Timestamp::now();
+    assert!(now > TimestampLiteral::Relative(-NANOS_PER_SEC));
+    assert!(now < TimestampLiteral::Relative(NANOS_PER_SEC));
+    assert!(now > TimestampLiteral::Absolute(timestamp("2023-06-01T00:00:00Z")));
+}
@@ -0,0 +1,201 @@
+use crate::{
+    lex::{Lex, LexResult},
+    rhs_types::Bytes,
+};
+use serde::{Serialize, Serializer};
+use std::fmt::{self, Debug, Formatter};
+
+// Checks a part of a pattern against a slice of the same length, where
+// `None` stands for `?` and matches any byte.
+fn segment_matches(segment: &[Option<u8>], haystack: &[u8]) -> bool {
+    segment
+        .iter()
+        .zip(haystack)
+        .all(|(&p, &b)| p.is_none() || p == Some(b))
+}
+
+/// A pattern for `wildcard` comparisons, where `*` matches any sequence of
+/// bytes and `?` matches any single byte.
+///
+/// The pattern has to match the whole value. A backslash matches the byte
+/// after it literally, e.g. `\*` matches only `*` and `\\` only a
+/// backslash; in a filter string the backslash itself has to be escaped, as
+/// in `"what\\?"`.
+///
+/// The pattern is split by `*`s at parse time. The parts between them are
+/// searched for in order, each at the earliest position, so matching never
+/// goes back to an earlier part and takes time proportional to at most the
+/// length of the value times the length of the pattern.
+pub struct Wildcard {
+    pattern: Bytes,
+    // Parts of the pattern anchored to the start and to the end of the
+    // value. The latter is `None` if there are no `*`s at all.
+    prefix: Box<[Option<u8>]>,
+    suffix: Option<Box<[Option<u8>]>>,
+    // Non-empty parts between `*`s, which are matched in order.
+    middle: Vec<Box<[Option<u8>]>>,
+}
+
+impl Wildcard {
+    /// Compiles a pattern.
+    pub fn new(pattern: Bytes) -> Self {
+        let mut parts = vec![Vec::new()];
+        let mut bytes = pattern.iter();
+
+        while let Some(&b) = bytes.next() {
+            let part = parts.last_mut().unwrap();
+            match b {
+                b'*' => parts.push(Vec::new()),
+                b'?' => part.push(None),
+                // A trailing backslash matches itself.
+                b'\\' => part.push(Some(*bytes.next().unwrap_or(&b'\\'))),
+                b => part.push(Some(b)),
+            }
+        }
+
+        let mut parts = parts.into_iter();
+        let prefix = parts.next().unwrap_or_default().into();
+        let suffix = parts.next_back().map(Into::into);
+        let middle = parts
+            .filter(|part| !part.is_empty())
+            .map(Into::into)
+            .collect();
+
+        Wildcard {
+            pattern,
+            prefix,
+            suffix,
+            middle,
+        }
+    }
+
+    /// Returns the pattern as it was written in a filter.
+    pub fn pattern(&self) -> &Bytes {
+        &self.pattern
+    }
+
+    /// Checks whether the whole value matches the pattern.
+    pub fn is_match(&self, value: &[u8]) -> bool {
+        let suffix = match &self.suffix {
+            Some(suffix) => suffix,
+            None => {
+                return value.len() == self.prefix.len() && segment_matches(&self.prefix, value)
+            }
+        };
+
+        if value.len() < self.prefix.len() + suffix.len() {
+            return false;
+        }
+
+        let (head, rest) = value.split_at(self.prefix.len());
+        let (mut rest, tail) = rest.split_at(rest.len() - suffix.len());
+
+        if !segment_matches(&self.prefix, head) || !segment_matches(suffix, tail) {
+            return false;
+        }
+
+        // Each part can match as early as possible, since anything skipped
+        // is covered by the preceding `*`.
+        for part in &self.middle {
+            match rest
+                .windows(part.len())
+                .position(|window| segment_matches(part, window))
+            {
+                Some(pos) => rest = &rest[pos + part.len()..],
+                None => return false,
+            }
+        }
+
+        true
+    }
+}
+
+impl From<Bytes> for Wildcard {
+    fn from(pattern: Bytes) -> Self {
+        Wildcard::new(pattern)
+    }
+}
+
+impl Clone for Wildcard {
+    fn clone(&self) -> Self {
+        Wildcard::new(self.pattern.clone())
+    }
+}
+
+impl PartialEq for Wildcard {
+    fn eq(&self, other: &Wildcard) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Eq for Wildcard {}
+
+impl Debug for Wildcard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.pattern.fmt(f)
+    }
+}
+
+impl<'i> Lex<'i> for Wildcard {
+    fn lex(input: &str) -> LexResult<'_, Self> {
+        let (pattern, input) = Bytes::lex(input)?;
+        Ok((Wildcard::new(pattern), input))
+    }
+}
+
+impl Serialize for Wildcard {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.pattern.serialize(ser)
+    }
+}
+
+#[test]
+fn test() {
+    let wildcard = |pattern: &str| Wildcard::new(pattern.to_owned().into());
+
+    let host = wildcard("*.example.com");
+    assert!(host.is_match(b"www.example.com"));
+    assert!(host.is_match(b"a.b.example.com"));
+    assert!(host.is_match(b".example.com"));
+    assert!(!host.is_match(b"example.com"));
+    assert!(!host.is_match(b"www.example.com.evil"));
+
+    let exact = wildcard("ab?d");
+    assert!(exact.is_match(b"abcd"));
+    assert!(exact.is_match(b"ab?d"));
+    assert!(!exact.is_match(b"abd"));
+    assert!(!exact.is_match(b"abcde"));
+
+    let middle = wildcard("a*b?c**d*");
+    assert!(middle.is_match(b"abxcd"));
+    assert!(middle.is_match(b"a__b_c__d__"));
+    assert!(middle.is_match(b"abbbxcd"));
+    assert!(!middle.is_match(b"abcd"));
+    assert!(!middle.is_match(b"xabxcd"));
+
+    // The suffix can't overlap with the prefix.
+    let overlap = wildcard("ab*ba");
+    assert!(overlap.is_match(b"abba"));
+    assert!(!overlap.is_match(b"aba"));
+
+    assert!(wildcard("*").is_match(b""));
+    assert!(wildcard("").is_match(b""));
+    assert!(!wildcard("").is_match(b"a"));
+    assert!(!wildcard("?").is_match(b""));
+
+    // Escaped metacharacters match themselves only.
+    let escaped = wildcard(r"what\?*\*");
+    assert!(escaped.is_match(b"what?*"));
+    assert!(escaped.is_match(b"what? is *"));
+    assert!(!escaped.is_match(b"whatx*"));
+    assert!(!escaped.is_match(b"what?"));
+    assert!(wildcard(r"a\\*").is_match(br"a\bc"));
+    assert!(!wildcard(r"a\\*").is_match(b"abc"));
+    assert!(wildcard(r"\a\").is_match(br"a\"));
+
+    assert_eq!(
+        Wildcard::lex(r#""*.example.com" rest"#),
+        Ok((host, " rest"))
+    );
+    assert!(Wildcard::lex(r#""2\\*2""#).unwrap().0.is_match(b"2*2"));
+}
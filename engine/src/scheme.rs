@@ -1,18 +1,27 @@
 use crate::{
-    ast::FilterAst,
-    functions::Function,
+    ast::{ComparisonRhs, FilterAst},
+    functions::{Function, FunctionImpl},
     lex::{complete, expect, span, take_while, LexErrorKind, LexResult, LexWith},
-    types::{GetType, Type},
+    list::ListDefinition,
+    parser_settings::ParserSettings,
+    types::{GetType, LhsValue, Type, TypeMismatchError},
 };
 use failure::Fail;
-use fnv::FnvBuildHasher;
-use indexmap::map::{Entry, IndexMap};
-use serde::{Deserialize, Serialize, Serializer};
+use fnv::{FnvBuildHasher, FnvHasher};
+use indexmap::{
+    map::{Entry, IndexMap},
+    IndexSet,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::{
     cmp::{max, min},
+    collections::HashSet,
     error::Error,
     fmt::{self, Debug, Display, Formatter},
+    hash::{Hash, Hasher},
+    ops::Range,
     ptr,
+    sync::Arc,
 };
 
 #[derive(PartialEq, Eq, Clone, Copy)]
@@ -71,6 +80,14 @@ impl<'s> Field<'s> {
     pub fn scheme(&self) -> &'s Scheme {
         self.scheme
     }
+
+    /// Looks up a field with the same name and type in another scheme.
+    pub fn rebind<'p>(&self, scheme: &'p Scheme) -> Option<Field<'p>> {
+        scheme
+            .get_field_index(self.name())
+            .ok()
+            .filter(|field| field.get_type() == self.get_type())
+    }
 }
 
 impl<'s> GetType for Field<'s> {
@@ -79,18 +96,125 @@ impl<'s> GetType for Field<'s> {
     }
 }
 
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub(crate) struct List<'s> {
+    scheme: &'s Scheme,
+    index: usize,
+}
+
+impl<'s> Serialize for List<'s> {
+    fn serialize<S: Serializer>(&self, ser: S) -> Result<S::Ok, S::Error> {
+        self.name().serialize(ser)
+    }
+}
+
+impl<'s> Debug for List<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "${}", self.name())
+    }
+}
+
+impl<'i, 's> LexWith<'i, &'s Scheme> for List<'s> {
+    fn lex_with(input: &'i str, scheme: &'s Scheme) -> LexResult<'i, Self> {
+        let initial_input = input;
+
+        let input = expect(input, "$")?;
+
+        let (name, input) = take_while(input, "list name character", |c| {
+            c.is_ascii_alphanumeric() || c == '_'
+        })?;
+
+        let list = scheme
+            .get_list(name)
+            .map_err(|err| (LexErrorKind::UnknownList(err), span(initial_input, input)))?;
+
+        Ok((list, input))
+    }
+}
+
+impl<'s> List<'s> {
+    pub fn name(&self) -> &'s str {
+        self.scheme.lists.get_index(self.index).unwrap().0
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Looks up a list with the same name and type in another scheme.
+    pub fn rebind<'p>(&self, scheme: &'p Scheme) -> Option<List<'p>> {
+        scheme
+            .get_list(self.name())
+            .ok()
+            .filter(|list| list.get_type() == self.get_type())
+    }
+}
+
+impl<'s> GetType for List<'s> {
+    fn get_type(&self) -> Type {
+        self.scheme.lists.get_index(self.index).unwrap().1.val_type
+    }
+}
+
+/// A read-only view of a field registered in a [`Scheme`](struct@Scheme).
+///
+/// These are produced by [`Scheme::iter`](::Scheme::iter).
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub struct FieldRef<'s>(Field<'s>);
+
+impl<'s> Debug for FieldRef<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FieldRef")
+            .field("name", &self.name())
+            .field("type", &self.get_type())
+            .field("index", &self.index())
+            .finish()
+    }
+}
+
+impl<'s> FieldRef<'s> {
+    /// Returns the name of the field.
+    pub fn name(&self) -> &'s str {
+        self.0.name()
+    }
+
+    /// Returns the position of the field in the scheme, which matches the
+    /// order in which fields were registered.
+    pub fn index(&self) -> usize {
+        self.0.index()
+    }
+
+    /// Returns the [default value](::Scheme::set_field_default) of the
+    /// field, if any.
+    pub fn default_value(&self) -> Option<&'s LhsValue<'static>> {
+        self.0.scheme().defaults.get(&self.0.index())
+    }
+}
+
+impl<'s> GetType for FieldRef<'s> {
+    fn get_type(&self) -> Type {
+        self.0.get_type()
+    }
+}
+
 /// An error that occurs if an unregistered field name was queried from a
 /// [`Scheme`](struct@Scheme).
-#[derive(Debug, PartialEq, Fail)]
+#[derive(Debug, PartialEq, Clone, Fail)]
 #[fail(display = "unknown field")]
 pub struct UnknownFieldError;
 
 /// An error that occurs if an unregistered function name was queried from a
 /// [`Scheme`](struct@Scheme).
-#[derive(Debug, PartialEq, Fail)]
+#[derive(Debug, PartialEq, Clone, Fail)]
 #[fail(display = "unknown function")]
 pub struct UnknownFunctionError;
 
+/// An error that occurs if an unregistered list name was queried from a
+/// [`Scheme`](struct@Scheme).
+#[derive(Debug, PartialEq, Clone, Fail)]
+#[fail(display = "unknown list")]
+pub struct UnknownListError;
+
 /// An error that occurs when previously defined field gets redefined.
 #[derive(Debug, PartialEq, Fail)]
 #[fail(display = "attempt to redefine field {}", _0)]
@@ -101,18 +225,107 @@ pub struct FieldRedefinitionError(String);
 #[fail(display = "attempt to redefine function {}", _0)]
 pub struct FunctionRedefinitionError(String);
 
+/// An error that occurs when previously defined list gets redefined.
+#[derive(Debug, PartialEq, Fail)]
+#[fail(display = "attempt to redefine list {}", _0)]
+pub struct ListRedefinitionError(String);
+
+/// An error that occurs when a field or a function is registered under a
+/// name that is already taken.
 #[derive(Debug, PartialEq, Fail)]
 pub enum ItemRedefinitionError {
+    /// The name is taken by a field.
     #[fail(display = "{}", _0)]
     Field(#[cause] FieldRedefinitionError),
 
+    /// The name is taken by a function.
     #[fail(display = "{}", _0)]
     Function(#[cause] FunctionRedefinitionError),
 }
 
-/// An opaque filter parsing error associated with the original input.
+/// A callback that checks a right-hand side of every comparison with a
+/// field when a filter is parsed, see
+/// [`Scheme::set_field_validator`](::Scheme::set_field_validator).
+///
+/// An error message is reported as a part of the [`ParseError`].
+pub type RhsValidator = fn(ComparisonRhs<'_>) -> Result<(), String>;
+
+/// A callback that is told about every isolated panic of a function with
+/// the name of the function and the panic message, see
+/// [`Scheme::set_function_panic_hook`](::Scheme::set_function_panic_hook).
+///
+/// It can capture state, like a logger or a metrics handle, and is shared
+/// with schemes [projected](::Scheme::project) from this one.
+pub type FunctionPanicHook = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+/// An error that occurs when registering a virtual field with
+/// [`Scheme::add_virtual_field`](::Scheme::add_virtual_field).
+#[derive(Debug, PartialEq, Fail)]
+pub enum VirtualFieldError {
+    /// One of the source fields is not registered in the scheme.
+    #[fail(display = "{}", _0)]
+    UnknownField(#[cause] UnknownFieldError),
+
+    /// A field or a function with the same name already exists.
+    #[fail(display = "{}", _0)]
+    Redefinition(#[cause] ItemRedefinitionError),
+}
+
+// A derivation of a virtual field from its source fields.
+#[derive(Clone)]
+pub(crate) struct VirtualField {
+    pub sources: Vec<usize>,
+    pub implementation: FunctionImpl,
+}
+
+/// An error that occurs when setting a default value of a field.
+#[derive(Debug, PartialEq, Fail)]
+pub enum FieldDefaultError {
+    /// The field is not registered in the scheme.
+    #[fail(display = "{}", _0)]
+    UnknownField(#[cause] UnknownFieldError),
+
+    /// The value has a different type than the field.
+    #[fail(display = "{}", _0)]
+    TypeMismatch(#[cause] TypeMismatchError),
+}
+
+/// How values of a `Bytes` field are normalized before every comparison,
+/// see [`Scheme::set_field_comparison_mode`].
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct ComparisonMode {
+    /// Ignore ASCII case, e.g. for hostnames.
+    pub case_insensitive: bool,
+    /// Ignore leading and trailing ASCII whitespace, e.g. for header values.
+    pub trim: bool,
+}
+
+/// An error that occurs when setting a comparison mode of a field with
+/// [`Scheme::set_field_comparison_mode`].
+#[derive(Debug, PartialEq, Fail)]
+pub enum FieldComparisonModeError {
+    /// The field is not registered in the scheme.
+    #[fail(display = "{}", _0)]
+    UnknownField(#[cause] UnknownFieldError),
+
+    /// The field is not of type `Bytes`.
+    #[fail(display = "{}", _0)]
+    TypeMismatch(#[cause] TypeMismatchError),
+}
+
+/// A filter parsing error associated with the original input.
+///
+/// It can be printed in a debug or a human-readable fashion, or inspected
+/// with accessors, e.g. to highlight the error in an editor.
 ///
-/// For now, you can just print it in a debug or a human-readable fashion.
+/// The human-readable form shows the line with the error and underlines
+/// the erroneous span with carets:
+///
+/// ```text
+/// Filter parsing error (1:19):
+/// http.host matches 42
+///                   ^^ expected literal "\""
+/// ```
 #[derive(Debug, PartialEq)]
 pub struct ParseError<'i> {
     kind: LexErrorKind,
@@ -120,6 +333,7 @@ pub struct ParseError<'i> {
     line_number: usize,
     span_start: usize,
     span_len: usize,
+    span: Range<usize>,
 }
 
 impl<'i> Error for ParseError<'i> {}
@@ -127,6 +341,7 @@ impl<'i> Error for ParseError<'i> {}
 impl<'i> ParseError<'i> {
     pub(crate) fn new(mut input: &'i str, (kind, span): (LexErrorKind, &'i str)) -> Self {
         let mut span_start = span.as_ptr() as usize - input.as_ptr() as usize;
+        let offsets = span_start..span_start + span.len();
 
         let (line_number, line_start) = input[..span_start]
             .match_indices('\n')
@@ -154,26 +369,83 @@ impl<'i> ParseError<'i> {
             line_number,
             span_start,
             span_len,
+            span: offsets,
         }
     }
+
+    /// Returns a description of the error, without the position.
+    pub fn reason(&self) -> String {
+        self.kind.to_string()
+    }
+
+    /// Returns the line of the input where the error occurred.
+    pub fn line(&self) -> &'i str {
+        self.input
+    }
+
+    /// Returns a 0-based number of the line where the error occurred.
+    pub fn line_number(&self) -> usize {
+        self.line_number
+    }
+
+    /// Returns a 0-based byte offset of the erroneous span in its line.
+    pub fn span_start(&self) -> usize {
+        self.span_start
+    }
+
+    /// Returns a byte length of the erroneous span, which can be 0 e.g. at
+    /// the end of the input.
+    pub fn span_len(&self) -> usize {
+        self.span_len
+    }
+
+    /// Returns a 0-based column of the erroneous span in its line, counted
+    /// in characters rather than bytes.
+    pub fn column(&self) -> usize {
+        self.input[..self.span_start].chars().count()
+    }
+
+    /// Returns a byte range of the erroneous span in the whole input given
+    /// to the parser.
+    ///
+    /// Unlike [`span_start`](ParseError::span_start) and
+    /// [`span_len`](ParseError::span_len), it's not limited to a single
+    /// line.
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    /// Returns literal tokens that would have been accepted at the position
+    /// of the error, like operators after a field name.
+    ///
+    /// It's empty when the parser expected something other than a fixed
+    /// token, like a number, or when the input was invalid in other ways.
+    pub fn expected(&self) -> Vec<&'static str> {
+        self.kind.expected_tokens()
+    }
 }
 
 impl<'i> Display for ParseError<'i> {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let column = self.column();
+        let width = self.input[self.span_start..self.span_start + self.span_len]
+            .chars()
+            .count();
+
         writeln!(
             f,
             "Filter parsing error ({}:{}):",
             self.line_number + 1,
-            self.span_start + 1
+            column + 1
         )?;
 
         writeln!(f, "{}", self.input)?;
 
-        for _ in 0..self.span_start {
+        for _ in 0..column {
             write!(f, " ")?;
         }
 
-        for _ in 0..max(1, self.span_len) {
+        for _ in 0..max(1, width) {
             write!(f, "^")?;
         }
 
@@ -183,17 +455,115 @@ impl<'i> Display for ParseError<'i> {
     }
 }
 
+/// An error that occurs when parsing a document with multiple rules, see
+/// [`Scheme::parse_rules`](::Scheme::parse_rules).
+#[derive(Debug, PartialEq)]
+pub enum RuleParseError<'i> {
+    /// A line at the start of a rule doesn't begin with `name:`.
+    InvalidName {
+        /// A 1-based line number.
+        line: usize,
+    },
+
+    /// A rule with the same name was already defined earlier.
+    DuplicateName {
+        /// A name of the rule.
+        name: &'i str,
+    },
+
+    /// An expression of a rule couldn't be parsed.
+    Filter {
+        /// A name of the rule.
+        name: &'i str,
+        /// An underlying parsing error.
+        error: ParseError<'i>,
+    },
+}
+
+impl<'i> Error for RuleParseError<'i> {}
+
+impl<'i> Display for RuleParseError<'i> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleParseError::InvalidName { line } => {
+                writeln!(f, "Expected a rule name followed by ':' on line {}", line)
+            }
+            RuleParseError::DuplicateName { name } => {
+                writeln!(f, "Rule {} is defined more than once", name)
+            }
+            RuleParseError::Filter { name, error } => write!(f, "Rule {}: {}", name, error),
+        }
+    }
+}
+
+fn is_rule_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// A difference between two schemes that makes filters using a field of
+/// one of them invalid in another, see
+/// [`Scheme::check_compatibility`](::Scheme::check_compatibility).
+#[derive(Debug, PartialEq, Clone, Fail)]
+pub enum SchemeIncompatibility {
+    /// The field is missing in the other scheme.
+    #[fail(display = "field {} is missing", _0)]
+    MissingField(String),
+
+    /// The field has a different type in the other scheme.
+    #[fail(
+        display = "field {} has type {:?} instead of {:?}",
+        name, actual, expected
+    )]
+    TypeChanged {
+        /// The name of the field.
+        name: String,
+        /// The type of the field in this scheme.
+        expected: Type,
+        /// The type of the field in the other scheme.
+        actual: Type,
+    },
+}
+
 /// The main registry for fields and their associated types.
 ///
 /// This is necessary to provide typechecking for runtime values provided
 /// to the [execution context](::ExecutionContext) and also to aid parser
 /// in ambiguous contexts.
-#[derive(Default, Deserialize)]
+///
+/// A scheme is serialized as a map of field names to their types, e.g.
+/// `{"http.host": "Bytes", "tcp.port": "Int"}` in JSON, in the order of
+/// field indices. This allows a control plane to publish the authoritative
+/// fields and other processes to load them. Functions, lists, defaults and
+/// other settings are not serialized and have to be registered separately.
+#[derive(Default, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Scheme {
     fields: IndexMap<String, Type, FnvBuildHasher>,
     #[serde(skip)]
+    defaults: IndexMap<usize, LhsValue<'static>, FnvBuildHasher>,
+    #[serde(skip)]
+    validators: IndexMap<usize, RhsValidator, FnvBuildHasher>,
+    #[serde(skip)]
+    multi_valued: IndexSet<usize, FnvBuildHasher>,
+    #[serde(skip)]
+    comparison_modes: IndexMap<usize, ComparisonMode, FnvBuildHasher>,
+    #[serde(skip)]
+    virtual_fields: IndexMap<usize, VirtualField, FnvBuildHasher>,
+    #[serde(skip)]
     functions: IndexMap<String, Function, FnvBuildHasher>,
+    #[serde(skip)]
+    lists: IndexMap<String, ListDefinition, FnvBuildHasher>,
+    #[serde(skip)]
+    isolate_function_panics: bool,
+    #[serde(skip)]
+    function_panic_hook: Option<FunctionPanicHook>,
+    #[serde(skip)]
+    ignore_missing_fields: bool,
+    #[serde(skip)]
+    parser_settings: ParserSettings,
 }
 
 impl PartialEq for Scheme {
@@ -214,7 +584,17 @@ impl<'s> Scheme {
     pub fn with_capacity(n: usize) -> Self {
         Scheme {
             fields: IndexMap::with_capacity_and_hasher(n, FnvBuildHasher::default()),
+            defaults: Default::default(),
+            validators: Default::default(),
+            multi_valued: Default::default(),
+            comparison_modes: Default::default(),
+            virtual_fields: Default::default(),
             functions: Default::default(),
+            lists: Default::default(),
+            isolate_function_panics: false,
+            function_panic_hook: None,
+            ignore_missing_fields: false,
+            parser_settings: Default::default(),
         }
     }
 
@@ -236,6 +616,39 @@ impl<'s> Scheme {
         }
     }
 
+    /// Registers a virtual field with a value derived from other fields,
+    /// e.g. a full URI made of a host and a path.
+    ///
+    /// The implementation is called with values of the source fields in the
+    /// given order and must return a value of type `ty`. It's called lazily
+    /// when a filter needs the field for the first time and the result is
+    /// reused until any field value in the
+    /// [execution context](::ExecutionContext) changes. A value set
+    /// explicitly in the context takes precedence over the derived one.
+    pub fn add_virtual_field(
+        &mut self,
+        name: String,
+        ty: Type,
+        sources: &[&str],
+        implementation: FunctionImpl,
+    ) -> Result<(), VirtualFieldError> {
+        let sources = sources
+            .iter()
+            .map(|name| self.get_field_index(name).map(|field| field.index()))
+            .collect::<Result<_, _>>()
+            .map_err(VirtualFieldError::UnknownField)?;
+        self.add_field(name, ty)
+            .map_err(VirtualFieldError::Redefinition)?;
+        self.virtual_fields.insert(
+            self.fields.len() - 1,
+            VirtualField {
+                sources,
+                implementation,
+            },
+        );
+        Ok(())
+    }
+
     /// Registers a series of fields from an iterable, reporting any conflicts.
     pub fn try_from_iter(
         iter: impl IntoIterator<Item = (String, Type)>,
@@ -249,6 +662,83 @@ impl<'s> Scheme {
         Ok(scheme)
     }
 
+    /// Iterates over registered fields in the order they were added.
+    pub fn iter(&'s self) -> impl ExactSizeIterator<Item = FieldRef<'s>> {
+        self.fields().map(FieldRef)
+    }
+
+    pub(crate) fn fields(&'s self) -> impl ExactSizeIterator<Item = Field<'s>> {
+        (0..self.fields.len()).map(move |index| self.field_at(index))
+    }
+
+    /// Returns the number of registered fields.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// Checks whether the scheme has no fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// Checks whether a field with a given name is registered.
+    pub fn contains(&self, name: &str) -> bool {
+        self.fields.contains_key(name)
+    }
+
+    /// Returns a hash of names and types of all fields, in the order of
+    /// their indices.
+    ///
+    /// Filters can only be used with the very scheme instance they were
+    /// parsed with, but fingerprints are stable across instances and
+    /// processes, so they tell apart a mix-up of unrelated schemes from
+    /// the same scheme created twice, e.g. in a
+    /// [`SchemeMismatchError`](::SchemeMismatchError).
+    pub fn fingerprint(&self) -> u64 {
+        let mut hasher = FnvHasher::default();
+        for (name, ty) in &self.fields {
+            name.hash(&mut hasher);
+            ty.hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// Checks that filters parsed with this scheme can be used with
+    /// another one, e.g. a newer version of it loaded from the control
+    /// plane, and returns all the fields that prevent it otherwise.
+    ///
+    /// Only fields have to match, while their indices and fields added in
+    /// the other scheme don't matter. Filters are bound to the other scheme
+    /// with [`FilterAst::rebind`](::FilterAst::rebind).
+    pub fn check_compatibility(&self, other: &Scheme) -> Result<(), Vec<SchemeIncompatibility>> {
+        let incompatibilities = self
+            .fields
+            .iter()
+            .filter_map(|(name, &expected)| match other.fields.get(name) {
+                None => Some(SchemeIncompatibility::MissingField(name.clone())),
+                Some(&actual) if actual != expected => Some(SchemeIncompatibility::TypeChanged {
+                    name: name.clone(),
+                    expected,
+                    actual,
+                }),
+                Some(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        if incompatibilities.is_empty() {
+            Ok(())
+        } else {
+            Err(incompatibilities)
+        }
+    }
+
+    pub(crate) fn field_at(&'s self, index: usize) -> Field<'s> {
+        Field {
+            scheme: self,
+            index,
+        }
+    }
+
     pub(crate) fn get_field_index(&'s self, name: &str) -> Result<Field<'s>, UnknownFieldError> {
         match self.fields.get_full(name) {
             Some((index, ..)) => Ok(Field {
@@ -259,6 +749,229 @@ impl<'s> Scheme {
         }
     }
 
+    /// Creates a new scheme with only a given subset of fields.
+    ///
+    /// All the registered functions and lists are copied over as-is. This is
+    /// useful
+    /// for splitting filter evaluation between environments that have
+    /// access to different sets of fields, see
+    /// [`FilterAst::restrict_to`](::FilterAst::restrict_to).
+    pub fn project<'a>(
+        &self,
+        fields: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Scheme, UnknownFieldError> {
+        let mut projection = Scheme {
+            fields: Default::default(),
+            defaults: Default::default(),
+            validators: Default::default(),
+            multi_valued: Default::default(),
+            comparison_modes: Default::default(),
+            virtual_fields: Default::default(),
+            functions: self.functions.clone(),
+            lists: self.lists.clone(),
+            isolate_function_panics: self.isolate_function_panics,
+            function_panic_hook: self.function_panic_hook.clone(),
+            ignore_missing_fields: self.ignore_missing_fields,
+            parser_settings: self.parser_settings.clone(),
+        };
+        for name in fields {
+            let field = self.get_field_index(name)?;
+            projection.fields.insert(name.to_owned(), field.get_type());
+            let index = projection.get_field_index(name)?.index();
+            if let Some(value) = self.defaults.get(&field.index()) {
+                projection.defaults.insert(index, value.clone());
+            }
+            if let Some(validator) = self.validators.get(&field.index()) {
+                projection.validators.insert(index, *validator);
+            }
+            if self.multi_valued.contains(&field.index()) {
+                projection.multi_valued.insert(index);
+            }
+            if let Some(mode) = self.comparison_modes.get(&field.index()) {
+                projection.comparison_modes.insert(index, *mode);
+            }
+        }
+        // Virtual fields stay virtual only if all their sources are kept,
+        // otherwise their values need to be set explicitly.
+        for (&index, virtual_field) in &self.virtual_fields {
+            let projected = |index| projection.fields.get_full(self.field_at(index).name());
+            if let Some((projected_index, ..)) = projected(index) {
+                let sources = virtual_field
+                    .sources
+                    .iter()
+                    .map(|&source| projected(source).map(|(index, ..)| index))
+                    .collect::<Option<_>>();
+                if let Some(sources) = sources {
+                    projection.virtual_fields.insert(
+                        projected_index,
+                        VirtualField {
+                            sources,
+                            implementation: virtual_field.implementation.clone(),
+                        },
+                    );
+                }
+            }
+        }
+        Ok(projection)
+    }
+
+    /// Sets a value that every [`ExecutionContext`](::ExecutionContext)
+    /// created from this scheme starts with for a given field.
+    ///
+    /// This way rarely set fields don't need to be filled in by the caller
+    /// on every execution.
+    pub fn set_field_default<V: Into<LhsValue<'static>>>(
+        &mut self,
+        name: &str,
+        value: V,
+    ) -> Result<(), FieldDefaultError> {
+        let field = self
+            .get_field_index(name)
+            .map_err(FieldDefaultError::UnknownField)?;
+        let (index, field_type) = (field.index(), field.get_type());
+        let value = value.into();
+        let value_type = value.get_type();
+
+        if field_type == value_type {
+            self.defaults.insert(index, value);
+            Ok(())
+        } else {
+            Err(FieldDefaultError::TypeMismatch(TypeMismatchError {
+                expected: field_type,
+                actual: value_type,
+            }))
+        }
+    }
+
+    /// Registers a callback that checks right-hand sides of comparisons with
+    /// a given field, so that invalid literals are rejected when parsing.
+    ///
+    /// For example, it can ensure that a header name field is only compared
+    /// against valid tokens. Comparisons with function calls are not
+    /// validated.
+    pub fn set_field_validator(
+        &mut self,
+        name: &str,
+        validator: RhsValidator,
+    ) -> Result<(), UnknownFieldError> {
+        let index = self.get_field_index(name)?.index();
+        self.validators.insert(index, validator);
+        Ok(())
+    }
+
+    pub(crate) fn get_field_validator(&self, field: Field<'_>) -> Option<RhsValidator> {
+        self.validators.get(&field.index()).cloned()
+    }
+
+    /// Allows or disallows multiple values for a given field, e.g. for
+    /// headers that can be repeated.
+    ///
+    /// Values of such fields are set with
+    /// [`ExecutionContext::set_field_values`](::ExecutionContext::set_field_values),
+    /// and a comparison with the field is `true` if it's `true` for any of
+    /// them, so it's `false` if the field has no values. Comparisons wrapped
+    /// into `all(...)`, like `all(http.cookie_name != "session")`, have to
+    /// be `true` for every value instead, so they are `true` if the field
+    /// has no values. Function calls and
+    /// virtual fields that take the field as an argument only get its first
+    /// value.
+    pub fn set_field_multi_valued(
+        &mut self,
+        name: &str,
+        multi_valued: bool,
+    ) -> Result<(), UnknownFieldError> {
+        let index = self.get_field_index(name)?.index();
+        if multi_valued {
+            self.multi_valued.insert(index);
+        } else {
+            self.multi_valued.swap_remove(&index);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn is_field_multi_valued(&self, field: Field<'_>) -> bool {
+        self.multi_valued.contains(&field.index())
+    }
+
+    /// Sets how values of a given `Bytes` field are normalized before every
+    /// comparison with it, so that rule authors don't need to do that with
+    /// function calls.
+    ///
+    /// Case-insensitive fields have both their values and literals they are
+    /// compared with lowercased, which covers `==`, `contains`, `in { ... }`
+    /// and other operators, while regular expressions are matched
+    /// case-insensitively. Trimmed fields have whitespace removed from their
+    /// values only, and matched spans still point into original values.
+    /// Function calls and virtual fields that take the field as an argument
+    /// get its values as they were set.
+    pub fn set_field_comparison_mode(
+        &mut self,
+        name: &str,
+        mode: ComparisonMode,
+    ) -> Result<(), FieldComparisonModeError> {
+        let field = self
+            .get_field_index(name)
+            .map_err(FieldComparisonModeError::UnknownField)?;
+        let field_type = field.get_type();
+        if field_type != Type::Bytes {
+            return Err(FieldComparisonModeError::TypeMismatch(TypeMismatchError {
+                expected: Type::Bytes,
+                actual: field_type,
+            }));
+        }
+        let index = field.index();
+        if mode == ComparisonMode::default() {
+            self.comparison_modes.swap_remove(&index);
+        } else {
+            self.comparison_modes.insert(index, mode);
+        }
+        Ok(())
+    }
+
+    /// Makes comparisons with a given `Bytes` field ignore ASCII case, e.g.
+    /// for header names or hostnames, keeping the rest of its
+    /// [comparison mode](Scheme::set_field_comparison_mode).
+    pub fn set_field_case_insensitive(
+        &mut self,
+        name: &str,
+        case_insensitive: bool,
+    ) -> Result<(), FieldComparisonModeError> {
+        let field = self
+            .get_field_index(name)
+            .map_err(FieldComparisonModeError::UnknownField)?;
+        let mode = ComparisonMode {
+            case_insensitive,
+            ..self.get_field_comparison_mode(field)
+        };
+        self.set_field_comparison_mode(name, mode)
+    }
+
+    pub(crate) fn get_field_comparison_mode(&self, field: Field<'_>) -> ComparisonMode {
+        self.comparison_modes
+            .get(&field.index())
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Returns a virtual field with its position among all virtual fields.
+    pub(crate) fn get_virtual_field(&self, field: Field<'_>) -> Option<(usize, &VirtualField)> {
+        self.virtual_fields
+            .get_full(&field.index())
+            .map(|(slot, _, virtual_field)| (slot, virtual_field))
+    }
+
+    pub(crate) fn get_virtual_field_count(&self) -> usize {
+        self.virtual_fields.len()
+    }
+
+    pub(crate) fn get_field_defaults(&self) -> impl Iterator<Item = (usize, &LhsValue<'static>)> {
+        self.defaults.iter().map(|(index, value)| (*index, value))
+    }
+
+    pub(crate) fn get_field_default(&self, index: usize) -> Option<&LhsValue<'static>> {
+        self.defaults.get(&index)
+    }
+
     pub(crate) fn get_field_count(&self) -> usize {
         self.fields.len()
     }
@@ -294,6 +1007,108 @@ impl<'s> Scheme {
         Ok(())
     }
 
+    /// Registers a named list that filters can refer to as `$name`, e.g.
+    /// `ip.src in $bad_ips`.
+    ///
+    /// Contents of lists are not a part of the scheme and are provided at
+    /// runtime with
+    /// [`ExecutionContext::set_list_values`](::ExecutionContext::set_list_values),
+    /// so they can be updated without parsing filters again. Lists have
+    /// their own namespace, so they can share names with fields.
+    pub fn add_list(
+        &mut self,
+        name: String,
+        definition: ListDefinition,
+    ) -> Result<(), ListRedefinitionError> {
+        match self.lists.entry(name) {
+            Entry::Occupied(entry) => Err(ListRedefinitionError(entry.key().to_string())),
+            Entry::Vacant(entry) => {
+                entry.insert(definition);
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn get_list(&'s self, name: &str) -> Result<List<'s>, UnknownListError> {
+        match self.lists.get_full(name) {
+            Some((index, ..)) => Ok(List {
+                scheme: self,
+                index,
+            }),
+            None => Err(UnknownListError),
+        }
+    }
+
+    pub(crate) fn get_list_count(&self) -> usize {
+        self.lists.len()
+    }
+
+    /// Enables or disables isolation of panics in registered functions.
+    ///
+    /// When enabled, a panic inside a function implementation is caught
+    /// and the comparison using the function call resolves to `false`
+    /// instead of unwinding through the caller of
+    /// [`Filter::execute`](::Filter::execute), and is reported to the
+    /// [hook](Scheme::set_function_panic_hook), if any.
+    ///
+    /// This only works when the crate is compiled with `panic = "unwind"`.
+    /// The release profile of this workspace, which builds the FFI and
+    /// WebAssembly libraries, sets `panic = "abort"`, so there a panicking
+    /// function still aborts the process and isolation does nothing.
+    pub fn set_isolate_function_panics(&mut self, isolate: bool) {
+        self.isolate_function_panics = isolate;
+    }
+
+    /// Returns whether panics in registered functions are isolated.
+    pub fn isolates_function_panics(&self) -> bool {
+        self.isolate_function_panics
+    }
+
+    /// Registers a callback that is called whenever a panic in a function
+    /// is [isolated](Scheme::set_isolate_function_panics), e.g. to log it or
+    /// count it in metrics, since it's otherwise indistinguishable from a
+    /// comparison that didn't match.
+    ///
+    /// It's called on the thread that executes the filter, with the name of
+    /// the function and the panic message. Like isolation itself, it's
+    /// never called when panics abort.
+    pub fn set_function_panic_hook(&mut self, hook: impl Fn(&str, &str) + Send + Sync + 'static) {
+        self.function_panic_hook = Some(Arc::new(hook));
+    }
+
+    pub(crate) fn get_function_panic_hook(&self) -> Option<&FunctionPanicHook> {
+        self.function_panic_hook.as_ref()
+    }
+
+    /// Enables or disables lenient handling of fields without a value.
+    ///
+    /// By default, executing a filter that uses a field which wasn't given a
+    /// value in the [`ExecutionContext`](::ExecutionContext) panics, so
+    /// such contexts should be checked with
+    /// [`ExecutionContext::validate_for`](::ExecutionContext::validate_for)
+    /// first. When enabled, comparisons of missing fields, including calls
+    /// taking them as arguments, resolve to `false` instead, just like in
+    /// Wireshark. This is useful for sparse data such as optional headers.
+    pub fn set_ignore_missing_fields(&mut self, ignore: bool) {
+        self.ignore_missing_fields = ignore;
+    }
+
+    /// Returns whether comparisons of fields without a value resolve to
+    /// `false`.
+    pub fn ignores_missing_fields(&self) -> bool {
+        self.ignore_missing_fields
+    }
+
+    /// Replaces settings used for parsing and compiling filters.
+    pub fn set_parser_settings(&mut self, settings: ParserSettings) {
+        self.parser_settings = settings;
+    }
+
+    /// Returns settings used for parsing and compiling filters.
+    pub fn parser_settings(&self) -> &ParserSettings {
+        &self.parser_settings
+    }
+
     pub(crate) fn get_function(&'s self, name: &str) -> Result<&'s Function, UnknownFunctionError> {
         self.functions.get(name).ok_or(UnknownFunctionError)
     }
@@ -302,10 +1117,204 @@ impl<'s> Scheme {
     pub fn parse<'i>(&'s self, input: &'i str) -> Result<FilterAst<'s>, ParseError<'i>> {
         complete(FilterAst::lex_with(input.trim(), self)).map_err(|err| ParseError::new(input, err))
     }
-}
 
-/// A convenience macro for constructing a [`Scheme`](struct@Scheme) with static
-/// contents.
+    /// Binds a serialized [`FilterAst`] to this scheme.
+    ///
+    /// This allows to parse a filter once, e.g. in a control plane, and to
+    /// ship the AST to other processes instead of reparsing the string.
+    /// Fields and functions are looked up by name, and the AST is checked
+    /// with the same rules as the parser uses, so ASTs that don't match the
+    /// local scheme are rejected.
+    pub fn parse_from_ast<'de, D: Deserializer<'de>>(
+        &'s self,
+        deserializer: D,
+    ) -> Result<FilterAst<'s>, D::Error> {
+        FilterAst::deserialize_with(deserializer, self)
+    }
+
+    /// Parses a document with multiple named filters.
+    ///
+    /// Each rule starts on a new line with its name followed by `:` and a
+    /// filter expression, which can continue on the following indented
+    /// lines. Empty lines and lines starting with `#` are ignored:
+    ///
+    /// ```text
+    /// # Block plain HTTP.
+    /// http: tcp.port == 80
+    /// internal: ip.src in { 10.0.0.0/8 }
+    ///     and not ssl
+    /// ```
+    ///
+    /// All the rules are parsed even if some of them fail, and an error is
+    /// returned for each of those.
+    pub fn parse_rules<'i>(
+        &'s self,
+        input: &'i str,
+    ) -> Result<Vec<(String, FilterAst<'s>)>, Vec<RuleParseError<'i>>> {
+        // Rule names with byte ranges of their expressions.
+        let mut items = Vec::new();
+        let mut open = None;
+        let mut offset = 0;
+
+        for (index, line) in input.split('\n').enumerate() {
+            let line_start = offset;
+            offset += line.len() + 1;
+
+            // Indented lines continue the previous rule.
+            if line.trim().is_empty() || line.starts_with(char::is_whitespace) {
+                continue;
+            }
+
+            if let Some(Ok((_, _, end))) = open.take().map(|i| &mut items[i]) {
+                *end = line_start;
+            }
+
+            if line.starts_with('#') {
+                continue;
+            }
+
+            match line.find(':') {
+                Some(pos) if is_rule_name(&line[..pos]) => {
+                    open = Some(items.len());
+                    items.push(Ok((&line[..pos], line_start + pos + 1, input.len())));
+                }
+                _ => items.push(Err(RuleParseError::InvalidName { line: index + 1 })),
+            }
+        }
+
+        let mut names = HashSet::new();
+        let mut rules = Vec::new();
+        let mut errors = Vec::new();
+
+        for item in items {
+            let rule = item.and_then(|(name, start, end)| {
+                if !names.insert(name) {
+                    return Err(RuleParseError::DuplicateName { name });
+                }
+                complete(FilterAst::lex_with(input[start..end].trim(), self))
+                    .map(|ast| (name.to_owned(), ast))
+                    .map_err(|err| RuleParseError::Filter {
+                        name,
+                        error: ParseError::new(input, err),
+                    })
+            });
+            match rule {
+                Ok(rule) => rules.push(rule),
+                Err(err) => errors.push(err),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(rules)
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// An error that occurs when adding a field with
+/// [`SchemeBuilder::add_field`].
+#[derive(Debug, PartialEq, Fail)]
+pub enum FieldDefinitionError {
+    /// A field with the same name already exists.
+    #[fail(display = "{}", _0)]
+    Redefinition(#[cause] FieldRedefinitionError),
+
+    /// The name can't be used in filters, because it's not made of
+    /// dot-separated identifiers of ASCII letters, digits and underscores.
+    #[fail(display = "invalid field name {:?}", _0)]
+    InvalidName(String),
+
+    /// A field is used as a namespace of another field, e.g. `http` and
+    /// `http.host`, so they can't be represented as nested objects.
+    #[fail(display = "field {} conflicts with nested field {}", field, nested)]
+    NestedConflict {
+        /// The name of the field used as a namespace.
+        field: String,
+        /// The name of the field nested in it.
+        nested: String,
+    },
+}
+
+/// A builder of a [`Scheme`](struct@Scheme) from fields known only at
+/// runtime, e.g. read from a configuration file.
+///
+/// Unlike [`Scheme::add_field`], it checks that names can be used in
+/// filters and that no field is nested in another one. Fields get indices
+/// in the order they are added, which don't change once the scheme is
+/// built.
+#[derive(Default)]
+pub struct SchemeBuilder {
+    scheme: Scheme,
+    namespaces: HashSet<String>,
+}
+
+impl SchemeBuilder {
+    /// Creates a builder without any fields.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Registers a field and its corresponding type.
+    pub fn add_field(&mut self, name: String, ty: Type) -> Result<(), FieldDefinitionError> {
+        if name.split('.').any(|part| {
+            part.is_empty() || !part.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }) {
+            return Err(FieldDefinitionError::InvalidName(name));
+        }
+
+        if self.scheme.contains(&name) {
+            return Err(FieldDefinitionError::Redefinition(FieldRedefinitionError(
+                name,
+            )));
+        }
+
+        if self.namespaces.contains(&name) {
+            let prefix = format!("{}.", name);
+            let nested = self
+                .scheme
+                .fields
+                .keys()
+                .find(|nested| nested.starts_with(&prefix))
+                .expect("namespaces are prefixes of fields")
+                .clone();
+            return Err(FieldDefinitionError::NestedConflict {
+                field: name,
+                nested,
+            });
+        }
+
+        let namespaces = name
+            .match_indices('.')
+            .map(|(end, _)| &name[..end])
+            .collect::<Vec<_>>();
+
+        if let Some(field) = namespaces
+            .iter()
+            .find(|namespace| self.scheme.contains(namespace))
+        {
+            return Err(FieldDefinitionError::NestedConflict {
+                field: (*field).to_owned(),
+                nested: name,
+            });
+        }
+
+        self.namespaces
+            .extend(namespaces.into_iter().map(str::to_owned));
+        self.scheme
+            .add_field(name, ty)
+            .expect("names are checked for conflicts above");
+        Ok(())
+    }
+
+    /// Finishes building and returns the scheme.
+    pub fn build(self) -> Scheme {
+        self.scheme
+    }
+}
+
+/// A convenience macro for constructing a [`Scheme`](struct@Scheme) with static
+/// contents.
 #[macro_export]
 macro_rules! Scheme {
     ($($ns:ident $(. $field:ident)*: $ty:ident),* $(,)*) => {
@@ -339,7 +1348,8 @@ fn test_parse_error() {
                 input: "xyz",
                 line_number: 0,
                 span_start: 0,
-                span_len: 3
+                span_len: 3,
+                span: 0..3,
             }
         );
         assert_eq!(
@@ -363,7 +1373,8 @@ fn test_parse_error() {
                 input: "xyz",
                 line_number: 0,
                 span_start: 0,
-                span_len: 3
+                span_len: 3,
+                span: 0..3,
             }
         );
         assert_eq!(
@@ -387,7 +1398,8 @@ fn test_parse_error() {
                 input: "    xyz",
                 line_number: 2,
                 span_start: 4,
-                span_len: 3
+                span_len: 3,
+                span: 6..9,
             }
         );
         assert_eq!(
@@ -400,6 +1412,11 @@ fn test_parse_error() {
                 "#
             )
         );
+        assert_eq!(err.reason(), "unknown field");
+        assert_eq!(err.line(), "    xyz");
+        assert_eq!(err.line_number(), 2);
+        assert_eq!(err.span_start(), 4);
+        assert_eq!(err.span_len(), 3);
     }
 
     {
@@ -419,7 +1436,8 @@ fn test_parse_error() {
                 input: "num == true or",
                 line_number: 1,
                 span_start: 7,
-                span_len: 7
+                span_len: 7,
+                span: 20..37,
             }
         );
         assert_eq!(
@@ -433,6 +1451,434 @@ fn test_parse_error() {
             )
         );
     }
+
+    {
+        let scheme = &Scheme! { http.host: Bytes };
+        let err = scheme.parse("http.host matches 42").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            indoc!(
+                r#"
+                Filter parsing error (1:19):
+                http.host matches 42
+                                  ^^ expected literal "\""
+                "#
+            )
+        );
+        assert_eq!(err.expected(), ["\""]);
+
+        // Columns are counted in characters, so that carets line up.
+        let err = scheme
+            .parse(r#"http.host == "ü" ||  http.host foo "x""#)
+            .unwrap_err();
+        assert_eq!(err.span_start(), 32);
+        assert_eq!(err.column(), 31);
+        assert_eq!(err.span(), 32..39);
+        assert_eq!(
+            err.to_string(),
+            indoc!(
+                r#"
+                Filter parsing error (1:32):
+                http.host == "ü" ||  http.host foo "x"
+                                               ^^^^^^^ expected ComparisonOp
+                "#
+            )
+        );
+        assert_eq!(
+            err.expected(),
+            [
+                "contains",
+                "~",
+                "matches",
+                "wildcard",
+                "like",
+                "in_domain",
+                "in",
+                "&",
+                "bitwise_and",
+                "|",
+                "bitwise_or",
+                "^",
+                "bitwise_xor",
+                "<<",
+                "shift_left",
+                ">>",
+                "shift_right",
+                "eq",
+                "==",
+                "ne",
+                "!=",
+                "ge",
+                ">=",
+                "le",
+                "<=",
+                "gt",
+                ">",
+                "lt",
+                "<",
+            ]
+        );
+    }
+}
+
+#[test]
+fn test_max_list_elements() {
+    let mut scheme = Scheme! { num: Int };
+    scheme.set_parser_settings(ParserSettings {
+        max_list_elements: Some(3),
+        ..Default::default()
+    });
+
+    assert!(scheme.parse("num in { 1 2 3 }").is_ok());
+
+    let err = scheme.parse("num in { 1 2 3 4 5 }").unwrap_err();
+    assert_eq!(err.kind, LexErrorKind::TooManyListElements { max: 3 });
+    assert_eq!(
+        err.to_string().lines().nth(2),
+        Some("               ^^^^^ list has more than 3 elements")
+    );
+}
+
+#[test]
+fn test_allowed_operators() {
+    use crate::{
+        ast::ComparisonOperator,
+        functions::{FunctionArgKind, FunctionParam},
+    };
+
+    let mut scheme = Scheme! { num: Int, host: Bytes, tcp: Bool };
+    scheme
+        .add_function(
+            "echo".into(),
+            Function {
+                params: vec![FunctionParam {
+                    arg_kind: FunctionArgKind::Field,
+                    val_type: Type::Bytes,
+                }],
+                opt_params: vec![],
+                return_type: Type::Bytes,
+                implementation: FunctionImpl::new(|args| args.next().unwrap()),
+                pure: true,
+            },
+        )
+        .unwrap();
+    scheme.set_parser_settings(ParserSettings {
+        allowed_operators: Some(vec![
+            ComparisonOperator::IsTrue,
+            ComparisonOperator::Equal,
+            ComparisonOperator::In,
+        ]),
+        allow_function_calls: false,
+        ..Default::default()
+    });
+
+    assert!(scheme
+        .parse(r#"tcp and num in { 80 443 } and host == "example.org""#)
+        .is_ok());
+
+    let err = scheme.parse(r#"tcp and host matches "^a""#).unwrap_err();
+    assert_eq!(
+        err.kind,
+        LexErrorKind::ForbiddenOperator(ComparisonOperator::Matches)
+    );
+    assert_eq!(err.span_start(), 8);
+    assert_eq!(err.span_len(), 17);
+
+    let err = scheme.parse(r#"echo(host) == "a""#).unwrap_err();
+    assert_eq!(err.kind, LexErrorKind::ForbiddenFunctionCall);
+    assert_eq!(err.span_len(), 10);
+
+    let json =
+        serde_json::to_string(&Scheme! { host: Bytes }.parse(r#"host ~ "a""#).unwrap()).unwrap();
+    assert_eq!(
+        scheme
+            .parse_from_ast(&mut serde_json::Deserializer::from_str(&json))
+            .unwrap_err()
+            .to_string(),
+        "operator Matches is not allowed"
+    );
+}
+
+#[test]
+fn test_ipv4_mapped_ipv6_interop() {
+    use crate::execution_context::ExecutionContext;
+    use std::net::IpAddr;
+
+    let mut scheme = Scheme! { ip: Ip };
+
+    let matches = |scheme: &Scheme, filter: &str, value: &str| {
+        let filter = scheme.parse(filter).unwrap().compile();
+        let mut ctx = ExecutionContext::new(scheme);
+        ctx.set_field_value("ip", value.parse::<IpAddr>().unwrap())
+            .unwrap();
+        filter.execute(&ctx).unwrap()
+    };
+
+    // By default, mapped addresses are distinct IPv6 addresses.
+    assert!(!matches(&scheme, "ip == 1.2.3.4", "::ffff:1.2.3.4"));
+    assert!(!matches(&scheme, "ip == ::ffff:1.2.3.4", "1.2.3.4"));
+    assert!(matches(&scheme, "ip == ::FFFF:1.2.3.4", "::ffff:1.2.3.4"));
+
+    scheme.set_parser_settings(ParserSettings {
+        ipv4_mapped_ipv6_interop: true,
+        ..Default::default()
+    });
+
+    assert!(matches(&scheme, "ip == 1.2.3.4", "::ffff:1.2.3.4"));
+    assert!(matches(&scheme, "ip == ::ffff:1.2.3.4", "1.2.3.4"));
+    assert!(matches(
+        &scheme,
+        "ip in { ::ffff:10.0.0.0/104 }",
+        "10.1.2.3"
+    ));
+    assert!(matches(&scheme, "ip in { 10.0.0.0/8 }", "::ffff:10.1.2.3"));
+    assert!(matches(&scheme, "ip >= 1.2.3.4", "::ffff:1.2.3.5"));
+    assert!(!matches(&scheme, "ip == ::1", "0.0.0.1"));
+
+    let ast = scheme.parse("ip in { ::ffff:10.0.0.0/104 }").unwrap();
+    assert_eq!(
+        serde_json::to_value(ast).unwrap()["rhs"],
+        serde_json::json!(["10.0.0.0/8"])
+    );
+}
+
+#[test]
+fn test_ipv6_zones() {
+    use crate::{execution_context::ExecutionContext, parser_settings::Ipv6Zones};
+
+    let mut scheme = Scheme! { ip: Ip, other: Ip };
+
+    let matches = |scheme: &Scheme, filter: &str, value: &str| {
+        let ast = scheme.parse(filter).unwrap();
+        let mut ctx = ExecutionContext::new(scheme);
+        ctx.set_from_pairs(vec![("ip", value), ("other", "::1")])
+            .unwrap();
+        let result = ast.clone().compile().execute(&ctx).unwrap();
+        assert_eq!(
+            ast.compile_to_bytecode().execute(&ctx),
+            Ok(result),
+            "{}",
+            filter
+        );
+        result
+    };
+
+    // By default, zones are rejected both in filters and in values.
+    assert_eq!(
+        scheme.parse("ip == fe80::1%eth0").unwrap_err().kind,
+        LexErrorKind::ForbiddenIpZone
+    );
+    assert!(ExecutionContext::new(&scheme)
+        .set_from_pairs(vec![("ip", "fe80::1%eth0")])
+        .is_err());
+
+    scheme.set_parser_settings(ParserSettings {
+        ipv6_zones: Ipv6Zones::Ignore,
+        ..Default::default()
+    });
+
+    assert!(matches(&scheme, "ip == fe80::1%eth0", "fe80::1%eth1"));
+    assert!(matches(&scheme, "ip == fe80::1", "fe80::1%eth1"));
+    assert!(!matches(&scheme, "ip != fe80::1%eth0", "fe80::1"));
+    assert_eq!(
+        scheme.parse("ip == fe80::1%eth0").unwrap().to_string(),
+        "ip == fe80::1"
+    );
+
+    for filter in &["ip == 10.0.0.1%eth0", "ip < fe80::1%eth0", "ip == fe80::1%"] {
+        assert!(scheme.parse(filter).is_err(), "{}", filter);
+    }
+    assert_eq!(
+        scheme.parse("ip == 10.0.0.1%eth0").unwrap_err().kind,
+        LexErrorKind::InvalidIpZone
+    );
+    assert!(ExecutionContext::new(&scheme)
+        .set_from_pairs(vec![("ip", "10.0.0.1%eth0")])
+        .is_err());
+
+    scheme.set_parser_settings(ParserSettings {
+        ipv6_zones: Ipv6Zones::Compare,
+        ..Default::default()
+    });
+
+    assert!(matches(&scheme, "ip == fe80::1%eth0", "fe80::1%eth0"));
+    assert!(!matches(&scheme, "ip == fe80::1%eth0", "fe80::1%eth1"));
+    assert!(!matches(&scheme, "ip == fe80::1%eth0", "fe80::1"));
+    assert!(!matches(&scheme, "ip == fe80::1%eth0", "fe80::2%eth0"));
+    assert!(matches(&scheme, "ip != fe80::1%eth0", "fe80::1%eth1"));
+    assert!(!matches(
+        &scheme,
+        "ip != fe80::1%eth0.100",
+        "fe80::1%eth0.100"
+    ));
+    assert!(!matches(&scheme, "other == ::1%eth0", "fe80::1%eth0"));
+    // Literals without zones match values in any zone.
+    assert!(matches(&scheme, "ip == fe80::1", "fe80::1%eth1"));
+    assert!(matches(&scheme, "ip in { fe80::/10 }", "fe80::1%eth1"));
+
+    let ast = scheme.parse("ip == fe80::1%eth0").unwrap();
+    assert_eq!(ast.to_string(), "ip == fe80::1%eth0");
+    let json = serde_json::to_string(&ast).unwrap();
+    assert_eq!(
+        json,
+        r#"{"lhs":"ip","op":"Equal","rhs":"fe80::1","zone":"eth0"}"#
+    );
+    assert_eq!(
+        scheme
+            .parse_from_ast(&mut serde_json::Deserializer::from_str(&json))
+            .unwrap(),
+        ast
+    );
+
+    // Zones of values are set separately from the values themselves.
+    let filter = ast.compile();
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("ip", "fe80::1".parse::<std::net::IpAddr>().unwrap())
+        .unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+    ctx.set_ip_zone("ip", Some("eth0")).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    ctx.set_field_value("ip", "fe80::1".parse::<std::net::IpAddr>().unwrap())
+        .unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+    assert!(ctx.set_ip_zone("unknown", Some("eth0")).is_err());
+}
+
+#[test]
+fn test_iter() {
+    let mut scheme = Scheme! {
+        http.host: Bytes,
+        tcp.port: Int,
+        ssl: Bool,
+    };
+    scheme.set_field_default("ssl", false).unwrap();
+
+    assert_eq!(scheme.len(), 3);
+    assert!(!scheme.is_empty());
+    assert!(Scheme::new().is_empty());
+
+    assert!(scheme.contains("tcp.port"));
+    assert!(!scheme.contains("tcp"));
+
+    assert_eq!(
+        scheme
+            .iter()
+            .map(|field| (
+                field.name(),
+                field.get_type(),
+                field.index(),
+                field.default_value()
+            ))
+            .collect::<Vec<_>>(),
+        vec![
+            ("http.host", Type::Bytes, 0, None),
+            ("tcp.port", Type::Int, 1, None),
+            ("ssl", Type::Bool, 2, Some(&LhsValue::Bool(false))),
+        ]
+    );
+}
+
+#[test]
+fn test_field_validator() {
+    fn validate_token(rhs: ComparisonRhs<'_>) -> Result<(), String> {
+        let is_token = |bytes: &[u8]| !bytes.is_empty() && !bytes.contains(&b' ');
+
+        match rhs {
+            ComparisonRhs::Value(crate::RhsValue::Bytes(bytes)) | ComparisonRhs::Bytes(bytes)
+                if !is_token(bytes) =>
+            {
+                Err(format!("{:?} is not a valid token", bytes))
+            }
+            ComparisonRhs::Values(crate::RhsValues::Bytes(values)) => {
+                match values.iter().find(|bytes| !is_token(bytes)) {
+                    Some(bytes) => Err(format!("{:?} is not a valid token", bytes)),
+                    None => Ok(()),
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    let mut scheme = Scheme! { header.name: Bytes, other: Bytes };
+
+    assert_eq!(
+        scheme.set_field_validator("unknown", validate_token),
+        Err(UnknownFieldError)
+    );
+    scheme
+        .set_field_validator("header.name", validate_token)
+        .unwrap();
+
+    assert!(scheme.parse(r#"header.name == "x-id""#).is_ok());
+    assert!(scheme.parse(r#"other == "x id""#).is_ok());
+
+    assert_eq!(
+        scheme.parse(r#"header.name == "x id""#),
+        Err(ParseError {
+            kind: LexErrorKind::InvalidRhs(r#""x id" is not a valid token"#.to_owned()),
+            input: r#"header.name == "x id""#,
+            line_number: 0,
+            span_start: 0,
+            span_len: 21,
+            span: 0..21,
+        })
+    );
+
+    assert!(scheme.parse(r#"header.name in { "a" "b c" }"#).is_err());
+    assert!(scheme.parse(r#"header.name contains " ""#).is_err());
+}
+
+#[test]
+fn test_parse_rules() {
+    use indoc::indoc;
+
+    let scheme = &Scheme! { num: Int, ssl: Bool };
+
+    let rules = scheme
+        .parse_rules(indoc!(
+            r#"
+            # A comment.
+            small: num < 10
+
+            big-and-secure: num >= 1000
+                and ssl
+            "#
+        ))
+        .unwrap();
+
+    assert_eq!(
+        rules,
+        vec![
+            ("small".to_owned(), scheme.parse("num < 10").unwrap()),
+            (
+                "big-and-secure".to_owned(),
+                scheme.parse("num >= 1000 and ssl").unwrap()
+            ),
+        ]
+    );
+
+    let input = indoc!(
+        r#"
+        first: num == 1
+        first: num == 2
+        not a rule
+        broken: num ==
+          xyz
+        "#
+    );
+
+    let errors = scheme.parse_rules(input).unwrap_err();
+
+    assert_eq!(errors.len(), 3);
+    assert_eq!(errors[0], RuleParseError::DuplicateName { name: "first" });
+    assert_eq!(errors[1], RuleParseError::InvalidName { line: 3 });
+    match &errors[2] {
+        RuleParseError::Filter { name, error } => {
+            assert_eq!(*name, "broken");
+            assert_eq!(error.line_number, 4);
+        }
+        err => panic!("unexpected error {:?}", err),
+    }
 }
 
 #[test]
@@ -495,3 +1941,304 @@ fn test_field_type_override() {
         ItemRedefinitionError::Field(FieldRedefinitionError("foo".into()))
     )
 }
+
+#[test]
+fn test_golden_encoding() {
+    let golden = include_str!("../tests/golden/scheme.json");
+    let scheme: Scheme = serde_json::from_str(golden).unwrap();
+
+    assert_eq!(
+        scheme
+            .iter()
+            .map(|field| (field.name(), field.get_type()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("http.host", Type::Bytes),
+            ("http.referer", Type::Bytes),
+            ("ip.src", Type::Ip),
+            ("ssl", Type::Bool),
+            ("tcp.port", Type::Int),
+        ]
+    );
+
+    assert_eq!(
+        serde_json::to_value(&scheme).unwrap(),
+        serde_json::from_str::<serde_json::Value>(golden).unwrap()
+    );
+}
+
+#[test]
+fn test_scheme_compatibility() {
+    use crate::ast::OperatorStyle;
+
+    let published = Scheme! {
+        http.host: Bytes,
+        ip.src: Ip,
+        tcp.port: Int,
+    };
+    let loaded: Scheme = serde_json::from_str(&serde_json::to_string(&published).unwrap()).unwrap();
+    assert_eq!(
+        loaded
+            .iter()
+            .map(|field| (field.name(), field.index(), field.get_type()))
+            .collect::<Vec<_>>(),
+        published
+            .iter()
+            .map(|field| (field.name(), field.index(), field.get_type()))
+            .collect::<Vec<_>>(),
+    );
+    assert_eq!(published.check_compatibility(&loaded), Ok(()));
+
+    let ast = published
+        .parse(r#"http.host == "example.org" && tcp.port == 443"#)
+        .unwrap();
+    let rebound = ast.rebind(&loaded).unwrap();
+    assert_eq!(
+        rebound.to_filter_string(OperatorStyle::Symbols),
+        ast.to_filter_string(OperatorStyle::Symbols)
+    );
+
+    // Fields can be reordered and added.
+    let newer: Scheme = serde_json::from_str(
+        r#"{"ssl": "Bool", "tcp.port": "Int", "ip.src": "Ip", "http.host": "Bytes"}"#,
+    )
+    .unwrap();
+    assert_eq!(published.check_compatibility(&newer), Ok(()));
+    assert!(ast.rebind(&newer).is_some());
+    assert_eq!(
+        newer.check_compatibility(&published),
+        Err(vec![SchemeIncompatibility::MissingField("ssl".into())])
+    );
+
+    let changed: Scheme =
+        serde_json::from_str(r#"{"http.host": "Bytes", "tcp.port": "Bytes"}"#).unwrap();
+    assert_eq!(
+        published.check_compatibility(&changed),
+        Err(vec![
+            SchemeIncompatibility::MissingField("ip.src".into()),
+            SchemeIncompatibility::TypeChanged {
+                name: "tcp.port".into(),
+                expected: Type::Int,
+                actual: Type::Bytes,
+            },
+        ])
+    );
+    assert!(ast.rebind(&changed).is_none());
+    assert!(published
+        .parse(r#"http.host == "example.org""#)
+        .unwrap()
+        .rebind(&changed)
+        .is_some());
+}
+
+#[test]
+fn test_case_insensitive_fields() {
+    use crate::execution_context::ExecutionContext;
+
+    let mut scheme = Scheme! { http.host: Bytes, http.path: Bytes, tcp.port: Int };
+    scheme
+        .set_field_case_insensitive("http.host", true)
+        .unwrap();
+    assert_eq!(
+        scheme.set_field_case_insensitive("tcp.port", true),
+        Err(FieldComparisonModeError::TypeMismatch(TypeMismatchError {
+            expected: Type::Bytes,
+            actual: Type::Int,
+        }))
+    );
+    assert_eq!(
+        scheme.set_field_case_insensitive("http.other", true),
+        Err(FieldComparisonModeError::UnknownField(UnknownFieldError))
+    );
+
+    let filters = [
+        (r#"http.host == "www.EXAMPLE.org""#, true),
+        (r#"http.host contains "AMPLE""#, true),
+        (
+            r#"http.host in { "www.example.com" "WWW.EXAMPLE.ORG" }"#,
+            true,
+        ),
+        (r#"http.host ~ "^WWW\.""#, true),
+        (r#"http.host wildcard "*.EXAMPLE.*""#, true),
+        // Several `contains` and `matches` are checked with a regex set.
+        (r#"http.host contains "NET" || http.host ~ "^W+\.EX""#, true),
+        (r#"http.host == "example.net""#, false),
+        // Other fields are still case-sensitive.
+        (r#"http.path == "/index.html""#, false),
+    ];
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.host", "WWW.Example.org").unwrap();
+    ctx.set_field_value("http.path", "/Index.html").unwrap();
+    ctx.set_field_value("tcp.port", 443).unwrap();
+
+    for (filter, matches) in &filters {
+        let ast = scheme.parse(filter).unwrap();
+        // Literals are kept as they were written.
+        assert_eq!(ast.to_string(), *filter);
+        assert_eq!(
+            ast.clone().compile().execute(&ctx),
+            Ok(*matches),
+            "{}",
+            filter
+        );
+        assert_eq!(
+            ast.compile_to_bytecode().execute(&ctx),
+            Ok(*matches),
+            "{}",
+            filter
+        );
+    }
+
+    scheme
+        .set_field_case_insensitive("http.host", false)
+        .unwrap();
+    let filter = scheme.parse(r#"http.host == "Example.ORG""#).unwrap();
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("http.host", "example.org").unwrap();
+    assert_eq!(filter.compile().execute(&ctx), Ok(false));
+}
+
+#[test]
+fn test_field_comparison_modes() {
+    use crate::execution_context::ExecutionContext;
+
+    let mut scheme = Scheme! { http.host: Bytes, http.ua: Bytes };
+    scheme
+        .set_field_comparison_mode(
+            "http.host",
+            ComparisonMode {
+                case_insensitive: true,
+                trim: true,
+            },
+        )
+        .unwrap();
+    scheme
+        .set_field_comparison_mode(
+            "http.ua",
+            ComparisonMode {
+                case_insensitive: false,
+                trim: true,
+            },
+        )
+        .unwrap();
+    // Case-insensitivity alone can be toggled without losing trimming.
+    scheme.set_field_case_insensitive("http.ua", false).unwrap();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_record_matches(true);
+    ctx.set_field_value("http.host", " \tExample.ORG\r\n")
+        .unwrap();
+    ctx.set_field_value("http.ua", "  Curl/7.64.1 ").unwrap();
+
+    let filters = [
+        (r#"http.host == "example.org""#, true),
+        (r#"http.host wildcard "*.org""#, true),
+        (r#"http.ua == "Curl/7.64.1""#, true),
+        (r#"http.ua == "curl/7.64.1""#, false),
+        (r#"http.ua contains " ""#, false),
+    ];
+
+    for (filter, matches) in &filters {
+        let ast = scheme.parse(filter).unwrap();
+        assert_eq!(
+            ast.clone().compile().execute(&ctx),
+            Ok(*matches),
+            "{}",
+            filter
+        );
+        assert_eq!(
+            ast.compile_to_bytecode().execute(&ctx),
+            Ok(*matches),
+            "{}",
+            filter
+        );
+    }
+
+    // Spans point into the values as they were set.
+    let filter = scheme
+        .parse(r#"http.host contains "ORG""#)
+        .unwrap()
+        .compile();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+    assert_eq!(
+        ctx.matched_spans(),
+        vec![crate::MatchedSpan {
+            field: "http.host",
+            range: 10..13,
+        }]
+    );
+
+    // Values with nothing but whitespace become empty.
+    ctx.set_field_value("http.ua", " \t ").unwrap();
+    let filter = scheme.parse(r#"http.ua == """#).unwrap().compile();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+}
+
+#[test]
+fn test_scheme_builder() {
+    let mut builder = SchemeBuilder::new();
+    builder.add_field("http.host".into(), Type::Bytes).unwrap();
+    builder.add_field("http.port".into(), Type::Int).unwrap();
+    builder.add_field("ip_src".into(), Type::Ip).unwrap();
+
+    assert_eq!(
+        builder.add_field("http.host".into(), Type::Int),
+        Err(FieldDefinitionError::Redefinition(FieldRedefinitionError(
+            "http.host".into()
+        )))
+    );
+
+    for name in &[
+        "",
+        "http.",
+        ".host",
+        "http..host",
+        "http-host",
+        "http host",
+        "ü",
+    ] {
+        assert_eq!(
+            builder.add_field((*name).to_owned(), Type::Bytes),
+            Err(FieldDefinitionError::InvalidName((*name).to_owned())),
+        );
+    }
+
+    assert_eq!(
+        builder.add_field("http".into(), Type::Bytes),
+        Err(FieldDefinitionError::NestedConflict {
+            field: "http".into(),
+            nested: "http.host".into(),
+        })
+    );
+    assert_eq!(
+        builder.add_field("ip_src.v4".into(), Type::Ip),
+        Err(FieldDefinitionError::NestedConflict {
+            field: "ip_src".into(),
+            nested: "ip_src.v4".into(),
+        })
+    );
+    assert_eq!(
+        builder
+            .add_field("http".into(), Type::Bytes)
+            .unwrap_err()
+            .to_string(),
+        "field http conflicts with nested field http.host"
+    );
+
+    let scheme = builder.build();
+    assert_eq!(
+        scheme
+            .iter()
+            .map(|field| (field.name(), field.index(), field.get_type()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("http.host", 0, Type::Bytes),
+            ("http.port", 1, Type::Int),
+            ("ip_src", 2, Type::Ip),
+        ]
+    );
+    assert!(scheme
+        .parse(r#"http.host == "example.org" && http.port == 443"#)
+        .is_ok());
+}
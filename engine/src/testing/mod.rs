@@ -0,0 +1,430 @@
+//! Helpers for testing filters and rule sets before deployment.
+//!
+//! With the `testing` feature, there are also checks for crates that
+//! register fields of a protocol, like `check_protocol_fields`.
+
+use crate::{
+    execution_context::ExecutionContext,
+    rhs_types::{ExplicitIpRange, Float, Timestamp},
+    scheme::{Scheme, UnknownFieldError},
+    types::{GetType, LhsValue, Type, TypeMismatchError},
+};
+use cfg_if::cfg_if;
+use failure::Fail;
+use std::{
+    borrow::Cow,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    ops::RangeInclusive,
+};
+
+cfg_if! {
+    if #[cfg(any(test, feature = "testing"))] {
+        mod protocol;
+        pub use self::protocol::*;
+    }
+}
+
+/// How values of a field are picked by a [`ContextGenerator`].
+#[derive(Debug, PartialEq, Clone)]
+pub enum Distribution {
+    /// One of given values, each equally likely.
+    OneOf(Vec<LhsValue<'static>>),
+
+    /// One of given values, with likelihoods proportional to their weights.
+    Weighted(Vec<(LhsValue<'static>, u32)>),
+
+    /// An `Int` from a range, each equally likely.
+    IntRange(RangeInclusive<i64>),
+
+    /// An `Ip` from a range, each equally likely.
+    IpRange(ExplicitIpRange),
+
+    /// A `Bool`, which is `true` with a given probability.
+    Chance(f64),
+
+    /// `Bytes` of a length from a range, each made of bytes picked from an
+    /// alphabet.
+    RandomBytes {
+        /// Lengths of values, each equally likely.
+        len: RangeInclusive<usize>,
+        /// Bytes that values are made of, each equally likely.
+        alphabet: Vec<u8>,
+    },
+
+    /// A `Float` between `0` and `1`.
+    UnitFloat,
+
+    /// A `Timestamp` from a range, each equally likely.
+    TimestampRange(RangeInclusive<Timestamp>),
+}
+
+impl Distribution {
+    /// Returns a distribution used for fields of a given type, unless
+    /// configured otherwise.
+    ///
+    /// Integers are picked like port numbers, addresses from the whole IPv4
+    /// space, bytes like short lowercase hostnames and timestamps from the
+    /// 2020s. The latter don't depend on the current time, so that generated
+    /// contexts stay reproducible.
+    pub fn default_for(ty: Type) -> Self {
+        match ty {
+            Type::Bool => Distribution::Chance(0.5),
+            Type::Int => Distribution::IntRange(0..=0xFFFF),
+            Type::Ip => Distribution::IpRange(ExplicitIpRange::V4(
+                Ipv4Addr::from(0)..=Ipv4Addr::from(u32::MAX),
+            )),
+            Type::Bytes => Distribution::RandomBytes {
+                len: 0..=16,
+                alphabet: b"abcdefghijklmnopqrstuvwxyz0123456789.-".to_vec(),
+            },
+            Type::Float => Distribution::UnitFloat,
+            Type::Timestamp => Distribution::TimestampRange(
+                Timestamp::from_nanos(1_577_836_800_000_000_000)
+                    ..=Timestamp::from_nanos(1_893_455_999_999_999_999),
+            ),
+        }
+    }
+
+    fn check(&self, ty: Type) -> Result<(), DistributionError> {
+        let mismatch = |actual| {
+            if actual == ty {
+                Ok(())
+            } else {
+                Err(DistributionError::TypeMismatch(TypeMismatchError {
+                    expected: ty,
+                    actual,
+                }))
+            }
+        };
+
+        match self {
+            Distribution::OneOf(values) => {
+                if values.is_empty() {
+                    return Err(DistributionError::Empty);
+                }
+                values
+                    .iter()
+                    .try_for_each(|value| mismatch(value.get_type()))
+            }
+            Distribution::Weighted(values) => {
+                if values.iter().all(|(_, weight)| *weight == 0) {
+                    return Err(DistributionError::Empty);
+                }
+                values
+                    .iter()
+                    .try_for_each(|(value, _)| mismatch(value.get_type()))
+            }
+            Distribution::IntRange(range) => {
+                if range.is_empty() {
+                    return Err(DistributionError::Empty);
+                }
+                mismatch(Type::Int)
+            }
+            Distribution::IpRange(range) => {
+                let empty = match range {
+                    ExplicitIpRange::V4(range) => range.is_empty(),
+                    ExplicitIpRange::V6(range) => range.is_empty(),
+                };
+                if empty {
+                    return Err(DistributionError::Empty);
+                }
+                mismatch(Type::Ip)
+            }
+            Distribution::Chance(_) => mismatch(Type::Bool),
+            Distribution::RandomBytes { len, alphabet } => {
+                if len.is_empty() || (alphabet.is_empty() && *len.end() > 0) {
+                    return Err(DistributionError::Empty);
+                }
+                mismatch(Type::Bytes)
+            }
+            Distribution::UnitFloat => mismatch(Type::Float),
+            Distribution::TimestampRange(range) => {
+                if range.is_empty() {
+                    return Err(DistributionError::Empty);
+                }
+                mismatch(Type::Timestamp)
+            }
+        }
+    }
+
+    fn sample(&self, rng: &mut Rng) -> LhsValue<'static> {
+        match self {
+            Distribution::OneOf(values) => values[rng.below(values.len() as u64) as usize].clone(),
+            Distribution::Weighted(values) => {
+                let total = values.iter().map(|(_, weight)| u64::from(*weight)).sum();
+                let mut pick = rng.below(total);
+                for (value, weight) in values {
+                    match pick.checked_sub(u64::from(*weight)) {
+                        Some(rest) => pick = rest,
+                        None => return value.clone(),
+                    }
+                }
+                unreachable!()
+            }
+            Distribution::IntRange(range) => {
+                let span = (i128::from(*range.end()) - i128::from(*range.start())) as u64;
+                let offset = match span.checked_add(1) {
+                    Some(len) => rng.below(len),
+                    None => rng.next(),
+                };
+                LhsValue::Int(range.start().wrapping_add(offset as i64))
+            }
+            Distribution::IpRange(ExplicitIpRange::V4(range)) => {
+                let start = u32::from(*range.start());
+                let span = u64::from(u32::from(*range.end()) - start) + 1;
+                LhsValue::Ip(IpAddr::V4(Ipv4Addr::from(start + rng.below(span) as u32)))
+            }
+            Distribution::IpRange(ExplicitIpRange::V6(range)) => {
+                let start = u128::from(*range.start());
+                let span = u128::from(*range.end()) - start;
+                let random = u128::from(rng.next()) << 64 | u128::from(rng.next());
+                let offset = match span.checked_add(1) {
+                    Some(len) => random % len,
+                    None => random,
+                };
+                LhsValue::Ip(IpAddr::V6(Ipv6Addr::from(start + offset)))
+            }
+            Distribution::Chance(probability) => LhsValue::Bool(rng.unit() < *probability),
+            Distribution::RandomBytes { len, alphabet } => {
+                let len = len.start() + rng.below((len.end() - len.start()) as u64 + 1) as usize;
+                let bytes = (0..len)
+                    .map(|_| alphabet[rng.below(alphabet.len() as u64) as usize])
+                    .collect::<Vec<_>>();
+                LhsValue::Bytes(Cow::Owned(bytes))
+            }
+            Distribution::UnitFloat => {
+                LhsValue::Float(Float::new(rng.unit()).expect("unit floats are finite"))
+            }
+            Distribution::TimestampRange(range) => {
+                let start = range.start().as_nanos();
+                let span = (i128::from(range.end().as_nanos()) - i128::from(start)) as u64;
+                let offset = match span.checked_add(1) {
+                    Some(len) => rng.below(len),
+                    None => rng.next(),
+                };
+                LhsValue::Timestamp(Timestamp::from_nanos(start.wrapping_add(offset as i64)))
+            }
+        }
+    }
+}
+
+/// An error that occurs when setting a [`Distribution`] of a field.
+#[derive(Debug, PartialEq, Fail)]
+pub enum DistributionError {
+    /// The field is not registered in the scheme.
+    #[fail(display = "{}", _0)]
+    UnknownField(#[cause] UnknownFieldError),
+
+    /// The distribution produces values of a different type than the field.
+    #[fail(display = "{}", _0)]
+    TypeMismatch(#[cause] TypeMismatchError),
+
+    /// The distribution has no values to pick from.
+    #[fail(display = "distribution has no values to pick from")]
+    Empty,
+}
+
+/// A generator of random [`ExecutionContext`]s with values of all fields
+/// of a scheme, e.g. to load-test a rule set with realistic traffic.
+///
+/// Every field gets a value from its [`Distribution`], or from
+/// [`Distribution::default_for`] its type. Fields that
+/// [accept multiple values](::Scheme::set_field_multi_valued) get up to
+/// three of them, and virtual fields are left to be derived.
+///
+/// Generators are seeded, so the same seed produces the same contexts.
+/// Randomness is not suitable for anything but testing.
+pub struct ContextGenerator<'s> {
+    scheme: &'s Scheme,
+    distributions: Vec<Distribution>,
+    rng: Rng,
+}
+
+impl<'s> ContextGenerator<'s> {
+    /// Creates a generator with default distributions for all fields.
+    pub fn new(scheme: &'s Scheme, seed: u64) -> Self {
+        ContextGenerator {
+            scheme,
+            distributions: scheme
+                .fields()
+                .map(|field| Distribution::default_for(field.get_type()))
+                .collect(),
+            rng: Rng(seed),
+        }
+    }
+
+    /// Sets a distribution of values of a given field.
+    pub fn set_distribution(
+        &mut self,
+        name: &str,
+        distribution: Distribution,
+    ) -> Result<(), DistributionError> {
+        let field = self
+            .scheme
+            .get_field_index(name)
+            .map_err(DistributionError::UnknownField)?;
+        distribution.check(field.get_type())?;
+        self.distributions[field.index()] = distribution;
+        Ok(())
+    }
+
+    /// Creates a context with random values of all fields.
+    pub fn generate(&mut self) -> ExecutionContext<'s> {
+        let mut ctx = ExecutionContext::new(self.scheme);
+
+        for field in self.scheme.fields() {
+            if self.scheme.get_virtual_field(field).is_some() {
+                continue;
+            }
+
+            let distribution = &self.distributions[field.index()];
+            let count = if self.scheme.is_field_multi_valued(field) {
+                self.rng.below(4) as usize
+            } else {
+                1
+            };
+            let rng = &mut self.rng;
+            let values = (0..count)
+                .map(|_| distribution.sample(rng))
+                .collect::<Vec<_>>();
+
+            ctx.set_field_values(field.name(), values)
+                .expect("distributions are checked against types of fields");
+        }
+
+        ctx
+    }
+}
+
+impl<'s> Iterator for ContextGenerator<'s> {
+    type Item = ExecutionContext<'s>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.generate())
+    }
+}
+
+// SplitMix64, which is tiny and good enough for picking test values.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Returns a number below a given non-zero bound.
+    fn below(&mut self, bound: u64) -> u64 {
+        ((u128::from(self.next()) * u128::from(bound)) >> 64) as u64
+    }
+
+    // Returns a number in `0..1`.
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[test]
+fn test_context_generator() {
+    let mut scheme = Scheme! {
+        ip.src: Ip,
+        tcp.port: Int,
+        http.host: Bytes,
+        http.accept: Bytes,
+        ssl: Bool,
+        ratio: Float,
+    };
+    scheme.set_field_multi_valued("http.accept", true).unwrap();
+    let scheme = &scheme;
+
+    let mut generator = ContextGenerator::new(scheme, 42);
+    generator
+        .set_distribution(
+            "http.host",
+            Distribution::Weighted(vec![
+                (LhsValue::from("example.org"), 3),
+                (LhsValue::from("example.com"), 1),
+                (LhsValue::from("never.net"), 0),
+            ]),
+        )
+        .unwrap();
+    generator
+        .set_distribution(
+            "ip.src",
+            Distribution::IpRange(ExplicitIpRange::V6(
+                "2001:db8::".parse().unwrap()..="2001:db8::ff".parse().unwrap(),
+            )),
+        )
+        .unwrap();
+    generator
+        .set_distribution("tcp.port", Distribution::IntRange(-1..=1))
+        .unwrap();
+
+    let filter = scheme
+        .parse(
+            r#"ip.src in { 2001:db8::/120 } and tcp.port in { -1..1 }
+                and http.host in { "example.org" "example.com" }
+                and ratio >= 0.0 and ratio < 1.0 and (ssl or not ssl)"#,
+        )
+        .unwrap()
+        .compile();
+    let org = scheme
+        .parse(r#"http.host == "example.org""#)
+        .unwrap()
+        .compile();
+    let accepts = scheme.parse(r#"http.accept != """#).unwrap().compile();
+
+    let mut orgs = 0;
+    let mut with_accept = 0;
+    for ctx in generator.take(1000) {
+        assert_eq!(filter.execute(&ctx), Ok(true));
+        orgs += org.execute(&ctx).unwrap() as usize;
+        with_accept += accepts.execute(&ctx).unwrap() as usize;
+    }
+    assert!((650..850).contains(&orgs), "{}", orgs);
+    // Multi-valued fields sometimes have no values at all.
+    assert!((650..850).contains(&with_accept), "{}", with_accept);
+
+    // The same seed gives the same contexts.
+    let host = |ctx: &ExecutionContext<'_>| {
+        ctx.get_field_value("http.host")
+            .unwrap()
+            .cloned()
+            .map(LhsValue::into_owned)
+    };
+    let mut a = ContextGenerator::new(scheme, 7);
+    let mut b = ContextGenerator::new(scheme, 7);
+    for _ in 0..10 {
+        assert_eq!(host(&a.generate()), host(&b.generate()));
+    }
+
+    let mut generator = ContextGenerator::new(scheme, 0);
+    assert_eq!(
+        generator.set_distribution("unknown", Distribution::Chance(0.5)),
+        Err(DistributionError::UnknownField(UnknownFieldError))
+    );
+    assert_eq!(
+        generator.set_distribution("ssl", Distribution::IntRange(0..=1)),
+        Err(DistributionError::TypeMismatch(TypeMismatchError {
+            expected: Type::Bool,
+            actual: Type::Int,
+        }))
+    );
+    assert_eq!(
+        generator.set_distribution("ssl", Distribution::OneOf(vec![])),
+        Err(DistributionError::Empty)
+    );
+    assert_eq!(
+        generator.set_distribution(
+            "http.host",
+            Distribution::RandomBytes {
+                len: 1..=4,
+                alphabet: vec![],
+            }
+        ),
+        Err(DistributionError::Empty)
+    );
+}
@@ -0,0 +1,131 @@
+use crate::{
+    execution_context::ExecutionContext,
+    scheme::{ItemRedefinitionError, Scheme},
+    types::{GetType, Type},
+};
+use failure::Fail;
+
+/// A problem with registering fields of a protocol found by
+/// [`check_protocol_fields`].
+#[derive(Debug, PartialEq, Fail)]
+pub enum ProtocolFieldsError {
+    /// Registering the fields next to other ones failed.
+    #[fail(display = "{}", _0)]
+    Redefinition(#[cause] ItemRedefinitionError),
+
+    /// A scheme got other fields than the declared ones.
+    #[fail(display = "expected fields {:?}, got {:?}", expected, actual)]
+    Mismatch {
+        /// Declared names and types of fields.
+        expected: Vec<(String, Type)>,
+        /// Names and types of fields the scheme got.
+        actual: Vec<(String, Type)>,
+    },
+
+    /// Registering the fields once more in the same scheme succeeded.
+    #[fail(display = "fields were registered twice")]
+    RegisteredTwice,
+}
+
+/// Checks how a crate for a protocol, like `wirefilter-scheme-http`,
+/// registers its fields.
+///
+/// `add_fields` has to register exactly the declared `fields`, in order,
+/// into a scheme that already has fields of other protocols, and fail when
+/// called twice on the same scheme instead of redefining them. `scheme`,
+/// e.g. the one the crate creates on its own, has to have the same fields.
+pub fn check_protocol_fields(
+    fields: &[(&str, Type)],
+    add_fields: impl Fn(&mut Scheme) -> Result<(), ItemRedefinitionError>,
+    scheme: &Scheme,
+) -> Result<(), ProtocolFieldsError> {
+    let expected = fields
+        .iter()
+        .map(|&(name, ty)| (name.to_owned(), ty))
+        .collect::<Vec<_>>();
+    let check = |scheme: &Scheme, skip: usize| {
+        let actual = scheme
+            .iter()
+            .skip(skip)
+            .map(|field| (field.name().to_owned(), field.get_type()))
+            .collect::<Vec<_>>();
+        if actual == expected {
+            Ok(())
+        } else {
+            Err(ProtocolFieldsError::Mismatch {
+                expected: expected.clone(),
+                actual,
+            })
+        }
+    };
+
+    check(scheme, 0)?;
+
+    let mut combined = Scheme::new();
+    combined
+        .add_field("other.protocol".to_owned(), Type::Bool)
+        .map_err(ProtocolFieldsError::Redefinition)?;
+    add_fields(&mut combined).map_err(ProtocolFieldsError::Redefinition)?;
+    check(&combined, 1)?;
+
+    match add_fields(&mut combined) {
+        Ok(()) => Err(ProtocolFieldsError::RegisteredTwice),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Returns names of fields without a value in a context, e.g. to check
+/// that values extracted from a request cover the whole scheme.
+///
+/// A [multi-valued](::Scheme::set_field_multi_valued) field without any
+/// values is returned as well.
+pub fn unset_fields<'e>(ctx: &ExecutionContext<'e>) -> Vec<&'e str> {
+    ctx.scheme()
+        .fields()
+        .filter(|field| !ctx.has_field_value(*field))
+        .map(|field| field.name())
+        .collect()
+}
+
+#[test]
+fn test_check_protocol_fields() {
+    const FIELDS: &[(&str, Type)] = &[("a.b", Type::Int), ("a.c", Type::Bytes)];
+
+    fn add_fields(scheme: &mut Scheme) -> Result<(), ItemRedefinitionError> {
+        for &(name, ty) in FIELDS {
+            scheme.add_field(name.to_owned(), ty)?;
+        }
+        Ok(())
+    }
+
+    let mut scheme = Scheme::new();
+    add_fields(&mut scheme).unwrap();
+    assert_eq!(check_protocol_fields(FIELDS, add_fields, &scheme), Ok(()));
+
+    assert_eq!(
+        check_protocol_fields(&FIELDS[..1], add_fields, &scheme),
+        Err(ProtocolFieldsError::Mismatch {
+            expected: vec![("a.b".to_owned(), Type::Int)],
+            actual: vec![
+                ("a.b".to_owned(), Type::Int),
+                ("a.c".to_owned(), Type::Bytes)
+            ],
+        })
+    );
+
+    // Redefinitions have to be reported.
+    let add_fields_leniently = |scheme: &mut Scheme| {
+        let _ = add_fields(scheme);
+        Ok(())
+    };
+    assert_eq!(
+        check_protocol_fields(FIELDS, add_fields_leniently, &scheme),
+        Err(ProtocolFieldsError::RegisteredTwice)
+    );
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("a.b", 1).unwrap();
+    assert_eq!(unset_fields(&ctx), ["a.c"]);
+    ctx.set_field_value("a.c", "").unwrap();
+    assert!(unset_fields(&ctx).is_empty());
+}
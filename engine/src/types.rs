@@ -1,10 +1,11 @@
 use crate::{
-    lex::{expect, skip_space, Lex, LexResult, LexWith},
-    rhs_types::{Bytes, IpRange, UninhabitedBool},
+    lex::{expect, skip_space, Lex, LexErrorKind, LexResult, LexWith},
+    rhs_types::{Bytes, Float, IpRange, Timestamp, TimestampLiteral, UninhabitedBool},
     strict_partial_ord::StrictPartialOrd,
 };
 use failure::Fail;
 use serde::{Deserialize, Serialize};
+use smallvec::SmallVec;
 use std::{
     borrow::Cow,
     cmp::Ordering,
@@ -12,26 +13,58 @@ use std::{
     fmt::{self, Debug, Formatter},
     net::IpAddr,
     ops::RangeInclusive,
+    time::SystemTime,
 };
 
-fn lex_rhs_values<'i, T: Lex<'i>>(input: &'i str) -> LexResult<'i, Vec<T>> {
-    let mut input = expect(input, "{")?;
-    let mut res = Vec::new();
+// Skips whitespace and `#` comments till the end of line, which are allowed
+// between elements of generated lists.
+fn skip_list_space(mut input: &str) -> &str {
     loop {
         input = skip_space(input);
+        if !input.starts_with('#') {
+            return input;
+        }
+        input = input.find('\n').map_or("", |pos| &input[pos..]);
+    }
+}
+
+// Most lists in filters have only a few elements, so those are stored inline
+// to avoid allocating while parsing.
+pub(crate) type RhsList<T> = SmallVec<[T; 4]>;
+
+fn lex_rhs_values<'i, T: Lex<'i>>(
+    input: &'i str,
+    max_elements: Option<usize>,
+) -> LexResult<'i, RhsList<T>> {
+    let mut input = expect(input, "{")?;
+    let mut res = RhsList::new();
+    loop {
+        input = skip_list_space(input);
         if let Ok(rest) = expect(input, "}") {
             input = rest;
             return Ok((res, input));
         } else {
-            let (item, rest) = T::lex(input)?;
+            // Bail out before lexing the rest of an oversized list.
+            if let Some(max) = max_elements.filter(|max| res.len() >= *max) {
+                return Err((LexErrorKind::TooManyListElements { max }, input));
+            }
+            let (item, rest) = T::lex(input).map_err(|(kind, span)| {
+                let kind = LexErrorKind::InvalidListElement {
+                    position: res.len() + 1,
+                    kind: Box::new(kind),
+                };
+                (kind, span)
+            })?;
             res.push(item);
-            input = rest;
+            // Elements can be optionally separated with commas.
+            input = skip_list_space(rest);
+            input = expect(input, ",").unwrap_or(input);
         }
     }
 }
 
 /// An error that occurs on a type mismatch.
-#[derive(Debug, PartialEq, Fail)]
+#[derive(Debug, PartialEq, Clone, Fail)]
 #[fail(
     display = "expected value of type {:?}, but got {:?}",
     expected, actual
@@ -70,7 +103,7 @@ macro_rules! declare_types {
 
     ($($(# $attrs:tt)* $name:ident ( $(# $lhs_attrs:tt)* $lhs_ty:ty | $rhs_ty:ty | $multi_rhs_ty:ty ) , )*) => {
         /// Enumeration of supported types for field values.
-        #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
         #[repr(C)]
         pub enum Type {
             $($(# $attrs)* $name,)*
@@ -168,15 +201,34 @@ macro_rules! declare_types {
             #[derive(PartialEq, Eq, Clone, Serialize)]
             #[serde(untagged)]
             enum RhsValues {
-                $($(# $attrs)* $name(Vec<$multi_rhs_ty>),)*
+                $($(# $attrs)* $name(RhsList<$multi_rhs_ty>),)*
             }
         }
 
         impl<'i> LexWith<'i, Type> for RhsValues {
             fn lex_with(input: &str, ty: Type) -> LexResult<'_, Self> {
+                RhsValues::lex_with_limit(input, ty, None)
+            }
+        }
+
+        impl RhsValues {
+            /// Returns the number of elements in a list.
+            pub(crate) fn len(&self) -> usize {
+                match self {
+                    $(RhsValues::$name(values) => values.len(),)*
+                }
+            }
+
+            /// Lexes a list, failing as soon as it has more than a given
+            /// number of elements.
+            pub(crate) fn lex_with_limit(
+                input: &str,
+                ty: Type,
+                max_elements: Option<usize>,
+            ) -> LexResult<'_, Self> {
                 Ok(match ty {
                     $(Type::$name => {
-                        let (value, input) = lex_rhs_values(input)?;
+                        let (value, input) = lex_rhs_values(input, max_elements)?;
                         (RhsValues::$name(value), input)
                     })*
                 })
@@ -192,6 +244,27 @@ impl<'a> From<&'a [u8]> for LhsValue<'a> {
     }
 }
 
+// special cases for passing narrower integers, which are common in
+// protocols and used to be the only supported integers
+impl<'a> From<i32> for LhsValue<'a> {
+    fn from(value: i32) -> Self {
+        LhsValue::Int(value.into())
+    }
+}
+
+impl<'a> From<u32> for LhsValue<'a> {
+    fn from(value: u32) -> Self {
+        LhsValue::Int(value.into())
+    }
+}
+
+// special case for passing system times
+impl<'a> From<SystemTime> for LhsValue<'a> {
+    fn from(time: SystemTime) -> Self {
+        LhsValue::Timestamp(time.into())
+    }
+}
+
 // special case for simply passing strings
 impl<'a> From<&'a str> for LhsValue<'a> {
     fn from(s: &'a str) -> Self {
@@ -205,11 +278,31 @@ impl<'a> From<&'a RhsValue> for LhsValue<'a> {
             RhsValue::Ip(ip) => LhsValue::Ip(*ip),
             RhsValue::Bytes(bytes) => LhsValue::Bytes(Cow::Borrowed(bytes)),
             RhsValue::Int(integer) => LhsValue::Int(*integer),
+            RhsValue::Float(float) => LhsValue::Float(*float),
+            RhsValue::Timestamp(timestamp) => LhsValue::Timestamp(timestamp.resolve()),
             RhsValue::Bool(b) => match *b {},
         }
     }
 }
 
+// Comparisons between two LHS values, used when both sides of a comparison
+// are computed at runtime.
+impl<'a> PartialOrd for LhsValue<'a> {
+    fn partial_cmp(&self, other: &LhsValue<'a>) -> Option<Ordering> {
+        match (self, other) {
+            (LhsValue::Ip(lhs), LhsValue::Ip(rhs)) => lhs.strict_partial_cmp(rhs),
+            (LhsValue::Bytes(lhs), LhsValue::Bytes(rhs)) => lhs[..].strict_partial_cmp(&rhs[..]),
+            (LhsValue::Int(lhs), LhsValue::Int(rhs)) => lhs.strict_partial_cmp(rhs),
+            (LhsValue::Float(lhs), LhsValue::Float(rhs)) => lhs.strict_partial_cmp(rhs),
+            (LhsValue::Timestamp(lhs), LhsValue::Timestamp(rhs)) => lhs.strict_partial_cmp(rhs),
+            (LhsValue::Bool(lhs), LhsValue::Bool(rhs)) => lhs.partial_cmp(rhs),
+            _ => None,
+        }
+    }
+}
+
+impl<'a> StrictPartialOrd for LhsValue<'a> {}
+
 impl<'a> LhsValue<'a> {
     /// Converts a reference to an LhsValue to an LhsValue with an internal
     /// references
@@ -219,6 +312,20 @@ impl<'a> LhsValue<'a> {
             LhsValue::Bytes(bytes) => LhsValue::Bytes(Cow::Borrowed(bytes)),
             LhsValue::Int(integer) => LhsValue::Int(*integer),
             LhsValue::Bool(b) => LhsValue::Bool(*b),
+            LhsValue::Float(float) => LhsValue::Float(*float),
+            LhsValue::Timestamp(timestamp) => LhsValue::Timestamp(*timestamp),
+        }
+    }
+
+    /// Converts an LhsValue with internal references into an owned one.
+    pub(crate) fn into_owned(self) -> LhsValue<'static> {
+        match self {
+            LhsValue::Ip(ip) => LhsValue::Ip(ip),
+            LhsValue::Bytes(bytes) => LhsValue::Bytes(Cow::Owned(bytes.into_owned())),
+            LhsValue::Int(integer) => LhsValue::Int(integer),
+            LhsValue::Bool(b) => LhsValue::Bool(b),
+            LhsValue::Float(float) => LhsValue::Float(float),
+            LhsValue::Timestamp(timestamp) => LhsValue::Timestamp(timestamp),
         }
     }
 }
@@ -235,11 +342,22 @@ declare_types!(
     /// syntax representation, so we represent them as a single type.
     Bytes(#[serde(borrow)] Cow<'a, [u8]> | Bytes | Bytes),
 
-    /// A 32-bit integer number.
-    Int(i32 | i32 | RangeInclusive<i32>),
+    /// A 64-bit signed integer number.
+    ///
+    /// This covers all unsigned 32-bit values too, like AS numbers, and
+    /// sizes or durations in nanoseconds. Unsigned 64-bit values above
+    /// `i64::MAX` have to be clamped or reinterpreted by the caller.
+    Int(i64 | i64 | RangeInclusive<i64>),
 
     /// A boolean.
     Bool(bool | UninhabitedBool | UninhabitedBool),
+
+    /// A 64-bit floating point number, never NaN.
+    Float(Float | Float | RangeInclusive<Float>),
+
+    /// A point in time, compared against absolute timestamps or ones
+    /// relative to the time of execution.
+    Timestamp(Timestamp | TimestampLiteral | RangeInclusive<Timestamp>),
 );
 
 #[test]
@@ -275,3 +393,58 @@ fn test_lhs_value_deserialize() {
     let b: LhsValue<'_> = serde_json::from_str("false").unwrap();
     assert_eq!(b, LhsValue::Bool(false));
 }
+
+#[test]
+fn test_lex_rhs_values() {
+    use indoc::indoc;
+    use smallvec::smallvec;
+
+    let input = indoc!(
+        r#"
+        {
+            # Generated list.
+            1, 2 # Second element.
+            3..5,
+
+            # Trailing separators are fine.
+            8,
+        }
+        "#
+    );
+
+    assert_eq!(
+        RhsValues::lex_with(input, Type::Int),
+        Ok((RhsValues::Int(smallvec![1..=1, 2..=2, 3..=5, 8..=8]), "\n"))
+    );
+
+    assert_eq!(
+        RhsValues::lex_with("{ 1, 2, x, 3 }", Type::Int),
+        Err((
+            LexErrorKind::InvalidListElement {
+                position: 3,
+                kind: Box::new(LexErrorKind::ExpectedName("digit")),
+            },
+            "x, 3 }"
+        ))
+    );
+
+    assert!(RhsValues::lex_with("{ 1,, 2 }", Type::Int).is_err());
+
+    // Errors point at the failing element and count elements from 1.
+    let scheme = Scheme! { port: Int };
+    let err = scheme.parse("port in { 1, 2, x, 3 }").unwrap_err();
+    assert_eq!(err.span(), 16..22);
+    assert!(err
+        .to_string()
+        .contains("invalid element #3 of the list: expected digit"));
+
+    assert_eq!(
+        RhsValues::lex_with_limit("{ 1 2 }", Type::Int, Some(2)),
+        Ok((RhsValues::Int(smallvec![1..=1, 2..=2]), ""))
+    );
+
+    assert_eq!(
+        RhsValues::lex_with_limit("{ 1 2 3 4 }", Type::Int, Some(2)),
+        Err((LexErrorKind::TooManyListElements { max: 2 }, "3 4 }"))
+    );
+}
@@ -0,0 +1,742 @@
+use crate::{
+    ast::{CachedCalls, CallCache, FilterAst, PreparedComparison},
+    execution_context::ExecutionContext,
+    filter::SchemeMismatchError,
+    scheme::Scheme,
+};
+use fnv::FnvHashMap;
+use std::{
+    cell::OnceCell,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// A single instruction of a [`BytecodeFilter`].
+///
+/// Instructions operate on a boolean accumulator, plus a stack of operands
+/// of `^^` which can't be short-circuited.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub(crate) enum Instr {
+    /// Sets the accumulator to the result of a comparison with a given
+    /// index.
+    Compare(u32),
+    /// Sets the accumulator to a constant.
+    Set(bool),
+    /// Negates the accumulator.
+    Not,
+    /// Jumps to a given instruction if the accumulator is false.
+    JumpIfFalse(u32),
+    /// Jumps to a given instruction if the accumulator is true.
+    JumpIfTrue(u32),
+    /// Pushes the accumulator onto the stack.
+    Push,
+    /// Pops a value from the stack and XORs it into the accumulator.
+    Xor,
+}
+
+/// A flat list of instructions and the comparisons they refer to.
+#[derive(Default)]
+pub(crate) struct Program<'s> {
+    instructions: Vec<Instr>,
+    comparisons: Vec<PreparedComparison<'s>>,
+    // Indices of comparisons by their filter strings, if identical ones are
+    // evaluated only once per execution, see `FilterSet`.
+    shared: Option<FnvHashMap<String, u32>>,
+}
+
+impl<'s> Program<'s> {
+    /// Creates a program in which identical comparisons are shared.
+    pub fn with_shared_comparisons() -> Self {
+        Program {
+            shared: Some(FnvHashMap::default()),
+            ..Program::default()
+        }
+    }
+
+    /// Checks whether comparisons should be appended with
+    /// [`compare_shared`](Program::compare_shared).
+    pub fn shares_comparisons(&self) -> bool {
+        self.shared.is_some()
+    }
+
+    /// Appends an instruction and returns its position.
+    pub fn push(&mut self, instr: Instr) -> usize {
+        self.instructions.push(instr);
+        self.instructions.len() - 1
+    }
+
+    /// Appends an instruction evaluating a given comparison.
+    pub fn compare(&mut self, comparison: PreparedComparison<'s>) {
+        self.comparisons.push(comparison);
+        self.push(Instr::Compare(self.comparisons.len() as u32 - 1));
+    }
+
+    /// Appends an instruction evaluating a comparison with a given filter
+    /// string, preparing it only if it's not in the program yet.
+    pub fn compare_shared(
+        &mut self,
+        key: String,
+        prepare: impl FnOnce() -> PreparedComparison<'s>,
+    ) {
+        let comparisons = &mut self.comparisons;
+        let index = *self
+            .shared
+            .as_mut()
+            .expect("program doesn't share comparisons")
+            .entry(key)
+            .or_insert_with(|| {
+                comparisons.push(prepare());
+                comparisons.len() as u32 - 1
+            });
+        self.push(Instr::Compare(index));
+    }
+
+    /// Removes instructions appended so far, keeping the comparisons, so
+    /// that the next filter can be emitted from the start.
+    pub fn take_instructions(&mut self) -> Box<[Instr]> {
+        mem::take(&mut self.instructions).into()
+    }
+
+    fn evaluate(&self, index: u32, ctx: &ExecutionContext<'s>, scratch: &Scratch<'_>) -> bool {
+        let comparison = &self.comparisons[index as usize];
+        match scratch.comparisons.get(index as usize) {
+            Some(result) => *result.get_or_init(|| comparison.execute(ctx, &scratch.calls)),
+            None => comparison.execute(ctx, &scratch.calls),
+        }
+    }
+
+    /// Points a jump at a given position to the next instruction to be
+    /// appended.
+    pub fn patch_jump(&mut self, at: usize) {
+        let next = self.instructions.len() as u32;
+        match &mut self.instructions[at] {
+            Instr::JumpIfFalse(target) | Instr::JumpIfTrue(target) => *target = next,
+            instr => panic!("{:?} is not a jump", instr),
+        }
+    }
+
+    fn execute(&self, ctx: &ExecutionContext<'s>, scratch: &Scratch<'_>) -> bool {
+        let mut state = State::default();
+        state.run(self, &self.instructions, ctx, scratch, usize::MAX);
+        state.acc
+    }
+}
+
+/// Values computed during a single execution of a program.
+///
+/// It's owned by the execution rather than by the context, so that
+/// executions sharing a context, including suspended ones, don't see each
+/// other's values.
+struct Scratch<'a> {
+    calls: CallCache<'a>,
+    // Results of comparisons shared between filters, see `FilterSet`.
+    comparisons: Box<[OnceCell<bool>]>,
+}
+
+impl<'a> Scratch<'a> {
+    fn new(
+        program: &Program<'_>,
+        calls: &'a CachedCalls<'a>,
+        ctx: &'a ExecutionContext<'a>,
+    ) -> Self {
+        let comparisons = if program.shares_comparisons() {
+            program.comparisons.len()
+        } else {
+            0
+        };
+
+        Scratch {
+            calls: CallCache::new(calls, ctx),
+            comparisons: (0..comparisons).map(|_| OnceCell::new()).collect(),
+        }
+    }
+}
+
+/// Registers of the interpreter, kept apart from the program so that an
+/// execution can be suspended and resumed.
+#[derive(Default)]
+struct State {
+    acc: bool,
+    pc: usize,
+
+    // The stack is only as deep as nested `^^`s, so it's kept in bits of an
+    // integer and spills onto the heap only in pathological cases.
+    stack: u64,
+    depth: usize,
+    spilled: Vec<bool>,
+}
+
+impl State {
+    /// Runs instructions until the end of the program or until a given
+    /// number of comparisons has been evaluated, and returns whether the
+    /// program has finished.
+    fn run<'s>(
+        &mut self,
+        program: &Program<'s>,
+        instructions: &[Instr],
+        ctx: &ExecutionContext<'s>,
+        scratch: &Scratch<'_>,
+        mut budget: usize,
+    ) -> bool {
+        while let Some(instr) = instructions.get(self.pc) {
+            match *instr {
+                Instr::Compare(_) if budget == 0 => return false,
+                Instr::Compare(index) => {
+                    budget -= 1;
+                    self.acc = program.evaluate(index, ctx, scratch);
+                }
+                Instr::Set(value) => self.acc = value,
+                Instr::Not => self.acc = !self.acc,
+                Instr::JumpIfFalse(target) if !self.acc => {
+                    self.pc = target as usize;
+                    continue;
+                }
+                Instr::JumpIfTrue(target) if self.acc => {
+                    self.pc = target as usize;
+                    continue;
+                }
+                Instr::JumpIfFalse(_) | Instr::JumpIfTrue(_) => {}
+                Instr::Push => {
+                    if self.depth >= 64 {
+                        self.spilled.push(self.stack >> 63 != 0);
+                    }
+                    self.stack = self.stack << 1 | u64::from(self.acc);
+                    self.depth += 1;
+                }
+                Instr::Xor => {
+                    self.acc ^= self.stack & 1 != 0;
+                    self.stack >>= 1;
+                    self.depth -= 1;
+                    if self.depth >= 64 {
+                        self.stack |= u64::from(self.spilled.pop().unwrap()) << 63;
+                    }
+                }
+            }
+            self.pc += 1;
+        }
+
+        true
+    }
+}
+
+/// An execution of a filter that can be suspended between comparisons, see
+/// [`BytecodeFilter::execute_yielding`] and
+/// [`Filter::execute_yielding`](::Filter::execute_yielding).
+///
+/// It can be driven either by calling [`resume`](YieldingExecution::resume)
+/// until it returns a result, or by awaiting it as a future, which yields
+/// to the executor after every slice of comparisons.
+pub struct YieldingExecution<'a, 's> {
+    program: &'a Program<'s>,
+    ctx: &'a ExecutionContext<'s>,
+    yield_every: usize,
+    state: State,
+    scratch: Scratch<'a>,
+    finished: bool,
+}
+
+impl<'a, 's> YieldingExecution<'a, 's> {
+    /// Evaluates up to the configured number of comparisons, and returns
+    /// the result of the filter if it has finished.
+    pub fn resume(&mut self) -> Option<bool> {
+        if !self.finished {
+            self.finished = self.state.run(
+                self.program,
+                &self.program.instructions,
+                self.ctx,
+                &self.scratch,
+                self.yield_every,
+            );
+        }
+        if self.finished {
+            Some(self.state.acc)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'a, 's> Future for YieldingExecution<'a, 's> {
+    type Output = bool;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<bool> {
+        match self.get_mut().resume() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                // There is nothing to wait for, so ask to be polled again
+                // as soon as other tasks had their turn.
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<'a, 's> Debug for YieldingExecution<'a, 's> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("YieldingExecution")
+            .field("pc", &self.state.pc)
+            .field("finished", &self.finished)
+            .finish()
+    }
+}
+
+/// A filter compiled into bytecode with
+/// [`FilterAst::compile_to_bytecode`](::FilterAst::compile_to_bytecode).
+///
+/// Unlike a [`Filter`](::Filter), which is a tree of boxed closures, it's a
+/// flat list of instructions and a table of comparisons executed by a small
+/// interpreter. This takes fewer allocations to compile and keeps the whole
+/// filter close together in memory, which matters when many filters are
+/// compiled at once.
+///
+/// It gives the same results as a [`Filter`](::Filter), but doesn't merge
+/// `contains` and `matches` comparisons into regex sets.
+pub struct BytecodeFilter<'s> {
+    program: Program<'s>,
+    scheme: &'s Scheme,
+    cached_calls: CachedCalls<'s>,
+}
+
+impl<'s> BytecodeFilter<'s> {
+    pub(crate) fn new(
+        program: Program<'s>,
+        scheme: &'s Scheme,
+        cached_calls: CachedCalls<'s>,
+    ) -> Self {
+        BytecodeFilter {
+            program,
+            scheme,
+            cached_calls,
+        }
+    }
+
+    /// Executes a filter against a provided context with values.
+    pub fn execute(&self, ctx: &ExecutionContext<'s>) -> Result<bool, SchemeMismatchError> {
+        if self.scheme == ctx.scheme() {
+            ctx.clear_matched_spans();
+            let scratch = Scratch::new(&self.program, &self.cached_calls, ctx);
+            Ok(self.program.execute(ctx, &scratch))
+        } else {
+            Err(SchemeMismatchError::new(self.scheme, ctx.scheme()))
+        }
+    }
+
+    /// Starts an execution that can be suspended after every
+    /// `yield_every` comparisons, so that evaluating a very large filter
+    /// doesn't block an async executor for too long.
+    ///
+    /// The context stays borrowed until the execution is finished or
+    /// dropped, so values can't change in the middle of it. Other
+    /// executions can still use the context in the meantime, since each
+    /// of them caches values of function calls on its own, but only spans
+    /// of the latest one are kept for
+    /// [`matched_spans`](::ExecutionContext::matched_spans). A
+    /// [`Filter`](::Filter) can be executed the same way with
+    /// [`Filter::execute_yielding`](::Filter::execute_yielding).
+    pub fn execute_yielding<'a>(
+        &'a self,
+        ctx: &'a ExecutionContext<'s>,
+        yield_every: usize,
+    ) -> Result<YieldingExecution<'a, 's>, SchemeMismatchError> {
+        if self.scheme == ctx.scheme() {
+            ctx.clear_matched_spans();
+            Ok(YieldingExecution {
+                program: &self.program,
+                ctx,
+                yield_every: yield_every.max(1),
+                state: State::default(),
+                scratch: Scratch::new(&self.program, &self.cached_calls, ctx),
+                finished: false,
+            })
+        } else {
+            Err(SchemeMismatchError::new(self.scheme, ctx.scheme()))
+        }
+    }
+}
+
+impl<'s> Debug for BytecodeFilter<'s> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(&self.program.instructions).finish()
+    }
+}
+
+/// Many filters, like rules of a firewall, compiled together so that they
+/// can be matched against a context in one pass.
+///
+/// Each filter is identified by a key of any type, e.g. a rule name as
+/// returned by [`Scheme::parse_rules`](::Scheme::parse_rules). Filters are
+/// compiled into bytecode with a common table of comparisons, and identical
+/// comparisons, like `ip.src in $blocked` shared by many rules, are
+/// evaluated at most once per execution.
+pub struct FilterSet<'s, K> {
+    program: Program<'s>,
+    filters: Vec<(K, Box<[Instr]>)>,
+    scheme: &'s Scheme,
+    cached_calls: CachedCalls<'s>,
+}
+
+impl<'s, K> FilterSet<'s, K> {
+    /// Compiles filters parsed with a given scheme into a set.
+    pub fn new(
+        scheme: &'s Scheme,
+        filters: impl IntoIterator<Item = (K, FilterAst<'s>)>,
+    ) -> Result<Self, SchemeMismatchError> {
+        let (keys, asts): (Vec<_>, Vec<_>) = filters.into_iter().unzip();
+
+        if let Some(ast) = asts.iter().find(|ast| ast.scheme() != scheme) {
+            return Err(SchemeMismatchError::new(scheme, ast.scheme()));
+        }
+
+        let mut program = Program::with_shared_comparisons();
+        let (code, cached_calls) = FilterAst::emit_all(asts, &mut program);
+
+        Ok(FilterSet {
+            program,
+            filters: keys.into_iter().zip(code).collect(),
+            scheme,
+            cached_calls,
+        })
+    }
+
+    /// Returns the number of filters in the set.
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Checks whether the set has no filters.
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Returns the number of distinct comparisons across all filters.
+    pub fn comparison_count(&self) -> usize {
+        self.program.comparisons.len()
+    }
+
+    /// Executes all filters against a provided context and returns keys of
+    /// those that match, in the order the filters were given.
+    pub fn execute(&self, ctx: &ExecutionContext<'s>) -> Result<Vec<&K>, SchemeMismatchError> {
+        if self.scheme != ctx.scheme() {
+            return Err(SchemeMismatchError::new(self.scheme, ctx.scheme()));
+        }
+
+        ctx.clear_matched_spans();
+        let scratch = Scratch::new(&self.program, &self.cached_calls, ctx);
+
+        Ok(self
+            .filters
+            .iter()
+            .filter(|(_, code)| {
+                let mut state = State::default();
+                state.run(&self.program, code, ctx, &scratch, usize::MAX);
+                state.acc
+            })
+            .map(|(key, _)| key)
+            .collect())
+    }
+}
+
+impl<'s, K: Debug> Debug for FilterSet<'s, K> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_map()
+            .entries(self.filters.iter().map(|(key, code)| (key, code)))
+            .finish()
+    }
+}
+
+#[test]
+fn test_bytecode() {
+    let scheme = &Scheme! { a: Bool, b: Bool, c: Bool, port: Int };
+
+    let compile = |filter: &str| scheme.parse(filter).unwrap().compile_to_bytecode();
+
+    assert_eq!(
+        compile("a && !(b || c)").program.instructions,
+        [
+            Instr::Compare(0),
+            Instr::JumpIfFalse(6),
+            Instr::Compare(1),
+            Instr::JumpIfTrue(5),
+            Instr::Compare(2),
+            Instr::Not,
+        ]
+    );
+
+    assert_eq!(
+        compile("a ^^ b ^^ c").program.instructions,
+        [
+            Instr::Compare(0),
+            Instr::Push,
+            Instr::Compare(1),
+            Instr::Xor,
+            Instr::Push,
+            Instr::Compare(2),
+            Instr::Xor,
+        ]
+    );
+
+    let filters = [
+        "a and (b or c)",
+        "not (a and b) or c and port > 1024",
+        "a xor b xor (c xor port in { 80 443 })",
+        "not (a xor (b and not c)) and (port & 1 or a)",
+    ];
+
+    for filter in &filters {
+        let ast = scheme.parse(filter).unwrap();
+        let bytecode = ast.clone().compile_to_bytecode();
+        let closures = ast.compile();
+
+        for bits in 0..16 {
+            let mut ctx = ExecutionContext::new(scheme);
+            for (i, name) in ["a", "b", "c"].iter().enumerate() {
+                ctx.set_field_value(name, bits & (1 << i) != 0).unwrap();
+            }
+            ctx.set_field_value("port", if bits & 8 != 0 { 443 } else { 8080 })
+                .unwrap();
+
+            assert_eq!(
+                bytecode.execute(&ctx),
+                closures.execute(&ctx),
+                "{} with {:04b}",
+                filter,
+                bits
+            );
+        }
+    }
+
+    // Deeply nested `^^`s spill the stack onto the heap.
+    let mut filter = "a".to_owned();
+    for _ in 0..100 {
+        filter = format!("b ^^ ({})", filter);
+    }
+    let bytecode = scheme.parse(&filter).unwrap().compile_to_bytecode();
+    let mut ctx = ExecutionContext::new(scheme);
+    ctx.set_field_value("a", true).unwrap();
+    ctx.set_field_value("b", true).unwrap();
+    assert_eq!(bytecode.execute(&ctx), Ok(true));
+
+    let other_scheme = Scheme! { a: Bool };
+    assert_eq!(
+        bytecode.execute(&ExecutionContext::new(&other_scheme)),
+        Err(SchemeMismatchError::new(scheme, &other_scheme))
+    );
+    assert!(bytecode
+        .execute_yielding(&ExecutionContext::new(&other_scheme), 1)
+        .is_err());
+}
+
+#[test]
+fn test_execute_yielding() {
+    use std::task::{RawWaker, RawWakerVTable, Waker};
+
+    let scheme = &Scheme! { a: Bool, b: Bool, port: Int };
+    let bytecode = scheme
+        .parse("(a || port == 80) && (b ^^ port > 1024) && !b")
+        .unwrap()
+        .compile_to_bytecode();
+
+    let mut ctx = ExecutionContext::new(scheme);
+    ctx.set_field_value("a", true).unwrap();
+    ctx.set_field_value("b", false).unwrap();
+    ctx.set_field_value("port", 8080).unwrap();
+
+    // `port == 80` is skipped, so there are four comparisons to evaluate.
+    let mut execution = bytecode.execute_yielding(&ctx, 2).unwrap();
+    assert_eq!(execution.resume(), None);
+    assert_eq!(execution.resume(), Some(true));
+    // Finished executions keep their result.
+    assert_eq!(execution.resume(), Some(true));
+
+    let mut execution = bytecode.execute_yielding(&ctx, 100).unwrap();
+    assert_eq!(execution.resume(), Some(true));
+
+    // A budget of zero still makes progress.
+    let mut execution = bytecode.execute_yielding(&ctx, 0).unwrap();
+    let mut resumes = 1;
+    while execution.resume().is_none() {
+        resumes += 1;
+    }
+    assert_eq!(resumes, 4);
+
+    // As a future, it asks to be polled again until it's finished.
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(std::ptr::null(), &VTABLE)
+    }
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut cx = Context::from_waker(&waker);
+
+    ctx.set_field_value("port", 80).unwrap();
+    let mut execution = bytecode.execute_yielding(&ctx, 1).unwrap();
+    let mut polls = 0;
+    let result = loop {
+        polls += 1;
+        if let Poll::Ready(result) = Pin::new(&mut execution).poll(&mut cx) {
+            break result;
+        }
+    };
+    assert_eq!(result, bytecode.execute(&ctx).unwrap());
+    assert!(!result);
+    assert_eq!(polls, 3);
+}
+
+#[test]
+fn test_interleaved_yielding_executions() {
+    use crate::{
+        execution_context::CallCacheStats,
+        functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+        types::{LhsValue, Type},
+    };
+
+    fn echo<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+        args.next().unwrap()
+    }
+
+    let mut scheme = Scheme! { a: Bytes, b: Bytes };
+    scheme
+        .add_function(
+            "echo".into(),
+            Function {
+                params: vec![FunctionParam {
+                    arg_kind: FunctionArgKind::Field,
+                    val_type: Type::Bytes,
+                }],
+                opt_params: vec![],
+                return_type: Type::Bytes,
+                implementation: FunctionImpl::new(echo),
+                pure: true,
+            },
+        )
+        .unwrap();
+
+    let compile = |filter: &str| scheme.parse(filter).unwrap().compile_to_bytecode();
+    let first = compile(r#"echo(a) == "x" || echo(a) == "y""#);
+    let second = compile(r#"echo(b) == "z" || echo(b) == "q""#);
+    let uncached = compile(r#"a == "y""#);
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    ctx.set_field_value("a", "y").unwrap();
+    ctx.set_field_value("b", "q").unwrap();
+
+    // Both executions are suspended after their first call is cached, and
+    // other executions in between don't touch their caches.
+    let mut first = first.execute_yielding(&ctx, 1).unwrap();
+    assert_eq!(first.resume(), None);
+    let mut second = second.execute_yielding(&ctx, 1).unwrap();
+    assert_eq!(second.resume(), None);
+    assert_eq!(uncached.execute(&ctx), Ok(true));
+
+    assert_eq!(first.resume(), Some(true));
+    assert_eq!(second.resume(), Some(true));
+    assert_eq!(
+        ctx.call_cache_stats(),
+        CallCacheStats { hits: 2, misses: 2 }
+    );
+}
+
+#[test]
+fn test_filter_set() {
+    use crate::{
+        functions::{Function, FunctionArgKind, FunctionArgs, FunctionImpl, FunctionParam},
+        types::{LhsValue, Type},
+    };
+    use std::cell::Cell;
+
+    thread_local! {
+        static CALLS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    fn probe<'a>(args: FunctionArgs<'_, 'a>) -> LhsValue<'a> {
+        CALLS.with(|calls| calls.set(calls.get() + 1));
+        args.next().unwrap()
+    }
+
+    let mut scheme = Scheme! { ip.src: Ip, port: Int, host: Bytes, ssl: Bool };
+    scheme
+        .add_function(
+            "probe".into(),
+            Function {
+                params: vec![FunctionParam {
+                    arg_kind: FunctionArgKind::Field,
+                    val_type: Type::Bytes,
+                }],
+                opt_params: vec![],
+                return_type: Type::Bytes,
+                implementation: FunctionImpl::new(probe),
+                pure: false,
+            },
+        )
+        .unwrap();
+    let scheme = &scheme;
+
+    let rules = scheme
+        .parse_rules(
+            r#"
+internal_http: ip.src in { 10.0.0.0/8 } and port == 80
+internal_tls: ip.src in { 10.0.0.0/8 } and ssl
+plain: not ssl
+probed: probe(host) == "example.org" || port == 80
+not_probed: !(probe(host) == "example.org")
+"#,
+        )
+        .unwrap();
+
+    let set = FilterSet::new(scheme, rules.clone()).unwrap();
+    assert_eq!(set.len(), 5);
+    assert!(!set.is_empty());
+    assert_eq!(set.comparison_count(), 4);
+
+    let filters = rules
+        .into_iter()
+        .map(|(name, ast)| (name, ast.compile()))
+        .collect::<Vec<_>>();
+
+    for &(ip, port, host, ssl) in &[
+        ("10.1.2.3", 80, "example.org", false),
+        ("10.1.2.3", 443, "example.com", true),
+        ("192.168.0.1", 80, "example.org", true),
+    ] {
+        let mut ctx = ExecutionContext::new(scheme);
+        ctx.set_field_value("ip.src", ip.parse::<std::net::IpAddr>().unwrap())
+            .unwrap();
+        ctx.set_field_value("port", port).unwrap();
+        ctx.set_field_value("host", host).unwrap();
+        ctx.set_field_value("ssl", ssl).unwrap();
+
+        let expected = filters
+            .iter()
+            .filter(|(_, filter)| filter.execute(&ctx).unwrap())
+            .map(|(name, _)| name)
+            .collect::<Vec<_>>();
+
+        // The comparison with `probe` is shared by two rules, but is
+        // evaluated only once.
+        CALLS.with(|calls| calls.set(0));
+        assert_eq!(set.execute(&ctx).unwrap(), expected);
+        assert_eq!(CALLS.with(Cell::get), 1);
+    }
+
+    let other_scheme = Scheme! { ssl: Bool };
+    assert_eq!(
+        set.execute(&ExecutionContext::new(&other_scheme))
+            .unwrap_err(),
+        SchemeMismatchError::new(scheme, &other_scheme)
+    );
+    assert!(FilterSet::new(&other_scheme, vec![("ssl", scheme.parse("ssl").unwrap())]).is_err());
+
+    let empty = FilterSet::<()>::new(scheme, vec![]).unwrap();
+    assert!(empty.is_empty());
+    assert_eq!(
+        empty.execute(&ExecutionContext::new(scheme)).unwrap(),
+        Vec::<&()>::new()
+    );
+}
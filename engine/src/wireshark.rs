@@ -0,0 +1,130 @@
+use crate::{scheme::Scheme, types::Type};
+use failure::Fail;
+use std::collections::HashSet;
+
+/// An error that occurs when importing fields from a Wireshark field list.
+#[derive(Debug, PartialEq, Fail)]
+pub enum WiresharkFieldsError {
+    /// A line describing a protocol or a field has too few columns.
+    #[fail(display = "malformed record on line {}", line)]
+    MalformedRecord {
+        /// A 1-based line number.
+        line: usize,
+    },
+
+    /// A requested protocol is not present in the field list.
+    #[fail(display = "unknown protocol {}", _0)]
+    UnknownProtocol(String),
+}
+
+// Maps a Wireshark `FT_*` field type onto one of ours.
+//
+// Protocols and text-less fields (`FT_NONE`) are checked for presence in
+// Wireshark, which is what our boolean fields do.
+fn field_type(ft: &str) -> Option<Type> {
+    Some(match ft {
+        "FT_PROTOCOL" | "FT_NONE" | "FT_BOOLEAN" => Type::Bool,
+        "FT_CHAR" | "FT_FRAMENUM" | "FT_UINT8" | "FT_UINT16" | "FT_UINT24" | "FT_UINT32"
+        | "FT_UINT40" | "FT_UINT48" | "FT_UINT56" | "FT_INT8" | "FT_INT16" | "FT_INT24"
+        | "FT_INT32" | "FT_INT40" | "FT_INT48" | "FT_INT56" | "FT_INT64" => Type::Int,
+        "FT_FLOAT" | "FT_DOUBLE" => Type::Float,
+        "FT_ABSOLUTE_TIME" => Type::Timestamp,
+        "FT_IPv4" | "FT_IPv6" => Type::Ip,
+        "FT_STRING" | "FT_STRINGZ" | "FT_STRINGZPAD" | "FT_STRINGZTRUNC" | "FT_UINT_STRING"
+        | "FT_BYTES" | "FT_UINT_BYTES" | "FT_ETHER" | "FT_EUI64" | "FT_GUID" | "FT_OID"
+        | "FT_REL_OID" | "FT_SYSTEM_ID" | "FT_AX25" | "FT_VINES" | "FT_FCWWN" => Type::Bytes,
+        _ => return None,
+    })
+}
+
+impl Scheme {
+    /// Creates a scheme from a Wireshark field list, as printed by
+    /// `tshark -G fields`, with fields of the given protocols only.
+    ///
+    /// Each protocol becomes a boolean field of its own, so that `tcp`
+    /// works as a filter just like in Wireshark. Fields of types that
+    /// don't have a counterpart here, such as relative times and unsigned
+    /// 64-bit integers, are skipped, as well as repeated registrations of
+    /// the same field name.
+    pub fn from_wireshark_fields<'a>(
+        input: &str,
+        protocols: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self, WiresharkFieldsError> {
+        let mut missing = protocols.into_iter().collect::<HashSet<_>>();
+        let protocols = missing.clone();
+        let mut scheme = Scheme::new();
+
+        for (index, line) in input.lines().enumerate() {
+            let columns = line.split('\t').collect::<Vec<_>>();
+
+            let (name, ft, protocol) = match columns[0] {
+                "P" if columns.len() >= 3 => (columns[2], "FT_PROTOCOL", columns[2]),
+                "F" if columns.len() >= 5 => (columns[2], columns[3], columns[4]),
+                "P" | "F" => return Err(WiresharkFieldsError::MalformedRecord { line: index + 1 }),
+                _ => continue,
+            };
+
+            if !protocols.contains(protocol) {
+                continue;
+            }
+            missing.remove(protocol);
+
+            if let Some(ty) = field_type(ft) {
+                // Some dissectors register the same name more than once.
+                let _ = scheme.add_field(name.to_owned(), ty);
+            }
+        }
+
+        match missing.into_iter().next() {
+            Some(protocol) => Err(WiresharkFieldsError::UnknownProtocol(protocol.to_owned())),
+            None => Ok(scheme),
+        }
+    }
+}
+
+#[test]
+fn test_from_wireshark_fields() {
+    use crate::types::GetType;
+
+    let input = [
+        "P\tTransmission Control Protocol\ttcp",
+        "F\tSource Port\ttcp.srcport\tFT_UINT16\ttcp\tBASE_PT_TCP\t0x0\t",
+        "F\tFlags\ttcp.flags.syn\tFT_BOOLEAN\ttcp\t12\t0x002\t",
+        "F\tSequence Number\ttcp.seq\tFT_UINT32\ttcp\tBASE_DEC\t0x0\t",
+        "F\tTime since first frame\ttcp.time_relative\tFT_RELATIVE_TIME\ttcp\t\t0x0\t",
+        "F\tSource Port\ttcp.srcport\tFT_UINT32\ttcp\tBASE_DEC\t0x0\t",
+        "P\tInternet Protocol Version 4\tip",
+        "F\tSource Address\tip.src\tFT_IPv4\tip\tBASE_NONE\t0x0\t",
+        "P\tHypertext Transfer Protocol\thttp",
+        "F\tHost\thttp.host\tFT_STRING\thttp\t\t0x0\t",
+    ]
+    .join("\n");
+
+    let scheme = Scheme::from_wireshark_fields(&input, vec!["tcp", "http"]).unwrap();
+
+    assert_eq!(
+        scheme
+            .iter()
+            .map(|field| (field.name(), field.get_type()))
+            .collect::<Vec<_>>(),
+        vec![
+            ("tcp", Type::Bool),
+            ("tcp.srcport", Type::Int),
+            ("tcp.flags.syn", Type::Bool),
+            ("tcp.seq", Type::Int),
+            ("http", Type::Bool),
+            ("http.host", Type::Bytes),
+        ]
+    );
+    assert!(scheme.parse("tcp && tcp.srcport == 443").is_ok());
+
+    assert_eq!(
+        Scheme::from_wireshark_fields(&input, vec!["udp"]).err(),
+        Some(WiresharkFieldsError::UnknownProtocol("udp".to_owned()))
+    );
+
+    assert_eq!(
+        Scheme::from_wireshark_fields("P\tTCP\ttcp\nF\ttcp.srcport", vec!["tcp"]).err(),
+        Some(WiresharkFieldsError::MalformedRecord { line: 2 })
+    );
+}
@@ -0,0 +1,114 @@
+//! Runs the conformance corpus in `tests/conformance/corpus.json` against
+//! the engine.
+//!
+//! The corpus describes expected semantics of filters independently of
+//! Rust, so that bindings and other implementations can run it as well.
+//! See `tests/conformance/README.md` for its format.
+
+use indexmap::IndexMap;
+use serde::Deserialize;
+use wirefilter::{ExecutionContext, Scheme};
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Corpus {
+    version: u32,
+    suites: Vec<Suite>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Suite {
+    name: String,
+    scheme: Scheme,
+    cases: Vec<Case>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Case {
+    filter: String,
+    #[serde(default)]
+    contexts: Vec<Context>,
+    error: Option<ExpectedError>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Context {
+    values: IndexMap<String, String>,
+    matches: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(deny_unknown_fields)]
+struct ExpectedError {
+    reason: String,
+    line_number: usize,
+    span_start: usize,
+    span_len: usize,
+}
+
+fn run_case(scheme: &Scheme, case: &Case) -> Result<(), String> {
+    let ast = match (scheme.parse(&case.filter), &case.error) {
+        (Ok(ast), None) => ast,
+        (Ok(_), Some(_)) => return Err("parsed, but an error was expected".to_owned()),
+        (Err(err), expected) => {
+            let actual = ExpectedError {
+                reason: err.reason(),
+                line_number: err.line_number(),
+                span_start: err.span_start(),
+                span_len: err.span_len(),
+            };
+            return match expected {
+                Some(expected) if *expected == actual => Ok(()),
+                _ => Err(format!("unexpected error {:?}", actual)),
+            };
+        }
+    };
+
+    let filter = ast.clone().compile();
+    let bytecode = ast.compile_to_bytecode();
+
+    for context in &case.contexts {
+        let mut ctx = ExecutionContext::new(scheme);
+        ctx.set_from_pairs(
+            context
+                .values
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+        .map_err(|err| format!("invalid values {:?}: {}", context.values, err))?;
+
+        for (kind, result) in [
+            ("closures", filter.execute(&ctx)),
+            ("bytecode", bytecode.execute(&ctx)),
+        ] {
+            if result != Ok(context.matches) {
+                return Err(format!(
+                    "{} returned {:?} for {:?}",
+                    kind, result, context.values
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_conformance() {
+    let corpus: Corpus = serde_json::from_str(include_str!("conformance/corpus.json")).unwrap();
+    assert_eq!(corpus.version, 1);
+
+    let mut failures = Vec::new();
+    for suite in &corpus.suites {
+        for case in &suite.cases {
+            if let Err(failure) = run_case(&suite.scheme, case) {
+                failures.push(format!("{}: {:?}: {}", suite.name, case.filter, failure));
+            }
+        }
+    }
+
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
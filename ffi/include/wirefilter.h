@@ -3,6 +3,7 @@
 
 #include <stdlib.h>
 #include <stdint.h>
+#include <stdbool.h>
 
 #ifdef __cplusplus
 extern "C" {
@@ -45,17 +46,43 @@ typedef union {
     } ok;
 } wirefilter_parsing_result_t;
 
+typedef union {
+    uint8_t success;
+    struct {
+        uint8_t _res1;
+        wirefilter_rust_allocated_str_t msg;
+    } err;
+    struct {
+        uint8_t _res2;
+        bool value;
+    } ok;
+} wirefilter_matching_result_t;
+
+typedef union {
+    uint8_t success;
+    struct {
+        uint8_t _res1;
+        wirefilter_rust_allocated_str_t msg;
+    } err;
+    struct {
+        uint8_t _res2;
+        bool value;
+    } ok;
+} wirefilter_using_result_t;
+
 typedef enum {
     WIREFILTER_TYPE_IP,
     WIREFILTER_TYPE_BYTES,
     WIREFILTER_TYPE_INT,
     WIREFILTER_TYPE_BOOL,
+    WIREFILTER_TYPE_FLOAT,
+    WIREFILTER_TYPE_TIMESTAMP,
 } wirefilter_type_t;
 
 wirefilter_scheme_t *wirefilter_create_scheme();
 void wirefilter_free_scheme(wirefilter_scheme_t *scheme);
 
-void wirefilter_add_type_field_to_scheme(
+bool wirefilter_add_type_field_to_scheme(
     wirefilter_scheme_t *scheme,
     wirefilter_externally_allocated_str_t name,
     wirefilter_type_t type
@@ -78,46 +105,68 @@ void wirefilter_free_execution_context(
     wirefilter_execution_context_t *exec_ctx
 );
 
-void wirefilter_add_int_value_to_execution_context(
+bool wirefilter_add_int_value_to_execution_context(
     wirefilter_execution_context_t *exec_ctx,
     wirefilter_externally_allocated_str_t name,
     int32_t value
 );
 
-void wirefilter_add_bytes_value_to_execution_context(
+bool wirefilter_add_int64_value_to_execution_context(
+    wirefilter_execution_context_t *exec_ctx,
+    wirefilter_externally_allocated_str_t name,
+    int64_t value
+);
+
+bool wirefilter_add_bytes_value_to_execution_context(
     wirefilter_execution_context_t *exec_ctx,
     wirefilter_externally_allocated_str_t name,
     wirefilter_externally_allocated_byte_arr_t value
 );
 
-void wirefilter_add_ipv6_value_to_execution_context(
+bool wirefilter_add_ipv6_value_to_execution_context(
     wirefilter_execution_context_t *exec_ctx,
     wirefilter_externally_allocated_str_t name,
     uint8_t value[16]
 );
 
-void wirefilter_add_ipv4_value_to_execution_context(
+bool wirefilter_add_ipv4_value_to_execution_context(
     wirefilter_execution_context_t *exec_ctx,
     wirefilter_externally_allocated_str_t name,
     uint8_t value[4]
 );
 
-void wirefilter_add_bool_value_to_execution_context(
+bool wirefilter_add_bool_value_to_execution_context(
     wirefilter_execution_context_t *exec_ctx,
     wirefilter_externally_allocated_str_t name,
     bool value
 );
 
-bool wirefilter_match(
+bool wirefilter_add_float_value_to_execution_context(
+    wirefilter_execution_context_t *exec_ctx,
+    wirefilter_externally_allocated_str_t name,
+    double value
+);
+
+bool wirefilter_add_timestamp_value_to_execution_context(
+    wirefilter_execution_context_t *exec_ctx,
+    wirefilter_externally_allocated_str_t name,
+    int64_t nanos_since_epoch
+);
+
+wirefilter_matching_result_t wirefilter_match(
     const wirefilter_filter_t *filter,
     const wirefilter_execution_context_t *exec_ctx
 );
 
-bool wirefilter_filter_uses(
+void wirefilter_free_matching_result(wirefilter_matching_result_t result);
+
+wirefilter_using_result_t wirefilter_filter_uses(
     const wirefilter_filter_ast_t *ast,
     wirefilter_externally_allocated_str_t field_name
 );
 
+void wirefilter_free_using_result(wirefilter_using_result_t result);
+
 wirefilter_static_rust_allocated_str_t wirefilter_get_version();
 
 #ifdef __cplusplus
@@ -10,7 +10,7 @@ use std::{
     io::{self, Write},
     net::IpAddr,
 };
-use wirefilter::{ExecutionContext, Filter, FilterAst, ParseError, Scheme, Type};
+use wirefilter::{ExecutionContext, Filter, FilterAst, Float, ParseError, Scheme, Timestamp, Type};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
@@ -41,6 +41,36 @@ impl<'s> ParsingResult<'s> {
     }
 }
 
+#[repr(u8)]
+pub enum MatchingResult {
+    Err(RustAllocatedString),
+    Ok(bool),
+}
+
+#[repr(u8)]
+pub enum UsingResult {
+    Err(RustAllocatedString),
+    Ok(bool),
+}
+
+impl MatchingResult {
+    pub fn unwrap(self) -> bool {
+        match self {
+            MatchingResult::Err(err) => panic!("{}", &err as &str),
+            MatchingResult::Ok(value) => value,
+        }
+    }
+}
+
+impl UsingResult {
+    pub fn unwrap(self) -> bool {
+        match self {
+            UsingResult::Err(err) => panic!("{}", &err as &str),
+            UsingResult::Ok(value) => value,
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn wirefilter_create_scheme() -> RustBox<Scheme> {
     Default::default()
@@ -56,8 +86,8 @@ pub extern "C" fn wirefilter_add_type_field_to_scheme(
     scheme: &mut Scheme,
     name: ExternallyAllocatedStr<'_>,
     ty: Type,
-) {
-    scheme.add_field(name.into_ref().to_owned(), ty).unwrap();
+) -> bool {
+    scheme.add_field(name.into_ref().to_owned(), ty).is_ok()
 }
 
 #[no_mangle]
@@ -148,10 +178,17 @@ pub extern "C" fn wirefilter_add_int_value_to_execution_context<'a>(
     exec_context: &mut ExecutionContext<'a>,
     name: ExternallyAllocatedStr<'_>,
     value: i32,
-) {
-    exec_context
-        .set_field_value(name.into_ref(), value)
-        .unwrap();
+) -> bool {
+    exec_context.set_field_value(name.into_ref(), value).is_ok()
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_add_int64_value_to_execution_context<'a>(
+    exec_context: &mut ExecutionContext<'a>,
+    name: ExternallyAllocatedStr<'_>,
+    value: i64,
+) -> bool {
+    exec_context.set_field_value(name.into_ref(), value).is_ok()
 }
 
 #[no_mangle]
@@ -159,11 +196,9 @@ pub extern "C" fn wirefilter_add_bytes_value_to_execution_context<'a>(
     exec_context: &mut ExecutionContext<'a>,
     name: ExternallyAllocatedStr<'_>,
     value: ExternallyAllocatedByteArr<'a>,
-) {
+) -> bool {
     let slice: &[u8] = value.into_ref();
-    exec_context
-        .set_field_value(name.into_ref(), slice)
-        .unwrap();
+    exec_context.set_field_value(name.into_ref(), slice).is_ok()
 }
 
 #[no_mangle]
@@ -171,10 +206,10 @@ pub extern "C" fn wirefilter_add_ipv6_value_to_execution_context(
     exec_context: &mut ExecutionContext<'_>,
     name: ExternallyAllocatedStr<'_>,
     value: &[u8; 16],
-) {
+) -> bool {
     exec_context
         .set_field_value(name.into_ref(), IpAddr::from(*value))
-        .unwrap();
+        .is_ok()
 }
 
 #[no_mangle]
@@ -182,10 +217,10 @@ pub extern "C" fn wirefilter_add_ipv4_value_to_execution_context(
     exec_context: &mut ExecutionContext<'_>,
     name: ExternallyAllocatedStr<'_>,
     value: &[u8; 4],
-) {
+) -> bool {
     exec_context
         .set_field_value(name.into_ref(), IpAddr::from(*value))
-        .unwrap();
+        .is_ok()
 }
 
 #[no_mangle]
@@ -193,10 +228,31 @@ pub extern "C" fn wirefilter_add_bool_value_to_execution_context(
     exec_context: &mut ExecutionContext<'_>,
     name: ExternallyAllocatedStr<'_>,
     value: bool,
-) {
+) -> bool {
+    exec_context.set_field_value(name.into_ref(), value).is_ok()
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_add_float_value_to_execution_context(
+    exec_context: &mut ExecutionContext<'_>,
+    name: ExternallyAllocatedStr<'_>,
+    value: f64,
+) -> bool {
+    match Float::new(value) {
+        Some(value) => exec_context.set_field_value(name.into_ref(), value).is_ok(),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_add_timestamp_value_to_execution_context(
+    exec_context: &mut ExecutionContext<'_>,
+    name: ExternallyAllocatedStr<'_>,
+    nanos_since_epoch: i64,
+) -> bool {
     exec_context
-        .set_field_value(name.into_ref(), value)
-        .unwrap();
+        .set_field_value(name.into_ref(), Timestamp::from_nanos(nanos_since_epoch))
+        .is_ok()
 }
 
 #[no_mangle]
@@ -211,8 +267,30 @@ pub extern "C" fn wirefilter_compile_filter<'s>(
 pub extern "C" fn wirefilter_match<'s>(
     filter: &Filter<'s>,
     exec_context: &ExecutionContext<'s>,
-) -> bool {
-    filter.execute(exec_context).unwrap()
+) -> MatchingResult {
+    // Executing with a missing value panics, which would abort the host
+    // process, so it's reported as an error instead.
+    if let Err(missing) = filter.validate_context(exec_context) {
+        let names = missing
+            .iter()
+            .map(|field| field.name)
+            .collect::<Vec<_>>()
+            .join(", ");
+        return MatchingResult::Err(RustAllocatedString::from(format!(
+            "missing values of fields {}",
+            names
+        )));
+    }
+
+    match filter.execute(exec_context) {
+        Ok(value) => MatchingResult::Ok(value),
+        Err(err) => MatchingResult::Err(RustAllocatedString::from(err.to_string())),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_free_matching_result(r: MatchingResult) {
+    drop(r);
 }
 
 #[no_mangle]
@@ -224,8 +302,16 @@ pub extern "C" fn wirefilter_free_compiled_filter(filter: RustBox<Filter<'_>>) {
 pub extern "C" fn wirefilter_filter_uses(
     filter_ast: &FilterAst<'_>,
     field_name: ExternallyAllocatedStr<'_>,
-) -> bool {
-    filter_ast.uses(field_name.into_ref()).unwrap()
+) -> UsingResult {
+    match filter_ast.uses(field_name.into_ref()) {
+        Ok(value) => UsingResult::Ok(value),
+        Err(err) => UsingResult::Err(RustAllocatedString::from(err.to_string())),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn wirefilter_free_using_result(r: UsingResult) {
+    drop(r);
 }
 
 #[no_mangle]
@@ -241,38 +327,43 @@ mod ffi_test {
     fn create_scheme() -> RustBox<Scheme> {
         let mut scheme = wirefilter_create_scheme();
 
-        wirefilter_add_type_field_to_scheme(
+        assert!(wirefilter_add_type_field_to_scheme(
             &mut scheme,
             ExternallyAllocatedStr::from("ip1"),
             Type::Ip,
-        );
-        wirefilter_add_type_field_to_scheme(
+        ));
+        assert!(wirefilter_add_type_field_to_scheme(
             &mut scheme,
             ExternallyAllocatedStr::from("ip2"),
             Type::Ip,
-        );
+        ));
 
-        wirefilter_add_type_field_to_scheme(
+        assert!(wirefilter_add_type_field_to_scheme(
             &mut scheme,
             ExternallyAllocatedStr::from("str1"),
             Type::Bytes,
-        );
-        wirefilter_add_type_field_to_scheme(
+        ));
+        assert!(wirefilter_add_type_field_to_scheme(
             &mut scheme,
             ExternallyAllocatedStr::from("str2"),
             Type::Bytes,
-        );
+        ));
 
-        wirefilter_add_type_field_to_scheme(
+        assert!(wirefilter_add_type_field_to_scheme(
             &mut scheme,
             ExternallyAllocatedStr::from("num1"),
             Type::Int,
-        );
-        wirefilter_add_type_field_to_scheme(
+        ));
+        assert!(wirefilter_add_type_field_to_scheme(
             &mut scheme,
             ExternallyAllocatedStr::from("num2"),
             Type::Int,
-        );
+        ));
+        assert!(wirefilter_add_type_field_to_scheme(
+            &mut scheme,
+            ExternallyAllocatedStr::from("num3"),
+            Type::Int,
+        ));
 
         scheme
     }
@@ -280,41 +371,47 @@ mod ffi_test {
     fn create_execution_context<'e, 's: 'e>(scheme: &'s Scheme) -> RustBox<ExecutionContext<'e>> {
         let mut exec_context = wirefilter_create_execution_context(scheme);
 
-        wirefilter_add_ipv4_value_to_execution_context(
+        assert!(wirefilter_add_ipv4_value_to_execution_context(
             &mut exec_context,
             ExternallyAllocatedStr::from("ip1"),
             &[127, 0, 0, 1],
-        );
+        ));
 
-        wirefilter_add_ipv6_value_to_execution_context(
+        assert!(wirefilter_add_ipv6_value_to_execution_context(
             &mut exec_context,
             ExternallyAllocatedStr::from("ip2"),
             b"\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\xFF\xFF\xC0\xA8\x00\x01",
-        );
+        ));
 
-        wirefilter_add_bytes_value_to_execution_context(
+        assert!(wirefilter_add_bytes_value_to_execution_context(
             &mut exec_context,
             ExternallyAllocatedStr::from("str1"),
             ExternallyAllocatedByteArr::from("Hey"),
-        );
+        ));
 
-        wirefilter_add_bytes_value_to_execution_context(
+        assert!(wirefilter_add_bytes_value_to_execution_context(
             &mut exec_context,
             ExternallyAllocatedStr::from("str2"),
             ExternallyAllocatedByteArr::from("yo123"),
-        );
+        ));
 
-        wirefilter_add_int_value_to_execution_context(
+        assert!(wirefilter_add_int_value_to_execution_context(
             &mut exec_context,
             ExternallyAllocatedStr::from("num1"),
             42,
-        );
+        ));
 
-        wirefilter_add_int_value_to_execution_context(
+        assert!(wirefilter_add_int_value_to_execution_context(
             &mut exec_context,
             ExternallyAllocatedStr::from("num2"),
             1337,
-        );
+        ));
+
+        assert!(wirefilter_add_int64_value_to_execution_context(
+            &mut exec_context,
+            ExternallyAllocatedStr::from("num3"),
+            i64::from(i32::MAX) + 1,
+        ));
 
         exec_context
     }
@@ -331,7 +428,7 @@ mod ffi_test {
         let filter = parse_filter(scheme, input).unwrap();
         let filter = wirefilter_compile_filter(filter);
 
-        let result = wirefilter_match(&filter, exec_context);
+        let result = wirefilter_match(&filter, exec_context).unwrap();
 
         wirefilter_free_compiled_filter(filter);
 
@@ -387,7 +484,10 @@ mod ffi_test {
 
             let json = wirefilter_serialize_filter_to_json(&filter);
 
-            assert_eq!(&json as &str, r#"{"op":"And","items":[{"lhs":"num1","op":"GreaterThan","rhs":3},{"lhs":"str2","op":"Equal","rhs":"abc"}]}"#);
+            assert_eq!(
+                &json as &str,
+                r#"{"op":"And","items":[{"lhs":"num1","op":"GreaterThan","rhs":3},{"lhs":"str2","op":"Equal","rhs":"abc"}]}"#
+            );
 
             wirefilter_free_string(json);
 
@@ -422,6 +522,12 @@ mod ffi_test {
                 &exec_context
             ));
 
+            assert!(match_filter(
+                "num3 == 2147483648 && num3 > 2147483647",
+                &scheme,
+                &exec_context
+            ));
+
             wirefilter_free_execution_context(exec_context);
         }
 
@@ -481,34 +587,99 @@ mod ffi_test {
             )
             .unwrap();
 
-            assert!(wirefilter_filter_uses(
-                &filter,
-                ExternallyAllocatedStr::from("num1")
-            ));
+            assert!(wirefilter_filter_uses(&filter, ExternallyAllocatedStr::from("num1")).unwrap());
 
-            assert!(wirefilter_filter_uses(
-                &filter,
-                ExternallyAllocatedStr::from("ip1")
-            ));
+            assert!(wirefilter_filter_uses(&filter, ExternallyAllocatedStr::from("ip1")).unwrap());
+
+            assert!(wirefilter_filter_uses(&filter, ExternallyAllocatedStr::from("str2")).unwrap());
+
+            assert!(
+                !wirefilter_filter_uses(&filter, ExternallyAllocatedStr::from("str1")).unwrap()
+            );
+
+            assert!(!wirefilter_filter_uses(&filter, ExternallyAllocatedStr::from("ip2")).unwrap());
+
+            wirefilter_free_parsed_filter(filter);
+        }
+
+        wirefilter_free_scheme(scheme);
+    }
 
-            assert!(wirefilter_filter_uses(
-                &filter,
-                ExternallyAllocatedStr::from("str2")
+    #[test]
+    fn errors() {
+        let mut scheme = create_scheme();
+        let other_scheme = create_scheme();
+
+        assert!(!wirefilter_add_type_field_to_scheme(
+            &mut scheme,
+            ExternallyAllocatedStr::from("num1"),
+            Type::Bytes,
+        ));
+
+        {
+            let mut exec_context = create_execution_context(&other_scheme);
+
+            assert!(!wirefilter_add_int_value_to_execution_context(
+                &mut exec_context,
+                ExternallyAllocatedStr::from("unknown"),
+                1,
             ));
 
-            assert!(!wirefilter_filter_uses(
-                &filter,
-                ExternallyAllocatedStr::from("str1")
+            assert!(!wirefilter_add_bool_value_to_execution_context(
+                &mut exec_context,
+                ExternallyAllocatedStr::from("num1"),
+                true,
             ));
 
-            assert!(!wirefilter_filter_uses(
-                &filter,
-                ExternallyAllocatedStr::from("ip2")
+            assert!(!wirefilter_add_float_value_to_execution_context(
+                &mut exec_context,
+                ExternallyAllocatedStr::from("num1"),
+                f64::NAN,
             ));
 
-            wirefilter_free_parsed_filter(filter);
+            let filter = parse_filter(&scheme, "num1 == 42").unwrap();
+
+            match wirefilter_filter_uses(&filter, ExternallyAllocatedStr::from("unknown")) {
+                UsingResult::Ok(_) => panic!("Error expected"),
+                UsingResult::Err(err) => {
+                    assert_eq!(&err as &str, "unknown field");
+                    wirefilter_free_string(err);
+                }
+            }
+
+            let filter = wirefilter_compile_filter(filter);
+
+            match wirefilter_match(&filter, &exec_context) {
+                MatchingResult::Ok(_) => panic!("Error expected"),
+                MatchingResult::Err(err) => {
+                    assert_eq!(
+                        &err as &str,
+                        "execution context doesn't match the scheme with which filter was parsed"
+                    );
+                    wirefilter_free_string(err);
+                }
+            }
+
+            wirefilter_free_compiled_filter(filter);
+            wirefilter_free_execution_context(exec_context);
+
+            let exec_context = wirefilter_create_execution_context(&scheme);
+            let filter = parse_filter(&scheme, "num1 == 42").unwrap();
+            let filter = wirefilter_compile_filter(filter);
+
+            match wirefilter_match(&filter, &exec_context) {
+                MatchingResult::Ok(_) => panic!("Error expected"),
+                MatchingResult::Err(err) => {
+                    assert_eq!(&err as &str, "missing values of fields num1");
+                    wirefilter_free_string(err);
+                }
+            }
+
+            wirefilter_free_compiled_filter(filter);
+            wirefilter_free_execution_context(exec_context);
         }
 
         wirefilter_free_scheme(scheme);
+        wirefilter_free_scheme(other_scheme);
     }
 }
@@ -31,13 +31,11 @@ impl<T> Clone for ExternSliceRepr<T> {
 impl<T> Copy for ExternSliceRepr<T> {}
 
 impl<T> From<*mut [T]> for ExternSliceRepr<T> {
-    #[allow(clippy::not_unsafe_ptr_arg_deref)]
     fn from(ptr: *mut [T]) -> Self {
-        unsafe {
-            ExternSliceRepr {
-                data: (*ptr).as_mut_ptr(),
-                length: (*ptr).len(),
-            }
+        // Slice metadata can be read without dereferencing the pointer.
+        ExternSliceRepr {
+            data: ptr as *mut T,
+            length: ptr.len(),
         }
     }
 }
@@ -38,5 +38,7 @@ mod ffi_ctest {
         create_execution_context,
         add_values_to_execution_context,
         match_filter,
+        match_with_other_scheme,
+        match_with_unset_field,
     );
 }
@@ -14,25 +14,37 @@ static wirefilter_externally_allocated_str_t wirefilter_string(const char *s) {
 }
 
 void initialize_scheme(wirefilter_scheme_t *scheme) {
-    wirefilter_add_type_field_to_scheme(
-        scheme,
-        wirefilter_string("http.host"),
-        WIREFILTER_TYPE_BYTES
+    rust_assert(
+        wirefilter_add_type_field_to_scheme(
+            scheme,
+            wirefilter_string("http.host"),
+            WIREFILTER_TYPE_BYTES
+        ),
+        "could not add field http.host to scheme"
     );
-    wirefilter_add_type_field_to_scheme(
-        scheme,
-        wirefilter_string("ip.addr"),
-        WIREFILTER_TYPE_IP
+    rust_assert(
+        wirefilter_add_type_field_to_scheme(
+            scheme,
+            wirefilter_string("ip.addr"),
+            WIREFILTER_TYPE_IP
+        ),
+        "could not add field ip.addr to scheme"
     );
-    wirefilter_add_type_field_to_scheme(
-        scheme,
-        wirefilter_string("ssl"),
-        WIREFILTER_TYPE_BOOL
+    rust_assert(
+        wirefilter_add_type_field_to_scheme(
+            scheme,
+            wirefilter_string("ssl"),
+            WIREFILTER_TYPE_BOOL
+        ),
+        "could not add field ssl to scheme"
     );
-    wirefilter_add_type_field_to_scheme(
-        scheme,
-        wirefilter_string("tcp.port"),
-        WIREFILTER_TYPE_INT
+    rust_assert(
+        wirefilter_add_type_field_to_scheme(
+            scheme,
+            wirefilter_string("tcp.port"),
+            WIREFILTER_TYPE_INT
+        ),
+        "could not add field tcp.port to scheme"
     );
 }
 
@@ -100,15 +112,26 @@ void wirefilter_ffi_ctest_filter_uses_field() {
     rust_assert(result.success == 1, "could not parse good filter");
     rust_assert(result.ok.ast != NULL, "could not parse good filter");
 
-    rust_assert(
-        wirefilter_filter_uses(result.ok.ast, wirefilter_string("tcp.port")) == true,
-        "filter should be using field tcp.port"
+    wirefilter_using_result_t using_result = wirefilter_filter_uses(
+        result.ok.ast,
+        wirefilter_string("tcp.port")
     );
+    rust_assert(using_result.success == true, "could not check if filter uses field");
+    rust_assert(using_result.ok.value == true, "filter should be using field tcp.port");
+    wirefilter_free_using_result(using_result);
+
+    using_result = wirefilter_filter_uses(result.ok.ast, wirefilter_string("ip.addr"));
+    rust_assert(using_result.success == true, "could not check if filter uses field");
+    rust_assert(using_result.ok.value == false, "filter should not be using field ip.addr");
+    wirefilter_free_using_result(using_result);
 
+    using_result = wirefilter_filter_uses(result.ok.ast, wirefilter_string("unknown"));
+    rust_assert(using_result.success == false, "should not check unknown field");
     rust_assert(
-        wirefilter_filter_uses(result.ok.ast, wirefilter_string("ip.addr")) == false,
-        "filter should not be using field ip.addr"
+        using_result.err.msg.data && using_result.err.msg.length > 0,
+        "missing error message"
     );
+    wirefilter_free_using_result(using_result);
 
     wirefilter_free_parsing_result(result);
 
@@ -160,29 +183,41 @@ void wirefilter_ffi_ctest_add_values_to_execution_context() {
     wirefilter_externally_allocated_byte_arr_t http_host;
     http_host.data = (unsigned char *)"www.cloudflare.com";
     http_host.length = strlen((char *)http_host.data);
-    wirefilter_add_bytes_value_to_execution_context(
-        exec_ctx,
-        wirefilter_string("http.host"),
-        http_host
+    rust_assert(
+        wirefilter_add_bytes_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("http.host"),
+            http_host
+        ),
+        "could not set value for field http.host"
     );
 
     uint8_t ip_addr[4] = {192, 168, 0, 1};
-    wirefilter_add_ipv4_value_to_execution_context(
-        exec_ctx,
-        wirefilter_string("ip.addr"),
-        ip_addr
+    rust_assert(
+        wirefilter_add_ipv4_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("ip.addr"),
+            ip_addr
+        ),
+        "could not set value for field ip.addr"
     );
 
-    wirefilter_add_bool_value_to_execution_context(
-        exec_ctx,
-        wirefilter_string("ssl"),
-        false
+    rust_assert(
+        wirefilter_add_bool_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("ssl"),
+            false
+        ),
+        "could not set value for field ssl"
     );
 
-    wirefilter_add_int_value_to_execution_context(
-        exec_ctx,
-        wirefilter_string("tcp.port"),
-        80
+    rust_assert(
+        wirefilter_add_int_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("tcp.port"),
+            80
+        ),
+        "could not set value for field tcp.port"
     );
 
     wirefilter_free_execution_context(exec_ctx);
@@ -212,32 +247,140 @@ void wirefilter_ffi_ctest_match_filter() {
     wirefilter_externally_allocated_byte_arr_t http_host;
     http_host.data = (unsigned char *)"www.cloudflare.com";
     http_host.length = strlen((char *)http_host.data);
-    wirefilter_add_bytes_value_to_execution_context(
-        exec_ctx,
-        wirefilter_string("http.host"),
-        http_host
+    rust_assert(
+        wirefilter_add_bytes_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("http.host"),
+            http_host
+        ),
+        "could not set value for field http.host"
     );
 
     uint8_t ip_addr[4] = {192, 168, 0, 1};
-    wirefilter_add_ipv4_value_to_execution_context(
-        exec_ctx,
-        wirefilter_string("ip.addr"),
-        ip_addr
+    rust_assert(
+        wirefilter_add_ipv4_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("ip.addr"),
+            ip_addr
+        ),
+        "could not set value for field ip.addr"
     );
 
-    wirefilter_add_bool_value_to_execution_context(
-        exec_ctx,
-        wirefilter_string("ssl"),
-        false
+    rust_assert(
+        wirefilter_add_bool_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("ssl"),
+            false
+        ),
+        "could not set value for field ssl"
+    );
+
+    rust_assert(
+        wirefilter_add_int_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("tcp.port"),
+            80
+        ),
+        "could not set value for field tcp.port"
     );
 
-    wirefilter_add_int_value_to_execution_context(
-        exec_ctx,
-        wirefilter_string("tcp.port"),
-        80
+    wirefilter_matching_result_t matching_result = wirefilter_match(filter, exec_ctx);
+    rust_assert(matching_result.success == true, "could not match filter");
+    rust_assert(matching_result.ok.value == true, "filter should match");
+    wirefilter_free_matching_result(matching_result);
+
+    wirefilter_free_execution_context(exec_ctx);
+
+    wirefilter_free_compiled_filter(filter);
+
+    wirefilter_free_scheme(scheme);
+}
+
+void wirefilter_ffi_ctest_match_with_other_scheme() {
+    wirefilter_scheme_t *scheme = wirefilter_create_scheme();
+    rust_assert(scheme != NULL, "could not create scheme");
+
+    initialize_scheme(scheme);
+
+    wirefilter_scheme_t *other_scheme = wirefilter_create_scheme();
+    rust_assert(other_scheme != NULL, "could not create scheme");
+
+    initialize_scheme(other_scheme);
+
+    wirefilter_parsing_result_t result = wirefilter_parse_filter(
+        scheme,
+        wirefilter_string("tcp.port == 80")
     );
+    rust_assert(result.success == true, "could not parse good filter");
 
-    rust_assert(wirefilter_match(filter, exec_ctx) == true, "could not match filter");
+    wirefilter_filter_t *filter = wirefilter_compile_filter(result.ok.ast);
+    rust_assert(filter != NULL, "could not compile filter");
+
+    wirefilter_execution_context_t *exec_ctx = wirefilter_create_execution_context(other_scheme);
+    rust_assert(exec_ctx != NULL, "could not create execution context");
+
+    rust_assert(
+        wirefilter_add_int_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("unknown"),
+            80
+        ) == false,
+        "should not set value for unknown field"
+    );
+
+    wirefilter_matching_result_t matching_result = wirefilter_match(filter, exec_ctx);
+    rust_assert(matching_result.success == false, "should not match with other scheme");
+    rust_assert(
+        matching_result.err.msg.data && matching_result.err.msg.length > 0,
+        "missing error message"
+    );
+    wirefilter_free_matching_result(matching_result);
+
+    wirefilter_free_execution_context(exec_ctx);
+
+    wirefilter_free_compiled_filter(filter);
+
+    wirefilter_free_scheme(other_scheme);
+
+    wirefilter_free_scheme(scheme);
+}
+
+void wirefilter_ffi_ctest_match_with_unset_field() {
+    wirefilter_scheme_t *scheme = wirefilter_create_scheme();
+    rust_assert(scheme != NULL, "could not create scheme");
+
+    initialize_scheme(scheme);
+
+    wirefilter_parsing_result_t result = wirefilter_parse_filter(
+        scheme,
+        wirefilter_string("tcp.port == 80 && ssl")
+    );
+    rust_assert(result.success == true, "could not parse good filter");
+
+    wirefilter_filter_t *filter = wirefilter_compile_filter(result.ok.ast);
+    rust_assert(filter != NULL, "could not compile filter");
+
+    wirefilter_execution_context_t *exec_ctx = wirefilter_create_execution_context(scheme);
+    rust_assert(exec_ctx != NULL, "could not create execution context");
+
+    rust_assert(
+        wirefilter_add_int_value_to_execution_context(
+            exec_ctx,
+            wirefilter_string("tcp.port"),
+            80
+        ) == true,
+        "could not set value for field tcp.port"
+    );
+
+    wirefilter_matching_result_t matching_result = wirefilter_match(filter, exec_ctx);
+    rust_assert(matching_result.success == false, "should not match with an unset field");
+    const char expected[] = "missing values of fields ssl";
+    rust_assert(
+        matching_result.err.msg.length == strlen(expected)
+            && memcmp(matching_result.err.msg.data, expected, strlen(expected)) == 0,
+        "unexpected error message"
+    );
+    wirefilter_free_matching_result(matching_result);
 
     wirefilter_free_execution_context(exec_ctx);
 
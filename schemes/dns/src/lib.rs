@@ -0,0 +1,187 @@
+//! DNS query fields for [Wirefilter](wirefilter) schemes.
+//!
+//! Fields describe the header and the question of a query message, named
+//! the way the Wireshark DNS dissector names them.
+//!
+//! | Field                  | Type  | Value                                                   |
+//! |------------------------|-------|---------------------------------------------------------|
+//! | `dns.qry.name`         | Bytes | Queried name without the trailing dot, case-insensitive |
+//! | `dns.qry.type`         | Int   | Queried record type, like `28` for `AAAA`               |
+//! | `dns.qry.class`        | Int   | Queried class, usually `1` for `IN`                     |
+//! | `dns.flags.response`   | Bool  | Whether the message is a response                       |
+//! | `dns.flags.opcode`     | Int   | Kind of the query, `0` for a standard one               |
+//! | `dns.flags.recdesired` | Bool  | Whether recursion is desired                            |
+//! | `dns.flags.rcode`      | Int   | Response code                                           |
+#![warn(missing_docs)]
+
+use wirefilter::{ExecutionContext, ItemRedefinitionError, Scheme, SetFieldValueError, Type};
+
+/// Names and types of the `dns.qry.*` and `dns.flags.*` fields in the
+/// order they're registered.
+pub const FIELDS: &[(&str, Type)] = &[
+    ("dns.qry.name", Type::Bytes),
+    ("dns.qry.type", Type::Int),
+    ("dns.qry.class", Type::Int),
+    ("dns.flags.response", Type::Bool),
+    ("dns.flags.opcode", Type::Int),
+    ("dns.flags.recdesired", Type::Bool),
+    ("dns.flags.rcode", Type::Int),
+];
+
+/// Registers the `dns.*` fields in a scheme, e.g. one that already has the
+/// `ip.*` and `udp.*` fields of the datagram a query came in.
+///
+/// `dns.qry.name` is compared case-insensitively, since DNS names are.
+pub fn add_fields(scheme: &mut Scheme) -> Result<(), ItemRedefinitionError> {
+    for &(name, ty) in FIELDS {
+        scheme.add_field(name.to_owned(), ty)?;
+    }
+    scheme
+        .set_field_case_insensitive("dns.qry.name", true)
+        .expect("dns.qry.name is a Bytes field");
+    Ok(())
+}
+
+/// Creates a scheme for filters on DNS queries alone, like
+/// `dns.qry.name in_domain "example.org" and dns.qry.type == 28`.
+pub fn scheme() -> Scheme {
+    let mut scheme = Scheme::new();
+    add_fields(&mut scheme).expect("fields have unique names");
+    scheme
+}
+
+/// A DNS message with a single question, which provides values of all
+/// fields at once.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Query {
+    /// Queried name, with labels separated by dots and without the trailing
+    /// dot.
+    pub name: Vec<u8>,
+    /// Queried record type.
+    pub qtype: u16,
+    /// Queried class.
+    pub qclass: u16,
+    /// Whether the message is a response.
+    pub response: bool,
+    /// Kind of the query.
+    pub opcode: u8,
+    /// Whether recursion is desired.
+    pub recursion_desired: bool,
+    /// Response code.
+    pub rcode: u8,
+}
+
+impl Query {
+    /// Reads the header and the first question of a message in the wire
+    /// format, or returns `None` if it's malformed or has no questions.
+    ///
+    /// Compressed names are not accepted, as they can't point anywhere but
+    /// backwards into the header of a message with a single question.
+    pub fn from_wire(message: &[u8]) -> Option<Self> {
+        let u16_at = |pos: usize| {
+            message
+                .get(pos..pos + 2)
+                .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        };
+
+        let flags = u16_at(2)?;
+        if u16_at(4)? == 0 {
+            return None;
+        }
+
+        let mut name = Vec::new();
+        let mut pos = 12;
+        loop {
+            let len = usize::from(*message.get(pos)?);
+            pos += 1;
+            match len {
+                0 => break,
+                1..=63 => {
+                    if !name.is_empty() {
+                        name.push(b'.');
+                    }
+                    name.extend_from_slice(message.get(pos..pos + len)?);
+                    pos += len;
+                }
+                _ => return None,
+            }
+        }
+
+        Some(Query {
+            name,
+            qtype: u16_at(pos)?,
+            qclass: u16_at(pos + 2)?,
+            response: flags & 0x8000 != 0,
+            opcode: ((flags >> 11) & 0xF) as u8,
+            recursion_desired: flags & 0x0100 != 0,
+            rcode: (flags & 0xF) as u8,
+        })
+    }
+
+    /// Sets the `dns.qry.*` fields from the question and the `dns.flags.*`
+    /// fields from the header of the message in a context.
+    pub fn set_fields<'e>(
+        &'e self,
+        ctx: &mut ExecutionContext<'e>,
+    ) -> Result<(), SetFieldValueError> {
+        ctx.set_field_value("dns.qry.name", &self.name[..])?;
+        ctx.set_field_value("dns.qry.type", i32::from(self.qtype))?;
+        ctx.set_field_value("dns.qry.class", i32::from(self.qclass))?;
+        ctx.set_field_value("dns.flags.response", self.response)?;
+        ctx.set_field_value("dns.flags.opcode", i32::from(self.opcode))?;
+        ctx.set_field_value("dns.flags.recdesired", self.recursion_desired)?;
+        ctx.set_field_value("dns.flags.rcode", i32::from(self.rcode))?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_query() {
+    use wirefilter::testing::{check_protocol_fields, unset_fields};
+
+    // A recursive `AAAA` query for `WWW.example.org`.
+    let message = [
+        0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 3, b'W', b'W',
+        b'W', 7, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 3, b'o', b'r', b'g', 0, 0x00, 0x1C,
+        0x00, 0x01,
+    ];
+
+    let query = Query::from_wire(&message).unwrap();
+    assert_eq!(
+        query,
+        Query {
+            name: b"WWW.example.org".to_vec(),
+            qtype: 28,
+            qclass: 1,
+            response: false,
+            opcode: 0,
+            recursion_desired: true,
+            rcode: 0,
+        }
+    );
+
+    assert_eq!(Query::from_wire(&message[..message.len() - 1]), None);
+    assert_eq!(Query::from_wire(&message[..12]), None);
+
+    let scheme = scheme();
+    check_protocol_fields(FIELDS, add_fields, &scheme).unwrap();
+
+    let filter = scheme
+        .parse(
+            r#"dns.qry.name == "www.example.org" and dns.qry.type == 28
+                and dns.flags.recdesired and not dns.flags.response"#,
+        )
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    query.set_fields(&mut ctx).unwrap();
+    assert!(unset_fields(&ctx).is_empty());
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    let filter = scheme
+        .parse(r#"dns.qry.name in_domain "EXAMPLE.org.""#)
+        .unwrap()
+        .compile();
+    assert_eq!(filter.execute(&ctx), Ok(true));
+}
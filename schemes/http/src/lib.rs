@@ -0,0 +1,152 @@
+//! HTTP request fields for [Wirefilter](wirefilter) schemes.
+//!
+//! Fields describe the request line and a few headers of a request, and
+//! are named after the Wireshark HTTP dissector where it has an equivalent.
+//!
+//! | Field                    | Type  | Value                                       |
+//! |--------------------------|-------|---------------------------------------------|
+//! | `http.request.method`    | Bytes | Method, like `GET`                          |
+//! | `http.host`              | Bytes | `Host` header, trimmed and case-insensitive |
+//! | `http.request.uri`       | Bytes | Request target, like `/search?q=1`          |
+//! | `http.request.uri.path`  | Bytes | Request target before `?`                   |
+//! | `http.request.uri.query` | Bytes | Request target after `?`, if any            |
+//! | `http.user_agent`        | Bytes | `User-Agent` header                         |
+//! | `http.referer`           | Bytes | `Referer` header                            |
+//! | `http.cookie`            | Bytes | `Cookie` header                             |
+#![warn(missing_docs)]
+
+use wirefilter::{
+    ComparisonMode, ExecutionContext, ItemRedefinitionError, Scheme, SetFieldValueError, Type,
+};
+
+/// Names and types of the `http.*` fields in the order they're registered,
+/// all of them `Bytes`.
+pub const FIELDS: &[(&str, Type)] = &[
+    ("http.request.method", Type::Bytes),
+    ("http.host", Type::Bytes),
+    ("http.request.uri", Type::Bytes),
+    ("http.request.uri.path", Type::Bytes),
+    ("http.request.uri.query", Type::Bytes),
+    ("http.user_agent", Type::Bytes),
+    ("http.referer", Type::Bytes),
+    ("http.cookie", Type::Bytes),
+];
+
+/// Registers the `http.*` fields in a scheme, e.g. one that already has the
+/// `ip.*` and `tcp.*` fields of the connection a request came over.
+///
+/// `http.host` is compared case-insensitively and without surrounding
+/// whitespace, the same way servers route requests by the `Host` header.
+pub fn add_fields(scheme: &mut Scheme) -> Result<(), ItemRedefinitionError> {
+    for &(name, ty) in FIELDS {
+        scheme.add_field(name.to_owned(), ty)?;
+    }
+    scheme
+        .set_field_comparison_mode(
+            "http.host",
+            ComparisonMode {
+                case_insensitive: true,
+                trim: true,
+            },
+        )
+        .expect("http.host is a Bytes field");
+    Ok(())
+}
+
+/// Creates a scheme for filters on HTTP requests alone, like
+/// `http.host == "example.org" and http.request.uri.path == "/login"`.
+pub fn scheme() -> Scheme {
+    let mut scheme = Scheme::new();
+    add_fields(&mut scheme).expect("fields have unique names");
+    scheme
+}
+
+/// An HTTP request, which provides values of all fields at once.
+///
+/// Missing headers are set as empty strings.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Request<'a> {
+    /// Method, like `GET`.
+    pub method: &'a str,
+    /// Value of the `Host` header, or the authority of an HTTP/2 request.
+    pub host: &'a str,
+    /// Request target, including the query string.
+    pub uri: &'a str,
+    /// Value of the `User-Agent` header.
+    pub user_agent: Option<&'a str>,
+    /// Value of the `Referer` header.
+    pub referer: Option<&'a str>,
+    /// Value of the `Cookie` header.
+    pub cookie: Option<&'a str>,
+}
+
+impl<'a> Request<'a> {
+    /// Sets the `http.*` fields in a context from the request line and
+    /// headers.
+    ///
+    /// `http.request.uri.path` and `http.request.uri.query` are split at the
+    /// first `?` of the request target, and absent `User-Agent`, `Referer`
+    /// and `Cookie` headers are set as empty strings, so that every field
+    /// has a value.
+    pub fn set_fields<'e>(&self, ctx: &mut ExecutionContext<'e>) -> Result<(), SetFieldValueError>
+    where
+        'a: 'e,
+    {
+        let (path, query) = match self.uri.find('?') {
+            Some(pos) => (&self.uri[..pos], &self.uri[pos + 1..]),
+            None => (self.uri, ""),
+        };
+
+        ctx.set_field_value("http.request.method", self.method)?;
+        ctx.set_field_value("http.host", self.host)?;
+        ctx.set_field_value("http.request.uri", self.uri)?;
+        ctx.set_field_value("http.request.uri.path", path)?;
+        ctx.set_field_value("http.request.uri.query", query)?;
+        ctx.set_field_value("http.user_agent", self.user_agent.unwrap_or_default())?;
+        ctx.set_field_value("http.referer", self.referer.unwrap_or_default())?;
+        ctx.set_field_value("http.cookie", self.cookie.unwrap_or_default())?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_request() {
+    use wirefilter::testing::{check_protocol_fields, unset_fields};
+
+    let scheme = scheme();
+    check_protocol_fields(FIELDS, add_fields, &scheme).unwrap();
+
+    let filter = scheme
+        .parse(
+            r#"http.request.method == "GET" and http.host == "example.org"
+                and http.request.uri.path == "/search" and http.request.uri.query contains "q="
+                and http.referer == """#,
+        )
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    let mut request = Request {
+        method: "GET",
+        host: " Example.ORG",
+        uri: "/search?q=1",
+        user_agent: Some("curl/7.64.1"),
+        ..Request::default()
+    };
+    request.set_fields(&mut ctx).unwrap();
+    assert!(unset_fields(&ctx).is_empty());
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    // Without a `?`, the whole target is the path.
+    request.uri = "/search";
+    request.set_fields(&mut ctx).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+    assert_eq!(
+        scheme
+            .parse(r#"http.request.uri.path == "/search" and http.request.uri.query == """#)
+            .unwrap()
+            .compile()
+            .execute(&ctx),
+        Ok(true)
+    );
+}
@@ -0,0 +1,154 @@
+//! IP, TCP and UDP fields for [Wirefilter](wirefilter) schemes.
+//!
+//! Fields describe the addresses and ports of a TCP connection or a UDP
+//! datagram, with names from the Wireshark IP, TCP and UDP dissectors.
+//!
+//! | Field         | Type | Value                                             |
+//! |---------------|------|---------------------------------------------------|
+//! | `ip.src`      | Ip   | Source address                                    |
+//! | `ip.dst`      | Ip   | Destination address                               |
+//! | `ip.proto`    | Int  | Transport protocol number, like `6` for TCP       |
+//! | `tcp`         | Bool | Whether the transport is TCP                      |
+//! | `tcp.srcport` | Int  | Source port of TCP, `0` for other transports      |
+//! | `tcp.dstport` | Int  | Destination port of TCP, `0` for other transports |
+//! | `udp`         | Bool | Whether the transport is UDP                      |
+//! | `udp.srcport` | Int  | Source port of UDP, `0` for other transports      |
+//! | `udp.dstport` | Int  | Destination port of UDP, `0` for other transports |
+//!
+//! Port `0` is reserved, so comparisons like `tcp.dstport == 443` don't
+//! need to check the transport as well.
+#![warn(missing_docs)]
+
+use std::net::SocketAddr;
+use wirefilter::{ExecutionContext, ItemRedefinitionError, Scheme, SetFieldValueError, Type};
+
+/// Names and types of the `ip.*`, `tcp.*` and `udp.*` fields in the order
+/// they're registered.
+pub const FIELDS: &[(&str, Type)] = &[
+    ("ip.src", Type::Ip),
+    ("ip.dst", Type::Ip),
+    ("ip.proto", Type::Int),
+    ("tcp", Type::Bool),
+    ("tcp.srcport", Type::Int),
+    ("tcp.dstport", Type::Int),
+    ("udp", Type::Bool),
+    ("udp.srcport", Type::Int),
+    ("udp.dstport", Type::Int),
+];
+
+/// Registers the `ip.*`, `tcp.*` and `udp.*` fields in a scheme, e.g. next
+/// to the `http.*` or `dns.*` fields of an application protocol on top of
+/// them.
+pub fn add_fields(scheme: &mut Scheme) -> Result<(), ItemRedefinitionError> {
+    for &(name, ty) in FIELDS {
+        scheme.add_field(name.to_owned(), ty)?;
+    }
+    Ok(())
+}
+
+/// Creates a scheme for filters on connections and datagrams alone, like
+/// `ip.src in { 10.0.0.0/8 } and tcp.dstport == 443`, e.g. in a firewall.
+pub fn scheme() -> Scheme {
+    let mut scheme = Scheme::new();
+    add_fields(&mut scheme).expect("fields have unique names");
+    scheme
+}
+
+/// A transport protocol of a [`Flow`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    /// TCP.
+    Tcp,
+    /// UDP.
+    Udp,
+    /// Any other protocol with a given number, which doesn't have ports.
+    Other(u8),
+}
+
+impl Transport {
+    /// Returns the IANA protocol number.
+    pub fn number(self) -> u8 {
+        match self {
+            Transport::Tcp => 6,
+            Transport::Udp => 17,
+            Transport::Other(number) => number,
+        }
+    }
+}
+
+/// A connection or a datagram, which provides values of all fields at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flow {
+    /// Transport protocol.
+    pub transport: Transport,
+    /// Source address and port.
+    pub src: SocketAddr,
+    /// Destination address and port.
+    pub dst: SocketAddr,
+}
+
+impl Flow {
+    /// Sets the `ip.*` fields from the addresses of the flow in a context,
+    /// and the `tcp.*` or `udp.*` fields from its ports, depending on the
+    /// transport.
+    ///
+    /// Ports of the other transport, or of both for transports without
+    /// ports, are set to `0`.
+    pub fn set_fields(&self, ctx: &mut ExecutionContext<'_>) -> Result<(), SetFieldValueError> {
+        let ports = |transport| {
+            if self.transport == transport {
+                (i32::from(self.src.port()), i32::from(self.dst.port()))
+            } else {
+                (0, 0)
+            }
+        };
+        let (tcp_src, tcp_dst) = ports(Transport::Tcp);
+        let (udp_src, udp_dst) = ports(Transport::Udp);
+
+        ctx.set_field_value("ip.src", self.src.ip())?;
+        ctx.set_field_value("ip.dst", self.dst.ip())?;
+        ctx.set_field_value("ip.proto", i32::from(self.transport.number()))?;
+        ctx.set_field_value("tcp", self.transport == Transport::Tcp)?;
+        ctx.set_field_value("tcp.srcport", tcp_src)?;
+        ctx.set_field_value("tcp.dstport", tcp_dst)?;
+        ctx.set_field_value("udp", self.transport == Transport::Udp)?;
+        ctx.set_field_value("udp.srcport", udp_src)?;
+        ctx.set_field_value("udp.dstport", udp_dst)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_flow() {
+    use wirefilter::testing::{check_protocol_fields, unset_fields};
+
+    let scheme = scheme();
+    check_protocol_fields(FIELDS, add_fields, &scheme).unwrap();
+
+    let filter = scheme
+        .parse(r#"ip.src in { 10.0.0.0/8 } and tcp.dstport == 443 and ip.proto == 6"#)
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    let mut flow = Flow {
+        transport: Transport::Tcp,
+        src: "10.1.2.3:51000".parse().unwrap(),
+        dst: "[2001:db8::1]:443".parse().unwrap(),
+    };
+    flow.set_fields(&mut ctx).unwrap();
+    assert!(unset_fields(&ctx).is_empty());
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    flow.transport = Transport::Udp;
+    flow.set_fields(&mut ctx).unwrap();
+    assert_eq!(filter.execute(&ctx), Ok(false));
+    assert_eq!(
+        scheme
+            .parse("udp and udp.dstport == 443")
+            .unwrap()
+            .compile()
+            .execute(&ctx),
+        Ok(true)
+    );
+}
@@ -0,0 +1,134 @@
+//! TLS handshake fields for [Wirefilter](wirefilter) schemes.
+//!
+//! Fields describe the `ClientHello` a client opens the handshake with, so
+//! that connections can be filtered before they're decrypted. Names come
+//! from the Wireshark TLS dissector.
+//!
+//! | Field                                  | Type  | Value                                                           |
+//! |----------------------------------------|-------|-----------------------------------------------------------------|
+//! | `tls.handshake.version`                | Int   | Version offered in the `ClientHello`, like `0x0303` for TLS 1.2 |
+//! | `tls.handshake.extensions_server_name` | Bytes | Server name indication, case-insensitive                        |
+//! | `tls.handshake.extensions_alpn_str`    | Bytes | Offered ALPN protocols, any of which can match                  |
+//! | `tls.handshake.ciphersuite`            | Int   | Offered cipher suites, any of which can match                   |
+#![warn(missing_docs)]
+
+use wirefilter::{ExecutionContext, ItemRedefinitionError, Scheme, SetFieldValueError, Type};
+
+/// Names and types of the `tls.handshake.*` fields in the order they're
+/// registered.
+pub const FIELDS: &[(&str, Type)] = &[
+    ("tls.handshake.version", Type::Int),
+    ("tls.handshake.extensions_server_name", Type::Bytes),
+    ("tls.handshake.extensions_alpn_str", Type::Bytes),
+    ("tls.handshake.ciphersuite", Type::Int),
+];
+
+/// Registers the `tls.handshake.*` fields in a scheme, e.g. one that
+/// already has the `ip.*` and `tcp.*` fields of the connection.
+///
+/// The server name is compared case-insensitively, and the offered ALPN
+/// protocols and cipher suites are
+/// [multi-valued](wirefilter::Scheme::set_field_multi_valued), so that
+/// `tls.handshake.ciphersuite == 0x1301` matches if any offered suite does.
+pub fn add_fields(scheme: &mut Scheme) -> Result<(), ItemRedefinitionError> {
+    for &(name, ty) in FIELDS {
+        scheme.add_field(name.to_owned(), ty)?;
+    }
+    scheme
+        .set_field_case_insensitive("tls.handshake.extensions_server_name", true)
+        .expect("tls.handshake.extensions_server_name is a Bytes field");
+    for name in &[
+        "tls.handshake.extensions_alpn_str",
+        "tls.handshake.ciphersuite",
+    ] {
+        scheme
+            .set_field_multi_valued(name, true)
+            .expect("field is registered");
+    }
+    Ok(())
+}
+
+/// Creates a scheme for filters on `ClientHello` messages alone, like
+/// `tls.handshake.extensions_server_name == "example.org"` to route
+/// connections before they're decrypted.
+pub fn scheme() -> Scheme {
+    let mut scheme = Scheme::new();
+    add_fields(&mut scheme).expect("fields have unique names");
+    scheme
+}
+
+/// A `ClientHello` message, which provides values of all fields at once.
+///
+/// A missing server name is set as an empty string.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ClientHello<'a> {
+    /// Offered protocol version.
+    pub version: u16,
+    /// Server name indication.
+    pub server_name: Option<&'a str>,
+    /// Offered ALPN protocols, like `h2`.
+    pub alpn: &'a [&'a str],
+    /// Offered cipher suites.
+    pub cipher_suites: &'a [u16],
+}
+
+impl<'a> ClientHello<'a> {
+    /// Sets the `tls.handshake.*` fields from the `ClientHello` in a
+    /// context, with one value per offered ALPN protocol and cipher suite.
+    ///
+    /// A missing server name is set as an empty string, while an empty
+    /// list of protocols leaves the field without values, so that no
+    /// comparison with it matches.
+    pub fn set_fields<'e>(&self, ctx: &mut ExecutionContext<'e>) -> Result<(), SetFieldValueError>
+    where
+        'a: 'e,
+    {
+        ctx.set_field_value("tls.handshake.version", i32::from(self.version))?;
+        ctx.set_field_value(
+            "tls.handshake.extensions_server_name",
+            self.server_name.unwrap_or_default(),
+        )?;
+        ctx.set_field_values(
+            "tls.handshake.extensions_alpn_str",
+            self.alpn.iter().cloned(),
+        )?;
+        ctx.set_field_values(
+            "tls.handshake.ciphersuite",
+            self.cipher_suites.iter().map(|&suite| i32::from(suite)),
+        )?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_client_hello() {
+    use wirefilter::testing::{check_protocol_fields, unset_fields};
+
+    let scheme = scheme();
+    check_protocol_fields(FIELDS, add_fields, &scheme).unwrap();
+
+    let filter = scheme
+        .parse(
+            r#"tls.handshake.extensions_server_name == "example.org"
+                and tls.handshake.extensions_alpn_str == "h2"
+                and tls.handshake.ciphersuite == 0x1301"#,
+        )
+        .unwrap()
+        .compile();
+
+    let mut ctx = ExecutionContext::new(&scheme);
+    let mut hello = ClientHello {
+        version: 0x0303,
+        server_name: Some("EXAMPLE.org"),
+        alpn: &["http/1.1", "h2"],
+        cipher_suites: &[0x1302, 0x1301],
+    };
+    hello.set_fields(&mut ctx).unwrap();
+    assert!(unset_fields(&ctx).is_empty());
+    assert_eq!(filter.execute(&ctx), Ok(true));
+
+    hello.alpn = &[];
+    hello.set_fields(&mut ctx).unwrap();
+    assert_eq!(unset_fields(&ctx), ["tls.handshake.extensions_alpn_str"]);
+    assert_eq!(filter.execute(&ctx), Ok(false));
+}
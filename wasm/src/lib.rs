@@ -1,13 +1,48 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::HashMap, fmt::Display};
 use wasm_bindgen::prelude::*;
+use wirefilter::{ExecutionContext, FilterAst, ParseError};
 
 #[wasm_bindgen]
 pub struct Scheme(wirefilter::Scheme);
 
 #[allow(clippy::needless_pass_by_value)]
-fn into_js_error(err: impl std::error::Error) -> JsValue {
+fn into_js_error(err: impl Display) -> JsValue {
     js_sys::Error::new(&err.to_string()).into()
 }
 
+/// A parsing error with a position that can be highlighted in an editor.
+///
+/// Line numbers and offsets are 0-based, and offsets are in bytes.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JsParseError<'i> {
+    message: String,
+    line: &'i str,
+    line_number: usize,
+    span_start: usize,
+    span_len: usize,
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn into_js_parse_error(err: ParseError<'_>) -> JsValue {
+    JsValue::from_serde(&JsParseError {
+        message: err.reason(),
+        line: err.line(),
+        line_number: err.line_number(),
+        span_start: err.span_start(),
+        span_len: err.span_len(),
+    })
+    .unwrap_or_else(into_js_error)
+}
+
+impl Scheme {
+    fn parse_ast<'s>(&'s self, s: &str) -> Result<FilterAst<'s>, JsValue> {
+        self.0.parse(s).map_err(into_js_parse_error)
+    }
+}
+
 #[wasm_bindgen]
 impl Scheme {
     #[wasm_bindgen(constructor)]
@@ -19,4 +54,59 @@ impl Scheme {
         let filter = self.0.parse(s).map_err(into_js_error)?;
         JsValue::from_serde(&filter).map_err(into_js_error)
     }
+
+    /// Parses a filter like `parse`, but throws a plain object with the
+    /// message and the position of the error instead of an `Error`.
+    #[wasm_bindgen(js_name = parseFilter)]
+    pub fn parse_filter(&self, s: &str) -> Result<JsValue, JsValue> {
+        let filter = self.parse_ast(s)?;
+        JsValue::from_serde(&filter).map_err(into_js_error)
+    }
+
+    /// Executes a filter against an object with values of fields.
+    ///
+    /// Values are given as strings, numbers or booleans and are converted
+    /// according to types of fields, so IP addresses are strings too.
+    pub fn execute(&self, filter: &str, values: &JsValue) -> Result<bool, JsValue> {
+        let values: HashMap<String, Value> = values.into_serde().map_err(into_js_error)?;
+
+        let values = values
+            .into_iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    Value::String(value) => value,
+                    Value::Number(value) => value.to_string(),
+                    Value::Bool(value) => value.to_string(),
+                    _ => {
+                        return Err(into_js_error(format!(
+                            "unsupported value of field {}",
+                            name
+                        )))
+                    }
+                };
+                Ok((name, value))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let ast = self.parse_ast(filter)?;
+
+        let mut ctx = ExecutionContext::new(&self.0);
+        ctx.set_from_pairs(
+            values
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        )
+        .map_err(into_js_error)?;
+
+        if let Err(missing) = ctx.validate_for(&ast) {
+            let names = missing
+                .iter()
+                .map(|field| field.name)
+                .collect::<Vec<_>>()
+                .join(", ");
+            return Err(into_js_error(format!("missing values of fields {}", names)));
+        }
+
+        ast.compile().execute(&ctx).map_err(into_js_error)
+    }
 }